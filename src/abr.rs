@@ -0,0 +1,190 @@
+//! Simulated bitrate switching (`--abr-switch 0=360p --abr-switch 30s=1080p`)
+//! on top of `--playback-order`.
+//!
+//! Among the renditions named by the switch points, a player fetches only
+//! the one it has switched to, plus the init segments and keys of a rendition
+//! whenever it switches to it; renditions not named (audio, subtitles) play
+//! along. What that player fetches is downloaded at its pace and, with
+//! `--abr-trace`, written down as JSON lines, for CDN cache studies on real
+//! manifests. Segments it skips are downloaded once playback has ended, so the
+//! mirror is complete all the same.
+
+#![cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+
+use crate::PlannedFile;
+use crate::report::json_string;
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// A switch point: from `at` seconds of playback on, play `rendition`.
+#[derive(Debug, Clone)]
+pub(crate) struct Switch {
+    pub at: f64,
+    /// Media playlist URL or Representation id, or part of one.
+    pub rendition: String,
+}
+
+/// Parse `--abr-switch`: `AT=RENDITION`, with `AT` a duration as
+/// `--record-for` takes it.
+pub fn parse_switch(s: &str) -> Result<Switch> {
+    let (at, rendition) = s
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected AT=RENDITION, got '{}'", s))?;
+    let rendition = rendition.trim();
+    if rendition.is_empty() {
+        bail!("no rendition in '{}'", s);
+    }
+    Ok(Switch {
+        at: crate::schedule::parse_duration(at)?.as_secs_f64(),
+        rendition: rendition.to_string(),
+    })
+}
+
+/// A file the switching player fetches.
+pub(crate) struct Fetch {
+    /// Seconds from the start of playback.
+    pub at: f64,
+    /// Index into the schedule.
+    pub file: usize,
+}
+
+/// Apply `switches` to `scheduled`, the (start, file) playback schedule of
+/// every rendition: files the switching player fetches keep their start or
+/// move to when it switches, the others move to `end`. Returns what the
+/// player fetches, in order.
+pub(crate) fn simulate(
+    switches: &[Switch],
+    scheduled: &mut [(f64, PlannedFile)],
+    end: f64,
+) -> Result<Vec<Fetch>> {
+    let renditions: BTreeSet<&str> = scheduled
+        .iter()
+        .map(|(_, f)| f.rendition.as_str())
+        .collect();
+    let mut points: Vec<(f64, String)> = switches
+        .iter()
+        .map(|switch| {
+            Ok((
+                switch.at,
+                resolve(&renditions, &switch.rendition)?.to_string(),
+            ))
+        })
+        .collect::<Result<_>>()?;
+    points.sort_by(|a, b| a.0.total_cmp(&b.0));
+    // Playback starts on the first rendition, whenever the first switch is.
+    let selected = |at: f64| {
+        points
+            .iter()
+            .rev()
+            .find(|(from, _)| *from <= at)
+            .unwrap_or(&points[0])
+            .1
+            .as_str()
+    };
+
+    /// Per switched rendition.
+    #[derive(Default)]
+    struct Track {
+        /// Init segments and keys seen so far, and those since the last
+        /// segment fetched.
+        headers: Vec<usize>,
+        fresh: Vec<usize>,
+        /// Whether its last segment was fetched.
+        playing: bool,
+    }
+    let mut tracks: HashMap<&str, Track> = points
+        .iter()
+        .map(|(_, rendition)| (rendition.as_str(), Track::default()))
+        .collect();
+
+    let mut fetches = Vec::new();
+    let mut times: Vec<Option<f64>> = vec![None; scheduled.len()];
+    for (index, (start, file)) in scheduled.iter().enumerate() {
+        let Some(track) = tracks.get_mut(file.rendition.as_str()) else {
+            fetches.push(Fetch {
+                at: *start,
+                file: index,
+            });
+            times[index] = Some(*start);
+            continue;
+        };
+        if file.duration.is_none() {
+            track.headers.push(index);
+            track.fresh.push(index);
+            continue;
+        }
+        if selected(*start) != file.rendition {
+            track.playing = false;
+            continue;
+        }
+        let headers = if track.playing {
+            std::mem::take(&mut track.fresh)
+        } else {
+            track.fresh.clear();
+            track.headers.clone()
+        };
+        for header in headers.into_iter().chain([index]) {
+            fetches.push(Fetch {
+                at: *start,
+                file: header,
+            });
+            times[header].get_or_insert(*start);
+        }
+        track.playing = true;
+    }
+
+    for ((start, _), time) in scheduled.iter_mut().zip(times) {
+        *start = time.unwrap_or(end);
+    }
+    // Stable, so files fetched at the same time keep their order.
+    fetches.sort_by(|a, b| a.at.total_cmp(&b.at));
+    Ok(fetches)
+}
+
+/// The rendition `name` refers to: the one called that, or else the only
+/// one whose name contains it.
+fn resolve<'a>(renditions: &BTreeSet<&'a str>, name: &str) -> Result<&'a str> {
+    if let Some(exact) = renditions.get(name) {
+        return Ok(exact);
+    }
+    let matches: Vec<&str> = renditions
+        .iter()
+        .copied()
+        .filter(|rendition| rendition.contains(name))
+        .collect();
+    match matches.as_slice() {
+        [only] => Ok(only),
+        [] => bail!(
+            "--abr-switch: no rendition matches '{}' (there are {})",
+            name,
+            renditions.iter().copied().collect::<Vec<_>>().join(", ")
+        ),
+        several => bail!(
+            "--abr-switch: '{}' matches several renditions ({})",
+            name,
+            several.join(", ")
+        ),
+    }
+}
+
+/// Write `fetches` of `scheduled` to `path`, one JSON object per line.
+pub(crate) async fn write_trace(
+    path: &Path,
+    fetches: &[Fetch],
+    scheduled: &[(f64, PlannedFile)],
+) -> Result<()> {
+    let mut out = String::new();
+    for fetch in fetches {
+        let file = &scheduled[fetch.file].1;
+        out.push_str(&format!(
+            "{{\"time\":{:.3},\"rendition\":{},\"url\":{}}}\n",
+            fetch.at,
+            json_string(&file.rendition),
+            json_string(file.url.as_str())
+        ));
+    }
+    tokio::fs::write(path, out)
+        .await
+        .with_context(|| format!("writing ABR trace {}", path.display()))
+}
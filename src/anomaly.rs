@@ -0,0 +1,191 @@
+//! Detection of segments much smaller than the rest of their rendition, which
+//! usually are error pages saved in place of media.
+
+use crate::Mirror;
+use crate::report::Event;
+use crate::units;
+use anyhow::{Result, bail};
+use std::collections::{HashMap, HashSet};
+use url::Url;
+
+/// Default for `--anomaly-ratio`.
+pub const DEFAULT_RATIO: f64 = 0.1;
+
+/// Default for `--anomaly-min-segments`.
+pub const DEFAULT_MIN_SEGMENTS: usize = 5;
+
+/// What to do about an undersized segment.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AnomalyAction {
+    /// Report it and keep going
+    #[default]
+    Warn,
+    /// Download it once more, reporting it if it is still too small
+    Refetch,
+    /// Report it and fail the run
+    Fail,
+}
+
+/// Thresholds for flagging undersized segments.
+#[derive(clap::Args, Debug, Clone)]
+pub struct SizeCheck {
+    /// Flag segments smaller than this fraction of their rendition's median
+    /// segment size; 0 disables the check
+    #[arg(long = "anomaly-ratio", value_name = "RATIO", default_value_t = DEFAULT_RATIO)]
+    pub ratio: f64,
+
+    /// Only check renditions with at least this many segments
+    #[arg(long = "anomaly-min-segments", value_name = "N", default_value_t = DEFAULT_MIN_SEGMENTS)]
+    pub min_segments: usize,
+
+    /// What to do with undersized segments
+    #[arg(
+        long = "on-anomaly",
+        value_enum,
+        value_name = "ACTION",
+        default_value_t
+    )]
+    pub action: AnomalyAction,
+}
+
+impl Default for SizeCheck {
+    fn default() -> Self {
+        Self {
+            ratio: DEFAULT_RATIO,
+            min_segments: DEFAULT_MIN_SEGMENTS,
+            action: AnomalyAction::Warn,
+        }
+    }
+}
+
+/// A segment much smaller than its rendition's median.
+#[derive(Debug, Clone)]
+pub struct Anomaly {
+    pub rendition: String,
+    pub url: String,
+    pub size: u64,
+    pub median: u64,
+    /// Whether the file starts like an HTML or XML document.
+    pub markup: bool,
+}
+
+impl Mirror {
+    /// Remember a media segment of the current rendition for the size check.
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    pub(crate) fn note_segment(&mut self, url: &Url) {
+        if self.plan.is_none() {
+            self.segments.push((self.rendition.clone(), url.clone()));
+        }
+    }
+
+    /// Compare every mirrored segment with the median size of its rendition,
+    /// record the undersized ones in the catalog and handle them according
+    /// to `--on-anomaly`.
+    pub(crate) async fn check_segment_sizes(&mut self) -> Result<()> {
+        if self.size_check.ratio <= 0.0 {
+            return Ok(());
+        }
+
+        let mut flagged = 0;
+        let mut seen = HashSet::new();
+        let mut renditions: Vec<String> = Vec::new();
+        let mut by_rendition: HashMap<String, Vec<(Url, u64)>> = HashMap::new();
+        for (rendition, url) in std::mem::take(&mut self.segments) {
+            if !seen.insert(self.dedup_key(&url)) {
+                continue;
+            }
+            // Dropped or failed segments have no local file.
+            let path = self.path_for_url(&url, false);
+            let Ok(meta) = tokio::fs::metadata(&path).await else {
+                continue;
+            };
+            if !by_rendition.contains_key(&rendition) {
+                renditions.push(rendition.clone());
+            }
+            by_rendition
+                .entry(rendition)
+                .or_default()
+                .push((url, meta.len()));
+        }
+
+        for rendition in renditions {
+            let segments = &by_rendition[&rendition];
+            if segments.len() < self.size_check.min_segments.max(1) {
+                continue;
+            }
+
+            let mut sizes: Vec<u64> = segments.iter().map(|(_, size)| *size).collect();
+            sizes.sort_unstable();
+            let median = sizes[sizes.len() / 2];
+            let threshold = median as f64 * self.size_check.ratio;
+
+            for (url, size) in segments {
+                if *size as f64 >= threshold {
+                    continue;
+                }
+
+                let mut size = *size;
+                if self.size_check.action == AnomalyAction::Refetch {
+                    size = self.refetch_segment(&rendition, url).await?;
+                    if size as f64 >= threshold {
+                        self.reporter.report(Event::Detail {
+                            message: format!("refetched {}, now {}", url, units::size(size)),
+                        });
+                        continue;
+                    }
+                }
+
+                let path = self.path_for_url(url, false);
+                let markup = crate::encrypt::read(&path)
+                    .await
+                    .map(|data| crate::container::is_markup(&data))
+                    .unwrap_or(false);
+
+                self.reporter.report(Event::Warning {
+                    message: format!(
+                        "segment {} is {}, the median of {} is {}{}",
+                        url,
+                        units::size(size),
+                        rendition,
+                        units::size(median),
+                        if markup {
+                            " (looks like an HTML/XML page)"
+                        } else {
+                            ""
+                        }
+                    ),
+                });
+                self.catalog.anomalies.push(Anomaly {
+                    rendition: rendition.clone(),
+                    url: url.to_string(),
+                    size,
+                    median,
+                    markup,
+                });
+                flagged += 1;
+            }
+        }
+
+        if flagged > 0 && self.size_check.action == AnomalyAction::Fail {
+            bail!(
+                "{} segment(s) much smaller than their rendition's median",
+                flagged
+            );
+        }
+        Ok(())
+    }
+
+    /// Download a segment again, returning its new size.
+    async fn refetch_segment(&mut self, rendition: &str, url: &Url) -> Result<u64> {
+        self.visited.remove(&self.dedup_key(url));
+        self.fetched_before.remove(&self.identity(url));
+
+        let previous = std::mem::replace(&mut self.rendition, rendition.to_string());
+        let result = self.mirror_binary(url.clone()).await;
+        self.rendition = previous;
+        result?;
+
+        let path = self.path_for_url(url, false);
+        Ok(tokio::fs::metadata(&path).await?.len())
+    }
+}
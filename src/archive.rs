@@ -0,0 +1,316 @@
+//! Compressed archives of mirrors (`--output-format tar.zst`) for cold
+//! storage, and unpacking them again (`streamrip extract`).
+//!
+//! An archive is a POSIX tar file compressed with zstd. `catalog.json` comes
+//! first, so what an archive holds can be read without decompressing the
+//! rest. Symlinks into a segment store are archived as the files they point
+//! to, so archives are self-contained. Paths longer than the tar header
+//! allows and files of 8 GiB or more get PAX extended headers.
+
+use crate::catalog::CATALOG_FILE;
+use crate::report::{Event, SharedReporter};
+use crate::units;
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Extension of archives written next to the output directory.
+pub const EXTENSION: &str = "tar.zst";
+
+const BLOCK: usize = 512;
+
+/// Largest size the octal size field holds.
+const MAX_OCTAL_SIZE: u64 = 0o77777777777;
+
+/// Where the archive of `out_dir` is written: next to it, named after it.
+pub fn archive_path(out_dir: &Path) -> PathBuf {
+    let name = out_dir
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "mirror".to_string());
+    out_dir.with_file_name(format!("{}.{}", name, EXTENSION))
+}
+
+/// Pack every file below `dir` into a zstd-compressed tar file at `archive`.
+pub async fn pack(dir: &Path, archive: &Path, reporter: &SharedReporter) -> Result<()> {
+    let (dir_owned, archive_owned) = (dir.to_path_buf(), archive.to_path_buf());
+    let (files, bytes) =
+        tokio::task::spawn_blocking(move || pack_blocking(&dir_owned, &archive_owned))
+            .await
+            .context("packing the archive")??;
+    let compressed = tokio::fs::metadata(archive)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+    reporter.report(Event::Status {
+        tag: "ARCH",
+        message: format!(
+            "{} file(s), {} packed into {} ({})",
+            units::count(files),
+            units::size(bytes),
+            archive.display(),
+            units::size(compressed)
+        ),
+    });
+    Ok(())
+}
+
+/// Unpack `archive` into `dir`.
+pub async fn extract(archive: &Path, dir: &Path, reporter: &SharedReporter) -> Result<()> {
+    let (archive_owned, dir_owned) = (archive.to_path_buf(), dir.to_path_buf());
+    let files = tokio::task::spawn_blocking(move || extract_blocking(&archive_owned, &dir_owned))
+        .await
+        .context("extracting the archive")??;
+    reporter.report(Event::Status {
+        tag: "ARCH",
+        message: format!("{} file(s) extracted into {}", files, dir.display()),
+    });
+    Ok(())
+}
+
+/// Default directory to extract `archive` into: its name without the
+/// extension, next to it.
+pub fn default_extract_dir(archive: &Path) -> PathBuf {
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let stem = name
+        .strip_suffix(&format!(".{}", EXTENSION))
+        .unwrap_or(&name)
+        .to_string();
+    archive.with_file_name(stem)
+}
+
+fn pack_blocking(dir: &Path, archive: &Path) -> Result<(usize, u64)> {
+    let mut files = Vec::new();
+    crate::collect_files(dir, dir, &mut files)?;
+    files.sort_by_key(|relative| (relative.as_str() != CATALOG_FILE, relative.clone()));
+
+    let out = File::create(archive).with_context(|| format!("creating {}", archive.display()))?;
+    let mut encoder = zstd::Encoder::new(BufWriter::new(out), 0)?;
+    let mut total = 0;
+    for relative in &files {
+        let path = dir.join(relative);
+        // Follows symlinks into a segment store.
+        let metadata =
+            std::fs::metadata(&path).with_context(|| format!("reading {}", path.display()))?;
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_secs());
+        write_header(&mut encoder, relative, metadata.len(), mtime)?;
+        let copied = std::io::copy(
+            &mut File::open(&path).with_context(|| format!("reading {}", path.display()))?,
+            &mut encoder,
+        )?;
+        if copied != metadata.len() {
+            bail!("{} changed while it was being archived", path.display());
+        }
+        pad(&mut encoder, copied)?;
+        total += copied;
+    }
+    // End of archive: two zero blocks.
+    encoder.write_all(&[0; 2 * BLOCK])?;
+    encoder
+        .finish()?
+        .flush()
+        .with_context(|| format!("writing {}", archive.display()))?;
+    Ok((files.len(), total))
+}
+
+/// Write the header of a regular file, preceded by a PAX extended header if
+/// its path or size doesn't fit.
+fn write_header(out: &mut impl Write, path: &str, size: u64, mtime: u64) -> Result<()> {
+    let split = split_path(path);
+    let mut pax = String::new();
+    if split.is_none() {
+        pax.push_str(&pax_record("path", path));
+    }
+    if size > MAX_OCTAL_SIZE {
+        pax.push_str(&pax_record("size", &size.to_string()));
+    }
+    if !pax.is_empty() {
+        let header = header(b'x', "", "PaxHeader", pax.len() as u64, mtime);
+        out.write_all(&header)?;
+        out.write_all(pax.as_bytes())?;
+        pad(out, pax.len() as u64)?;
+    }
+
+    let (prefix, name) = split.unwrap_or(("", truncate(path, 100)));
+    out.write_all(&header(b'0', prefix, name, size.min(MAX_OCTAL_SIZE), mtime))?;
+    Ok(())
+}
+
+/// `path` as the prefix and name fields of a ustar header, if it fits.
+fn split_path(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 {
+        return Some(("", path));
+    }
+    path.match_indices('/')
+        .map(|(i, _)| (&path[..i], &path[i + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+/// A PAX record: `<length> <key>=<value>\n`, the length counting itself.
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = format!(" {}={}\n", key, value);
+    let mut length = rest.len() + 1;
+    while (length.to_string().len() + rest.len()) != length {
+        length += 1;
+    }
+    format!("{}{}", length, rest)
+}
+
+fn header(kind: u8, prefix: &str, name: &str, size: u64, mtime: u64) -> [u8; BLOCK] {
+    let mut header = [0u8; BLOCK];
+    let mut put = |offset: usize, value: &[u8]| {
+        header[offset..offset + value.len()].copy_from_slice(value);
+    };
+    put(0, name.as_bytes());
+    put(100, b"0000644\0");
+    put(108, b"0000000\0");
+    put(116, b"0000000\0");
+    put(124, format!("{:011o}\0", size).as_bytes());
+    put(
+        136,
+        format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes(),
+    );
+    put(148, b"        ");
+    put(156, &[kind]);
+    put(257, b"ustar\0");
+    put(263, b"00");
+    put(345, prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|b| u32::from(*b)).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    header
+}
+
+/// Zeros up to the end of the block after `len` bytes of data.
+fn pad(out: &mut impl Write, len: u64) -> std::io::Result<()> {
+    let rest = (BLOCK - (len % BLOCK as u64) as usize) % BLOCK;
+    out.write_all(&[0; BLOCK][..rest])
+}
+
+fn extract_blocking(archive: &Path, dir: &Path) -> Result<usize> {
+    let file = File::open(archive).with_context(|| format!("opening {}", archive.display()))?;
+    let mut input = zstd::Decoder::new(BufReader::new(file))
+        .with_context(|| format!("reading {}", archive.display()))?;
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+
+    let mut files = 0;
+    let mut pax_path: Option<String> = None;
+    let mut pax_size: Option<u64> = None;
+    let mut header = [0u8; BLOCK];
+    loop {
+        input
+            .read_exact(&mut header)
+            .with_context(|| format!("{} ends unexpectedly", archive.display()))?;
+        if header.iter().all(|b| *b == 0) {
+            break;
+        }
+        let size = pax_size.take().unwrap_or(octal(&header[124..136])?);
+        let kind = header[156];
+        match kind {
+            b'x' => {
+                let mut records = vec![0; size as usize];
+                input.read_exact(&mut records)?;
+                skip(&mut input, padding(size))?;
+                for (key, value) in pax_records(&records) {
+                    match key {
+                        "path" => pax_path = Some(value.to_string()),
+                        "size" => pax_size = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+            b'0' | b'\0' => {
+                let name = pax_path.take().unwrap_or_else(|| ustar_path(&header));
+                let path = dir.join(safe_path(&name)?);
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .with_context(|| format!("creating {}", parent.display()))?;
+                }
+                let mut out =
+                    File::create(&path).with_context(|| format!("creating {}", path.display()))?;
+                let copied = std::io::copy(&mut (&mut input).take(size), &mut out)?;
+                if copied != size {
+                    bail!("{} ends unexpectedly", archive.display());
+                }
+                let mtime = octal(&header[136..148]).unwrap_or(0);
+                out.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+                skip(&mut input, padding(size))?;
+                files += 1;
+            }
+            // Directories are created as needed; other entries aren't written
+            // by `pack`.
+            _ => {
+                pax_path = None;
+                skip(&mut input, size + padding(size))?;
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// The path of an archived file, refusing ones that would leave the
+/// extraction directory.
+fn safe_path(name: &str) -> Result<PathBuf> {
+    let path = Path::new(name);
+    if path
+        .components()
+        .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir))
+    {
+        return Err(anyhow!("refusing to extract '{}' outside the target", name));
+    }
+    Ok(path.to_path_buf())
+}
+
+fn ustar_path(header: &[u8; BLOCK]) -> String {
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+    let (prefix, name) = (field(&header[345..500]), field(&header[0..100]));
+    if prefix.is_empty() {
+        name
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+fn octal(field: &[u8]) -> Result<u64> {
+    let text = String::from_utf8_lossy(field);
+    let digits = text.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).map_err(|_| anyhow!("invalid tar header field '{}'", digits))
+}
+
+fn pax_records(data: &[u8]) -> Vec<(&str, &str)> {
+    let text = std::str::from_utf8(data).unwrap_or_default();
+    text.lines()
+        .filter_map(|line| line.split_once(' ')?.1.split_once('='))
+        .collect()
+}
+
+fn padding(size: u64) -> u64 {
+    (BLOCK as u64 - size % BLOCK as u64) % BLOCK as u64
+}
+
+fn skip(input: &mut impl Read, len: u64) -> Result<()> {
+    let skipped = std::io::copy(&mut input.take(len), &mut std::io::sink())?;
+    if skipped != len {
+        bail!("the archive ends unexpectedly");
+    }
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Boxes of the ISO base media file format (MP4, fragmented MP4), as read
+//! from init segments for their `pssh` boxes and sample entries.
+
+#![cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+
+/// A box: its type, its body and the whole box, header included.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Mp4Box<'a> {
+    pub kind: [u8; 4],
+    pub body: &'a [u8],
+    pub whole: &'a [u8],
+}
+
+/// The boxes of `data`, one after another, up to the first malformed one: a
+/// header cut short, or a size smaller than the header or running past the
+/// end of `data`.
+pub(crate) fn boxes(data: &[u8]) -> Boxes<'_> {
+    Boxes { rest: data }
+}
+
+/// Iterator returned by [`boxes`].
+pub(crate) struct Boxes<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Boxes<'a> {
+    type Item = Mp4Box<'a>;
+
+    fn next(&mut self) -> Option<Mp4Box<'a>> {
+        let (found, rest) = parse(self.rest)?;
+        self.rest = rest;
+        Some(found)
+    }
+}
+
+/// The box at the start of `data`, and what follows it.
+fn parse(data: &[u8]) -> Option<(Mp4Box<'_>, &[u8])> {
+    let size = u32::from_be_bytes(data.get(0..4)?.try_into().ok()?);
+    let kind = data.get(4..8)?.try_into().ok()?;
+    let (size, header) = match size {
+        // A 64-bit size follows the type.
+        1 => (u64::from_be_bytes(data.get(8..16)?.try_into().ok()?), 16),
+        // The box runs to the end of the file.
+        0 => (data.len() as u64, 8),
+        size => (u64::from(size), 8),
+    };
+    let size = usize::try_from(size)
+        .ok()
+        .filter(|size| (header..=data.len()).contains(size))?;
+    let (whole, rest) = data.split_at(size);
+    Some((
+        Mp4Box {
+            kind,
+            body: &whole[header..],
+            whole,
+        },
+        rest,
+    ))
+}
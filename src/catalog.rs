@@ -0,0 +1,210 @@
+//! `catalog.json`: machine-readable facts about a mirror, written next to
+//! the mirrored files at the end of a run.
+
+use crate::anomaly::Anomaly;
+use crate::container::Container;
+use crate::provenance::Provenance;
+use crate::report::json_string;
+use crate::variants::VariantRecord;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+/// File name of the catalog inside the output directory.
+pub const CATALOG_FILE: &str = "catalog.json";
+
+/// Everything recorded about a mirror while it is being made.
+#[derive(Debug, Default)]
+pub struct Catalog {
+    /// How and when the mirror was made, with `--provenance`.
+    pub provenance: Option<Provenance>,
+    /// DRM signalling found in manifests and init segments.
+    pub protection: Vec<Protection>,
+    /// Segment container per rendition, as detected from its first segment.
+    pub containers: Vec<(String, Container)>,
+    /// Resources referenced per header variant, when mirrored with `--header-variants`.
+    pub variants: Vec<VariantRecord>,
+    /// Segments much smaller than the rest of their rendition.
+    pub anomalies: Vec<Anomaly>,
+}
+
+/// One piece of DRM signalling.
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Protection {
+    /// Rendition (media playlist URL or DASH Representation id) it applies
+    /// to, or the manifest URL for manifest-wide signalling.
+    pub rendition: String,
+    /// Where it was found: `mpd`, `playlist` or `init`.
+    pub origin: &'static str,
+    /// `schemeIdUri` of a DASH ContentProtection, or `METHOD`/`KEYFORMAT` of an HLS key.
+    pub scheme: Option<String>,
+    /// DRM system id as a lowercase UUID.
+    pub system_id: Option<String>,
+    /// `cenc:default_KID` as a lowercase UUID.
+    pub default_kid: Option<String>,
+    /// Base64 encoded PSSH box.
+    pub pssh: Option<String>,
+}
+
+impl Catalog {
+    /// Record `protection` unless an identical entry exists already; returns
+    /// whether it was new.
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    pub fn add_protection(&mut self, protection: Protection) -> bool {
+        if self.protection.contains(&protection) {
+            return false;
+        }
+        self.protection.push(protection);
+        true
+    }
+
+    /// Record the container of `rendition` unless one is known already;
+    /// returns whether it was new.
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    pub fn add_container(&mut self, rendition: &str, container: Container) -> bool {
+        if self.containers.iter().any(|(r, _)| r == rendition) {
+            return false;
+        }
+        self.containers.push((rendition.to_string(), container));
+        true
+    }
+
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    pub fn has_container(&self, rendition: &str) -> bool {
+        self.containers.iter().any(|(r, _)| r == rendition)
+    }
+
+    /// Sort every list by its content rather than by the order things
+    /// were found in, which depends on how downloads interleave.
+    pub fn sort(&mut self) {
+        self.containers.sort_by(|a, b| a.0.cmp(&b.0));
+        self.protection.sort_by(|a, b| {
+            (
+                &a.rendition,
+                a.origin,
+                &a.scheme,
+                &a.system_id,
+                &a.default_kid,
+                &a.pssh,
+            )
+                .cmp(&(
+                    &b.rendition,
+                    b.origin,
+                    &b.scheme,
+                    &b.system_id,
+                    &b.default_kid,
+                    &b.pssh,
+                ))
+        });
+        self.anomalies
+            .sort_by(|a, b| (&a.rendition, &a.url).cmp(&(&b.rendition, &b.url)));
+    }
+
+    pub fn to_json(&self) -> String {
+        let renditions: Vec<String> = self
+            .containers
+            .iter()
+            .map(|(rendition, container)| {
+                format!(
+                    "    {{\"rendition\": {}, \"container\": {}}}",
+                    json_string(rendition),
+                    json_string(container.name())
+                )
+            })
+            .collect();
+
+        let protection: Vec<String> = self
+            .protection
+            .iter()
+            .map(|p| {
+                format!(
+                    "    {{\"rendition\": {}, \"origin\": {}, \"scheme\": {}, \"system_id\": {}, \"system\": {}, \"default_kid\": {}, \"pssh\": {}}}",
+                    json_string(&p.rendition),
+                    json_string(p.origin),
+                    json_opt(p.scheme.as_deref()),
+                    json_opt(p.system_id.as_deref()),
+                    json_opt(p.system_id.as_deref().and_then(system_name)),
+                    json_opt(p.default_kid.as_deref()),
+                    json_opt(p.pssh.as_deref()),
+                )
+            })
+            .collect();
+
+        let variants: Vec<String> = self
+            .variants
+            .iter()
+            .map(|v| {
+                let resources: Vec<String> = v.resources.iter().map(|r| json_string(r)).collect();
+                format!(
+                    "    {{\"name\": {}, \"manifest\": {}, \"resources\": [{}]}}",
+                    json_string(&v.name),
+                    json_string(&v.manifest),
+                    resources.join(", ")
+                )
+            })
+            .collect();
+
+        let anomalies: Vec<String> = self
+            .anomalies
+            .iter()
+            .map(|a| {
+                format!(
+                    "    {{\"rendition\": {}, \"url\": {}, \"size\": {}, \"median\": {}, \"markup\": {}}}",
+                    json_string(&a.rendition),
+                    json_string(&a.url),
+                    a.size,
+                    a.median,
+                    a.markup
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\n{}  \"renditions\": {},\n  \"protection\": {},\n  \"variants\": {},\n  \"anomalies\": {}\n}}\n",
+            self.provenance
+                .as_ref()
+                .map_or_else(String::new, |p| format!(
+                    "  \"provenance\": {},\n",
+                    p.to_json()
+                )),
+            json_array(&renditions),
+            json_array(&protection),
+            json_array(&variants),
+            json_array(&anomalies)
+        )
+    }
+
+    /// Write the catalog to `out_dir`.
+    pub async fn write(&self, out_dir: &Path) -> Result<()> {
+        let path = out_dir.join(CATALOG_FILE);
+        tokio::fs::write(&path, crate::encrypt::seal(self.to_json().as_bytes())?)
+            .await
+            .with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+fn json_array(items: &[String]) -> String {
+    if items.is_empty() {
+        "[]".to_string()
+    } else {
+        format!("[\n{}\n  ]", items.join(",\n"))
+    }
+}
+
+fn json_opt(value: Option<&str>) -> String {
+    value.map_or_else(|| "null".to_string(), json_string)
+}
+
+/// Well-known name of a DRM system id.
+pub fn system_name(system_id: &str) -> Option<&'static str> {
+    Some(match system_id {
+        "edef8ba9-79d6-4ace-a3c8-27dcd51d21ed" => "Widevine",
+        "9a04f079-9840-4286-ab92-e65be0885f95" => "PlayReady",
+        "94ce86fb-07ff-4f43-adb8-93d2fa968ca2" => "FairPlay",
+        "e2719d58-a985-b3c9-781a-b030af78d30e" => "ClearKey",
+        "1077efec-c0b2-4d02-ace3-3c1e52e2fb4b" => "W3C Common PSSH",
+        "3d5e6d35-9b9a-41e8-b843-dd3c6e72c42c" => "ChinaDRM",
+        "f239e769-efa3-4850-9c16-a903c6932efb" => "Adobe Primetime",
+        _ => return None,
+    })
+}
@@ -0,0 +1,94 @@
+//! The checksum catalog (`--checksums`): `SHA256SUMS` at the root of the
+//! mirror lists the SHA-256 of every file in it, in `sha256sum` format, so
+//! that a copy can be checked with `sha256sum -c SHA256SUMS`.
+//!
+//! Files this run writes are hashed on their way to disk (see
+//! [`crate::digests`]); only the others, left by earlier runs or changed
+//! since, are read back.
+
+use crate::digests::{Digests, Sha256Digest};
+use crate::report::{Event, SharedReporter};
+use crate::state::STATE_FILE;
+use crate::status::STATUS_FILE;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+/// The checksum catalog, at the root of the mirror.
+pub const SUMS_FILE: &str = "SHA256SUMS";
+
+/// Files at the root of the mirror that aren't listed, as they change
+/// whenever the mirror is updated.
+const UNLISTED: &[&str] = &[
+    STATE_FILE,
+    STATUS_FILE,
+    SUMS_FILE,
+    crate::signing::SIGNATURE_FILE,
+];
+
+/// Write the checksum catalog of the mirror in `out_dir`, with the digests of
+/// the files this run wrote taken from `digests`. Returns the catalog.
+pub async fn write(out_dir: &Path, digests: Digests, reporter: &SharedReporter) -> Result<String> {
+    let dir = out_dir.to_path_buf();
+    let (sums, read) = tokio::task::spawn_blocking(move || list(&dir, &digests))
+        .await
+        .context("hashing the mirror")??;
+    reporter.report(Event::Detail {
+        message: format!(
+            "{} file(s) hashed while written, {} read back",
+            sums.len() - read,
+            read
+        ),
+    });
+    let catalog: String = sums
+        .iter()
+        .map(|(hash, path)| format!("{}  {}\n", hash, path))
+        .collect();
+
+    // In the clear even with --encrypt-output, like the state file: it
+    // lists what's in the mirror, not its content.
+    let path = out_dir.join(SUMS_FILE);
+    tokio::fs::write(&path, &catalog)
+        .await
+        .with_context(|| format!("writing {}", path.display()))?;
+    reporter.report(Event::Status {
+        tag: "SUMS",
+        message: format!("{} file(s) listed in {}", sums.len(), SUMS_FILE),
+    });
+    Ok(catalog)
+}
+
+/// SHA-256 and path (relative, `/`-separated) of every file below `dir`
+/// except the unlisted ones, sorted by path, and how many of them had to be
+/// read: the others' `digests` are known.
+pub fn list(dir: &Path, digests: &Digests) -> Result<(Vec<(String, String)>, usize)> {
+    let mut files = Vec::new();
+    crate::collect_files(dir, dir, &mut files)?;
+    files.retain(|relative| !UNLISTED.contains(&relative.as_str()));
+    files.sort();
+    let mut read = 0;
+    let sums = files
+        .into_iter()
+        .map(|relative| {
+            let path = dir.join(&relative);
+            let digest: Sha256Digest = match digests.get(&path) {
+                Some(digest) => digest,
+                None => {
+                    read += 1;
+                    let mut hasher = Sha256::new();
+                    // Follows symlinks into a segment store.
+                    std::io::copy(
+                        &mut std::fs::File::open(&path)
+                            .with_context(|| format!("reading {}", path.display()))?,
+                        &mut hasher,
+                    )
+                    .with_context(|| format!("reading {}", path.display()))?;
+                    hasher.finalize().into()
+                }
+            };
+            let hash = digest.iter().map(|b| format!("{:02x}", b)).collect();
+            Ok((hash, relative))
+        })
+        .collect::<Result<_>>()?;
+    Ok((sums, read))
+}
@@ -0,0 +1,120 @@
+//! Segment container detection (MPEG-TS, fragmented MP4/CMAF, WebM).
+
+use crate::units;
+use std::path::Path;
+
+/// Container format of media segments.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    MpegTs,
+    /// Fragmented ISO BMFF, including CMAF.
+    Fmp4,
+    WebM,
+}
+
+/// Size of an MPEG-TS packet.
+const TS_PACKET: usize = 188;
+
+/// Top-level ISO BMFF boxes that may start an init or media segment.
+const BMFF_LEADING_BOXES: &[&[u8; 4]] = &[
+    b"ftyp", b"styp", b"moov", b"moof", b"sidx", b"emsg", b"prft", b"free",
+];
+
+impl Container {
+    /// Detect the container from the first bytes of a segment.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+            return Some(Container::WebM);
+        }
+        if data.len() >= 8 && BMFF_LEADING_BOXES.iter().any(|b| &data[4..8] == *b) {
+            return Some(Container::Fmp4);
+        }
+        // Require a second sync byte so short files starting with 0x47
+        // (e.g. keys) aren't mistaken for transport streams.
+        if data.len() >= TS_PACKET
+            && data[0] == 0x47
+            && (data.len() == TS_PACKET || data[TS_PACKET] == 0x47)
+        {
+            return Some(Container::MpegTs);
+        }
+        None
+    }
+
+    /// Guess the container from a segment's file extension.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "ts" | "m2ts" => Some(Container::MpegTs),
+            "mp4" | "m4s" | "m4v" | "m4a" | "cmfv" | "cmfa" | "cmft" => Some(Container::Fmp4),
+            "webm" | "weba" | "mkv" => Some(Container::WebM),
+            _ => None,
+        }
+    }
+
+    /// Why a downloaded file is not what its extension promises, if it looks
+    /// like an error page rather than a segment. Content-Type and content are
+    /// checked; `encrypted` marks whole-segment encryption (HLS `AES-128`),
+    /// where only markup gives a segment away.
+    pub fn rejection(
+        content_type: Option<&str>,
+        path: &Path,
+        data: &[u8],
+        encrypted: bool,
+    ) -> Option<String> {
+        if let Some(ct) = content_type
+            && ct.to_ascii_lowercase().starts_with("text/html")
+        {
+            return Some(format!("served as {}", ct));
+        }
+
+        let markup = is_markup(data);
+        match Container::from_extension(path) {
+            Some(expected) if Container::sniff(data).is_none() && (markup || !encrypted) => {
+                Some(if markup {
+                    format!(
+                        "expected {}, got a document starting with '<'",
+                        expected.name()
+                    )
+                } else {
+                    format!(
+                        "expected {}, got {} of something else",
+                        expected.name(),
+                        units::size(data.len() as u64)
+                    )
+                })
+            }
+            None if markup && is_html(data) => Some("got an HTML document".to_string()),
+            _ => None,
+        }
+    }
+
+    /// Identifier used in `catalog.json`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Container::MpegTs => "mpegts",
+            Container::Fmp4 => "fmp4",
+            Container::WebM => "webm",
+        }
+    }
+
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Container::MpegTs => "video/mp2t",
+            Container::Fmp4 => "video/mp4",
+            Container::WebM => "video/webm",
+        }
+    }
+}
+
+/// Whether `data` starts like an HTML or XML document.
+pub fn is_markup(data: &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(b"<")
+}
+
+fn is_html(data: &[u8]) -> bool {
+    let head = data.trim_ascii_start();
+    let head = &head[..head.len().min(64)];
+    let head = head.to_ascii_lowercase();
+    head.starts_with(b"<!doctype html") || head.starts_with(b"<html")
+}
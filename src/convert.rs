@@ -0,0 +1,775 @@
+//! Experimental conversion between HLS and DASH mirrors (`streamrip convert`).
+//!
+//! An HLS mirror of CMAF (fMP4) segments gets an MPD listing the same files
+//! in `SegmentList`s; a DASH mirror gets a master playlist and a media
+//! playlist per Representation. Nothing is copied: the new manifests refer to
+//! the mirrored files where they are, so players of both ecosystems can be
+//! tested against a single capture. Only what maps directly is converted;
+//! other renditions (MPEG-TS or encrypted HLS segments, DASH
+//! `SegmentTimeline`s and sub-numbered segments) are skipped with a warning.
+
+use crate::mpd::MpdModel;
+use crate::playlist::{HlsPlaylist, Line, Tag};
+use crate::report::{Event, SharedReporter};
+use crate::{encrypt, relative_reference, template};
+use anyhow::{Context, Result, anyhow, bail};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Timescale of the converted MPD: milliseconds.
+const TIMESCALE: f64 = 1000.0;
+
+/// Codecs (by prefix) that are audio, to split the `CODECS` of a variant with
+/// a separate audio rendition.
+const AUDIO_CODECS: &[&str] = &["mp4a", "ac-3", "ec-3", "ac-4", "opus", "flac", "mp3"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Video,
+    Audio,
+}
+
+/// A rendition of either format, with its files on disk.
+struct Track {
+    kind: Kind,
+    id: String,
+    bandwidth: Option<u64>,
+    codecs: Option<String>,
+    resolution: Option<(u32, u32)>,
+    language: Option<String>,
+    entries: Vec<Entry>,
+}
+
+/// What a media playlist lists, in order.
+enum Entry {
+    /// An init segment, with its byte range as (offset, length).
+    Init(PathBuf, Option<(u64, u64)>),
+    /// A media segment, with its duration in seconds and byte range.
+    Segment(PathBuf, f64, Option<(u64, u64)>),
+    Discontinuity,
+}
+
+/// Write the other format's manifest for the mirrored manifest at `input`:
+/// an MPD for an HLS playlist, HLS playlists for an MPD. They go to
+/// `output`, by default next to `input` with the other extension.
+pub async fn convert(input: &Path, output: Option<&Path>, reporter: &SharedReporter) -> Result<()> {
+    let input =
+        std::path::absolute(input).with_context(|| format!("resolving {}", input.display()))?;
+    let data = encrypt::read(&input)
+        .await
+        .with_context(|| format!("reading {}", input.display()))?;
+    let text = String::from_utf8(data)
+        .with_context(|| format!("{} is not a text manifest", input.display()))?;
+    let to_dash = HlsPlaylist::parse(&text).is_some();
+    let output = match output {
+        Some(output) => std::path::absolute(output)
+            .with_context(|| format!("resolving {}", output.display()))?,
+        None => input.with_extension(if to_dash { "mpd" } else { "m3u8" }),
+    };
+    // Don't overwrite what was mirrored.
+    let mut orig = output.clone().into_os_string();
+    orig.push(".orig");
+    if output == input || Path::new(&orig).exists() {
+        bail!(
+            "{} is a mirrored manifest; choose another --output",
+            output.display()
+        );
+    }
+    let out_dir = output
+        .parent()
+        .ok_or_else(|| anyhow!("{} has no parent directory", output.display()))?;
+
+    let tracks = if to_dash {
+        read_hls(&input, &text, reporter).await?
+    } else {
+        read_dash(&input, &text, reporter)?
+    };
+    if tracks.is_empty() {
+        bail!("nothing in {} could be converted", input.display());
+    }
+
+    let written = if to_dash {
+        crate::write_file(&output, mpd(&tracks, out_dir).as_bytes()).await?;
+        vec![output.clone()]
+    } else {
+        write_hls(&output, &tracks).await?
+    };
+    reporter.report(Event::Status {
+        tag: "CONV",
+        message: format!(
+            "{} rendition(s) of {} converted to {}",
+            tracks.len(),
+            input.display(),
+            written
+                .iter()
+                .map(|p| p.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    });
+    Ok(())
+}
+
+/// The renditions of the HLS master (or media) playlist at `path`.
+async fn read_hls(path: &Path, text: &str, reporter: &SharedReporter) -> Result<Vec<Track>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let master = match HlsPlaylist::parse(text) {
+        Some(HlsPlaylist::Master(master)) => master,
+        _ => {
+            let Some(entries) = media_entries(path, reporter).await? else {
+                return Ok(Vec::new());
+            };
+            return Ok(vec![Track {
+                kind: Kind::Video,
+                id: "0".to_string(),
+                bandwidth: peak_bandwidth(&entries).await,
+                codecs: None,
+                resolution: None,
+                language: None,
+                entries,
+            }]);
+        }
+    };
+
+    let mut tracks = Vec::new();
+    let mut seen = Vec::new();
+    for media in &master.media {
+        let Line::Tag(tag) = master.lines[media.tag] else {
+            continue;
+        };
+        let Some(uri) = tag.attribute("URI") else {
+            continue;
+        };
+        if media.kind.as_deref() != Some("AUDIO") {
+            reporter.report(Event::Detail {
+                message: format!(
+                    "skipping {} rendition {}",
+                    media.kind.as_deref().unwrap_or("untyped"),
+                    uri
+                ),
+            });
+            continue;
+        }
+        let playlist = dir.join(&uri);
+        if seen.contains(&playlist) {
+            continue;
+        }
+        seen.push(playlist.clone());
+        let Some(entries) = media_entries(&playlist, reporter).await? else {
+            continue;
+        };
+        tracks.push(Track {
+            kind: Kind::Audio,
+            id: format!("audio-{}", tracks.len()),
+            bandwidth: peak_bandwidth(&entries).await,
+            codecs: None,
+            resolution: None,
+            language: media.language.clone(),
+            entries,
+        });
+    }
+    let has_audio = !tracks.is_empty();
+    let mut videos = 0;
+
+    for variant in master.variants.iter().filter(|v| !v.i_frame) {
+        let (Some(uri), Line::Tag(tag)) = (variant.uri, master.lines[variant.tag]) else {
+            continue;
+        };
+        let Line::Uri { uri, .. } = master.lines[uri] else {
+            continue;
+        };
+        let playlist = dir.join(uri);
+        if seen.contains(&playlist) {
+            continue;
+        }
+        seen.push(playlist.clone());
+        let Some(entries) = media_entries(&playlist, reporter).await? else {
+            continue;
+        };
+        let codecs = tag.attribute("CODECS").map(|codecs| {
+            let (audio, video): (Vec<&str>, Vec<&str>) = codecs
+                .split(',')
+                .map(str::trim)
+                .partition(|c| is_audio_codec(c));
+            // Audio is muxed into the variant's segments unless there is a
+            // separate rendition for it.
+            if has_audio && tag.attribute("AUDIO").is_some() && !video.is_empty() {
+                for codec in audio {
+                    fill_audio_codec(&mut tracks, codec);
+                }
+                video.join(",")
+            } else {
+                codecs.clone()
+            }
+        });
+        tracks.push(Track {
+            kind: Kind::Video,
+            id: format!("video-{}", videos),
+            bandwidth: variant.bandwidth,
+            codecs,
+            resolution: tag.attribute("RESOLUTION").and_then(|r| {
+                let (w, h) = r.split_once('x')?;
+                Some((w.parse().ok()?, h.parse().ok()?))
+            }),
+            language: None,
+            entries,
+        });
+        videos += 1;
+    }
+    Ok(tracks)
+}
+
+/// Give the audio tracks without codecs `codec`, as named by a variant.
+fn fill_audio_codec(tracks: &mut [Track], codec: &str) {
+    for track in tracks.iter_mut().filter(|t| t.kind == Kind::Audio) {
+        track.codecs.get_or_insert_with(|| codec.to_string());
+    }
+}
+
+fn is_audio_codec(codec: &str) -> bool {
+    let codec = codec.to_ascii_lowercase();
+    AUDIO_CODECS.iter().any(|prefix| codec.starts_with(prefix))
+}
+
+/// The init segment and segments of the mirrored media playlist at `path`,
+/// or `None` (reported) if it can't be expressed in an MPD.
+async fn media_entries(path: &Path, reporter: &SharedReporter) -> Result<Option<Vec<Entry>>> {
+    let data = encrypt::read(path)
+        .await
+        .with_context(|| format!("reading {}", path.display()))?;
+    let text = String::from_utf8_lossy(&data);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let skip = |reason: &str| {
+        reporter.report(Event::Warning {
+            message: format!("not converting {}: {}", path.display(), reason),
+        });
+        Ok(None)
+    };
+
+    let mut entries = Vec::new();
+    let mut duration = None;
+    let mut range = None;
+    // Where a byte range without an offset starts: after the previous one.
+    let mut next_offset = 0;
+    for line in text.lines().map(Line::parse) {
+        match line {
+            Line::Tag(tag)
+                if tag.is("#EXT-X-KEY")
+                    && tag
+                        .attribute("METHOD")
+                        .is_some_and(|method| method != "NONE") =>
+            {
+                return skip("its segments are encrypted");
+            }
+            Line::Tag(tag) if tag.is("#EXT-X-MAP") => {
+                let Some(uri) = tag.attribute("URI") else {
+                    continue;
+                };
+                let range = tag.attribute("BYTERANGE").and_then(|r| byte_range(&r, 0));
+                entries.push(Entry::Init(dir.join(uri), range));
+            }
+            Line::Tag(tag) if tag.is("#EXTINF") => duration = Some(extinf(&tag)),
+            Line::Tag(tag) if tag.is("#EXT-X-BYTERANGE") => {
+                range = byte_range(tag.value(), next_offset);
+            }
+            Line::Tag(tag) if tag.is("#EXT-X-DISCONTINUITY") => {
+                entries.push(Entry::Discontinuity);
+            }
+            Line::Uri { uri, .. } => {
+                let range = range.take();
+                if let Some((offset, length)) = range {
+                    next_offset = offset + length;
+                }
+                entries.push(Entry::Segment(
+                    dir.join(uri),
+                    duration.take().unwrap_or(0.0),
+                    range,
+                ));
+            }
+            _ => {}
+        }
+    }
+    if !matches!(entries.first(), Some(Entry::Init(..))) {
+        return skip("its segments are not fMP4 (no #EXT-X-MAP before the first segment)");
+    }
+    Ok(Some(entries))
+}
+
+/// The highest bitrate of the segments among `entries`, for renditions
+/// the playlist gives none for.
+async fn peak_bandwidth(entries: &[Entry]) -> Option<u64> {
+    let mut peak = None;
+    for entry in entries {
+        let Entry::Segment(path, secs, range) = entry else {
+            continue;
+        };
+        let bytes = match range {
+            Some((_, length)) => *length,
+            None => match tokio::fs::metadata(path).await {
+                Ok(metadata) => metadata.len(),
+                Err(_) => continue,
+            },
+        };
+        if *secs > 0.0 {
+            let bits = (bytes as f64 * 8.0 / secs).ceil() as u64;
+            peak = peak.max(Some(bits));
+        }
+    }
+    peak
+}
+
+/// Seconds of an `#EXTINF`.
+fn extinf(tag: &Tag<'_>) -> f64 {
+    tag.duration().unwrap_or(0.0)
+}
+
+/// `length[@offset]` as (offset, length), the offset defaulting to `next`.
+fn byte_range(value: &str, next: u64) -> Option<(u64, u64)> {
+    let (length, offset) = match value.split_once('@') {
+        Some((length, offset)) => (length, offset.trim().parse().ok()?),
+        None => (value, next),
+    };
+    Some((offset, length.trim().parse().ok()?))
+}
+
+/// An MPD of `tracks`, referring to their files relative to `dir`.
+fn mpd(tracks: &[Track], dir: &Path) -> String {
+    let duration: f64 = tracks
+        .iter()
+        .map(|track| {
+            track
+                .entries
+                .iter()
+                .map(|e| match e {
+                    Entry::Segment(_, secs, _) => *secs,
+                    _ => 0.0,
+                })
+                .sum::<f64>()
+        })
+        .fold(0.0, f64::max);
+    let reference = |path: &Path| xml_escape(&relative_reference(path, dir));
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(&format!(
+        "<MPD xmlns=\"urn:mpeg:dash:schema:mpd:2011\" profiles=\"urn:mpeg:dash:profile:isoff-main:2011\" type=\"static\" mediaPresentationDuration=\"PT{:.3}S\" minBufferTime=\"PT2S\">\n",
+        duration
+    ));
+    out.push_str("  <!-- Converted by streamrip from an HLS mirror -->\n");
+    out.push_str("  <Period>\n");
+    // One AdaptationSet per kind, and per language for audio.
+    let mut sets: Vec<(Kind, Option<&str>)> = Vec::new();
+    for track in tracks {
+        let key = (track.kind, track.language.as_deref());
+        if !sets.contains(&key) {
+            sets.push(key);
+        }
+    }
+    sets.sort_by_key(|(kind, _)| *kind == Kind::Audio);
+    for (set, (kind, language)) in sets.into_iter().enumerate() {
+        let members = tracks
+            .iter()
+            .filter(|t| t.kind == kind && t.language.as_deref() == language);
+        let (content_type, mime_type) = match kind {
+            Kind::Video => ("video", "video/mp4"),
+            Kind::Audio => ("audio", "audio/mp4"),
+        };
+        out.push_str(&format!(
+            "    <AdaptationSet id=\"{}\" contentType=\"{}\" mimeType=\"{}\"",
+            set, content_type, mime_type
+        ));
+        if let Some(language) = language {
+            out.push_str(&format!(" lang=\"{}\"", xml_escape(language)));
+        }
+        out.push_str(">\n");
+        for track in members {
+            out.push_str(&format!(
+                "      <Representation id=\"{}\" bandwidth=\"{}\"",
+                xml_escape(&track.id),
+                track.bandwidth.unwrap_or(0)
+            ));
+            if let Some(codecs) = &track.codecs {
+                out.push_str(&format!(" codecs=\"{}\"", xml_escape(codecs)));
+            }
+            if let Some((width, height)) = track.resolution {
+                out.push_str(&format!(" width=\"{}\" height=\"{}\"", width, height));
+            }
+            out.push_str(">\n");
+            out.push_str(&format!(
+                "        <SegmentList timescale=\"{}\">\n",
+                TIMESCALE
+            ));
+            if let Some(Entry::Init(path, range)) = track.entries.first() {
+                out.push_str(&format!(
+                    "          <Initialization sourceURL=\"{}\"{}/>\n",
+                    reference(path),
+                    range_attribute("range", *range)
+                ));
+            }
+            out.push_str("          <SegmentTimeline>\n");
+            let mut runs: Vec<(u64, u64)> = Vec::new();
+            for entry in &track.entries {
+                if let Entry::Segment(_, secs, _) = entry {
+                    let units = (secs * TIMESCALE).round() as u64;
+                    match runs.last_mut() {
+                        Some((d, r)) if *d == units => *r += 1,
+                        _ => runs.push((units, 0)),
+                    }
+                }
+            }
+            for (i, (d, r)) in runs.into_iter().enumerate() {
+                let start = if i == 0 { " t=\"0\"" } else { "" };
+                let repeat = if r > 0 {
+                    format!(" r=\"{}\"", r)
+                } else {
+                    String::new()
+                };
+                out.push_str(&format!(
+                    "            <S{} d=\"{}\"{}/>\n",
+                    start, d, repeat
+                ));
+            }
+            out.push_str("          </SegmentTimeline>\n");
+            for entry in &track.entries {
+                if let Entry::Segment(path, _, range) = entry {
+                    out.push_str(&format!(
+                        "          <SegmentURL media=\"{}\"{}/>\n",
+                        reference(path),
+                        range_attribute("mediaRange", *range)
+                    ));
+                }
+            }
+            out.push_str("        </SegmentList>\n");
+            out.push_str("      </Representation>\n");
+        }
+        out.push_str("    </AdaptationSet>\n");
+    }
+    out.push_str("  </Period>\n</MPD>\n");
+    out
+}
+
+/// A byte range as an MPD attribute (`first-last`), if there is one.
+fn range_attribute(name: &str, range: Option<(u64, u64)>) -> String {
+    match range {
+        Some((offset, length)) if length > 0 => {
+            format!(" {}=\"{}-{}\"", name, offset, offset + length - 1)
+        }
+        _ => String::new(),
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The Representations of the mirrored MPD at `path`.
+fn read_dash(path: &Path, text: &str, reporter: &SharedReporter) -> Result<Vec<Track>> {
+    let doc = roxmltree::Document::parse(text)
+        .with_context(|| format!("{} is neither an HLS playlist nor an MPD", path.display()))?;
+    let model = MpdModel::parse(&doc)
+        .ok_or_else(|| anyhow!("{} is neither an HLS playlist nor an MPD", path.display()))?;
+    let mpd_url = Url::from_file_path(path)
+        .map_err(|_| anyhow!("{} is not an absolute path", path.display()))?;
+    let skip = |id: &str, reason: &str| {
+        reporter.report(Event::Warning {
+            message: format!("not converting Representation {}: {}", id, reason),
+        });
+    };
+
+    let mut tracks: Vec<Track> = Vec::new();
+    for (index, period) in model.periods.iter().enumerate() {
+        let period_base = join(&mpd_url, period.base_url)?;
+        for aset in &period.adaptation_sets {
+            let kind = match aset.content_type {
+                Some("video") => Kind::Video,
+                Some("audio") => Kind::Audio,
+                other => {
+                    reporter.report(Event::Detail {
+                        message: format!(
+                            "skipping {} AdaptationSet {}",
+                            other.unwrap_or("untyped"),
+                            aset.id.unwrap_or("(without id)")
+                        ),
+                    });
+                    continue;
+                }
+            };
+            let aset_base = join(&period_base, aset.base_url)?;
+            for rep in &aset.representations {
+                let Some(id) = rep.id else {
+                    skip("(without id)", "it has no id");
+                    continue;
+                };
+                let Some(st) = &rep.template else {
+                    skip(id, "it has no SegmentTemplate");
+                    continue;
+                };
+                let timeline = [rep.node, aset.node].iter().any(|node| {
+                    node.children()
+                        .filter(|n| n.tag_name().name() == "SegmentTemplate")
+                        .any(|st| {
+                            st.children()
+                                .any(|n| n.tag_name().name() == "SegmentTimeline")
+                        })
+                });
+                if timeline {
+                    skip(id, "its segments are listed in a SegmentTimeline");
+                    continue;
+                }
+                let media = st.attribute("media").unwrap_or_default();
+                let Some(units) = st.attribute("duration").and_then(|d| d.parse::<u64>().ok())
+                else {
+                    skip(id, "its SegmentTemplate has no duration");
+                    continue;
+                };
+                if media.is_empty() || template::has_sub_number(media) {
+                    skip(id, "its media segments are not numbered one by one");
+                    continue;
+                }
+                let timescale = st
+                    .attribute("timescale")
+                    .and_then(|t| t.parse::<u64>().ok())
+                    .unwrap_or(1);
+                if timescale == 0 {
+                    skip(id, "its SegmentTemplate has timescale 0");
+                    continue;
+                }
+                let start = st
+                    .attribute("startNumber")
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .unwrap_or(1);
+                let count = match st
+                    .attribute("endNumber")
+                    .and_then(|n| n.parse::<u64>().ok())
+                {
+                    Some(end) => template::segment_numbers(start, end)
+                        .map(|numbers| Some(numbers.end() - numbers.start() + 1)),
+                    None => period
+                        .duration_secs
+                        .map(|total| template::segment_count(total, units, timescale))
+                        .transpose(),
+                };
+                let count = match count {
+                    Ok(count) => count,
+                    Err(e) => {
+                        skip(id, &format!("{:#}", e));
+                        continue;
+                    }
+                };
+                let base = join(&aset_base, rep.base_url)?;
+                let file = |path: &str| -> Result<PathBuf> {
+                    base.join(path.trim())
+                        .ok()
+                        .and_then(|url| url.to_file_path().ok())
+                        .ok_or_else(|| anyhow!("can't resolve '{}' in {}", path, base))
+                };
+
+                let track = match tracks.iter().position(|t| t.id == id) {
+                    Some(existing) => &mut tracks[existing],
+                    None => {
+                        tracks.push(Track {
+                            kind,
+                            id: id.to_string(),
+                            bandwidth: rep.bandwidth,
+                            codecs: attribute(rep.node, aset.node, "codecs"),
+                            resolution: attribute(rep.node, aset.node, "width")
+                                .zip(attribute(rep.node, aset.node, "height"))
+                                .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?))),
+                            language: aset.node.attribute("lang").map(str::to_string),
+                            entries: Vec::new(),
+                        });
+                        tracks.last_mut().expect("just pushed")
+                    }
+                };
+                if index > 0 && !track.entries.is_empty() {
+                    track.entries.push(Entry::Discontinuity);
+                }
+                if let Some(init) = st.attribute("initialization") {
+                    let path = file(&init.replace("$RepresentationID$", id))?;
+                    track.entries.push(Entry::Init(path, None));
+                }
+                let secs = units as f64 / timescale as f64;
+                let mut number = start;
+                while count.is_none_or(|count| number - start < count) {
+                    let path = file(&template::expand(media, id, number, None))?;
+                    // What wasn't mirrored (the end of an unbounded list, or
+                    // dropped segments) ends the list.
+                    if !path.exists() {
+                        break;
+                    }
+                    let elapsed = (number - start) as f64 * secs;
+                    let length = match period.duration_secs {
+                        Some(total) => secs.min(total - elapsed),
+                        None => secs,
+                    };
+                    track.entries.push(Entry::Segment(path, length, None));
+                    let Some(next) = number.checked_add(1) else {
+                        break;
+                    };
+                    number = next;
+                }
+            }
+        }
+    }
+    tracks.retain(|track| {
+        let has_segments = track
+            .entries
+            .iter()
+            .any(|e| matches!(e, Entry::Segment(..)));
+        if !has_segments {
+            skip(&track.id, "none of its segments are mirrored");
+        }
+        has_segments
+    });
+    Ok(tracks)
+}
+
+/// `base` joined with a `BaseURL`, if there is one.
+fn join(base: &Url, relative: Option<&str>) -> Result<Url> {
+    match relative {
+        Some(relative) => base
+            .join(relative)
+            .with_context(|| format!("resolving BaseURL '{}' against {}", relative, base)),
+        None => Ok(base.clone()),
+    }
+}
+
+/// An attribute of a Representation, or else of its AdaptationSet.
+fn attribute(rep: roxmltree::Node, aset: roxmltree::Node, name: &str) -> Option<String> {
+    rep.attribute(name)
+        .or(aset.attribute(name))
+        .map(str::to_string)
+}
+
+/// Write a master playlist for `tracks` to `path`, and a media playlist for
+/// each next to it; returns the paths written.
+async fn write_hls(path: &Path, tracks: &[Track]) -> Result<Vec<PathBuf>> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let reference = |target: &Path| relative_reference(target, dir);
+
+    let mut written = Vec::new();
+    let mut media_playlists = Vec::new();
+    for track in tracks {
+        let name: String = track
+            .id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let media_path = dir.join(format!("{}-{}.m3u8", stem, name));
+        let target = track
+            .entries
+            .iter()
+            .map(|e| match e {
+                Entry::Segment(_, secs, _) => secs.ceil() as u64,
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        let mut lines = vec![
+            "#EXTM3U".to_string(),
+            "#EXT-X-VERSION:7".to_string(),
+            format!("#EXT-X-TARGETDURATION:{}", target.max(1)),
+            "#EXT-X-PLAYLIST-TYPE:VOD".to_string(),
+            "#EXT-X-MEDIA-SEQUENCE:0".to_string(),
+        ];
+        for entry in &track.entries {
+            match entry {
+                Entry::Init(init, _) => {
+                    lines.push(format!("#EXT-X-MAP:URI=\"{}\"", reference(init)));
+                }
+                Entry::Segment(segment, secs, _) => {
+                    lines.push(format!("#EXTINF:{:.3},", secs));
+                    lines.push(reference(segment));
+                }
+                Entry::Discontinuity => lines.push("#EXT-X-DISCONTINUITY".to_string()),
+            }
+        }
+        lines.push("#EXT-X-ENDLIST".to_string());
+        crate::write_file(&media_path, (lines.join("\n") + "\n").as_bytes()).await?;
+        media_playlists.push(media_path.clone());
+        written.push(media_path);
+    }
+
+    let audio: Vec<(&Track, &PathBuf)> = tracks
+        .iter()
+        .zip(&media_playlists)
+        .filter(|(t, _)| t.kind == Kind::Audio)
+        .collect();
+    let audio_bandwidth = audio
+        .iter()
+        .filter_map(|(t, _)| t.bandwidth)
+        .max()
+        .unwrap_or(0);
+    let audio_codecs = audio.first().and_then(|(t, _)| t.codecs.clone());
+
+    let mut lines = vec![
+        "#EXTM3U".to_string(),
+        "## Converted by streamrip from a DASH mirror".to_string(),
+        "#EXT-X-INDEPENDENT-SEGMENTS".to_string(),
+    ];
+    for (i, (track, playlist)) in audio.iter().enumerate() {
+        let mut line = format!(
+            "#EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",NAME=\"{}\"",
+            track.id
+        );
+        if let Some(language) = &track.language {
+            line.push_str(&format!(",LANGUAGE=\"{}\"", language));
+        }
+        line.push_str(&format!(
+            ",DEFAULT={},AUTOSELECT=YES,URI=\"{}\"",
+            if i == 0 { "YES" } else { "NO" },
+            reference(playlist)
+        ));
+        lines.push(line);
+    }
+    let videos: Vec<(&Track, &PathBuf)> = tracks
+        .iter()
+        .zip(&media_playlists)
+        .filter(|(t, _)| t.kind == Kind::Video)
+        .collect();
+    // Audio alone is played from a variant of its own.
+    let variants = if videos.is_empty() {
+        audio.clone()
+    } else {
+        videos
+    };
+    for (track, playlist) in variants {
+        let mut line = format!(
+            "#EXT-X-STREAM-INF:BANDWIDTH={}",
+            track.bandwidth.unwrap_or(0) + audio_bandwidth * u64::from(track.kind == Kind::Video)
+        );
+        let codecs: Vec<&str> = track
+            .codecs
+            .iter()
+            .chain(audio_codecs.iter().filter(|_| track.kind == Kind::Video))
+            .map(String::as_str)
+            .collect();
+        if !codecs.is_empty() {
+            line.push_str(&format!(",CODECS=\"{}\"", codecs.join(",")));
+        }
+        if let Some((width, height)) = track.resolution {
+            line.push_str(&format!(",RESOLUTION={}x{}", width, height));
+        }
+        if track.kind == Kind::Video && !audio.is_empty() {
+            line.push_str(",AUDIO=\"audio\"");
+        }
+        lines.push(line);
+        lines.push(reference(playlist));
+    }
+    crate::write_file(path, (lines.join("\n") + "\n").as_bytes()).await?;
+    written.insert(0, path.to_path_buf());
+    Ok(written)
+}
@@ -0,0 +1,178 @@
+//! `--debug-http`: a line per request and per response, with the headers
+//! that matter when debugging an origin and the time it took.
+//!
+//! Credentials are redacted before anything is printed: `Authorization`,
+//! cookies, user info in URLs and query parameters that look like tokens or
+//! signatures, so the output can be shared as is.
+
+use crate::hosts::{self, Outcome};
+use crate::report::{Event, SharedReporter};
+use reqwest::header::HeaderMap;
+use reqwest::{RequestBuilder, Response};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Where requests are reported, once `--debug-http` is given.
+static REPORTER: OnceLock<SharedReporter> = OnceLock::new();
+
+const REDACTED: &str = "REDACTED";
+
+/// Request headers worth printing.
+const REQUEST_HEADERS: &[&str] = &["authorization", "cookie", "range", "user-agent"];
+
+/// Response headers worth printing.
+const RESPONSE_HEADERS: &[&str] = &[
+    "content-type",
+    "content-length",
+    "content-range",
+    "location",
+    "cache-control",
+    "age",
+    "etag",
+    "server",
+    "via",
+    "x-cache",
+    "set-cookie",
+];
+
+/// Headers whose values are never printed.
+const SECRET_HEADERS: &[&str] = &["authorization", "cookie", "set-cookie"];
+
+/// Query parameter names containing any of these are redacted.
+const SECRET_PARAMS: &[&str] = &[
+    "token",
+    "sig",
+    "auth",
+    "key",
+    "secret",
+    "password",
+    "credential",
+    "session",
+    "policy",
+    "hdnts",
+    "hdnea",
+];
+
+/// Report every request sent with [`SendLogged::send_logged`] from now on.
+pub fn enable(reporter: SharedReporter) {
+    let _ = REPORTER.set(reporter);
+}
+
+pub(crate) trait SendLogged {
+    /// Send the request like `send`, reporting it and its response with
+    /// `--debug-http`, and count it in the [`crate::hosts`] statistics.
+    fn send_logged(self) -> impl Future<Output = reqwest::Result<Response>> + Send;
+}
+
+impl SendLogged for RequestBuilder {
+    async fn send_logged(self) -> reqwest::Result<Response> {
+        let (client, request) = self.build_split();
+        let request = request?;
+        let Some(reporter) = REPORTER.get() else {
+            let url = request.url().clone();
+            let started = Instant::now();
+            let result = client.execute(request).await;
+            record(&url, &result, started.elapsed());
+            return result;
+        };
+
+        let request_url = request.url().clone();
+        let method = request.method().clone();
+        let url = redact_url(request.url());
+        reporter.report(Event::Status {
+            tag: "HTTP",
+            message: format!(
+                "> {} {}{}",
+                method,
+                url,
+                headers(request.headers(), REQUEST_HEADERS)
+            ),
+        });
+
+        let started = Instant::now();
+        let result = client.execute(request).await;
+        record(&request_url, &result, started.elapsed());
+        let took = started.elapsed().as_millis();
+        let message = match &result {
+            Ok(resp) => {
+                let redirected = redact_url(resp.url());
+                format!(
+                    "< {} {} {} in {}ms{}{}",
+                    resp.status(),
+                    method,
+                    url,
+                    took,
+                    if redirected != url {
+                        format!(" (redirected to {})", redirected)
+                    } else {
+                        String::new()
+                    },
+                    headers(resp.headers(), RESPONSE_HEADERS)
+                )
+            }
+            Err(e) => format!("< {} {} failed after {}ms: {}", method, url, took, e),
+        };
+        reporter.report(Event::Status {
+            tag: "HTTP",
+            message,
+        });
+        result
+    }
+}
+
+/// Count the request for `url` that ended with `result` after `took`.
+fn record(url: &Url, result: &reqwest::Result<Response>, took: Duration) {
+    let outcome = match result {
+        Ok(resp) => Outcome::Status(resp.status()),
+        Err(e) => Outcome::Error(e),
+    };
+    hosts::record(url, outcome, took);
+}
+
+/// `url` with user info and secret-looking query parameters replaced.
+pub(crate) fn redact_url(url: &Url) -> String {
+    let mut url = url.clone();
+    if !url.username().is_empty() {
+        let _ = url.set_username(REDACTED);
+    }
+    if url.password().is_some() {
+        let _ = url.set_password(Some(REDACTED));
+    }
+    let is_secret = |name: &str| {
+        let name = name.to_ascii_lowercase();
+        SECRET_PARAMS.iter().any(|secret| name.contains(secret))
+    };
+    // Other URLs keep their query exactly as written.
+    if url.query_pairs().any(|(name, _)| is_secret(&name)) {
+        let pairs: Vec<(String, String)> = url
+            .query_pairs()
+            .map(|(name, value)| {
+                let value = if is_secret(&name) {
+                    REDACTED.to_string()
+                } else {
+                    value.into_owned()
+                };
+                (name.into_owned(), value)
+            })
+            .collect();
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+    url.to_string()
+}
+
+/// `, Name: value` for each of `names` present in `headers`.
+fn headers(headers: &HeaderMap, names: &[&str]) -> String {
+    let mut out = String::new();
+    for name in names {
+        for value in headers.get_all(*name) {
+            let value = if SECRET_HEADERS.contains(name) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            out.push_str(&format!(", {}: {}", name, value));
+        }
+    }
+    out
+}
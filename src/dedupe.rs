@@ -0,0 +1,136 @@
+//! Files of a combined HLS and DASH capture (`--companion-url`) kept once.
+//!
+//! CMAF origins serve the same fMP4 segments to both manifest families,
+//! often under the same URLs, which are downloaded once anyway. Where the
+//! URLs differ (`/hls/...` and `/dash/...` paths, packager-specific names),
+//! both copies are downloaded; once the run is done, files with the same
+//! content become hard links to one of them, so the tree takes the space of
+//! a single capture and serves both families.
+
+use crate::report::{Event, SharedReporter};
+use crate::{catalog, checksums, signing, state, status, units};
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// Replace every file below `dir` whose content another one has by a hard
+/// link to that one. Manifests, their `.orig` copies and streamrip's own
+/// files are left alone, as are links into a `--segment-store`.
+pub(crate) async fn link_duplicates(dir: &Path, reporter: &SharedReporter) -> Result<()> {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    collect(dir, dir, &mut files, &mut seen)
+        .await
+        .with_context(|| format!("listing {}", dir.display()))?;
+
+    // Only files of the same size can have the same content.
+    let mut by_size: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for (path, size) in files {
+        by_size.entry(size).or_default().push(path);
+    }
+    let mut linked = 0;
+    let mut saved = 0;
+    for (size, paths) in by_size.into_iter().filter(|(_, p)| p.len() > 1) {
+        let mut by_hash: HashMap<[u8; 32], &Path> = HashMap::new();
+        for path in &paths {
+            let data = tokio::fs::read(path)
+                .await
+                .with_context(|| format!("reading {}", path.display()))?;
+            let hash: [u8; 32] = Sha256::digest(&data).into();
+            let Some(original) = by_hash.get(&hash) else {
+                by_hash.insert(hash, path);
+                continue;
+            };
+            link(original, path)?;
+            linked += 1;
+            saved += size;
+        }
+    }
+    if linked > 0 {
+        reporter.report(Event::Status {
+            tag: "LINK",
+            message: format!(
+                "{} file(s) shared by the manifests hard-linked, saving {}",
+                units::count(linked),
+                units::size(saved)
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Regular files below `dir` that may be duplicates, with their sizes;
+/// one path per file already linked by an earlier run (`seen`).
+async fn collect(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(PathBuf, u64)>,
+    seen: &mut HashSet<(u64, u64)>,
+) -> Result<()> {
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        // Not following links: store links are deduplicated already.
+        let file_type = entry.file_type().await?;
+        if file_type.is_dir() {
+            Box::pin(collect(root, &path, files, seen)).await?;
+            continue;
+        }
+        if !file_type.is_file() || !is_media(root, &path) {
+            continue;
+        }
+        let metadata = entry.metadata().await?;
+        if file_id(&metadata).is_some_and(|id| !seen.insert(id)) {
+            continue;
+        }
+        files.push((path, metadata.len()));
+    }
+    Ok(())
+}
+
+/// Device and inode of a file, shared by its hard links.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Whether `path` is a mirrored resource other than a manifest.
+fn is_media(root: &Path, path: &Path) -> bool {
+    let own = [
+        catalog::CATALOG_FILE,
+        state::STATE_FILE,
+        status::STATUS_FILE,
+        checksums::SUMS_FILE,
+        signing::SIGNATURE_FILE,
+    ];
+    if path.parent() == Some(root)
+        && path
+            .file_name()
+            .is_some_and(|name| own.iter().any(|own| name == *own))
+    {
+        return false;
+    }
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    !matches!(extension.as_str(), "m3u8" | "m3u" | "mpd" | "orig")
+}
+
+/// Make `duplicate` a hard link to `original`, replacing it in one step.
+fn link(original: &Path, duplicate: &Path) -> Result<()> {
+    let mut partial = duplicate.as_os_str().to_owned();
+    partial.push(".link");
+    let partial = PathBuf::from(partial);
+    let _ = std::fs::remove_file(&partial);
+    std::fs::hard_link(original, &partial)
+        .and_then(|()| std::fs::rename(&partial, duplicate))
+        .with_context(|| format!("linking {} to {}", duplicate.display(), original.display()))
+}
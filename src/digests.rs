@@ -0,0 +1,66 @@
+//! SHA-256 of the files a run writes, taken from the bytes on their way to
+//! disk.
+//!
+//! `SHA256SUMS` (`--checksums`) lists the hash of every file in the mirror.
+//! Files this run writes are hashed while they are in memory anyway, so
+//! listing a mirror of hundreds of gigabytes doesn't read it all back. Each
+//! digest is kept with the size and modification time of the file once
+//! written; a file changed since no longer matches them and is read back,
+//! like the files of earlier runs.
+
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// A SHA-256 hash.
+pub(crate) type Sha256Digest = [u8; 32];
+
+/// SHA-256 of `data`.
+pub(crate) fn sha256(data: &[u8]) -> Sha256Digest {
+    Sha256::digest(data).into()
+}
+
+/// The digests of the files written so far, shared by the writers of a run.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Digests(Arc<Mutex<HashMap<PathBuf, Written>>>);
+
+#[derive(Debug)]
+struct Written {
+    digest: Sha256Digest,
+    len: u64,
+    modified: SystemTime,
+}
+
+impl Digests {
+    /// Note that the file at `path`, as it is now, hashes to `digest`.
+    pub fn record(&self, path: &Path, digest: Sha256Digest) {
+        let mut files = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        // Follows links into a segment store, as hashing the file would.
+        match std::fs::metadata(path).and_then(|meta| Ok((meta.len(), meta.modified()?))) {
+            Ok((len, modified)) => {
+                files.insert(
+                    path.to_path_buf(),
+                    Written {
+                        digest,
+                        len,
+                        modified,
+                    },
+                );
+            }
+            Err(_) => {
+                files.remove(path);
+            }
+        }
+    }
+
+    /// The digest of the file at `path`, if it is still as this run wrote it.
+    pub fn get(&self, path: &Path) -> Option<Sha256Digest> {
+        let files = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let written = files.get(path)?;
+        let meta = std::fs::metadata(path).ok()?;
+        (meta.len() == written.len && meta.modified().ok()? == written.modified)
+            .then_some(written.digest)
+    }
+}
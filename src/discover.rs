@@ -0,0 +1,318 @@
+//! Discovery ahead of downloading, so that rewritten manifests don't depend
+//! on the order in which downloads complete.
+//!
+//! A first pass walks the manifests without downloading anything and collects
+//! every file they reference. Keys and init segments are then fetched one by
+//! one, the remaining files concurrently (`--concurrency` at a time), and
+//! only then does the regular pass rewrite the manifests, working on the very
+//! texts the first pass saw. Two runs over the same input thus produce
+//! byte-identical manifests, whatever the network did in between.
+
+use crate::container::Container;
+use crate::disk::{DiskWriter, PendingWrite, Writes};
+use crate::priority::PriorityFile;
+use crate::quota::Limits;
+use crate::race::Race;
+use crate::report::{Event, Phase, Progress, Resource, SharedReporter, Silent};
+use crate::store::SegmentStore;
+use crate::timeouts::Timeouts;
+use crate::{Mirror, PlannedFile, StreamFormat, abr, units};
+use anyhow::{Result, anyhow};
+use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use url::Url;
+
+impl Mirror {
+    /// Walk the manifests below `url` without downloading anything, returning
+    /// the files they reference and, separately, their keys and init
+    /// segments. Unless recording live, the manifest texts are kept for the
+    /// rewrite pass.
+    pub(crate) async fn discover(
+        &mut self,
+        url: Url,
+        format: StreamFormat,
+    ) -> Result<(Vec<PlannedFile>, Vec<PriorityFile>)> {
+        self.reporter.progress(Progress::Phase(Phase::Planning));
+        // Everything the walk could report is reported again by the rewrite pass.
+        let silent: SharedReporter = Arc::new(Silent);
+        let mut walker = Mirror::new(self.out_dir.clone(), self.paths.clone(), silent);
+        walker.client = self.client.clone();
+        walker.filter = self.filter.clone();
+        walker.ignore_query_params = self.ignore_query_params.clone();
+        walker.fetched_before = self.fetched_before.clone();
+        walker.manifests_only = self.manifests_only;
+        walker.segments_only = self.segments_only;
+        walker.probe_segments = self.probe_segments;
+        walker.timeouts = self.timeouts;
+        walker.race = self.race.clone();
+        walker.limits = self.limits.clone();
+        #[cfg(feature = "hls")]
+        {
+            walker.keyring = self.keyring.clone();
+        }
+        walker.plan = Some(Vec::new());
+        walker.priority = Some(Default::default());
+        walker.mirror_root(url, format).await?;
+
+        if !self.live {
+            self.discovered = std::mem::take(&mut walker.discovered);
+        }
+        Ok((
+            walker.plan.take().unwrap_or_default(),
+            walker.priority.take().unwrap_or_default().into_files(),
+        ))
+    }
+
+    /// Download the files found by the discovery pass, at most `concurrency`
+    /// at a time. Files that fail are dropped by the rewrite pass with
+    /// `--skip-failed` and abort the run otherwise.
+    pub(crate) async fn download_planned(
+        &mut self,
+        files: Vec<PlannedFile>,
+        concurrency: usize,
+    ) -> Result<()> {
+        let (files, starts) = if self.playback_order {
+            self.playback_schedule(files).await?
+        } else {
+            // Keys and init segments are done already.
+            let files: Vec<PlannedFile> = files
+                .into_iter()
+                .filter(|f| !self.visited.contains(&self.dedup_key(&f.url)))
+                .collect();
+            let starts = vec![0.0; files.len()];
+            (files, starts)
+        };
+        self.reporter.progress(Progress::Phase(Phase::Downloading));
+        self.reporter.progress(Progress::Planned(files.len()));
+        let began = Instant::now();
+
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let writer = DiskWriter::start(
+            self.write_queue,
+            self.reporter.clone(),
+            self.digests.clone(),
+        );
+        let mut tasks = JoinSet::new();
+
+        for (index, file) in files.iter().enumerate() {
+            self.visited.insert(self.dedup_key(&file.url));
+            let path = self.path_for_url(&file.url, false);
+            let client = self.client.clone();
+            let reporter = self.reporter.clone();
+            let url = file.url.clone();
+            let encrypted = file.encrypted;
+            let preserve_mtime = self.preserve_mtime;
+            let timeouts = self.timeouts;
+            let race = self.race.clone();
+            let limits = self.limits.clone();
+            let segment_store = self.segment_store.clone();
+            let writes = writer.writes();
+            let semaphore = semaphore.clone();
+            let start = began + Duration::from_secs_f64(starts[index]);
+            tasks.spawn(async move {
+                tokio::time::sleep_until(start).await;
+                let result = match semaphore.acquire_owned().await {
+                    Ok(_permit) => {
+                        reporter.report(Event::Fetch {
+                            kind: Resource::Binary,
+                            url: url.as_str(),
+                            path: &path,
+                        });
+                        let options = DownloadOptions {
+                            encrypted,
+                            availability_window: None,
+                            timeouts,
+                            race,
+                            limits,
+                            preserve_mtime,
+                            segment_store,
+                            writes: Some(writes),
+                        };
+                        download(&client, &url, &path, options, &reporter).await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                (index, result)
+            });
+        }
+
+        let mut containers = vec![None; files.len()];
+        while let Some(joined) = tasks.join_next().await {
+            let (index, result) = joined?;
+            let url = &files[index].url;
+            match result {
+                Ok(container) => {
+                    self.reporter.progress(Progress::Completed);
+                    containers[index] = container;
+                    self.prefetched.insert(self.dedup_key(url));
+                    self.tally.fetched += 1;
+                    let identity = self.identity(url);
+                    if let Some(state) = &mut self.state {
+                        state.record(&identity).await?;
+                    }
+                }
+                Err(e) if self.skip_failed => {
+                    self.reporter.progress(Progress::Failed);
+                    let key = self.dedup_key(url);
+                    self.visited.remove(&key);
+                    self.failed.insert(key, format!("{:#}", e));
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        // The rewrite pass and the size check expect the files on disk.
+        writer.finish().await?;
+
+        // In discovery order, not completion order, to keep the catalog stable.
+        for (file, container) in files.iter().zip(containers) {
+            if let Some(container) = container {
+                self.catalog.add_container(&file.rendition, container);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `files` not downloaded yet in the order a player requests them, with
+    /// when it does, in seconds from the start of playback: each segment once
+    /// playback reaches it, with all renditions playing along, or switching
+    /// between them with `--abr-switch`. Files of unknown duration are
+    /// requested along with the segment that follows them.
+    async fn playback_schedule(
+        &self,
+        files: Vec<PlannedFile>,
+    ) -> Result<(Vec<PlannedFile>, Vec<f64>)> {
+        let mut clocks: HashMap<String, f64> = HashMap::new();
+        let mut scheduled: Vec<(f64, PlannedFile)> = files
+            .into_iter()
+            .map(|file| {
+                let clock = clocks.entry(file.rendition.clone()).or_default();
+                let start = *clock;
+                *clock += file.duration.unwrap_or(0.0);
+                (start, file)
+            })
+            .collect();
+        let length = clocks.values().copied().fold(0.0, f64::max);
+
+        // Simulated on everything, as a switching player fetches init
+        // segments and keys again.
+        let fetched = if self.abr_switches.is_empty() || scheduled.is_empty() {
+            None
+        } else {
+            let fetches = abr::simulate(&self.abr_switches, &mut scheduled, length)?;
+            if let Some(path) = &self.abr_trace {
+                abr::write_trace(path, &fetches, &scheduled).await?;
+            }
+            Some(fetches.len())
+        };
+
+        // Keys and init segments are done already.
+        scheduled.retain(|(_, f)| !self.visited.contains(&self.dedup_key(&f.url)));
+        if let Some(fetched) = fetched {
+            let skipped = scheduled
+                .iter()
+                .filter(|(start, _)| *start >= length)
+                .count();
+            self.reporter.report(Event::Status {
+                tag: "ABR ",
+                message: format!(
+                    "the switching player fetches {} file(s), {} more are downloaded after playback",
+                    units::count(fetched),
+                    units::count(skipped)
+                ),
+            });
+        }
+        // Stable, so each rendition keeps its order.
+        scheduled.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        self.reporter.report(Event::Status {
+            tag: "PLAY",
+            message: format!(
+                "downloading {} file(s) of {} rendition(s) in playback order over {}",
+                units::count(scheduled.len()),
+                clocks.len(),
+                units::duration(length)
+            ),
+        });
+        Ok(scheduled
+            .into_iter()
+            .map(|(start, file)| (file, start))
+            .unzip())
+    }
+}
+
+/// How [`download`] treats a file.
+#[derive(Clone)]
+pub(crate) struct DownloadOptions {
+    /// Whether the file is encrypted, so its content can't be checked.
+    pub encrypted: bool,
+    /// How long 404s are retried (see [`crate::get_binary`]).
+    pub availability_window: Option<Duration>,
+    /// How long the request may take (`--min-throughput`).
+    pub timeouts: Timeouts,
+    /// Other origins to race the request against (`--race-origins`).
+    pub race: Race,
+    /// Rate and disk limits the file counts against.
+    pub limits: Limits,
+    /// Date the file to the origin's `Last-Modified` header.
+    pub preserve_mtime: bool,
+    /// Where the file is kept, with a link at its path, with `--segment-store`.
+    pub segment_store: Option<SegmentStore>,
+    /// Queue to hand the file to instead of writing it right away.
+    pub writes: Option<Writes>,
+}
+
+/// GET `url` into `path`, rejecting error pages, and return the container
+/// its content or extension suggests.
+pub(crate) async fn download(
+    client: &Client,
+    url: &Url,
+    path: &Path,
+    options: DownloadOptions,
+    reporter: &SharedReporter,
+) -> Result<Option<Container>> {
+    let resp = crate::get_binary(
+        client,
+        url,
+        options.availability_window,
+        options.timeouts,
+        &options.race,
+        reporter,
+    )
+    .await?;
+
+    let content_type = resp
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let modified = crate::last_modified(&resp).filter(|_| options.preserve_mtime);
+    let bytes = options.timeouts.body(resp, url).await?;
+    reporter.progress(Progress::Bytes(bytes.len() as u64));
+    if let Some(reason) =
+        Container::rejection(content_type.as_deref(), path, &bytes, options.encrypted)
+    {
+        return Err(anyhow!("{} does not look like a segment: {}", url, reason));
+    }
+    options.limits.take(bytes.len() as u64).await?;
+
+    let container = Container::sniff(&bytes).or_else(|| Container::from_extension(path));
+    let write = PendingWrite {
+        path: path.to_path_buf(),
+        data: bytes,
+        modified,
+        store: options.segment_store,
+    };
+    match &options.writes {
+        Some(writes) => writes.write(write).await?,
+        None => write.perform(None).await?,
+    }
+    Ok(container)
+}
@@ -0,0 +1,166 @@
+//! Disk writes decoupled from downloads.
+//!
+//! Downloads hand their files to a single writer task through a bounded
+//! queue (`--write-queue`) and go on with the next download. A slow disk (a
+//! NAS, an SD card) fills the queue, and downloads then wait for room in it
+//! instead of piling up finished files in memory. How many files wait is
+//! reported as [`Progress::WriteQueue`] whenever it changes.
+
+use crate::digests::Digests;
+use crate::report::{Progress, SharedReporter};
+use crate::store::SegmentStore;
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+#[cfg(feature = "hls")]
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+/// A downloaded file to be written.
+#[derive(Debug)]
+pub(crate) struct PendingWrite {
+    pub path: PathBuf,
+    pub data: bytes::Bytes,
+    /// Time to date the file to (`Last-Modified`).
+    pub modified: Option<SystemTime>,
+    pub store: Option<SegmentStore>,
+}
+
+impl PendingWrite {
+    /// Write the file, noting its SHA-256 in `digests` if given, hashed
+    /// from the bytes at hand rather than read back.
+    pub async fn perform(self, digests: Option<&Digests>) -> Result<()> {
+        let digest = crate::write_download(
+            &self.path,
+            &self.data,
+            self.store.as_ref(),
+            digests.is_some(),
+        )
+        .await?;
+        if let Some(modified) = self.modified {
+            crate::set_modified(&self.path, modified)?;
+        }
+        if let (Some(digests), Some(digest)) = (digests, digest) {
+            digests.record(&self.path, digest);
+        }
+        Ok(())
+    }
+}
+
+enum Job {
+    Write(PendingWrite),
+    /// Answered once everything queued before is written.
+    #[cfg(feature = "hls")]
+    Flush(oneshot::Sender<Result<(), String>>),
+}
+
+/// Queue of files to write, shared by the downloads.
+#[derive(Clone)]
+pub(crate) struct Writes {
+    queue: mpsc::Sender<Job>,
+    reporter: SharedReporter,
+}
+
+impl Writes {
+    /// Queue `write`, waiting for room if the queue is full.
+    pub async fn write(&self, write: PendingWrite) -> Result<()> {
+        if self.queue.capacity() == 0 {
+            // Full: the download waits for the disk.
+            report_depth(&self.queue, &self.reporter);
+        }
+        self.queue
+            .send(Job::Write(write))
+            .await
+            .map_err(|_| anyhow!("the disk writer stopped"))?;
+        report_depth(&self.queue, &self.reporter);
+        Ok(())
+    }
+
+    /// Wait until everything queued so far is written; fails if any write
+    /// failed since the last flush.
+    #[cfg(feature = "hls")]
+    pub async fn flush(&self) -> Result<()> {
+        let (done, written) = oneshot::channel();
+        self.queue
+            .send(Job::Flush(done))
+            .await
+            .map_err(|_| anyhow!("the disk writer stopped"))?;
+        written
+            .await
+            .map_err(|_| anyhow!("the disk writer stopped"))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+/// Background task writing queued files in order.
+pub(crate) struct DiskWriter {
+    writes: Writes,
+    task: JoinHandle<Result<(), String>>,
+}
+
+impl DiskWriter {
+    /// Start writing, with at most `depth` files waiting, noting their
+    /// SHA-256 in `digests` if given.
+    pub fn start(depth: usize, reporter: SharedReporter, digests: Option<Digests>) -> Self {
+        let (queue, jobs) = mpsc::channel(depth.max(1));
+        let task = tokio::spawn(work(jobs, queue.downgrade(), reporter.clone(), digests));
+        Self {
+            writes: Writes { queue, reporter },
+            task,
+        }
+    }
+
+    pub fn writes(&self) -> Writes {
+        self.writes.clone()
+    }
+
+    /// Wait until everything queued is written. Other [`Writes`] handles must
+    /// be dropped by then.
+    pub async fn finish(self) -> Result<()> {
+        drop(self.writes);
+        self.task
+            .await
+            .map_err(|e| anyhow!("the disk writer failed: {}", e))?
+            .map_err(|e| anyhow!(e))
+    }
+}
+
+/// Report how many files wait in `queue`.
+fn report_depth(queue: &mpsc::Sender<Job>, reporter: &SharedReporter) {
+    let capacity = queue.max_capacity();
+    reporter.progress(Progress::WriteQueue {
+        pending: capacity - queue.capacity(),
+        capacity,
+    });
+}
+
+/// Write queued files until every handle is gone, remembering the first
+/// failure until it is reported; files queued after it are dropped.
+async fn work(
+    mut jobs: mpsc::Receiver<Job>,
+    queue: mpsc::WeakSender<Job>,
+    reporter: SharedReporter,
+    digests: Option<Digests>,
+) -> Result<(), String> {
+    let mut failure: Option<String> = None;
+    while let Some(job) = jobs.recv().await {
+        match job {
+            Job::Write(write) => {
+                if failure.is_none()
+                    && let Err(e) = write.perform(digests.as_ref()).await
+                {
+                    failure = Some(format!("{:#}", e));
+                }
+                if let Some(queue) = queue.upgrade() {
+                    report_depth(&queue, &reporter);
+                }
+            }
+            #[cfg(feature = "hls")]
+            Job::Flush(done) => {
+                let _ = done.send(failure.take().map_or(Ok(()), Err));
+            }
+        }
+    }
+    failure.map_or(Ok(()), Err)
+}
@@ -0,0 +1,139 @@
+//! Encryption at rest (`--encrypt-output age:<recipient>`): every manifest,
+//! segment and catalog is written as an [age](https://age-encryption.org)
+//! file, so content mirrored for QA isn't stored in the clear, and
+//! `streamrip serve --decrypt-with` decrypts files as it serves them.
+//!
+//! Files keep their names, so rewritten manifests and the state file work as
+//! before. Besides the given recipients, files are encrypted to a key that
+//! exists only in memory for the run, which lets it read back what it wrote
+//! (init segments, chunk playlists) without the recipient's identity.
+
+use anyhow::Result;
+#[cfg(feature = "encrypt")]
+use anyhow::{anyhow, bail};
+use std::borrow::Cow;
+#[cfg(feature = "encrypt")]
+use std::io::{Read, Write};
+use std::path::Path;
+#[cfg(feature = "encrypt")]
+use std::sync::OnceLock;
+
+/// Start of every age file.
+#[cfg(feature = "encrypt")]
+const MAGIC: &[u8] = b"age-encryption.org/";
+
+/// Recipients files are encrypted to, once enabled.
+#[cfg(feature = "encrypt")]
+static RECIPIENTS: OnceLock<Vec<Box<dyn age::Recipient + Send + Sync>>> = OnceLock::new();
+
+/// Identities encrypted files are read with: the run's own key while
+/// mirroring, those of `--decrypt-with` while serving.
+#[cfg(feature = "encrypt")]
+static IDENTITIES: OnceLock<Vec<Box<dyn age::Identity + Send + Sync>>> = OnceLock::new();
+
+/// Encrypt every file written from now on as `spec` (`age:<recipient>`,
+/// recipients separated by commas) says.
+#[cfg(feature = "encrypt")]
+pub fn enable(spec: &str) -> Result<()> {
+    let recipients = spec
+        .strip_prefix("age:")
+        .ok_or_else(|| anyhow!("expected age:<recipient>, got '{}'", spec))?;
+    let session = age::x25519::Identity::generate();
+    let mut parsed: Vec<Box<dyn age::Recipient + Send + Sync>> =
+        vec![Box::new(session.to_public())];
+    for recipient in recipients.split(',').map(str::trim) {
+        let recipient: age::x25519::Recipient = recipient
+            .parse()
+            .map_err(|e| anyhow!("invalid age recipient '{}': {}", recipient, e))?;
+        parsed.push(Box::new(recipient));
+    }
+    if RECIPIENTS.set(parsed).is_err() || IDENTITIES.set(vec![Box::new(session)]).is_err() {
+        bail!("output encryption is enabled already");
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "encrypt"))]
+pub fn enable(_spec: &str) -> Result<()> {
+    anyhow::bail!("--encrypt-output requires the `encrypt` feature. Build with --features encrypt.")
+}
+
+/// Read encrypted files with the identities (`AGE-SECRET-KEY-...` lines,
+/// as written by `age-keygen`) in the file at `path`.
+#[cfg(all(feature = "encrypt", feature = "serve"))]
+pub fn decrypt_with(path: &Path) -> Result<()> {
+    use anyhow::Context;
+
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading age identities {}", path.display()))?;
+    let identities: Vec<Box<dyn age::Identity + Send + Sync>> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            line.parse::<age::x25519::Identity>()
+                .map(|identity| Box::new(identity) as _)
+                .map_err(|e| anyhow!("invalid age identity in {}: {}", path.display(), e))
+        })
+        .collect::<Result<_>>()?;
+    if identities.is_empty() {
+        bail!("no age identities in {}", path.display());
+    }
+    if IDENTITIES.set(identities).is_err() {
+        bail!("decryption is enabled already");
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "serve", not(feature = "encrypt")))]
+pub fn decrypt_with(_path: &Path) -> Result<()> {
+    anyhow::bail!("--decrypt-with requires the `encrypt` feature. Build with --features encrypt.")
+}
+
+/// Whether files are encrypted as they are written.
+#[cfg_attr(not(feature = "hls"), allow(dead_code))]
+pub fn enabled() -> bool {
+    #[cfg(feature = "encrypt")]
+    return RECIPIENTS.get().is_some();
+    #[cfg(not(feature = "encrypt"))]
+    false
+}
+
+/// `data` as it is to be written: encrypted if output encryption is on.
+pub fn seal(data: &[u8]) -> Result<Cow<'_, [u8]>> {
+    #[cfg(feature = "encrypt")]
+    if let Some(recipients) = RECIPIENTS.get() {
+        let encryptor = age::Encryptor::with_recipients(recipients.iter().map(|r| r.as_ref() as _))
+            .map_err(|e| anyhow!("encrypting: {}", e))?;
+        let mut sealed = Vec::with_capacity(data.len() + 256);
+        let mut writer = encryptor.wrap_output(&mut sealed)?;
+        writer.write_all(data)?;
+        writer.finish()?;
+        return Ok(Cow::Owned(sealed));
+    }
+    Ok(Cow::Borrowed(data))
+}
+
+/// The content of a file read from a mirror: decrypted if it is encrypted
+/// and an identity for it is known, as read otherwise.
+pub fn open(data: Vec<u8>) -> Result<Vec<u8>> {
+    #[cfg(feature = "encrypt")]
+    if data.starts_with(MAGIC)
+        && let Some(identities) = IDENTITIES.get()
+    {
+        let decryptor = age::Decryptor::new_buffered(data.as_slice())
+            .map_err(|e| anyhow!("decrypting: {}", e))?;
+        let mut reader = decryptor
+            .decrypt(identities.iter().map(|i| i.as_ref() as _))
+            .map_err(|e| anyhow!("decrypting: {}", e))?;
+        let mut plain = Vec::new();
+        reader.read_to_end(&mut plain)?;
+        return Ok(plain);
+    }
+    Ok(data)
+}
+
+/// Read a file of the mirror with [`open`].
+pub async fn read(path: &Path) -> Result<Vec<u8>> {
+    open(tokio::fs::read(path).await?)
+}
@@ -0,0 +1,118 @@
+//! Preflight estimation: HEAD every planned resource and project the download size.
+
+use crate::PlannedFile;
+use crate::debug_http::SendLogged;
+use crate::report::{Event, SharedReporter};
+use crate::select;
+use crate::units;
+use reqwest::Client;
+use reqwest::header::CONTENT_LENGTH;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+/// Projected totals for one rendition.
+#[derive(Default)]
+struct Totals {
+    files: usize,
+    bytes: u64,
+    /// Files whose size could not be determined.
+    unknown: usize,
+}
+
+/// Issue HEAD requests for all planned files, at most `concurrency` at a
+/// time, and report projected byte totals per rendition.
+pub(crate) async fn estimate(
+    client: &Client,
+    planned: Vec<PlannedFile>,
+    manifests: usize,
+    concurrency: usize,
+    reporter: &SharedReporter,
+) {
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+
+    for (index, file) in planned.iter().enumerate() {
+        let client = client.clone();
+        let url = file.url.clone();
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await.ok()?;
+            let resp = client
+                .head(url)
+                .send_logged()
+                .await
+                .ok()?
+                .error_for_status()
+                .ok()?;
+            let len = resp
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            Some((index, len))
+        });
+    }
+
+    let mut sizes = vec![None; planned.len()];
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Some((index, len))) = result {
+            sizes[index] = len;
+        }
+    }
+
+    // Keep renditions in discovery order.
+    let mut renditions: Vec<(String, Totals)> = Vec::new();
+    for (file, size) in planned.iter().zip(sizes) {
+        let idx = match renditions.iter().position(|(r, _)| *r == file.rendition) {
+            Some(idx) => idx,
+            None => {
+                renditions.push((file.rendition.clone(), Totals::default()));
+                renditions.len() - 1
+            }
+        };
+        let totals = &mut renditions[idx].1;
+        totals.files += 1;
+        match size {
+            Some(len) => totals.bytes += len,
+            None => totals.unknown += 1,
+        }
+    }
+
+    let mut overall = Totals::default();
+    for (rendition, totals) in &renditions {
+        reporter.report(Event::Status {
+            tag: "EST",
+            message: format!(
+                "{} {} : {}",
+                select::rendition_id(rendition),
+                rendition,
+                describe(totals)
+            ),
+        });
+        overall.files += totals.files;
+        overall.bytes += totals.bytes;
+        overall.unknown += totals.unknown;
+    }
+
+    reporter.report(Event::Status {
+        tag: "EST",
+        message: format!(
+            "total: {} plus {} manifest(s)",
+            describe(&overall),
+            manifests
+        ),
+    });
+}
+
+fn describe(totals: &Totals) -> String {
+    let mut s = format!(
+        "{} file(s), {}",
+        units::count(totals.files),
+        units::size(totals.bytes)
+    );
+    if totals.unknown > 0 {
+        s.push_str(&format!(" ({} of unknown size)", totals.unknown));
+    }
+    s
+}
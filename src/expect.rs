@@ -0,0 +1,360 @@
+//! Expected-stream checks (`--expect`): comparing what the manifests
+//! describe with a small expectation file, so a run can serve as a QC gate
+//! for an origin.
+//!
+//! The file is a flat YAML or JSON object; lists are written `[a, b]` (or as
+//! YAML `- item` lines below their key):
+//!
+//! ```yaml
+//! renditions: 4              # HLS variants, DASH video Representations
+//! heights: [1080, 720, 540, 360]
+//! languages: [en, de]        # HLS audio/subtitle renditions, DASH AdaptationSets
+//! codecs: [avc1, mp4a]       # matched against the start of each codec string
+//! duration: 1h               # of every VOD rendition
+//! duration_tolerance: 2      # seconds, default 1
+//! ```
+
+use crate::Mirror;
+#[cfg(feature = "hls")]
+use crate::playlist::{HlsPlaylist, Line};
+use crate::report::Event;
+use crate::schedule;
+use crate::units;
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::BTreeSet;
+use std::path::Path;
+#[cfg(any(feature = "hls", feature = "dash"))]
+use url::Url;
+
+/// Default for `duration_tolerance`, in seconds.
+const DEFAULT_DURATION_TOLERANCE: f64 = 1.0;
+
+/// What the stream is expected to look like; unset fields aren't checked.
+#[derive(Debug, Clone, Default)]
+pub struct Expectations {
+    pub renditions: Option<usize>,
+    pub heights: Option<Vec<u32>>,
+    pub languages: Option<Vec<String>>,
+    pub codecs: Option<Vec<String>>,
+    /// In seconds.
+    pub duration: Option<f64>,
+    pub duration_tolerance: Option<f64>,
+}
+
+/// What the manifests describe, collected while mirroring.
+#[derive(Debug, Default)]
+pub struct Observed {
+    /// Height of each video rendition, if known.
+    pub renditions: Vec<Option<u32>>,
+    pub languages: BTreeSet<String>,
+    pub codecs: BTreeSet<String>,
+    /// Duration in seconds of each VOD media playlist or MPD.
+    pub durations: Vec<(String, f64)>,
+}
+
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+impl Observed {
+    /// Note the codecs of a `CODECS` or `@codecs` list.
+    pub fn add_codecs(&mut self, codecs: &str) {
+        self.codecs.extend(
+            codecs
+                .split(',')
+                .map(str::trim)
+                .filter(|c| !c.is_empty())
+                .map(str::to_string),
+        );
+    }
+}
+
+/// Read an expectation file.
+pub async fn load(path: &Path) -> Result<Expectations> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("reading expectations {}", path.display()))?;
+    parse(&text).with_context(|| format!("parsing expectations {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Expectations> {
+    let mut expectations = Expectations::default();
+    for (key, values) in entries(text)? {
+        let single = || match values.as_slice() {
+            [value] => Ok(value.as_str()),
+            _ => Err(anyhow!("expected a single value for {}", key)),
+        };
+        match key.as_str() {
+            "renditions" => {
+                expectations.renditions = Some(
+                    single()?
+                        .parse()
+                        .map_err(|_| anyhow!("invalid renditions"))?,
+                )
+            }
+            "heights" => {
+                expectations.heights = Some(
+                    values
+                        .iter()
+                        .map(|h| h.parse().map_err(|_| anyhow!("invalid height '{}'", h)))
+                        .collect::<Result<_>>()?,
+                )
+            }
+            "languages" => expectations.languages = Some(values),
+            "codecs" => expectations.codecs = Some(values),
+            "duration" => {
+                expectations.duration = Some(schedule::parse_duration(single()?)?.as_secs_f64())
+            }
+            "duration_tolerance" => {
+                expectations.duration_tolerance =
+                    Some(schedule::parse_duration(single()?)?.as_secs_f64())
+            }
+            other => bail!("unknown expectation '{}'", other),
+        }
+    }
+    Ok(expectations)
+}
+
+/// The `key: value` entries of a flat YAML or JSON object, with list values
+/// split into their items and quotes removed.
+fn entries(text: &str) -> Result<Vec<(String, Vec<String>)>> {
+    // Comments, and the braces of a JSON object.
+    let text: String = text
+        .lines()
+        .map(|line| match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = text.trim();
+    let text = text
+        .strip_prefix('{')
+        .and_then(|t| t.strip_suffix('}'))
+        .unwrap_or(text);
+
+    let mut entries: Vec<(String, Vec<String>)> = Vec::new();
+    for item in split_top_level(text) {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+        // A YAML block list item belongs to the key before it.
+        if let Some(value) = item.strip_prefix("- ") {
+            let (_, values) = entries
+                .last_mut()
+                .ok_or_else(|| anyhow!("list item '{}' without a key", item))?;
+            values.push(unquote(value).to_string());
+            continue;
+        }
+        let (key, value) = item
+            .split_once(':')
+            .ok_or_else(|| anyhow!("expected `key: value`, got '{}'", item))?;
+        let value = value.trim();
+        let values = match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(list) => list
+                .split(',')
+                .map(|v| unquote(v).to_string())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            None if value.is_empty() => Vec::new(),
+            None => vec![unquote(value).to_string()],
+        };
+        entries.push((unquote(key).to_string(), values));
+    }
+    Ok(entries)
+}
+
+/// Split at newlines and at commas outside of brackets and quotes.
+fn split_top_level(text: &str) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '[' if !in_quotes => depth += 1,
+            ']' if !in_quotes => depth -= 1,
+            ',' | '\n' if !in_quotes && depth == 0 => {
+                items.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&text[start..]);
+    items
+}
+
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .or_else(|| s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')))
+        .unwrap_or(s)
+}
+
+impl Mirror {
+    /// Note the renditions, languages, codecs and duration an HLS playlist
+    /// describes.
+    #[cfg(feature = "hls")]
+    pub(crate) fn observe_playlist(&mut self, url: &Url, playlist: &HlsPlaylist<'_>) {
+        match playlist {
+            HlsPlaylist::Master(master) => {
+                for variant in master.variants.iter().filter(|v| !v.i_frame) {
+                    self.observed.renditions.push(variant.height);
+                    if let Line::Tag(tag) = master.lines[variant.tag]
+                        && let Some(codecs) = tag.attribute("CODECS")
+                    {
+                        self.observed.add_codecs(&codecs);
+                    }
+                }
+                for media in &master.media {
+                    if media.kind.as_deref() != Some("VIDEO")
+                        && let Some(language) = &media.language
+                    {
+                        self.observed.languages.insert(language.clone());
+                    }
+                }
+            }
+            HlsPlaylist::Media(media) => {
+                // A media playlist as the start URL is the only rendition.
+                if self.rendition.is_empty() {
+                    self.observed.renditions.push(None);
+                }
+                if media.ended {
+                    self.observed
+                        .durations
+                        .push((url.to_string(), media.duration));
+                }
+            }
+        }
+    }
+
+    /// Note the duration of a static MPD.
+    #[cfg(feature = "dash")]
+    pub(crate) fn observe_mpd(&mut self, url: &Url, dynamic: bool, mpd: &crate::mpd::MpdModel) {
+        let durations: Option<Vec<f64>> = mpd.periods.iter().map(|p| p.duration_secs).collect();
+        if let Some(durations) = durations.filter(|d| !dynamic && !d.is_empty()) {
+            self.observed
+                .durations
+                .push((url.to_string(), durations.iter().sum()));
+        }
+    }
+
+    /// Note what a mirrored DASH Representation is: a video rendition, with
+    /// its codecs, or one in a language.
+    #[cfg(feature = "dash")]
+    pub(crate) fn observe_representation(
+        &mut self,
+        aset: &crate::mpd::AdaptationSet,
+        rep: &crate::mpd::Representation,
+    ) {
+        let video = aset.content_type == Some("video") || rep.height.is_some();
+        if video {
+            self.observed.renditions.push(rep.height);
+        } else if let Some(language) = aset.node.attribute("lang") {
+            self.observed.languages.insert(language.to_string());
+        }
+        if let Some(codecs) = rep
+            .node
+            .attribute("codecs")
+            .or(aset.node.attribute("codecs"))
+        {
+            self.observed.add_codecs(codecs);
+        }
+    }
+
+    /// Compare what the manifests described with `expectations`, reporting
+    /// every difference; fails if there are any.
+    pub(crate) fn check_expectations(&self, expectations: &Expectations) -> Result<()> {
+        let observed = &self.observed;
+        let mut mismatches = Vec::new();
+
+        if let Some(expected) = expectations.renditions
+            && observed.renditions.len() != expected
+        {
+            mismatches.push((
+                "renditions",
+                format!("expected {}, found {}", expected, observed.renditions.len()),
+            ));
+        }
+
+        if let Some(expected) = &expectations.heights {
+            let found: BTreeSet<u32> = observed.renditions.iter().flatten().copied().collect();
+            let expected: BTreeSet<u32> = expected.iter().copied().collect();
+            for height in expected.difference(&found) {
+                mismatches.push(("heights", format!("missing {}", height)));
+            }
+            for height in found.difference(&expected) {
+                mismatches.push(("heights", format!("unexpected {}", height)));
+            }
+        }
+
+        if let Some(expected) = &expectations.languages {
+            let expected: BTreeSet<String> = expected.iter().cloned().collect();
+            for language in expected.difference(&observed.languages) {
+                mismatches.push(("languages", format!("missing {}", language)));
+            }
+            for language in observed.languages.difference(&expected) {
+                mismatches.push(("languages", format!("unexpected {}", language)));
+            }
+        }
+
+        if let Some(expected) = &expectations.codecs {
+            let matches = |expected: &String, codec: &String| codec.starts_with(expected.as_str());
+            for codec in expected {
+                if !observed.codecs.iter().any(|c| matches(codec, c)) {
+                    mismatches.push(("codecs", format!("missing {}", codec)));
+                }
+            }
+            for codec in &observed.codecs {
+                if !expected.iter().any(|e| matches(e, codec)) {
+                    mismatches.push(("codecs", format!("unexpected {}", codec)));
+                }
+            }
+        }
+
+        if let Some(expected) = expectations.duration {
+            let tolerance = expectations
+                .duration_tolerance
+                .unwrap_or(DEFAULT_DURATION_TOLERANCE);
+            if observed.durations.is_empty() {
+                mismatches.push((
+                    "duration",
+                    "no VOD rendition to take the duration of".to_string(),
+                ));
+            }
+            for (rendition, duration) in &observed.durations {
+                if (duration - expected).abs() > tolerance {
+                    mismatches.push((
+                        "duration",
+                        format!(
+                            "{}: expected {} ±{}, found {}",
+                            rendition,
+                            units::duration(expected),
+                            units::duration(tolerance),
+                            units::duration(*duration)
+                        ),
+                    ));
+                }
+            }
+        }
+
+        for (subject, message) in &mismatches {
+            self.reporter.report(Event::Failure {
+                subject: format!("expected {}", subject),
+                message: message.clone(),
+            });
+        }
+        if !mismatches.is_empty() {
+            bail!(
+                "the stream doesn't match {} expectation(s)",
+                mismatches.len()
+            );
+        }
+        self.reporter.report(Event::Status {
+            tag: "EXPT",
+            message: "the stream matches the expectations".to_string(),
+        });
+        Ok(())
+    }
+}
@@ -0,0 +1,126 @@
+//! Request timing and failures per host and path prefix, summarized at the
+//! end of a run.
+//!
+//! Every request sent with [`crate::debug_http::SendLogged::send_logged`]
+//! is counted under its host and the first segment of its path, with how
+//! long the response took to start and, if it failed, why: the HTTP status,
+//! or a timeout or connection error. A stream spread over several CDNs or
+//! origin paths then shows which of them is slow or failing.
+
+use crate::report::{Event, SharedReporter};
+use crate::units;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use url::Url;
+
+/// Statistics of every host requested from, by host and path prefix.
+static HOSTS: Mutex<BTreeMap<String, BTreeMap<String, Stats>>> = Mutex::new(BTreeMap::new());
+
+/// How a request ended.
+pub(crate) enum Outcome<'a> {
+    /// A response with this status, failed from 400 on.
+    Status(reqwest::StatusCode),
+    /// No response, for this reason.
+    Error(&'a reqwest::Error),
+    /// No response in time.
+    Timeout,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Stats {
+    requests: usize,
+    total: Duration,
+    slowest: Duration,
+    /// Failed requests by status or kind of error.
+    failures: BTreeMap<String, usize>,
+}
+
+impl Stats {
+    fn add(&mut self, other: &Stats) {
+        self.requests += other.requests;
+        self.total += other.total;
+        self.slowest = self.slowest.max(other.slowest);
+        for (reason, count) in &other.failures {
+            *self.failures.entry(reason.clone()).or_default() += count;
+        }
+    }
+
+    /// `12 requests, 85ms average, 2,140ms slowest, 3 failed (404 ×2, timeout ×1)`
+    fn describe(&self) -> String {
+        let average = self.total.as_millis() / self.requests.max(1) as u128;
+        let mut text = format!(
+            "{} request(s), {}ms average, {}ms slowest",
+            units::count(self.requests),
+            units::count(average as usize),
+            units::count(self.slowest.as_millis() as usize)
+        );
+        let failed: usize = self.failures.values().sum();
+        if failed > 0 {
+            let reasons: Vec<String> = self
+                .failures
+                .iter()
+                .map(|(reason, count)| format!("{} ×{}", reason, units::count(*count)))
+                .collect();
+            text.push_str(&format!(
+                ", {} failed ({})",
+                units::count(failed),
+                reasons.join(", ")
+            ));
+        }
+        text
+    }
+}
+
+/// Count a request for `url` that ended with `outcome` after `took`.
+pub(crate) fn record(url: &Url, outcome: Outcome<'_>, took: Duration) {
+    let failure = match outcome {
+        Outcome::Status(status) if status.as_u16() >= 400 => Some(status.as_u16().to_string()),
+        Outcome::Status(_) => None,
+        Outcome::Error(e) if e.is_timeout() => Some("timeout".to_string()),
+        Outcome::Error(e) if e.is_connect() => Some("connection".to_string()),
+        Outcome::Error(_) => Some("error".to_string()),
+        Outcome::Timeout => Some("timeout".to_string()),
+    };
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => url.scheme().to_string(),
+    };
+    let prefix = match url.path().trim_start_matches('/').split_once('/') {
+        Some((first, _)) => format!("/{}/", first),
+        None => "/".to_string(),
+    };
+
+    let mut hosts = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    let stats = hosts.entry(host).or_default().entry(prefix).or_default();
+    stats.requests += 1;
+    stats.total += took;
+    stats.slowest = stats.slowest.max(took);
+    if let Some(failure) = failure {
+        *stats.failures.entry(failure).or_default() += 1;
+    }
+}
+
+/// Report the requests per host, and per path prefix of hosts with more
+/// than one.
+pub(crate) fn summarize(reporter: &SharedReporter) {
+    let hosts = HOSTS.lock().unwrap_or_else(|e| e.into_inner());
+    for (host, prefixes) in hosts.iter() {
+        let mut total = Stats::default();
+        for stats in prefixes.values() {
+            total.add(stats);
+        }
+        reporter.report(Event::Status {
+            tag: "HOST",
+            message: format!("{}: {}", host, total.describe()),
+        });
+        if prefixes.len() > 1 {
+            for (prefix, stats) in prefixes {
+                reporter.report(Event::Detail {
+                    message: format!("{}: {}", prefix, stats.describe()),
+                });
+            }
+        }
+    }
+}
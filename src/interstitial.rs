@@ -0,0 +1,139 @@
+//! HLS interstitials: `#EXT-X-DATERANGE` tags scheduling other content
+//! through `X-ASSET-URI` (a playlist) or `X-ASSET-LIST` (a JSON document
+//! listing several playlists).
+
+use crate::Mirror;
+use crate::debug_http::SendLogged;
+use crate::report::{Event, Resource, json_string};
+use anyhow::{Context, Result};
+use url::Url;
+
+impl Mirror {
+    /// Mirror an interstitial asset list and every asset it names, rewriting
+    /// the asset `URI`s to local relative paths.
+    ///
+    /// Asset lists are usually generated per request, so unlike manifests no
+    /// `.orig` copy is kept.
+    pub(crate) async fn mirror_asset_list(&mut self, url: Url) -> Result<()> {
+        if !self.visited.insert(self.dedup_key(&url)) {
+            self.tally.duplicates += 1;
+            return Ok(());
+        }
+        let local_path = self.path_for_url(&url, false);
+
+        self.reporter.report(Event::Fetch {
+            kind: Resource::Playlist,
+            url: url.as_str(),
+            path: &local_path,
+        });
+
+        let text = self
+            .client
+            .get(url.clone())
+            .send_logged()
+            .await
+            .with_context(|| format!("GET {}", url))?
+            .error_for_status()
+            .with_context(|| format!("status error for {}", url))?
+            .text()
+            .await?;
+        self.tally.fetched += 1;
+
+        let local_dir = local_path.parent().unwrap_or(&self.out_dir).to_path_buf();
+
+        let mut rewritten = String::with_capacity(text.len());
+        let mut copied = 0;
+        for (start, end) in asset_uri_spans(&text) {
+            let uri_val = json_unescape(&text[start..end]);
+            let asset_url = url.join(&uri_val).with_context(|| {
+                format!("resolving asset URI '{}' relative to {}", uri_val, url)
+            })?;
+            if !crate::is_fetchable(&asset_url) {
+                continue;
+            }
+
+            self.mirror_manifest(asset_url.clone()).await?;
+            let target_path = self.path_for_url(&asset_url, true);
+            let rel = self.rewritten_reference(&uri_val, &target_path, &local_dir);
+
+            // Replace the string contents, keeping the surrounding quotes.
+            let quoted = json_string(&rel);
+            rewritten.push_str(&text[copied..start]);
+            rewritten.push_str(&quoted[1..quoted.len() - 1]);
+            copied = end;
+        }
+        rewritten.push_str(&text[copied..]);
+
+        self.write_output(&local_path, rewritten.as_bytes()).await
+    }
+}
+
+/// Byte ranges of the string values of all `"URI"` keys in a JSON document,
+/// excluding the quotes.
+fn asset_uri_spans(text: &str) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+    let mut spans = Vec::new();
+    let mut last_string: Option<(usize, usize)> = None;
+    let mut expect_uri = false;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let start = i + 1;
+                let mut end = start;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    if bytes[end] == b'\\' {
+                        end += 1;
+                    }
+                    end += 1;
+                }
+                let end = end.min(bytes.len());
+                if expect_uri {
+                    spans.push((start, end));
+                    expect_uri = false;
+                }
+                last_string = Some((start, end));
+                i = end + 1;
+                continue;
+            }
+            b':' => expect_uri = last_string.is_some_and(|(s, e)| &text[s..e] == "URI"),
+            c if c.is_ascii_whitespace() => {}
+            _ => {
+                expect_uri = false;
+                last_string = None;
+            }
+        }
+        i += 1;
+    }
+
+    spans
+}
+
+/// Decode the escape sequences of a JSON string's contents.
+fn json_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                if let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    out.push(c);
+                }
+            }
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
@@ -0,0 +1,374 @@
+//! Status of the jobs of `streamrip watch`, served as JSON (`--status-addr`).
+//!
+//! Each job runs with a [`JobReporter`], which passes every event on and
+//! keeps what a dashboard or monitoring system asks about: the phase, how
+//! many of the planned files are done, how far a live recording is behind,
+//! warnings and failures, the download rate and the time left. `GET /jobs` lists every job
+//! the watcher has come across, `GET /jobs/<id>` returns one by its output
+//! directory name.
+
+#![cfg_attr(not(all(feature = "hls", feature = "serve")), allow(dead_code))]
+
+use crate::report::{Event, Phase, Progress, Reporter, SharedReporter, json_string};
+use crate::schedule;
+use anyhow::Result;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// The download rate of a running job is measured over this long.
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(10);
+
+/// Every job of the watcher, shared with the status server.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct JobBoard {
+    jobs: Arc<Mutex<Vec<JobStatus>>>,
+}
+
+#[derive(Debug)]
+struct JobStatus {
+    id: String,
+    start_url: String,
+    state: State,
+    /// Files announced by the run, and how many of them are done.
+    planned: usize,
+    completed: usize,
+    /// Planned files that failed and were skipped (`skip_failed`).
+    failed: usize,
+    /// Seconds the live rendition furthest behind is late.
+    lag: Option<f64>,
+    /// Files waiting in the disk write queue, and how many it holds.
+    write_queue: Option<(usize, usize)>,
+    warnings: usize,
+    failures: usize,
+    bytes: u64,
+    /// Recent downloads, for the current rate.
+    recent: VecDeque<(Instant, u64)>,
+    started: Option<(Instant, SystemTime)>,
+    /// When the planned files started downloading.
+    downloading: Option<Instant>,
+    finished: Option<(Instant, SystemTime)>,
+}
+
+#[derive(Debug)]
+enum State {
+    Queued,
+    /// Running, in the phase last reported, if any.
+    Running(Option<Phase>),
+    Done,
+    Failed(String),
+}
+
+impl JobStatus {
+    fn new(id: &str, start_url: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            start_url: start_url.to_string(),
+            state: State::Queued,
+            planned: 0,
+            completed: 0,
+            failed: 0,
+            lag: None,
+            write_queue: None,
+            warnings: 0,
+            failures: 0,
+            bytes: 0,
+            recent: VecDeque::new(),
+            started: None,
+            downloading: None,
+            finished: None,
+        }
+    }
+
+    fn phase(&self) -> &'static str {
+        match &self.state {
+            State::Queued => "queued",
+            State::Running(None) => "starting",
+            State::Running(Some(Phase::Planning)) => "planning",
+            State::Running(Some(Phase::Downloading)) => "downloading",
+            State::Running(Some(Phase::Recording)) => "recording",
+            State::Done => "done",
+            State::Failed(_) => "failed",
+        }
+    }
+
+    /// Share of the planned files done; unknown before planning and while
+    /// recording, which has no end in sight.
+    fn percent(&self) -> Option<f64> {
+        match self.state {
+            State::Done => Some(100.0),
+            State::Running(Some(Phase::Recording)) => None,
+            _ if self.planned > 0 => {
+                Some((self.completed + self.failed) as f64 * 100.0 / self.planned as f64)
+            }
+            _ => None,
+        }
+    }
+
+    /// Seconds until the planned files are done, at the pace they were
+    /// downloaded so far; unknown outside of downloading and before the
+    /// first of them is done.
+    fn eta(&self, now: Instant) -> Option<f64> {
+        let downloading = self.downloading?;
+        let done = self.completed + self.failed;
+        if !matches!(self.state, State::Running(Some(Phase::Downloading))) || done == 0 {
+            return None;
+        }
+        let remaining = self.planned.saturating_sub(done);
+        Some((now - downloading).as_secs_f64() / done as f64 * remaining as f64)
+    }
+
+    /// Bytes per second: over the last [`THROUGHPUT_WINDOW`] while running,
+    /// over the whole run once it ended.
+    fn throughput(&mut self, now: Instant) -> f64 {
+        let Some((started, _)) = self.started else {
+            return 0.0;
+        };
+        if let Some((finished, _)) = self.finished {
+            let took = (finished - started).as_secs_f64();
+            return if took > 0.0 {
+                self.bytes as f64 / took
+            } else {
+                0.0
+            };
+        }
+        self.forget_before(now);
+        let window = (now - started).min(THROUGHPUT_WINDOW).as_secs_f64();
+        let recent: u64 = self.recent.iter().map(|(_, bytes)| bytes).sum();
+        if window > 0.0 {
+            recent as f64 / window
+        } else {
+            0.0
+        }
+    }
+
+    fn forget_before(&mut self, now: Instant) {
+        while self
+            .recent
+            .front()
+            .is_some_and(|(at, _)| now - *at > THROUGHPUT_WINDOW)
+        {
+            self.recent.pop_front();
+        }
+    }
+
+    fn json(&mut self) -> String {
+        let now = Instant::now();
+        let number =
+            |value: Option<f64>| value.map_or_else(|| "null".to_string(), |v| format!("{:.1}", v));
+        let time = |at: Option<(Instant, SystemTime)>| {
+            at.map_or_else(
+                || "null".to_string(),
+                |(_, at)| json_string(&schedule::format_timestamp(at)),
+            )
+        };
+        let throughput = self.throughput(now).round() as u64;
+        let eta = self.eta(now);
+        let write_queue = self.write_queue.map_or_else(
+            || "null".to_string(),
+            |(pending, capacity)| format!("{{\"pending\":{},\"capacity\":{}}}", pending, capacity),
+        );
+        let error = match &self.state {
+            State::Failed(message) => json_string(message),
+            _ => "null".to_string(),
+        };
+        format!(
+            "{{\"id\":{},\"start_url\":{},\"phase\":\"{}\",\"percent\":{},\"files\":{{\"planned\":{},\"completed\":{},\"failed\":{}}},\"eta_seconds\":{},\"lag_seconds\":{},\"write_queue\":{},\"warnings\":{},\"failures\":{},\"bytes\":{},\"throughput\":{},\"started\":{},\"finished\":{},\"error\":{}}}",
+            json_string(&self.id),
+            json_string(&self.start_url),
+            self.phase(),
+            number(self.percent()),
+            self.planned,
+            self.completed,
+            self.failed,
+            number(eta),
+            number(self.lag),
+            write_queue,
+            self.warnings,
+            self.failures,
+            self.bytes,
+            throughput,
+            time(self.started),
+            time(self.finished),
+            error
+        )
+    }
+}
+
+impl JobBoard {
+    fn update(&self, id: &str, f: impl FnOnce(&mut JobStatus)) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id) {
+            f(job);
+        }
+    }
+
+    /// Add a job about to run, or reset one run before.
+    pub fn queue(&self, id: &str, start_url: &str) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        match jobs.iter_mut().find(|job| job.id == id) {
+            Some(job) if matches!(job.state, State::Running(_)) => {}
+            Some(job) => *job = JobStatus::new(id, start_url),
+            None => jobs.push(JobStatus::new(id, start_url)),
+        }
+    }
+
+    /// Mark the job `id` as running and return the reporter for its run,
+    /// which passes every event on to `inner`.
+    pub fn start(&self, id: &str, start_url: &str, inner: SharedReporter) -> SharedReporter {
+        self.queue(id, start_url);
+        self.update(id, |job| {
+            job.state = State::Running(None);
+            job.started = Some((Instant::now(), SystemTime::now()));
+        });
+        Arc::new(JobReporter {
+            board: self.clone(),
+            id: id.to_string(),
+            inner,
+        })
+    }
+
+    /// Mark the job `id` as ended with `result`.
+    pub fn finish(&self, id: &str, result: &Result<()>) {
+        self.update(id, |job| {
+            job.state = match result {
+                Ok(()) => State::Done,
+                Err(e) => State::Failed(format!("{:#}", e)),
+            };
+            job.finished = Some((Instant::now(), SystemTime::now()));
+        });
+    }
+
+    /// Every job, as `{"jobs":[...]}`.
+    fn list_json(&self) -> String {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let items: Vec<String> = jobs.iter_mut().map(JobStatus::json).collect();
+        format!("{{\"jobs\":[{}]}}\n", items.join(","))
+    }
+
+    /// The job `id`, as JSON.
+    pub fn job_json(&self, id: &str) -> Option<String> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.iter_mut().find(|job| job.id == id)?;
+        Some(job.json() + "\n")
+    }
+}
+
+/// Reports a job's events and keeps its status on the board.
+struct JobReporter {
+    board: JobBoard,
+    id: String,
+    inner: SharedReporter,
+}
+
+impl Reporter for JobReporter {
+    fn report(&self, event: Event<'_>) {
+        match &event {
+            Event::Warning { .. } => self.board.update(&self.id, |job| job.warnings += 1),
+            Event::Failure { .. } => self.board.update(&self.id, |job| job.failures += 1),
+            _ => {}
+        }
+        self.inner.report(event);
+    }
+
+    fn progress(&self, update: Progress) {
+        self.board.update(&self.id, |job| match update {
+            Progress::Phase(phase) => {
+                if phase == Phase::Downloading && job.downloading.is_none() {
+                    job.downloading = Some(Instant::now());
+                }
+                job.state = State::Running(Some(phase));
+            }
+            Progress::Planned(count) => job.planned += count,
+            Progress::Completed => job.completed += 1,
+            Progress::Failed => job.failed += 1,
+            Progress::Bytes(bytes) => {
+                let now = Instant::now();
+                job.bytes += bytes;
+                job.recent.push_back((now, bytes));
+                job.forget_before(now);
+            }
+            Progress::Lag(lag) => job.lag = Some(lag),
+            Progress::WriteQueue { pending, capacity } => {
+                job.write_queue = Some((pending, capacity))
+            }
+        });
+        self.inner.progress(update);
+    }
+}
+
+/// Serve the status of the jobs on `board` at `addr` in the background.
+#[cfg(feature = "serve")]
+pub(crate) async fn serve(
+    addr: std::net::SocketAddr,
+    board: JobBoard,
+    reporter: SharedReporter,
+) -> Result<()> {
+    use anyhow::Context;
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+    use hyper::header::{ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE};
+    use hyper::service::service_fn;
+    use hyper::{Method, Response, StatusCode};
+    use hyper_util::rt::TokioIo;
+
+    if !addr.ip().is_loopback() {
+        reporter.report(Event::Warning {
+            message: format!(
+                "serving job status on {}; start URLs are readable by anyone who can reach this address",
+                addr
+            ),
+        });
+    }
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding {}", addr))?;
+    reporter.report(Event::Status {
+        tag: "SERV",
+        message: format!("job status on http://{}/jobs", listener.local_addr()?),
+    });
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    reporter.report(Event::Warning {
+                        message: format!("job status server stopped: {}", e),
+                    });
+                    return;
+                }
+            };
+            let board = board.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: hyper::Request<hyper::body::Incoming>| {
+                    let body = if req.method() != Method::GET {
+                        None
+                    } else {
+                        match req.uri().path().trim_end_matches('/') {
+                            "/jobs" => Some(board.list_json()),
+                            path => path
+                                .strip_prefix("/jobs/")
+                                .and_then(crate::serve::percent_decode)
+                                .and_then(|id| board.job_json(&id)),
+                        }
+                    };
+                    let resp = match body {
+                        Some(body) => Response::builder()
+                            .header(CONTENT_TYPE, "application/json")
+                            .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+                            .body(Full::new(Bytes::from(body))),
+                        None => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Full::new(Bytes::from("Not Found"))),
+                    };
+                    std::future::ready(resp)
+                });
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,193 @@
+//! Keys supplied on the command line (`--key`, `--key-file`) for HLS
+//! content whose key URIs can't be fetched, such as ones behind an
+//! entitlement check.
+//!
+//! A supplied key is written to the mirror in place of the origin's, and
+//! the `#EXT-X-KEY` is rewritten to refer to it, so the mirror plays offline
+//! with the keys its owner has. Segments stay encrypted: players decrypt
+//! them with the key, streamrip doesn't. Only keys in the clear (`KEYFORMAT`
+//! identity, `METHOD=AES-128` or `SAMPLE-AES`) can be supplied. A key given
+//! with its URI is used for that URI, as written in the playlist or
+//! resolved; the others are used, in the order the mirror comes across
+//! them, for key URIs that can't be fetched: ones of a scheme of their own,
+//! and ones whose download failed.
+
+#![cfg_attr(not(feature = "hls"), allow(dead_code))]
+
+use crate::playlist::{self, IDENTITY_KEY_FORMAT, Tag};
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use url::Url;
+
+/// Length of an AES-128 key.
+const KEY_LEN: usize = 16;
+
+/// A `--key`: the key, and the URI it is for unless it goes by order.
+#[derive(Clone)]
+pub struct SuppliedKey {
+    uri: Option<String>,
+    key: [u8; KEY_LEN],
+}
+
+// The options end up in provenance stamps; the key itself must not.
+impl std::fmt::Debug for SuppliedKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SuppliedKey")
+            .field("uri", &self.uri)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Parse `[URI=]HEX`, the key given as 32 hex digits with an optional `0x`.
+pub fn parse_key(s: &str) -> Result<SuppliedKey> {
+    let s = s.trim();
+    // URIs may contain `=`, hex digits don't.
+    let (uri, hex) = match s.rsplit_once('=') {
+        Some((uri, hex)) => (Some(uri.trim().to_string()), hex.trim()),
+        None => (None, s),
+    };
+    let hex = hex
+        .strip_prefix("0x")
+        .or_else(|| hex.strip_prefix("0X"))
+        .unwrap_or(hex);
+    if hex.len() != KEY_LEN * 2 || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        bail!("expected [URI=]HEX with 32 hex digits, got '{}'", s);
+    }
+    let mut key = [0; KEY_LEN];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(SuppliedKey { uri, key })
+}
+
+/// Read a `--key-file`: one `[URI=]HEX` per line; blank lines and lines
+/// starting with `#` are skipped.
+pub async fn load(path: &Path) -> Result<Vec<SuppliedKey>> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("reading key file {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| {
+            parse_key(line).map_err(|e| anyhow!("{}, line {}: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+/// A supplied key given to a key URI.
+pub struct Assigned {
+    pub key: [u8; KEY_LEN],
+    /// Tells the key URIs given a key apart, numbered in the order they
+    /// came up.
+    pub number: usize,
+    /// Whether the key URI came up for the first time.
+    pub first: bool,
+}
+
+/// The supplied keys, and which key URI each of those going by order went to.
+#[derive(Default, Clone)]
+pub struct Keyring {
+    by_uri: HashMap<String, [u8; KEY_LEN]>,
+    by_order: Vec<[u8; KEY_LEN]>,
+    /// Key URIs (resolved) given one of `by_order`, by its index.
+    assigned: HashMap<Url, usize>,
+    /// Entries of `by_uri` used so far.
+    used: HashSet<String>,
+    /// Key URIs (resolved) given a key, numbered in the order they came up.
+    numbers: HashMap<Url, usize>,
+}
+
+impl Keyring {
+    pub fn new(keys: impl IntoIterator<Item = SuppliedKey>) -> Self {
+        let mut keyring = Self::default();
+        for supplied in keys {
+            match supplied.uri {
+                Some(uri) => {
+                    keyring.by_uri.insert(uri, supplied.key);
+                }
+                None => keyring.by_order.push(supplied.key),
+            }
+        }
+        keyring
+    }
+
+    /// The supplied key for the key `tag` refers to as `uri`, resolved to
+    /// `url`: the one given with its URI, or the one going by order `url`
+    /// was given before. `None` for keys that can't be supplied.
+    pub fn key_for(&mut self, tag: &Tag<'_>, uri: &str, url: &Url) -> Option<Assigned> {
+        if !suppliable(tag) {
+            return None;
+        }
+        let key = match [uri, url.as_str()]
+            .into_iter()
+            .find(|written| self.by_uri.contains_key(*written))
+        {
+            Some(written) => {
+                self.used.insert(written.to_string());
+                self.by_uri[written]
+            }
+            None => self.by_order[*self.assigned.get(url)?],
+        };
+        Some(self.number(url, key))
+    }
+
+    /// The next key going by order, for the key `tag` refers to as `url`
+    /// when the origin's can't be fetched. `None` for keys that can't be
+    /// supplied, and once the keys going by order are used up.
+    pub fn next_key_for(&mut self, tag: &Tag<'_>, url: &Url) -> Option<Assigned> {
+        if !suppliable(tag) {
+            return None;
+        }
+        let next = self.assigned.len();
+        let key = *self.by_order.get(next)?;
+        self.assigned.insert(url.clone(), next);
+        Some(self.number(url, key))
+    }
+
+    /// Whether keys going by order are left for key URIs that can't be
+    /// fetched.
+    pub fn has_keys_by_order(&self) -> bool {
+        self.assigned.len() < self.by_order.len()
+    }
+
+    /// `key` as given to `url`, numbered in the order key URIs came up.
+    fn number(&mut self, url: &Url, key: [u8; KEY_LEN]) -> Assigned {
+        let next = self.numbers.len();
+        let number = *self.numbers.entry(url.clone()).or_insert(next);
+        Assigned {
+            key,
+            number,
+            first: number == next,
+        }
+    }
+
+    /// The supplied keys no key URI was matched with, such as
+    /// `2 of the keys given by order`.
+    pub fn unused(&self) -> Vec<String> {
+        let mut unused: Vec<String> = self
+            .by_uri
+            .keys()
+            .filter(|uri| !self.used.contains(*uri))
+            .map(|uri| format!("the key for {}", uri))
+            .collect();
+        unused.sort();
+        let left = self.by_order.len().saturating_sub(self.assigned.len());
+        if left > 0 {
+            unused.push(format!("{} of the keys given by order", left));
+        }
+        unused
+    }
+}
+
+/// Whether the key `tag` is for can be supplied: an AES-128 or SAMPLE-AES
+/// key in the clear.
+fn suppliable(tag: &Tag<'_>) -> bool {
+    (tag.is("#EXT-X-KEY") || tag.is("#EXT-X-SESSION-KEY"))
+        && playlist::key_format(tag) == IDENTITY_KEY_FORMAT
+        && matches!(
+            tag.attribute("METHOD").as_deref(),
+            Some("AES-128" | "SAMPLE-AES")
+        )
+}
@@ -0,0 +1,3090 @@
+//! Recursively mirror an HLS (.m3u8) or DASH (.mpd) stream for local hosting.
+//!
+//! This is the library behind the `streamrip` command line, which [`run`]
+//! is. Embedders that bring their own download and storage policies can
+//! reuse just the traversal: [`discover_resources`] yields the manifests,
+//! keys, init segments and segments of a stream as it finds them.
+
+#![forbid(unsafe_code)]
+
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{CommandFactory, Parser, Subcommand};
+use reqwest::Client;
+use reqwest::header::{CONTENT_TYPE, LAST_MODIFIED};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use report::{Event, Resource, SharedReporter};
+#[cfg(any(feature = "hls", feature = "dash"))]
+use streamrip_core::paths::relative_reference;
+use streamrip_core::playlist;
+#[cfg(feature = "hls")]
+use streamrip_core::rewrite::{self, is_fetchable};
+#[cfg(feature = "dash")]
+use streamrip_core::{mpd, template};
+
+#[cfg(feature = "dash")]
+use roxmltree::{Document, Node};
+
+mod abr;
+mod anomaly;
+#[cfg(feature = "archive")]
+mod archive;
+mod bmff;
+mod catalog;
+mod checksums;
+mod container;
+#[cfg(all(feature = "hls", feature = "dash"))]
+mod convert;
+mod debug_http;
+mod dedupe;
+mod digests;
+#[cfg(any(feature = "hls", feature = "dash"))]
+mod discover;
+#[cfg(any(feature = "hls", feature = "dash"))]
+mod disk;
+mod encrypt;
+mod estimate;
+mod expect;
+mod hosts;
+#[cfg(feature = "hls")]
+mod interstitial;
+mod jobs;
+mod keyring;
+#[cfg(feature = "hls")]
+mod live;
+mod plan;
+#[cfg(any(feature = "hls", feature = "dash"))]
+mod priority;
+mod profile;
+#[cfg(any(feature = "hls", feature = "dash"))]
+mod protection;
+mod provenance;
+mod quota;
+mod race;
+#[cfg(feature = "hls")]
+mod renumber;
+mod report;
+mod reproducible;
+#[cfg(any(feature = "hls", feature = "dash"))]
+mod resources;
+mod sample;
+mod schedule;
+mod select;
+#[cfg(feature = "serve")]
+mod selftest;
+#[cfg(feature = "serve")]
+mod serve;
+mod signing;
+mod state;
+mod status;
+mod store;
+mod synthetic;
+mod tally;
+#[cfg(feature = "dash")]
+mod thumbnails;
+mod timeouts;
+mod units;
+mod unsupported;
+mod upload;
+mod user_agent;
+mod variants;
+mod verify;
+mod watch;
+
+#[cfg(any(feature = "hls", feature = "dash"))]
+pub use resources::{
+    DiscoveredResource, ResourceKind, ResourceStream, discover_resources, discover_resources_with,
+};
+#[cfg(feature = "hls")]
+pub use streamrip_core::rewrite_hls;
+pub use streamrip_core::{OriginPaths, PathMapper};
+
+/// Default for `--write-queue`.
+const DEFAULT_WRITE_QUEUE: usize = 16;
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Recursively mirror an HLS (.m3u8) or DASH (.mpd) stream for local hosting",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    args_override_self = true
+)]
+struct Args {
+    /// Starting manifest URL (master .m3u8 or .mpd)
+    #[arg(short, long, required = true)]
+    start_url: Option<String>,
+
+    /// Output directory to mirror into
+    #[arg(short, long, required = true)]
+    output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    options: MirrorOptions,
+
+    /// Load the options saved as `[NAME]` in the profiles file; options on
+    /// the command line override them
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Profiles file to load --profile from, instead of
+    /// `$XDG_CONFIG_HOME/streamrip/profiles`
+    #[arg(long, value_name = "FILE", requires = "profile")]
+    profiles: Option<PathBuf>,
+
+    /// How to report progress and diagnostics
+    #[arg(long, value_enum, global = true, default_value_t = report::ReportFormat::Human)]
+    report: report::ReportFormat,
+
+    /// Print every HTTP request and response with selected headers and
+    /// timing; credentials and token query parameters are redacted
+    #[arg(long, global = true)]
+    debug_http: bool,
+
+    /// Write sizes, durations and counts as plain numbers (bytes, seconds)
+    /// instead of `1.5 GiB`, `01:23:45` and `12,431`, for scripts parsing
+    /// the output
+    #[arg(long, global = true)]
+    porcelain: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Manifest format of the start URL.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum StreamFormat {
+    /// Detect from extension, Content-Type or content
+    #[default]
+    Auto,
+    /// HLS (.m3u8)
+    Hls,
+    /// DASH (.mpd)
+    Dash,
+}
+
+/// What a mirror is written as.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    /// A directory of files
+    #[default]
+    Dir,
+    /// A zstd-compressed tar file next to the output directory, which is
+    /// removed unless it existed before
+    #[value(name = "tar.zst")]
+    TarZst,
+}
+
+/// How to treat HLS media playlists regardless of their `#EXT-X-ENDLIST` state.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum PlaylistTreatment {
+    /// Live without `#EXT-X-ENDLIST`, VOD with it
+    #[default]
+    Auto,
+    /// Treat every media playlist as VOD (append `#EXT-X-ENDLIST` where missing)
+    Vod,
+    /// Treat every media playlist as live (keep refreshing despite `#EXT-X-ENDLIST`)
+    Live,
+}
+
+/// Options controlling a single mirroring run.
+#[derive(clap::Args, Debug, Clone, Default)]
+struct MirrorOptions {
+    /// Format of the start URL
+    #[arg(long, value_enum, default_value_t = StreamFormat::Auto)]
+    format: StreamFormat,
+
+    /// What to write the mirror as
+    #[arg(long, value_enum, default_value_t = OutputFormat::Dir, conflicts_with_all = ["estimate", "plan"])]
+    output_format: OutputFormat,
+
+    /// Only fetch manifests, HEAD every discovered resource and print the
+    /// projected download size per rendition; nothing is written to disk
+    #[arg(long)]
+    estimate: bool,
+
+    /// Only fetch manifests and list what mirroring would do to the output
+    /// directory: the files it would add, those it would replace and those
+    /// it would leave alone; nothing is written to disk
+    #[arg(long, conflicts_with = "estimate")]
+    plan: bool,
+
+    /// Mirror into an output directory holding a mirror of another start
+    /// URL; both are accepted there from then on
+    #[arg(long)]
+    force: bool,
+
+    /// Rewrite `status.json` in the output directory every few seconds with
+    /// the run's phase, file counts, bytes, rate and estimated time left
+    #[arg(long, conflicts_with_all = ["estimate", "plan"])]
+    status_file: bool,
+
+    /// Maximum number of concurrent requests
+    #[arg(long, default_value_t = 8)]
+    concurrency: usize,
+
+    /// Download segments the way a player would: renditions interleaved by
+    /// playback time, each segment requested when playback reaches it, to
+    /// reproduce realistic origin and CDN load (a VOD mirror takes as long
+    /// as the stream)
+    #[arg(long, conflicts_with_all = ["estimate", "plan"])]
+    playback_order: bool,
+
+    /// With --playback-order, switch renditions like an adaptive player: from
+    /// AT (e.g. 0, 30s, 01:30) on, only RENDITION (a media playlist URL or
+    /// Representation id, or part of one) is fetched of the renditions named;
+    /// the segments skipped are downloaded after playback
+    #[arg(long, value_name = "AT=RENDITION", value_parser = abr::parse_switch, requires = "playback_order")]
+    abr_switch: Vec<abr::Switch>,
+
+    /// Write what the switching player fetches, and when, to FILE as JSON
+    /// lines
+    #[arg(long, value_name = "FILE", requires = "abr_switch")]
+    abr_trace: Option<PathBuf>,
+
+    /// Maximum number of downloaded files waiting to be written; downloads
+    /// wait for the disk once that many are queued
+    #[arg(long, value_name = "FILES", default_value_t = DEFAULT_WRITE_QUEUE)]
+    write_queue: usize,
+
+    /// Keep refreshing live HLS media playlists and record new segments until
+    /// the stream ends or Ctrl-C is pressed, then finalize them as VOD
+    #[arg(long)]
+    live: bool,
+
+    /// Continue the archive playlists an interrupted live recording left in
+    /// the output directory instead of starting them afresh
+    #[arg(long)]
+    resume_recording: bool,
+
+    /// Override live/VOD detection for all media playlists, e.g. when an
+    /// origin has `#EXT-X-ENDLIST` on some renditions but not on others
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = PlaylistTreatment::Auto)]
+    treat_as: PlaylistTreatment,
+
+    /// While recording live, stop recording a rendition that stays behind
+    /// the live edge for this many refreshes in a row, so the others keep up
+    #[arg(long, value_name = "REFRESHES", value_parser = clap::value_parser!(u32).range(1..))]
+    drop_lagging: Option<u32>,
+
+    /// Stop live recording after this long (e.g. 90m, 1h30m, 01:30:00); implies --live
+    #[arg(long, value_name = "DURATION", value_parser = schedule::parse_duration)]
+    record_for: Option<Duration>,
+
+    /// Record live continuously, keeping only this much of each media
+    /// playlist (e.g. 6h): older segments are deleted and dropped from the
+    /// archive playlists; implies --live
+    #[arg(long, value_name = "DURATION", value_parser = schedule::parse_duration)]
+    retention: Option<Duration>,
+
+    /// Finalize a live recording into a new, independently playable mirror
+    /// every DURATION (e.g. 1h): `<output-dir>_000`, `<output-dir>_001`, ...;
+    /// implies --live
+    #[arg(long, value_name = "DURATION", value_parser = schedule::parse_duration)]
+    chunk_every: Option<Duration>,
+
+    /// While recording live, upload every finished segment and updated
+    /// playlist below this http(s) URL with PUT requests, so the remote copy
+    /// stays within a few segments of real time; implies --live
+    #[arg(long, value_name = "URL", value_parser = upload::parse_base)]
+    upload_to: Option<Url>,
+
+    /// Wait until this time before starting (RFC 3339 such as 2026-10-16T20:00:00Z, or Unix seconds)
+    #[arg(long, value_name = "TIMESTAMP", value_parser = schedule::parse_timestamp)]
+    start_at: Option<SystemTime>,
+
+    /// After mirroring, serve the mirror on a local port and check that every
+    /// manifest and file it references is served correctly
+    #[arg(long, conflicts_with_all = ["estimate", "plan"])]
+    self_test: bool,
+
+    /// Fail requests whose body arrives slower than RATE bytes per second
+    /// (e.g. 64k, 1.5M), allowing each its size at that rate on top of
+    /// --response-timeout; without it requests never time out
+    #[arg(long, value_name = "RATE", value_parser = timeouts::parse_throughput)]
+    min_throughput: Option<u64>,
+
+    /// Request files whose manifests name equivalent origins (several DASH
+    /// BaseURLs, HLS content steering pathways) from two of them at once
+    /// and download from the first to answer
+    #[arg(long)]
+    race_origins: bool,
+
+    /// Write which origin served each raced file to FILE as JSON lines
+    #[arg(long, value_name = "FILE", requires = "race_origins")]
+    race_trace: Option<PathBuf>,
+
+    /// With --min-throughput, how long a response may take to start [default: 10s]
+    #[arg(long, value_name = "DURATION", value_parser = schedule::parse_duration, requires = "min_throughput")]
+    response_timeout: Option<Duration>,
+
+    /// Download at most RATE bytes per second on average (e.g. 512k, 2M)
+    #[arg(long, value_name = "RATE", value_parser = timeouts::parse_throughput)]
+    max_rate: Option<u64>,
+
+    /// Fail rather than let the output directory grow beyond SIZE (e.g.
+    /// 500M, 20G), counting what it holds already
+    #[arg(long, value_name = "SIZE", value_parser = quota::parse_size)]
+    disk_quota: Option<u64>,
+
+    /// The share of a watcher's rate the run gets (see `watch --max-rate`).
+    #[arg(skip)]
+    slot: Option<quota::Slot>,
+
+    /// Leave segments that fail to download out of the rewritten HLS
+    /// playlists (adjusting their sequence numbers) instead of aborting
+    #[arg(long)]
+    skip_failed: bool,
+
+    /// Fail instead of warning about anything the mirror would lack:
+    /// unsupported manifest features, Representations whose segments can't
+    /// be enumerated, and DRM-protected renditions
+    #[arg(long, conflicts_with = "skip_failed")]
+    strict: bool,
+
+    /// Date downloaded files to when they were written rather than to the
+    /// origin's `Last-Modified` header
+    #[arg(long)]
+    no_preserve_mtime: bool,
+
+    /// Keep downloaded files once in DIR, named by their content, and link to
+    /// them from the mirror; mirrors sharing a store share identical files
+    #[arg(long, value_name = "DIR")]
+    segment_store: Option<PathBuf>,
+
+    /// List the SHA-256 of every file of the mirror in `SHA256SUMS` at the
+    /// end of the run, for `sha256sum -c`
+    #[arg(long, conflicts_with_all = ["estimate", "plan"])]
+    checksums: bool,
+
+    /// Query parameters to ignore when deciding whether two URLs name the same
+    /// file, e.g. `token,expires,sig` for signed URLs; a trailing `*` matches
+    /// a prefix (`X-Amz-*`)
+    #[arg(long, value_name = "NAMES", value_delimiter = ',')]
+    ignore_query_params: Vec<String>,
+
+    /// User-Agent to send: a preset (`safari-ios`, `chrome`, `exoplayer`) or
+    /// any literal string; defaults to `streamrip/<version>`
+    #[arg(long, value_name = "UA", value_parser = user_agent::parse)]
+    user_agent: Option<String>,
+
+    /// Fetch the stream once per header set in FILE (`[name]` sections of
+    /// `Header: value` lines) and mirror the union, each variant with its own
+    /// manifests
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["estimate", "plan", "live", "record_for", "retention", "chunk_every", "upload_to"])]
+    header_variants: Option<PathBuf>,
+
+    /// Also mirror URL, a manifest of the same content in the other format
+    /// (the MPD of an HLS start URL or the other way round), into the same
+    /// tree; segments both list are downloaded once, and files with the same
+    /// content are hard-linked
+    #[arg(long, value_name = "URL", conflicts_with_all = ["header_variants", "live", "record_for", "retention", "chunk_every", "upload_to"])]
+    companion_url: Option<Url>,
+
+    /// Fetch and rewrite every manifest but download no media segments;
+    /// keys, init segments and sidecars are still mirrored
+    #[arg(long, conflicts_with_all = ["estimate", "self_test"])]
+    manifests_only: bool,
+
+    /// Download the files referenced by the manifests already in the output
+    /// directory that are missing locally, without fetching any manifest
+    #[arg(long, conflicts_with_all = ["manifests_only", "live", "record_for", "retention", "chunk_every", "upload_to", "header_variants"])]
+    segments_only: bool,
+
+    /// Find the segments of DASH templates that have neither an endNumber nor
+    /// any duration by probing segment numbers with HEAD requests
+    #[arg(long)]
+    probe_segments: bool,
+
+    /// Write rewritten manifests with LF line endings and without a byte
+    /// order mark instead of keeping those of the originals
+    #[arg(long)]
+    normalize_manifests: bool,
+
+    /// Leave relative URIs in HLS playlists as they are when they already
+    /// lead to the mirrored file, so rewritten playlists differ from the
+    /// origin's as little as possible
+    #[arg(long)]
+    preserve_relative: bool,
+
+    /// Reference mirrored files in rewritten manifests by this root-relative
+    /// path or absolute URL (e.g. `/streams/event1/`) followed by their path
+    /// in the output directory, instead of by relative paths
+    #[arg(long, value_name = "BASE", value_parser = parse_rewrite_base, conflicts_with = "self_test")]
+    rewrite_base: Option<String>,
+
+    /// Compare the renditions, languages, codecs and duration the manifests
+    /// describe with the expectations in FILE (flat YAML or JSON) and fail
+    /// the run on any difference
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["header_variants", "segments_only"])]
+    expect: Option<PathBuf>,
+
+    /// Stamp rewritten manifests and `catalog.json` with the streamrip
+    /// version, source URL, time of the run and a hash of its options
+    #[arg(long)]
+    provenance: bool,
+
+    /// Make two runs against an unchanged origin produce identical output:
+    /// sort `catalog.json` and the state file, and date every file (and
+    /// provenance stamps) to `SOURCE_DATE_EPOCH`, or the Unix epoch
+    #[arg(long, conflicts_with_all = ["estimate", "plan"])]
+    reproducible: bool,
+
+    /// Encrypt every manifest, segment and catalog written as an age file to
+    /// the given recipients (`age:<recipient>[,<recipient>...]`)
+    #[arg(long, value_name = "SPEC", conflicts_with_all = ["estimate", "plan", "segments_only"])]
+    encrypt_output: Option<String>,
+
+    /// Sign `SHA256SUMS` with the (unencrypted) minisign secret key in KEY;
+    /// implies `--checksums`
+    #[arg(long, value_name = "KEY", conflicts_with_all = ["estimate", "plan"])]
+    sign: Option<PathBuf>,
+
+    #[command(flatten)]
+    filter: select::RenditionFilter,
+
+    #[command(flatten)]
+    size_check: anomaly::SizeCheck,
+
+    /// Once the segments are mirrored, download SHARE of them (e.g. 1%)
+    /// again, picked at random, and compare them with the local copies
+    #[arg(long, value_name = "SHARE", value_parser = sample::parse_share, conflicts_with_all = ["estimate", "plan", "manifests_only"])]
+    verify_sample: Option<f64>,
+
+    /// Use KEY, given as 32 hex digits, for the HLS key at URI, or with no
+    /// URI for the next key URI that can't be fetched; for keys the origin
+    /// only hands out after an entitlement check. Segments stay encrypted
+    /// (repeatable)
+    #[arg(long = "key", value_name = "[URI=]HEX", value_parser = keyring::parse_key)]
+    keys: Vec<keyring::SuppliedKey>,
+
+    /// Read keys as for --key from FILE, one per line
+    #[arg(long, value_name = "FILE")]
+    key_file: Option<PathBuf>,
+
+    #[command(flatten)]
+    master: synthetic::MasterOptions,
+}
+
+impl MirrorOptions {
+    /// Whether only manifests are fetched, to report on the run rather than
+    /// make it.
+    fn plans_only(&self) -> bool {
+        self.estimate || self.plan
+    }
+
+    fn is_live(&self) -> bool {
+        self.live
+            || self.record_for.is_some()
+            || self.retention.is_some()
+            || self.chunk_every.is_some()
+            || self.upload_to.is_some()
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Serve a mirrored directory over HTTP
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Directory to serve (usually a previous --output-dir)
+        #[arg(short, long, required_unless_present = "root", conflicts_with = "root")]
+        dir: Option<PathBuf>,
+
+        /// Serve every mirror below this directory under /assets/<name>/, with an index at /
+        #[arg(short, long)]
+        root: Option<PathBuf>,
+
+        /// Address to listen on
+        #[arg(short, long, default_value = "127.0.0.1:8080")]
+        bind: std::net::SocketAddr,
+
+        /// Require HTTP basic authentication with the given user:pass
+        #[arg(long, value_name = "USER:PASS", value_parser = serve::parse_basic_auth)]
+        auth: Option<String>,
+
+        /// Require a shared token, given as ?token=, a Bearer header or cookie
+        #[arg(long)]
+        token: Option<String>,
+
+        /// Decrypt files mirrored with --encrypt-output with the age
+        /// identities in FILE as they are served
+        #[arg(long, value_name = "FILE")]
+        decrypt_with: Option<PathBuf>,
+    },
+
+    /// Unpack an archive written with `--output-format tar.zst`
+    #[cfg(feature = "archive")]
+    Extract {
+        /// Archive to unpack
+        #[arg(short, long)]
+        archive: PathBuf,
+
+        /// Directory to unpack into; defaults to the archive's name without
+        /// its extension
+        #[arg(short, long)]
+        dir: Option<PathBuf>,
+    },
+
+    /// Experimental: write an MPD for an HLS mirror, or HLS playlists for a DASH mirror
+    ///
+    /// The new manifests refer to the mirrored files; only fMP4 (CMAF) HLS
+    /// segments and numbered DASH templates are converted.
+    #[cfg(all(feature = "hls", feature = "dash"))]
+    Convert {
+        /// The mirror's master playlist, media playlist or MPD
+        #[arg(short, long)]
+        manifest: PathBuf,
+
+        /// Where to write the converted manifest; defaults to the input
+        /// with the other format's extension
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Check that rewritten manifests differ from their `.orig` copies only in URIs
+    Verify {
+        /// Mirror directory to check
+        #[arg(short, long)]
+        dir: PathBuf,
+
+        /// Stop at the first failure instead of reporting every difference
+        #[arg(long)]
+        fail_fast: bool,
+
+        /// Check the signature of `SHA256SUMS` with the minisign public key
+        /// in FILE, and every file of the mirror against it
+        #[arg(long, value_name = "FILE")]
+        signature: Option<PathBuf>,
+    },
+
+    /// Watch a job folder or URL list and mirror each new entry as it appears
+    Watch {
+        /// Directory to poll for `*.job` files
+        #[arg(
+            short,
+            long,
+            required_unless_present = "list_url",
+            conflicts_with = "list_url"
+        )]
+        jobs: Option<PathBuf>,
+
+        /// URL of a plain-text list of start URLs to poll (one per line, optionally followed by a name)
+        #[arg(short, long)]
+        list_url: Option<String>,
+
+        /// Directory under which each job's mirror is created
+        #[arg(short, long)]
+        output_root: PathBuf,
+
+        /// Polling interval in seconds
+        #[arg(short, long, default_value_t = 10)]
+        interval: u64,
+
+        /// Run up to this many jobs at the same time
+        #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+        max_jobs: u32,
+
+        /// Download at most RATE bytes per second for all jobs together (e.g. 10M),
+        /// shared among the running jobs by their `bandwidth_share`
+        #[arg(long, value_name = "RATE", value_parser = timeouts::parse_throughput)]
+        max_rate: Option<u64>,
+
+        /// Serve the status of every job as JSON on this address, at /jobs
+        /// and /jobs/<id>
+        #[cfg(feature = "serve")]
+        #[arg(long, value_name = "ADDR")]
+        status_addr: Option<std::net::SocketAddr>,
+    },
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to complete for
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Print the man page (roff) to stdout
+    Man,
+}
+
+/// A binary resource discovered while planning, not yet downloaded.
+struct PlannedFile {
+    rendition: String,
+    url: Url,
+    /// Whether it is a segment encrypted as a whole (see [`Mirror::keys`]).
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    encrypted: bool,
+    /// Playback duration of the segment in seconds, if it is a media segment
+    /// with a known one.
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    duration: Option<f64>,
+}
+
+// Without any format feature the binary still builds (e.g. for `serve`), but
+// nothing reaches the download machinery.
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+struct Mirror {
+    client: Client,
+    out_dir: PathBuf,
+    visited: HashSet<Url>,
+    /// Where resources are kept below `out_dir`.
+    paths: Arc<dyn PathMapper>,
+    url_to_path: HashMap<Url, PathBuf>,
+    /// The paths handed out, by their lowercase form.
+    case_folded: HashMap<String, PathBuf>,
+    /// Media playlists without `#EXT-X-ENDLIST`, as (URL, local path).
+    #[cfg(feature = "hls")]
+    live_playlists: Vec<(Url, PathBuf)>,
+    /// Every media playlist seen, with whether it lacked `#EXT-X-ENDLIST`.
+    #[cfg(feature = "hls")]
+    playlist_states: Vec<(Url, bool)>,
+    #[cfg(feature = "hls")]
+    treat_as: PlaylistTreatment,
+    /// Stop recording a live rendition after this many lagging refreshes in a row.
+    #[cfg(feature = "hls")]
+    drop_lagging: Option<u32>,
+    /// Continue the archives of an interrupted recording.
+    #[cfg(feature = "hls")]
+    resume_recording: bool,
+    /// How much of each live media playlist to keep.
+    #[cfg(feature = "hls")]
+    retention: Option<Duration>,
+    /// How often to cut a live recording into a chunk of its own.
+    #[cfg(feature = "hls")]
+    chunk_every: Option<Duration>,
+    /// Where to upload a live recording while it is being made.
+    #[cfg(feature = "hls")]
+    upload_to: Option<Url>,
+    /// Last reported offset of the origin's clock from ours, in seconds.
+    #[cfg(feature = "hls")]
+    clock_skew: Option<f64>,
+    /// Master playlists as (local path, rewritten lines).
+    #[cfg(feature = "hls")]
+    master_playlists: Vec<(PathBuf, Vec<String>, playlist::Layout)>,
+    /// Keys supplied with `--key` and `--key-file`.
+    #[cfg(feature = "hls")]
+    keyring: keyring::Keyring,
+    /// Whether live playlists are being recorded rather than mirrored once.
+    live: bool,
+    /// When set, binary resources are only collected here instead of being
+    /// downloaded, and nothing is written to disk.
+    plan: Option<Vec<PlannedFile>>,
+    /// Files a planning pass would write.
+    planned_writes: Vec<PathBuf>,
+    /// Rendition (media playlist URL or DASH Representation id) that
+    /// currently discovered resources belong to.
+    rendition: String,
+    reporter: SharedReporter,
+    /// How long a just-published segment may keep returning 404 before it
+    /// counts as a failure; only set while processing a live playlist.
+    availability_window: Option<Duration>,
+    /// How long requests may take (`--min-throughput`).
+    timeouts: timeouts::Timeouts,
+    /// Equivalent origins to race binary downloads between.
+    race: race::Race,
+    /// Download rate and disk limits (`--max-rate`, `--disk-quota`).
+    limits: quota::Limits,
+    /// Which HLS variants and DASH Representations to mirror.
+    filter: select::RenditionFilter,
+    /// Facts collected for `catalog.json`.
+    catalog: catalog::Catalog,
+    /// Drop segments that fail to download instead of aborting.
+    skip_failed: bool,
+    /// Fail instead of warning about anything the mirror would lack.
+    strict: bool,
+    /// Date downloaded files to their `Last-Modified` header.
+    preserve_mtime: bool,
+    /// Where downloaded files are kept, by content, with `--segment-store`.
+    segment_store: Option<store::SegmentStore>,
+    /// Downloaded files waiting for the disk at most.
+    write_queue: usize,
+    /// SHA-256 of the files written, kept for `--checksums`.
+    digests: Option<digests::Digests>,
+    /// Identities of binary resources downloaded by previous runs into the
+    /// output directory.
+    fetched_before: HashSet<String>,
+    /// Where downloaded resources are recorded for later runs.
+    state: Option<state::StateFile>,
+    /// Query parameter names (`*` suffix for prefixes) ignored when deciding
+    /// whether two URLs are the same resource.
+    ignore_query_params: Vec<String>,
+    /// What happened to the resources of this run.
+    tally: tally::Tally,
+    /// Unsupported manifest features seen so far.
+    unsupported: unsupported::Unsupported,
+    /// Header variant being mirrored; its manifests get variant-specific paths.
+    variant: Option<String>,
+    /// Manifests mirrored for the current header variant.
+    variant_manifests: Vec<Url>,
+    /// Every resource referenced by the current header variant.
+    variant_resources: Option<Vec<String>>,
+    /// When set, keys and init segments are collected here.
+    #[cfg(any(feature = "hls", feature = "dash"))]
+    priority: Option<priority::PriorityFiles>,
+    /// Where the walk of a [`ResourceStream`] yields what it finds.
+    #[cfg(any(feature = "hls", feature = "dash"))]
+    resources: Option<tokio::sync::mpsc::Sender<Result<DiscoveredResource>>>,
+    /// Files downloaded ahead of the rewrite pass (keys and init segments
+    /// first, then everything else); their first reference there is not a
+    /// duplicate.
+    prefetched: HashSet<Url>,
+    /// Manifests fetched by the discovery pass, taken by the rewrite pass so
+    /// both work on the same text.
+    discovered: HashMap<Url, String>,
+    /// Files that failed to download ahead of the rewrite pass, with the
+    /// reason; the rewrite pass treats them as failing there.
+    failed: HashMap<Url, String>,
+    /// Leave media segments out, mirroring only manifests and the files
+    /// needed to make sense of them.
+    manifests_only: bool,
+    /// Read manifests from their local `.orig` copies instead of the origin
+    /// and only download files missing locally.
+    segments_only: bool,
+    /// Find the segment range of DASH templates without duration information
+    /// with HEAD requests instead of skipping them.
+    probe_segments: bool,
+    /// Write rewritten manifests with LF line endings and no byte order mark.
+    normalize_manifests: bool,
+    /// Keep relative URIs that already lead to the mirrored file.
+    preserve_relative: bool,
+    /// Public base path or URL of the output directory (`--rewrite-base`),
+    /// ending in a slash.
+    rewrite_base: Option<String>,
+    /// Media segments mirrored so far, with their rendition, for the size check.
+    segments: Vec<(String, Url)>,
+    /// Keys in effect for the segments currently referenced. Those encrypted
+    /// as a whole (`#EXT-X-KEY:METHOD=AES-128`) carry no container signature.
+    keys: playlist::Keys,
+    /// Playback duration of the media segment currently referenced
+    /// (`#EXTINF` or the template duration), for `--playback-order`.
+    segment_duration: Option<f64>,
+    /// Download planned segments in playback order and at playback pace.
+    playback_order: bool,
+    /// Renditions to switch between during playback (see [`abr`]).
+    abr_switches: Vec<abr::Switch>,
+    /// Where to write what the switching player fetches.
+    abr_trace: Option<PathBuf>,
+    /// When segments count as suspiciously small and what to do about them.
+    size_check: anomaly::SizeCheck,
+    /// What the manifests describe, for `--expect`.
+    observed: expect::Observed,
+}
+
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+impl Mirror {
+    fn new(out_dir: PathBuf, paths: Arc<dyn PathMapper>, reporter: SharedReporter) -> Self {
+        let client = user_agent::http_client(&user_agent::default_user_agent());
+
+        Self {
+            client,
+            out_dir,
+            visited: HashSet::new(),
+            paths,
+            url_to_path: HashMap::new(),
+            case_folded: HashMap::new(),
+            #[cfg(feature = "hls")]
+            live_playlists: Vec::new(),
+            #[cfg(feature = "hls")]
+            playlist_states: Vec::new(),
+            #[cfg(feature = "hls")]
+            treat_as: PlaylistTreatment::Auto,
+            #[cfg(feature = "hls")]
+            drop_lagging: None,
+            #[cfg(feature = "hls")]
+            resume_recording: false,
+            #[cfg(feature = "hls")]
+            retention: None,
+            #[cfg(feature = "hls")]
+            chunk_every: None,
+            #[cfg(feature = "hls")]
+            upload_to: None,
+            #[cfg(feature = "hls")]
+            clock_skew: None,
+            #[cfg(feature = "hls")]
+            master_playlists: Vec::new(),
+            #[cfg(feature = "hls")]
+            keyring: keyring::Keyring::default(),
+            live: false,
+            plan: None,
+            planned_writes: Vec::new(),
+            rendition: String::new(),
+            reporter,
+            availability_window: None,
+            timeouts: timeouts::Timeouts::default(),
+            race: race::Race::default(),
+            limits: quota::Limits::default(),
+            filter: select::RenditionFilter::default(),
+            catalog: catalog::Catalog::default(),
+            skip_failed: false,
+            strict: false,
+            preserve_mtime: true,
+            segment_store: None,
+            write_queue: DEFAULT_WRITE_QUEUE,
+            digests: None,
+            fetched_before: HashSet::new(),
+            state: None,
+            ignore_query_params: Vec::new(),
+            tally: tally::Tally::default(),
+            unsupported: unsupported::Unsupported::default(),
+            variant: None,
+            variant_manifests: Vec::new(),
+            variant_resources: None,
+            #[cfg(any(feature = "hls", feature = "dash"))]
+            priority: None,
+            #[cfg(any(feature = "hls", feature = "dash"))]
+            resources: None,
+            prefetched: HashSet::new(),
+            discovered: HashMap::new(),
+            failed: HashMap::new(),
+            manifests_only: false,
+            segments_only: false,
+            probe_segments: false,
+            normalize_manifests: false,
+            preserve_relative: false,
+            rewrite_base: None,
+            segments: Vec::new(),
+            keys: playlist::Keys::default(),
+            segment_duration: None,
+            playback_order: false,
+            abr_switches: Vec::new(),
+            abr_trace: None,
+            size_check: anomaly::SizeCheck::default(),
+            observed: expect::Observed::default(),
+        }
+    }
+
+    /// Decide the local path for a URL with the mirror's [`PathMapper`],
+    /// remembering it for later references.
+    fn path_for_url(&mut self, url: &Url, is_manifest: bool) -> PathBuf {
+        let url = &self.dedup_key(url);
+        if let Some(existing) = self.url_to_path.get(url) {
+            return existing.clone();
+        }
+
+        let mut local_path = self.out_dir.join(self.paths.path(url, is_manifest));
+        if is_manifest && let Some(variant) = &self.variant {
+            local_path = variants::variant_path(&local_path, variant);
+        }
+        let local_path = self.case_distinct(url, local_path);
+
+        self.url_to_path.insert(url.clone(), local_path.clone());
+        local_path
+    }
+
+    /// `path` for `url`, or if a path handed out before differs from it only
+    /// in case, as `Seg1.ts` and `seg1.TS` do, the first of `seg1~2.TS`,
+    /// `seg1~3.TS`, ... that doesn't. Case-insensitive file systems (macOS,
+    /// Windows) would take both for one file and keep only the last written.
+    fn case_distinct(&mut self, url: &Url, path: PathBuf) -> PathBuf {
+        let folded = |path: &std::path::Path| path.to_string_lossy().to_lowercase();
+        let taken = match self.case_folded.get(&folded(&path)) {
+            Some(taken) if *taken != path => taken.clone(),
+            _ => {
+                self.case_folded.insert(folded(&path), path.clone());
+                return path;
+            }
+        };
+
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, Some(ext)),
+            _ => (&*name, None),
+        };
+        let distinct = (2u32..)
+            .map(|n| {
+                path.with_file_name(match ext {
+                    Some(ext) => format!("{stem}~{n}.{ext}"),
+                    None => format!("{stem}~{n}"),
+                })
+            })
+            .find(|candidate| !self.case_folded.contains_key(&folded(candidate)))
+            .expect("some suffix is free");
+        self.reporter.report(Event::Warning {
+            message: format!(
+                "{} and {} differ only in case; writing {} to {}",
+                taken.display(),
+                path.display(),
+                url,
+                distinct.display()
+            ),
+        });
+        self.case_folded.insert(folded(&distinct), distinct.clone());
+        distinct
+    }
+
+    /// How a rewritten manifest in `from_dir` refers to the local file
+    /// `target`: by its relative path, or below `--rewrite-base`.
+    #[cfg(any(feature = "hls", feature = "dash"))]
+    fn reference(&self, target: &std::path::Path, from_dir: &std::path::Path) -> String {
+        match &self.rewrite_base {
+            Some(base) => format!("{}{}", base, relative_reference(target, &self.out_dir)),
+            None => relative_reference(target, from_dir),
+        }
+    }
+
+    /// How a rewritten manifest in `from_dir` refers to `target`, which the
+    /// manifest originally named `original`. With `--preserve-relative`, a
+    /// relative reference that already leads to `target` is kept as written.
+    #[cfg(feature = "hls")]
+    fn rewritten_reference(
+        &self,
+        original: &str,
+        target: &std::path::Path,
+        from_dir: &std::path::Path,
+    ) -> String {
+        if self.preserve_relative && resolves_to(original, from_dir, target) {
+            return original.to_string();
+        }
+        self.reference(target, from_dir)
+    }
+
+    /// Detect the stream format (unless forced) and delegate to the proper handler.
+    ///
+    /// Detection tries the URL's extension first, then the HTTP Content-Type,
+    /// and finally sniffs the start of the body, so extension-less URLs such as
+    /// `/live/stream` work too.
+    async fn mirror_root(&mut self, url: Url, format: StreamFormat) -> Result<()> {
+        let format = match format {
+            StreamFormat::Auto => self.detect_format(&url).await?,
+            forced => forced,
+        };
+
+        match format {
+            StreamFormat::Hls => {
+                #[cfg(feature = "hls")]
+                {
+                    self.mirror_manifest(url).await
+                }
+                #[cfg(not(feature = "hls"))]
+                {
+                    Err(anyhow!(
+                        "This is an HLS (m3u8) stream, but this build of streamrip lacks the `hls` feature. Rebuild with --features hls."
+                    ))
+                }
+            }
+            StreamFormat::Dash => {
+                #[cfg(feature = "dash")]
+                {
+                    self.mirror_mpd(url).await
+                }
+                #[cfg(not(feature = "dash"))]
+                {
+                    Err(anyhow!(
+                        "This is a DASH (mpd) stream, but this build of streamrip lacks the `dash` feature. Rebuild with --features dash."
+                    ))
+                }
+            }
+            StreamFormat::Auto => unreachable!("format detection never returns Auto"),
+        }
+    }
+
+    async fn detect_format(&mut self, url: &Url) -> Result<StreamFormat> {
+        let ext = url
+            .path()
+            .rsplit_once('.')
+            .map(|(_, e)| e.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        match ext.as_str() {
+            "m3u8" => return Ok(StreamFormat::Hls),
+            "mpd" => return Ok(StreamFormat::Dash),
+            _ => {}
+        }
+
+        if self.segments_only {
+            let path = orig_path(&self.path_for_url(url, true), "manifest");
+            let body = tokio::fs::read_to_string(&path)
+                .await
+                .with_context(|| format!("reading {} (for type detection)", path.display()))?;
+            return sniff_format(&body).ok_or_else(|| {
+                anyhow!(
+                    "Could not determine stream type from the content of {}; use --format to override",
+                    path.display()
+                )
+            });
+        }
+
+        let resp = self
+            .timeouts
+            .send(self.client.get(url.clone()), url)
+            .await
+            .context("for type detection")?
+            .error_for_status()
+            .with_context(|| format!("status error (for type detection) {}", url))?;
+
+        let ctype = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|s| s.to_ascii_lowercase());
+
+        if let Some(ref ct) = ctype {
+            // Common HLS types
+            if ct.starts_with("application/vnd.apple.mpegurl")
+                || ct.starts_with("application/x-mpegurl")
+                || ct.starts_with("audio/mpegurl")
+                || ct.starts_with("audio/x-mpegurl")
+            {
+                return Ok(StreamFormat::Hls);
+            } else if ct.starts_with("application/dash+xml") {
+                return Ok(StreamFormat::Dash);
+            }
+        }
+
+        // Neither extension nor Content-Type helped; look at the content.
+        // The real handler fetches the manifest again.
+        let body = self.timeouts.body(resp, url).await?;
+        if let Some(format) = sniff_format(&String::from_utf8_lossy(&body)) {
+            return Ok(format);
+        }
+
+        Err(anyhow!(
+            "Could not determine stream type from extension, Content-Type {:?} or content for {}; use --format to override",
+            ctype,
+            url
+        ))
+    }
+
+    /// [`Self::mirror_binary`] for a media segment lasting `duration` seconds.
+    #[cfg(any(feature = "hls", feature = "dash"))]
+    async fn mirror_segment(&mut self, url: Url, duration: Option<f64>) -> Result<()> {
+        self.segment_duration = duration;
+        let result = self.mirror_binary(url).await;
+        self.segment_duration = None;
+        result
+    }
+
+    async fn mirror_binary(&mut self, url: Url) -> Result<()> {
+        self.note_variant_resource(&url);
+        if let Some(reason) = self.failed.get(&self.dedup_key(&url)) {
+            return Err(anyhow!("{}", reason));
+        }
+        if !self.visited.insert(self.dedup_key(&url)) {
+            if !self.prefetched.remove(&self.dedup_key(&url)) {
+                self.tally.duplicates += 1;
+            }
+            return Ok(());
+        }
+
+        let local_path = self.path_for_url(&url, false);
+
+        // Downloaded by an earlier run; keep the local copy unless it's gone.
+        if (self.segments_only || self.fetched_before.contains(&self.identity(&url)))
+            && tokio::fs::try_exists(&local_path).await.unwrap_or(false)
+        {
+            if !self.catalog.has_container(&self.rendition)
+                && let Ok(data) = encrypt::read(&local_path).await
+            {
+                self.note_container(&local_path, &data);
+            }
+            self.tally.up_to_date += 1;
+            return Ok(());
+        }
+
+        #[cfg(any(feature = "hls", feature = "dash"))]
+        if self.plan.is_some() {
+            self.announce(&url, &local_path, false).await?;
+        }
+        if let Some(plan) = &mut self.plan {
+            self.planned_writes.push(local_path);
+            plan.push(PlannedFile {
+                rendition: self.rendition.clone(),
+                url,
+                encrypted: self.keys.whole_segments(),
+                duration: self.segment_duration,
+            });
+            return Ok(());
+        }
+
+        self.reporter.report(Event::Fetch {
+            kind: Resource::Binary,
+            url: url.as_str(),
+            path: &local_path,
+        });
+
+        let resp = match self.fetch_binary(&url).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                // Allow a later pass (e.g. the next live refresh) to try again.
+                self.visited.remove(&self.dedup_key(&url));
+                return Err(e);
+            }
+        };
+
+        let content_type = resp
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let modified = last_modified(&resp).filter(|_| self.preserve_mtime);
+        let bytes = self.timeouts.body(resp, &url).await?;
+        if let Some(reason) = container::Container::rejection(
+            content_type.as_deref(),
+            &local_path,
+            &bytes,
+            self.keys.whole_segments(),
+        ) {
+            self.visited.remove(&self.dedup_key(&url));
+            return Err(anyhow!("{} does not look like a segment: {}", url, reason));
+        }
+        self.note_container(&local_path, &bytes);
+        let mut digest = None;
+        if self.plan.is_none() {
+            self.limits.take(bytes.len() as u64).await?;
+            digest = write_download(
+                &local_path,
+                &bytes,
+                self.segment_store.as_ref(),
+                self.digests.is_some(),
+            )
+            .await?;
+        }
+        if let Some(modified) = modified {
+            set_modified(&local_path, modified)?;
+        }
+        if let (Some(digests), Some(digest)) = (&self.digests, digest) {
+            digests.record(&local_path, digest);
+        }
+        self.tally.fetched += 1;
+
+        let identity = self.identity(&url);
+        if let Some(state) = &mut self.state {
+            state.record(&identity).await?;
+        }
+        Ok(())
+    }
+
+    /// Report `message` as a warning, or fail with it under `--strict`.
+    fn warn_or_fail(&self, message: String) -> Result<()> {
+        if self.strict {
+            bail!("{} (--strict)", message);
+        }
+        self.reporter.report(Event::Warning { message });
+        Ok(())
+    }
+
+    /// Key under which a resource is remembered across runs.
+    fn identity(&self, url: &Url) -> String {
+        self.dedup_key(url).to_string()
+    }
+
+    /// `url` without its fragment and the query parameters that don't affect
+    /// the content (`--ignore-query-params`), so e.g. signed URLs differing
+    /// only in their token map to the same resource and local file.
+    fn dedup_key(&self, url: &Url) -> Url {
+        let mut key = url.clone();
+        key.set_fragment(None);
+        if self.ignore_query_params.is_empty() || url.query().is_none() {
+            return key;
+        }
+
+        let kept: Vec<(String, String)> =
+            url.query_pairs()
+                .filter(|(name, _)| {
+                    !self.ignore_query_params.iter().any(|pattern| {
+                        match pattern.strip_suffix('*') {
+                            Some(prefix) => name
+                                .to_ascii_lowercase()
+                                .starts_with(&prefix.to_ascii_lowercase()),
+                            None => name.eq_ignore_ascii_case(pattern),
+                        }
+                    })
+                })
+                .map(|(name, value)| (name.into_owned(), value.into_owned()))
+                .collect();
+
+        if kept.is_empty() {
+            key.set_query(None);
+        } else {
+            key.query_pairs_mut().clear().extend_pairs(kept);
+        }
+        key
+    }
+
+    /// Record the container of the current rendition from its first segment.
+    fn note_container(&mut self, path: &std::path::Path, data: &[u8]) {
+        if self.catalog.has_container(&self.rendition) {
+            return;
+        }
+        if let Some(container) =
+            container::Container::sniff(data).or_else(|| container::Container::from_extension(path))
+        {
+            self.catalog.add_container(&self.rendition, container);
+            self.reporter.report(Event::Detail {
+                message: format!("{} segments", container.name()),
+            });
+        }
+    }
+
+    /// Write `data` to `path`, creating parent directories as needed. While
+    /// only planning, `path` is noted instead.
+    async fn write_output(&mut self, path: &std::path::Path, data: &[u8]) -> Result<()> {
+        if self.plan.is_some() {
+            self.planned_writes.push(path.to_path_buf());
+            return Ok(());
+        }
+        let digest = write_file_hashed(path, data, self.digests.is_some()).await?;
+        if let (Some(digests), Some(digest)) = (&self.digests, digest) {
+            digests.record(path, digest);
+        }
+        Ok(())
+    }
+
+    /// GET a manifest's text, or read it from `orig_path` with
+    /// `--segments-only`. While planning, the text is kept for the rewrite
+    /// pass, which takes it instead of fetching the manifest again.
+    async fn fetch_manifest(&mut self, url: &Url, orig_path: &std::path::Path) -> Result<String> {
+        let key = self.dedup_key(url);
+        if let Some(text) = self.discovered.remove(&key) {
+            return Ok(text);
+        }
+
+        if self.segments_only {
+            return tokio::fs::read_to_string(orig_path).await.with_context(|| {
+                format!(
+                    "reading {} (the local copy of {}); mirror the manifests first",
+                    orig_path.display(),
+                    url
+                )
+            });
+        }
+
+        // Manifests are UTF-8; decoding them here rather than with `text()`
+        // keeps a byte order mark for the rewritten copy.
+        let resp = self
+            .timeouts
+            .send(self.client.get(url.clone()), url)
+            .await?
+            .error_for_status()
+            .with_context(|| format!("status error for {}", url))?;
+        let body = self.timeouts.body(resp, url).await?;
+        let text = String::from_utf8_lossy(&body).into_owned();
+
+        if self.plan.is_some() {
+            self.discovered.insert(key, text.clone());
+        }
+        Ok(text)
+    }
+
+    /// GET a binary resource, retrying 404s within the availability window
+    /// of the live playlist being processed, if any.
+    async fn fetch_binary(&self, url: &Url) -> Result<reqwest::Response> {
+        get_binary(
+            &self.client,
+            url,
+            self.availability_window,
+            self.timeouts,
+            &self.race,
+            &self.reporter,
+        )
+        .await
+    }
+
+    /// Mirror an HLS manifest (.m3u8), rewriting all URIs to local relative paths.
+    #[cfg(feature = "hls")]
+    #[async_recursion::async_recursion]
+    async fn mirror_manifest(&mut self, url: Url) -> Result<()> {
+        self.note_variant_resource(&url);
+        if !self.visited.insert(self.dedup_key(&url)) {
+            self.tally.duplicates += 1;
+            return Ok(());
+        }
+        if self.variant.is_some() {
+            self.variant_manifests.push(self.dedup_key(&url));
+        }
+
+        let local_path = self.path_for_url(&url, true);
+
+        self.reporter.report(Event::Fetch {
+            kind: Resource::Playlist,
+            url: url.as_str(),
+            path: &local_path,
+        });
+
+        let orig_path = orig_path(&local_path, "manifest.m3u8");
+        let text = self.fetch_manifest(&url, &orig_path).await?;
+
+        // Quick check that it's an HLS manifest.
+        let Some(playlist) = playlist::HlsPlaylist::parse(&text) else {
+            self.reporter.report(Event::Detail {
+                message: "not an HLS manifest, saving as binary".to_string(),
+            });
+            self.visited.remove(&self.dedup_key(&url));
+            return self.mirror_binary(url).await;
+        };
+        if !self.segments_only {
+            self.tally.fetched += 1;
+        }
+        self.announce(&url, &local_path, true).await?;
+
+        // Save original manifest next to rewritten one
+        if !self.segments_only {
+            self.write_output(&orig_path, text.as_bytes()).await?;
+        }
+
+        if let playlist::HlsPlaylist::Master(master) = &playlist {
+            self.race.add_pathways(&url, master, &self.reporter);
+        }
+        let playlist = match playlist {
+            playlist::HlsPlaylist::Master(master) if self.filter.is_active() => {
+                let had_variants = master.variants.iter().any(|v| !v.i_frame);
+                let (filtered, removed, fallback) =
+                    self.filter.filter_master_playlist(&url, master);
+                if had_variants && filtered.variants.iter().all(|v| v.i_frame) {
+                    self.reporter.report(Event::Warning {
+                        message: format!(
+                            "every variant of {} is excluded by the rendition filter",
+                            url
+                        ),
+                    });
+                }
+                if fallback {
+                    self.reporter.report(Event::Warning {
+                        message: format!(
+                            "no variant of {} matches the rendition filter, keeping the lowest bandwidth",
+                            url
+                        ),
+                    });
+                }
+                self.tally.filtered += removed;
+                if removed > 0 {
+                    self.reporter.report(Event::Detail {
+                        message: format!(
+                            "skipping {} rendition(s) excluded by the filter",
+                            removed
+                        ),
+                    });
+                }
+                playlist::HlsPlaylist::Master(filtered)
+            }
+            playlist => playlist,
+        };
+
+        let local_dir = local_path
+            .parent()
+            .ok_or_else(|| anyhow!("manifest path has no parent: {}", local_path.display()))?
+            .to_path_buf();
+
+        let is_master = matches!(playlist, playlist::HlsPlaylist::Master(_));
+        // A playlist referenced as a variant may itself be a master; it is
+        // rewritten the same way and its variants are followed in turn.
+        if is_master && !self.rendition.is_empty() {
+            self.reporter.report(Event::Detail {
+                message: "nested master playlist, following its variants".to_string(),
+            });
+        }
+
+        let media = match &playlist {
+            playlist::HlsPlaylist::Media(media) if media.is_media() => Some(media),
+            _ => None,
+        };
+        if let Some(media) = media {
+            self.playlist_states.push((url.clone(), !media.ended));
+        }
+        self.observe_playlist(&url, &playlist);
+
+        let is_live = media.is_some_and(|media| match self.treat_as {
+            PlaylistTreatment::Auto => !media.ended,
+            PlaylistTreatment::Vod => false,
+            PlaylistTreatment::Live => true,
+        });
+        if is_live {
+            self.live_playlists.push((url.clone(), local_path.clone()));
+        }
+
+        let previous_window = self.availability_window;
+        if is_live
+            && self.live
+            && let Some(media) = media
+        {
+            self.availability_window = Some(live::availability_window(media));
+        }
+        let previous_rendition = std::mem::replace(&mut self.rendition, url.to_string());
+        let output_lines = self
+            .rewrite_manifest(&url, playlist.lines(), &local_dir)
+            .await;
+        self.availability_window = previous_window;
+        self.rendition = previous_rendition;
+        let mut output_lines = output_lines?;
+
+        if media.is_some_and(|media| !media.ended) && self.treat_as == PlaylistTreatment::Vod {
+            output_lines.push("#EXT-X-ENDLIST".to_string());
+        }
+        if let Some(provenance) = &self.catalog.provenance
+            && !output_lines.is_empty()
+        {
+            output_lines.insert(1, provenance.hls_comment(&url));
+        }
+
+        // Rewritten manifest (this is the one you actually serve)
+        let layout = if self.normalize_manifests {
+            playlist::Layout::default()
+        } else {
+            playlist::Layout::of(&text)
+        };
+        if is_master {
+            self.master_playlists
+                .push((local_path.clone(), output_lines.clone(), layout));
+        }
+        if self.plan.is_some() {
+            self.planned_writes.push(local_path);
+            return Ok(());
+        }
+        write_playlist(&local_path, layout, &output_lines).await
+    }
+
+    /// Warn when some media playlists are live and others are VOD, which
+    /// usually indicates a broken origin.
+    #[cfg(feature = "hls")]
+    fn report_mixed_playlist_states(&self) {
+        let live: Vec<&str> = self
+            .playlist_states
+            .iter()
+            .filter(|(_, live)| *live)
+            .map(|(url, _)| url.as_str())
+            .collect();
+        let vod: Vec<&str> = self
+            .playlist_states
+            .iter()
+            .filter(|(_, live)| !*live)
+            .map(|(url, _)| url.as_str())
+            .collect();
+
+        if live.is_empty() || vod.is_empty() {
+            return;
+        }
+
+        self.reporter.report(Event::Warning {
+            message: format!(
+                "renditions disagree on live/VOD state: {} live ({}), {} VOD ({}); use --treat-as to override",
+                live.len(),
+                live.join(", "),
+                vod.len(),
+                vod.join(", ")
+            ),
+        });
+    }
+
+    /// Surface an `#EXT-X-SESSION-DATA` entry: its inline `VALUE`, or the
+    /// local copy of the sidecar referenced by `URI`.
+    #[cfg(feature = "hls")]
+    fn report_session_data(&self, tag: playlist::Tag<'_>, local: Option<&std::path::Path>) {
+        let id = tag.attribute("DATA-ID").unwrap_or_default();
+        let language = tag
+            .attribute("LANGUAGE")
+            .map(|l| format!(" [{l}]"))
+            .unwrap_or_default();
+
+        let payload = match (tag.attribute("VALUE"), local) {
+            (Some(value), _) => format!("= {value}"),
+            (None, Some(path)) => match std::fs::read(path)
+                .map_err(anyhow::Error::from)
+                .and_then(encrypt::open)
+            {
+                Ok(body) => format!("= {}", String::from_utf8_lossy(&body).trim()),
+                Err(_) => format!("-> {}", path.display()),
+            },
+            (None, None) => "(no value)".to_string(),
+        };
+
+        self.reporter.report(Event::Status {
+            tag: "DATA",
+            message: format!("{id}{language} {payload}"),
+        });
+    }
+
+    /// Mirror every URI referenced by an HLS manifest and return its lines
+    /// rewritten to paths relative to `local_dir`.
+    #[cfg(feature = "hls")]
+    async fn rewrite_manifest(
+        &mut self,
+        url: &Url,
+        lines: &[playlist::Line<'_>],
+        local_dir: &std::path::Path,
+    ) -> Result<Vec<String>> {
+        let mut output_lines = Vec::with_capacity(lines.len());
+        // The URI line following #EXT-X-STREAM-INF names a playlist, whatever
+        // its extension.
+        let mut variant_pending = false;
+        let mut renumbering = renumber::Renumbering::default();
+        let previous_keys = std::mem::take(&mut self.keys);
+        // From the #EXTINF of the segment URI that follows.
+        let mut segment_duration = None;
+
+        for line in lines {
+            let (line, uri_val) = match *line {
+                playlist::Line::Tag(tag) if tag.is_comment() => {
+                    output_lines.push(tag.line.to_string());
+                    continue;
+                }
+                playlist::Line::Tag(tag) => {
+                    if tag.is("#EXT-X-STREAM-INF") {
+                        variant_pending = true;
+                    }
+                    if tag.is("#EXTINF") {
+                        segment_duration = tag.duration();
+                    }
+                    if tag.is("#EXT-X-MEDIA-SEQUENCE") {
+                        renumbering.media_sequence(tag.value().parse().unwrap_or(0));
+                    }
+                    if let Some(feature) = unsupported::hls_tag(tag.name)
+                        && let Some(message) = self.unsupported.note(feature, url.as_str())
+                    {
+                        self.warn_or_fail(message)?;
+                    }
+                    let new_line = self.rewrite_tag(url, tag, local_dir).await?;
+                    output_lines.push(new_line);
+                    continue;
+                }
+                playlist::Line::Blank(blank) => {
+                    output_lines.push(blank.to_string());
+                    continue;
+                }
+                playlist::Line::Uri { line, uri } => (line, uri),
+            };
+            let duration = segment_duration.take();
+
+            let child_url = rewrite::resolve(url, uri_val)?;
+
+            let is_variant = std::mem::take(&mut variant_pending);
+            if !is_fetchable(&child_url) {
+                output_lines.push(line.to_string());
+                renumbering.keep(&mut output_lines, &self.keys);
+                continue;
+            }
+
+            let is_manifest = is_variant || rewrite::is_playlist_url(&child_url);
+
+            if is_manifest {
+                self.mirror_manifest(child_url.clone()).await?;
+            } else if self.manifests_only {
+                self.tally.skipped += 1;
+            } else if let Err(e) = self.mirror_segment(child_url.clone(), duration).await {
+                // Live recordings retry failed segments on the next reload
+                // instead, as they are numbered by their media sequence.
+                if !self.skip_failed || self.availability_window.is_some() {
+                    return Err(e);
+                }
+                self.reporter.report(Event::Warning {
+                    message: format!("dropping segment {}: {:#}", child_url, e),
+                });
+                renumbering.drop_segment(&mut output_lines);
+                self.tally.failed += 1;
+                continue;
+            }
+            if !is_manifest && !self.manifests_only {
+                self.note_segment(&child_url);
+            }
+
+            let target_path = self.path_for_url(&child_url, is_manifest);
+            let rel = self.rewritten_reference(uri_val, &target_path, local_dir);
+            output_lines.push(playlist::Line::with_uri(line, &rel));
+            renumbering.keep(&mut output_lines, &self.keys);
+        }
+
+        if renumbering.dropped() > 0 {
+            renumbering.finish(&mut output_lines);
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "{} segment(s) of {} dropped, sequence numbers adjusted",
+                    renumbering.dropped(),
+                    url
+                ),
+            });
+        }
+
+        self.keys = previous_keys;
+        Ok(output_lines)
+    }
+
+    /// Warn about keys supplied with `--key` or `--key-file` that no key
+    /// URI was given; under `--strict`, fail.
+    #[cfg(feature = "hls")]
+    fn report_unused_keys(&self) -> Result<()> {
+        let unused = self.keyring.unused();
+        if unused.is_empty() {
+            return Ok(());
+        }
+        self.warn_or_fail(format!("supplied keys not used: {}", unused.join(", ")))
+    }
+
+    /// Where the key supplied for `url`, the `number`th key URI given one,
+    /// is kept: where the origin's would be, or in `keys/` for key URIs
+    /// that can't be mirrored.
+    #[cfg(feature = "hls")]
+    fn supplied_key_path(&mut self, url: &Url, number: usize) -> PathBuf {
+        if is_fetchable(url) {
+            self.path_for_url(url, false)
+        } else {
+            self.out_dir.join("keys").join(format!("{}.key", number))
+        }
+    }
+
+    /// Write the key `assigned` to `url` the first time it comes up, and
+    /// return where it is kept.
+    #[cfg(feature = "hls")]
+    async fn write_supplied_key(
+        &mut self,
+        url: &Url,
+        assigned: &keyring::Assigned,
+    ) -> Result<PathBuf> {
+        let target_path = self.supplied_key_path(url, assigned.number);
+        if assigned.first {
+            self.reporter.report(Event::Detail {
+                message: format!("using the key supplied for {}", url),
+            });
+            self.write_output(&target_path, &assigned.key).await?;
+        }
+        Ok(target_path)
+    }
+
+    /// Mirror what a tag's URI attributes (KEY, MEDIA, I-FRAME-STREAM-INF,
+    /// SESSION-KEY, SESSION-DATA, etc.) and interstitial DATERANGEs reference
+    /// and return the tag rewritten to paths relative to `local_dir`.
+    #[cfg(feature = "hls")]
+    async fn rewrite_tag(
+        &mut self,
+        url: &Url,
+        tag: playlist::Tag<'_>,
+        local_dir: &std::path::Path,
+    ) -> Result<String> {
+        if (tag.is("#EXT-X-KEY") || tag.is("#EXT-X-SESSION-KEY"))
+            && let Some(protection) = protection::hls_key_protection(tag.line, &self.rendition)
+        {
+            self.record_protection(protection)?;
+        }
+
+        // Every URI attribute is rewritten in place; the rest of the line is
+        // copied as it is.
+        let mut new_line = String::with_capacity(tag.line.len());
+        let mut copied = 0;
+        let mut session_data_path = None;
+        for (attr, (start, end)) in playlist::attribute_spans(tag.line) {
+            if !playlist::is_uri_attribute(attr) {
+                continue;
+            }
+            let uri_val = &tag.line[start..end];
+            let child_url = rewrite::resolve(url, uri_val)?;
+
+            let mut assigned = self.keyring.key_for(&tag, uri_val, &child_url);
+            if assigned.is_none() && !is_fetchable(&child_url) {
+                assigned = self.keyring.next_key_for(&tag, &child_url);
+            }
+            if let Some(assigned) = assigned {
+                let target_path = self.write_supplied_key(&child_url, &assigned).await?;
+                let rel = self.rewritten_reference(uri_val, &target_path, local_dir);
+                new_line.push_str(&tag.line[copied..start]);
+                new_line.push_str(&rel);
+                copied = end;
+                continue;
+            }
+
+            // Key server schemes such as skd:// (FairPlay) or inline
+            // data: URIs cannot be mirrored; keep them as they are.
+            if !is_fetchable(&child_url) {
+                continue;
+            }
+
+            let is_manifest = rewrite::names_playlist(tag, attr, &child_url);
+
+            if attr == "X-ASSET-LIST" {
+                self.mirror_asset_list(child_url.clone()).await?;
+            } else if is_manifest {
+                self.mirror_manifest(child_url.clone()).await?;
+            } else {
+                let is_key = tag.is("#EXT-X-KEY") || tag.is("#EXT-X-SESSION-KEY");
+                if is_key {
+                    self.note_priority(priority::Priority::Key, &child_url);
+                } else if tag.is("#EXT-X-MAP") {
+                    self.note_priority(priority::Priority::Init, &child_url);
+                }
+                if let Err(e) = self.mirror_binary(child_url.clone()).await {
+                    // A key the origin won't hand out takes the next one
+                    // given by order, if any.
+                    let Some(assigned) = is_key
+                        .then(|| self.keyring.next_key_for(&tag, &child_url))
+                        .flatten()
+                    else {
+                        return Err(e);
+                    };
+                    self.reporter.report(Event::Detail {
+                        message: format!("{:#}; using the next key given by order", e),
+                    });
+                    let target_path = self.write_supplied_key(&child_url, &assigned).await?;
+                    let rel = self.rewritten_reference(uri_val, &target_path, local_dir);
+                    new_line.push_str(&tag.line[copied..start]);
+                    new_line.push_str(&rel);
+                    copied = end;
+                    continue;
+                }
+            }
+
+            if tag.is("#EXT-X-MAP") {
+                self.scan_init_segment(&child_url).await?;
+            }
+
+            let target_path = self.path_for_url(&child_url, is_manifest);
+            let rel = self.rewritten_reference(uri_val, &target_path, local_dir);
+            new_line.push_str(&tag.line[copied..start]);
+            new_line.push_str(&rel);
+            copied = end;
+
+            if attr == "URI" {
+                session_data_path = Some(target_path);
+            }
+        }
+        new_line.push_str(&tag.line[copied..]);
+        // Keys are kept as rewritten, to be repeated with an explicit IV.
+        if let playlist::Line::Tag(rewritten) = playlist::Line::parse(&new_line) {
+            self.keys.apply(&rewritten);
+        }
+
+        if tag.is("#EXT-X-SESSION-DATA") {
+            self.report_session_data(tag, session_data_path.as_deref());
+        }
+        Ok(new_line)
+    }
+
+    // ===== DASH (.mpd) support =====
+
+    /// Mirror a DASH MPD: save MPD as-is, but download all referenced segments / sidecars.
+    #[cfg(feature = "dash")]
+    async fn mirror_mpd(&mut self, url: Url) -> Result<()> {
+        self.note_variant_resource(&url);
+        if !self.visited.insert(self.dedup_key(&url)) {
+            self.tally.duplicates += 1;
+            return Ok(());
+        }
+        if self.variant.is_some() {
+            self.variant_manifests.push(self.dedup_key(&url));
+        }
+
+        let local_path = self.path_for_url(&url, true);
+
+        self.reporter.report(Event::Fetch {
+            kind: Resource::Mpd,
+            url: url.as_str(),
+            path: &local_path,
+        });
+
+        let orig_path = orig_path(&local_path, "manifest.mpd");
+        let text = self.fetch_manifest(&url, &orig_path).await?;
+
+        // Save original
+        if !self.segments_only {
+            self.write_output(&orig_path, text.as_bytes()).await?;
+        }
+
+        // Parse MPD and discover segments
+        let doc = Document::parse(&text)?;
+        let Some(mpd) = mpd::MpdModel::parse(&doc) else {
+            self.reporter.report(Event::Detail {
+                message: "not an MPD root element, treating as binary".to_string(),
+            });
+            self.visited.remove(&self.dedup_key(&url));
+            return self.mirror_binary(url).await;
+        };
+        if !self.segments_only {
+            self.tally.fetched += 1;
+        }
+        self.announce(&url, &local_path, true).await?;
+        for (attribute, value) in &mpd.unparsed_durations {
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "ignoring {}=\"{}\" of {}: not an ISO 8601 duration",
+                    attribute, value, url
+                ),
+            });
+        }
+
+        // Byte ranges of Representations excluded by the filter; the
+        // rewritten MPD is otherwise identical to the original.
+        let mut excluded = Vec::new();
+
+        let mpd_url = url.clone();
+        self.rendition = url.to_string();
+        let dynamic = doc.root_element().attribute("type") == Some("dynamic");
+        self.observe_mpd(&url, dynamic, &mpd);
+        for node in doc.descendants().filter(|n| n.is_element()) {
+            if let Some(feature) = unsupported::dash_element(node.tag_name().name())
+                && let Some(message) = self.unsupported.note(feature, url.as_str())
+            {
+                self.warn_or_fail(message)?;
+            }
+        }
+
+        // Walk: MPD -> Period -> AdaptationSet -> Representation
+        for period in &mpd.periods {
+            // Period BaseURL (e.g. "dash/")
+            let period_base = mpd::join_base_url(&mpd_url, period.base_url, "Period")?;
+            self.race_base_urls(&mpd_url, &period_base, &period.other_base_urls);
+
+            let kept_sets = if self.filter.is_active() {
+                let candidates: Vec<select::AdaptationSetCandidate> = period
+                    .adaptation_sets
+                    .iter()
+                    .map(|aset| select::AdaptationSetCandidate {
+                        id: aset.id,
+                        content_type: aset.content_type,
+                    })
+                    .collect();
+                self.filter.select_adaptation_sets(&candidates)
+            } else {
+                vec![true; period.adaptation_sets.len()]
+            };
+
+            for (aset, kept) in period.adaptation_sets.iter().zip(kept_sets) {
+                let keep = if !kept {
+                    vec![false; aset.representations.len()]
+                } else if self.filter.is_active() {
+                    let stable_ids: Vec<Option<String>> = aset
+                        .representations
+                        .iter()
+                        .map(|rep| rep.id.map(select::rendition_id))
+                        .collect();
+                    let candidates: Vec<select::Candidate> = aset
+                        .representations
+                        .iter()
+                        .zip(&stable_ids)
+                        .map(|(rep, stable_id)| select::Candidate {
+                            id: rep.id,
+                            stable_id: stable_id.as_deref(),
+                            height: rep.height,
+                            bandwidth: rep.bandwidth,
+                            excluded: self.filter.is_excluded(rep.id),
+                        })
+                        .collect();
+                    let (keep, fallback) = self.filter.select(&candidates);
+                    if fallback {
+                        self.reporter.report(Event::Warning {
+                            message: format!(
+                                "no Representation of AdaptationSet {} matches the rendition filter, keeping the lowest bandwidth",
+                                aset.id.unwrap_or("(without id)")
+                            ),
+                        });
+                    }
+                    keep
+                } else {
+                    vec![true; aset.representations.len()]
+                };
+                // An AdaptationSet without Representations is invalid.
+                if !kept || !keep.is_empty() && !keep.contains(&true) {
+                    self.reporter.report(Event::Detail {
+                        message: format!(
+                            "skipping AdaptationSet {} excluded by the filter",
+                            aset.id.unwrap_or("(without id)")
+                        ),
+                    });
+                    excluded.push(element_line_range(&text, aset.node.range()));
+                    self.tally.filtered += aset.representations.len();
+                    continue;
+                }
+
+                // AdaptationSet BaseURL overrides Period BaseURL if present
+                let aset_base = mpd::join_base_url(&period_base, aset.base_url, "AdaptationSet")?;
+                self.race_base_urls(&period_base, &aset_base, &aset.other_base_urls);
+
+                for (rep, kept) in aset.representations.iter().zip(keep) {
+                    if !kept {
+                        self.reporter.report(Event::Detail {
+                            message: format!(
+                                "skipping Representation {} excluded by the filter",
+                                rep.id.unwrap_or("(without id)")
+                            ),
+                        });
+                        excluded.push(element_line_range(&text, rep.node.range()));
+                        self.tally.filtered += 1;
+                        continue;
+                    }
+
+                    self.observe_representation(aset, rep);
+                    let rep_id = match rep.id {
+                        Some(id) => id.to_string(),
+                        None => {
+                            if self.strict {
+                                bail!("a Representation in {} has no id (--strict)", url);
+                            }
+                            continue;
+                        }
+                    };
+
+                    // Representation BaseURL overrides AdaptationSet BaseURL if present
+                    let rep_base = mpd::join_base_url(&aset_base, rep.base_url, "Representation")?;
+                    let rep_base_is_file = if let Some(b) = rep.base_url {
+                        match mpd::base_url_kind(b, rep.template.is_some()) {
+                            mpd::BaseUrlKind::Directory => false,
+                            mpd::BaseUrlKind::File => true,
+                            mpd::BaseUrlKind::UnterminatedDirectory => {
+                                // Players resolve template paths next to
+                                // such a BaseURL, not below it, and so do we.
+                                self.reporter.report(Event::Detail {
+                                    message: format!(
+                                        "BaseURL '{}' of {} lacks a trailing slash, segments resolve next to it",
+                                        b, rep_id
+                                    ),
+                                });
+                                false
+                            }
+                        }
+                    } else {
+                        false
+                    };
+                    self.race_base_urls(&aset_base, &rep_base, &rep.other_base_urls);
+
+                    self.rendition = rep_id.clone();
+
+                    let protections = protection::content_protection(&aset.node, &rep_id)
+                        .into_iter()
+                        .chain(protection::content_protection(&rep.node, &rep_id));
+                    for protection in protections {
+                        self.record_protection(protection)?;
+                    }
+
+                    // A Period-level template typically names one
+                    // initialization segment shared by every Representation
+                    if let Some(st) = &rep.template {
+                        let media = self
+                            .handle_segment_template(&rep_base, &rep_id, st, period.duration_secs)
+                            .await?;
+
+                        if thumbnails::is_image(&aset.node) || thumbnails::is_image(&rep.node) {
+                            self.index_thumbnails(&aset.node, &rep.node, st, media, &local_path)
+                                .await?;
+                        }
+                    }
+
+                    // If there was a Representation BaseURL that looks like a file
+                    // (e.g. "textstream_eng=1000.webvtt"), download it.
+                    if rep_base_is_file {
+                        if self.manifests_only {
+                            self.tally.skipped += 1;
+                        } else {
+                            self.mirror_binary(rep_base.clone()).await?;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut rewritten = String::with_capacity(text.len());
+        let mut copied = 0;
+        for range in excluded {
+            rewritten.push_str(&text[copied..range.start]);
+            copied = range.end;
+        }
+        rewritten.push_str(&text[copied..]);
+        if self.normalize_manifests {
+            rewritten = rewritten
+                .trim_start_matches('\u{feff}')
+                .replace("\r\n", "\n");
+        }
+        if let Some(provenance) = &self.catalog.provenance {
+            rewritten = provenance.stamp_xml(&rewritten, &url);
+        }
+        self.write_output(&local_path, rewritten.as_bytes()).await
+    }
+
+    /// Write the thumbnail index of an image Representation next to the MPD.
+    #[cfg(feature = "dash")]
+    async fn index_thumbnails(
+        &mut self,
+        aset: &Node<'_, '_>,
+        rep: &Node<'_, '_>,
+        st: &template::SegmentTemplate<'_, '_>,
+        urls: Vec<Url>,
+        mpd_path: &std::path::Path,
+    ) -> Result<()> {
+        let representation = rep.attribute("id").unwrap_or_default().to_string();
+        let timescale = st
+            .attribute("timescale")
+            .and_then(|v| v.parse::<f64>().ok())
+            .unwrap_or(1.0);
+        let segment_secs = st
+            .attribute("duration")
+            .and_then(|v| v.parse::<f64>().ok())
+            .map(|d| d / timescale)
+            .filter(|secs| secs.is_finite() && *secs > 0.0);
+        let width = rep.attribute("width").and_then(|v| v.parse().ok());
+        let height = rep.attribute("height").and_then(|v| v.parse().ok());
+
+        let (Some(segment_secs), Some(width), Some(height)) = (segment_secs, width, height) else {
+            self.reporter.report(Event::Detail {
+                message: format!(
+                    "no thumbnail index for {} (needs a template duration, width and height)",
+                    representation
+                ),
+            });
+            return Ok(());
+        };
+
+        let (columns, rows) = thumbnails::tiling(aset, rep);
+        let track = thumbnails::ThumbnailTrack {
+            representation,
+            columns,
+            rows,
+            width,
+            height,
+            segment_secs,
+            urls,
+        };
+        let mpd_dir = mpd_path.parent().unwrap_or(&self.out_dir).to_path_buf();
+        self.write_thumbnail_index(&track, &mpd_dir).await
+    }
+
+    /// Handle a <SegmentTemplate> for a given Representation, returning the
+    /// URLs of its media segments in order.
+    #[cfg(feature = "dash")]
+    async fn handle_segment_template(
+        &mut self,
+        base_url: &Url,
+        representation_id: &str,
+        st: &template::SegmentTemplate<'_, '_>,
+        period_duration_secs: Option<f64>,
+    ) -> Result<Vec<Url>> {
+        let init_tmpl = st.attribute("initialization");
+        if let Some(tmpl) = init_tmpl {
+            let full = template::initialization_url(base_url, tmpl, representation_id)?;
+            self.note_priority(priority::Priority::Init, &full);
+            self.mirror_binary(full.clone()).await?;
+            self.scan_init_segment(&full).await?;
+        }
+
+        let media_tmpl = match st.attribute("media") {
+            Some(v) if !v.is_empty() => v,
+            _ => return Ok(Vec::new()),
+        };
+
+        let timescale = st
+            .attribute("timescale")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(1);
+        let duration_units = st.attribute("duration").and_then(|v| v.parse::<u64>().ok());
+        let start_number = st
+            .attribute("startNumber")
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(1);
+        let end_number_attr = st
+            .attribute("endNumber")
+            .and_then(|v| v.parse::<u64>().ok());
+
+        // Low-latency templates make segments available early, in chunks;
+        // once published, a segment is complete and mirrored as such.
+        if let Some(offset) = st.attribute("availabilityTimeOffset") {
+            self.reporter.report(Event::Detail {
+                message: format!(
+                    "low-latency Representation {} (availabilityTimeOffset {}s)",
+                    representation_id, offset
+                ),
+            });
+        }
+        let sub_segments = template::has_sub_number(media_tmpl);
+        let parts_per_segment = st.attribute("k").and_then(|v| v.parse::<u64>().ok());
+        if timescale == 0 {
+            return self.skip_media_segments(representation_id, "timescale 0");
+        }
+        if let Some(k) = parts_per_segment
+            && !(1..=template::MAX_SUB_SEGMENTS).contains(&k)
+        {
+            return self.skip_media_segments(
+                representation_id,
+                format!("k {} is not 1 to {}", k, template::MAX_SUB_SEGMENTS),
+            );
+        }
+
+        let media_url = |num: u64, sub: Option<u64>| {
+            template::media_url(base_url, media_tmpl, representation_id, num, sub)
+        };
+
+        let end_number = if let Some(en) = end_number_attr {
+            if let Err(e) = template::segment_numbers(start_number, en) {
+                return self.skip_media_segments(representation_id, e);
+            }
+            en
+        } else if let (Some(dur_u), Some(total_secs)) = (duration_units, period_duration_secs) {
+            let mut count = match template::segment_count(total_secs, dur_u, timescale) {
+                Ok(0) => return Ok(Vec::new()),
+                Ok(count) => count,
+                Err(e) => return self.skip_media_segments(representation_id, e),
+            };
+            if count > template::MAX_SEGMENTS {
+                self.warn_or_fail(format!(
+                    "{} would have {} segments, limiting to {}",
+                    representation_id,
+                    count,
+                    template::MAX_SEGMENTS
+                ))?;
+                count = template::MAX_SEGMENTS;
+            }
+
+            let inferred = match template::last_number(start_number, count) {
+                Ok(inferred) => inferred,
+                Err(e) => return self.skip_media_segments(representation_id, e),
+            };
+            let end = self
+                .probe_end_number(start_number, inferred, |num| {
+                    media_url(num, sub_segments.then_some(1))
+                })
+                .await?;
+            self.reporter.report(Event::Detail {
+                message: format!(
+                    "segments {}..={} of {}{}",
+                    start_number,
+                    end,
+                    representation_id,
+                    if end == inferred {
+                        String::new()
+                    } else {
+                        format!(" ({} inferred from the duration)", inferred)
+                    }
+                ),
+            });
+            end
+        } else if self.probe_segments {
+            let found = self
+                .probe_last_number(start_number, |num| {
+                    media_url(num, sub_segments.then_some(1))
+                })
+                .await?;
+            let Some(end) = found else {
+                return self.skip_media_segments(
+                    representation_id,
+                    format!("segment {} does not exist", start_number),
+                );
+            };
+            self.reporter.report(Event::Detail {
+                message: format!(
+                    "segments {}..={} of {} (probed)",
+                    start_number, end, representation_id
+                ),
+            });
+            end
+        } else {
+            return self.skip_media_segments(
+                representation_id,
+                "no endNumber, and no segment, Period or MPD duration; try --probe-segments",
+            );
+        };
+
+        let segment_secs = duration_units.map(|d| d as f64 / timescale as f64);
+        let mut media = Vec::new();
+        for num in start_number..=end_number {
+            let urls = if !sub_segments {
+                vec![media_url(num, None)?]
+            } else {
+                let parts = match parts_per_segment {
+                    Some(k) => k,
+                    None => {
+                        self.count_sub_segments(|sub| media_url(num, Some(sub)))
+                            .await?
+                    }
+                };
+                (1..=parts)
+                    .map(|sub| media_url(num, Some(sub)))
+                    .collect::<Result<Vec<_>>>()?
+            };
+
+            // Parts split the segment's duration between them.
+            let duration = segment_secs.map(|secs| secs / urls.len() as f64);
+            for full in urls {
+                if self.manifests_only {
+                    self.tally.skipped += 1;
+                } else {
+                    self.mirror_segment(full.clone(), duration).await?;
+                    self.note_segment(&full);
+                }
+                media.push(full);
+            }
+        }
+
+        Ok(media)
+    }
+
+    /// Warn that the media segments of `representation_id` are skipped for
+    /// `reason`, or fail with `--strict`.
+    #[cfg(feature = "dash")]
+    fn skip_media_segments(
+        &self,
+        representation_id: &str,
+        reason: impl std::fmt::Display,
+    ) -> Result<Vec<Url>> {
+        self.warn_or_fail(format!(
+            "skipping media segments of {} ({:#})",
+            representation_id, reason
+        ))?;
+        Ok(Vec::new())
+    }
+
+    /// Check the last segment number inferred from durations with HEAD
+    /// requests, stepping back while it is missing or forward while the next
+    /// one exists, at most [`template::END_PROBES`] times. Keeps the inferred
+    /// number when the origin doesn't answer HEAD requests usefully.
+    #[cfg(feature = "dash")]
+    async fn probe_end_number(
+        &self,
+        start_number: u64,
+        inferred: u64,
+        segment_url: impl Fn(u64) -> Result<Url>,
+    ) -> Result<u64> {
+        let mut end = inferred;
+        if self.exists(segment_url(end)?).await {
+            for _ in 0..template::END_PROBES {
+                let Some(next) = end.checked_add(1) else {
+                    break;
+                };
+                if !self.exists(segment_url(next)?).await {
+                    break;
+                }
+                end = next;
+            }
+            return Ok(end);
+        }
+
+        for _ in 0..template::END_PROBES {
+            if end <= start_number {
+                break;
+            }
+            end -= 1;
+            if self.exists(segment_url(end)?).await {
+                return Ok(end);
+            }
+        }
+        Ok(inferred)
+    }
+
+    /// Find the last segment number of a template without duration
+    /// information: HEAD requests double the distance from `start_number`
+    /// until a segment is missing, then bisect. `None` if not even the first
+    /// segment exists.
+    #[cfg(feature = "dash")]
+    async fn probe_last_number(
+        &self,
+        start_number: u64,
+        segment_url: impl Fn(u64) -> Result<Url>,
+    ) -> Result<Option<u64>> {
+        if !self.exists(segment_url(start_number)?).await {
+            return Ok(None);
+        }
+
+        let limit = start_number.saturating_add(template::MAX_SEGMENTS - 1);
+        let mut present = start_number;
+        let mut step = 1u64;
+        let missing = loop {
+            let candidate = present.saturating_add(step).min(limit);
+            if candidate == present {
+                return Ok(Some(present));
+            }
+            if !self.exists(segment_url(candidate)?).await {
+                break candidate;
+            }
+            present = candidate;
+            step = step.saturating_mul(2);
+        };
+
+        // `present` exists and `missing` doesn't.
+        let (mut low, mut high) = (present, missing);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if self.exists(segment_url(mid)?).await {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        Ok(Some(low))
+    }
+
+    /// Whether a HEAD request for `url` succeeds.
+    #[cfg(feature = "dash")]
+    async fn exists(&self, url: Url) -> bool {
+        self.timeouts
+            .send(self.client.head(url.clone()), &url)
+            .await
+            .is_ok_and(|resp| resp.status().is_success())
+    }
+
+    /// Number of `$SubNumber$` parts of a segment, found by HEAD requests for
+    /// parts 1, 2, ... until one is missing.
+    #[cfg(feature = "dash")]
+    async fn count_sub_segments(&self, part_url: impl Fn(u64) -> Result<Url>) -> Result<u64> {
+        let mut parts = 0;
+        while parts < template::MAX_SUB_SEGMENTS && self.exists(part_url(parts + 1)?).await {
+            parts += 1;
+        }
+
+        if parts == 0 {
+            self.warn_or_fail(format!("no parts found for {}", part_url(1)?))?;
+        }
+        Ok(parts)
+    }
+}
+
+/// Recognize a manifest by its content.
+fn sniff_format(body: &str) -> Option<StreamFormat> {
+    let head = body.trim_start_matches('\u{feff}').trim_start();
+    if head.starts_with("#EXTM3U") {
+        Some(StreamFormat::Hls)
+    } else if head.starts_with('<') && head.contains("<MPD") {
+        Some(StreamFormat::Dash)
+    } else {
+        None
+    }
+}
+
+/// Widen an element's byte range to cover its whole line(s) when nothing else
+/// shares them, so removing it leaves no blank line behind.
+#[cfg(feature = "dash")]
+fn element_line_range(text: &str, range: std::ops::Range<usize>) -> std::ops::Range<usize> {
+    let line_start = text[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[range.end..]
+        .find('\n')
+        .map_or(text.len(), |i| range.end + i + 1);
+    if text[line_start..range.start].trim().is_empty()
+        && text[range.end..line_end].trim().is_empty()
+    {
+        line_start..line_end
+    } else {
+        range
+    }
+}
+
+/// Whether the relative reference `uri`, resolved against `dir`, names the
+/// file `target`. References with a scheme, an absolute path, a query or a
+/// fragment never do, as mirrored files are named differently.
+#[cfg(feature = "hls")]
+fn resolves_to(uri: &str, dir: &std::path::Path, target: &std::path::Path) -> bool {
+    if uri.starts_with('/') || uri.contains(['?', '#']) || Url::parse(uri).is_ok() {
+        return false;
+    }
+    let mut path = dir.to_path_buf();
+    for segment in uri.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if !path.pop() {
+                    return false;
+                }
+            }
+            segment => path.push(segment),
+        }
+    }
+    path == target
+}
+
+/// Parse `--rewrite-base`: a root-relative path or an absolute URL, given a
+/// trailing slash if it lacks one.
+fn parse_rewrite_base(s: &str) -> Result<String> {
+    let s = s.trim();
+    if !s.starts_with('/') && Url::parse(s).is_err() {
+        anyhow::bail!(
+            "expected a path starting with '/' or an absolute URL, got '{}'",
+            s
+        );
+    }
+    let mut base = s.to_string();
+    if !base.ends_with('/') {
+        base.push('/');
+    }
+    Ok(base)
+}
+
+/// Path of the untouched copy saved next to a rewritten manifest.
+fn orig_path(path: &std::path::Path, default_name: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .unwrap_or(default_name);
+    path.with_file_name(format!("{name}.orig"))
+}
+
+/// GET a binary resource. While an availability window is set (live
+/// recording), a 404 is retried with short backoff until the window is used
+/// up, since origins may list a segment slightly before serving it. The
+/// body is to be read with `timeouts` as well. Files below an origin `race`
+/// knows another of are fetched from whichever answers first.
+async fn get_binary(
+    client: &Client,
+    url: &Url,
+    availability_window: Option<Duration>,
+    timeouts: timeouts::Timeouts,
+    race: &race::Race,
+    reporter: &SharedReporter,
+) -> Result<reqwest::Response> {
+    let started = tokio::time::Instant::now();
+    let mut delay = Duration::from_millis(250);
+
+    loop {
+        let resp = race.send(client, url, timeouts).await?;
+
+        if resp.status() == reqwest::StatusCode::NOT_FOUND
+            && let Some(window) = availability_window
+            && started.elapsed() + delay <= window
+        {
+            reporter.report(Event::Detail {
+                message: format!("not yet available, retrying in {}ms", delay.as_millis()),
+            });
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(Duration::from_secs(2));
+            continue;
+        }
+
+        return resp
+            .error_for_status()
+            .with_context(|| format!("status error for {}", url));
+    }
+}
+
+/// Write `data` to `path`, creating parent directories as needed.
+#[cfg(feature = "hls")]
+async fn write_file(path: &std::path::Path, data: &[u8]) -> Result<()> {
+    write_file_hashed(path, data, false).await.map(drop)
+}
+
+/// [`write_file`], returning the SHA-256 of what ends up on disk (`data`
+/// itself, or sealed with `--encrypt-output`) if asked to `hash` it.
+async fn write_file_hashed(
+    path: &std::path::Path,
+    data: &[u8],
+    hash: bool,
+) -> Result<Option<digests::Sha256Digest>> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("creating directory {}", parent.display()))?;
+    }
+
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("creating {}", path.display()))?;
+    let sealed = encrypt::seal(data)?;
+    file.write_all(&sealed).await?;
+    // Completes the write before the file is dated or read back.
+    file.flush().await?;
+    Ok(hash.then(|| digests::sha256(&sealed)))
+}
+
+/// Write rewritten playlist lines to `path` as [`playlist::Layout::render`]
+/// joins them, without holding the whole text in memory, which matters for
+/// archives of long live recordings. Encrypted output is sealed as a whole,
+/// so it is rendered in memory first.
+#[cfg(feature = "hls")]
+pub(crate) async fn write_playlist<S: AsRef<str>>(
+    path: &std::path::Path,
+    layout: playlist::Layout,
+    lines: impl IntoIterator<Item = S>,
+) -> Result<()> {
+    if encrypt::enabled() {
+        let mut text = Vec::new();
+        write_playlist_to(&mut text, layout, lines).await?;
+        return write_file(path, &text).await;
+    }
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .with_context(|| format!("creating directory {}", parent.display()))?;
+    }
+    let file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("creating {}", path.display()))?;
+    let mut out = tokio::io::BufWriter::new(file);
+    write_playlist_to(&mut out, layout, lines).await?;
+    out.flush()
+        .await
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+#[cfg(feature = "hls")]
+async fn write_playlist_to<S: AsRef<str>>(
+    out: &mut (impl tokio::io::AsyncWrite + Unpin),
+    layout: playlist::Layout,
+    lines: impl IntoIterator<Item = S>,
+) -> std::io::Result<()> {
+    let newline: &[u8] = if layout.crlf { b"\r\n" } else { b"\n" };
+    if layout.bom {
+        out.write_all(playlist::BOM.encode_utf8(&mut [0; 4]).as_bytes())
+            .await?;
+    }
+    for (i, line) in lines.into_iter().enumerate() {
+        if i > 0 {
+            out.write_all(newline).await?;
+        }
+        out.write_all(line.as_ref().as_bytes()).await?;
+    }
+    if layout.final_newline {
+        out.write_all(newline).await?;
+    }
+    Ok(())
+}
+
+/// Paths of the files below `dir`, relative to `root`, with `/` separators.
+pub(crate) fn collect_files(
+    root: &std::path::Path,
+    dir: &std::path::Path,
+    files: &mut Vec<String>,
+) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).with_context(|| format!("reading directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(root, &path, files)?;
+        } else {
+            let relative = path.strip_prefix(root)?;
+            let parts: Vec<String> = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            files.push(parts.join("/"));
+        }
+    }
+    Ok(())
+}
+
+/// Write a downloaded file to `path`, or link it there from `store`,
+/// returning its SHA-256 if asked to `hash` it.
+async fn write_download(
+    path: &std::path::Path,
+    data: &[u8],
+    store: Option<&store::SegmentStore>,
+    hash: bool,
+) -> Result<Option<digests::Sha256Digest>> {
+    match store {
+        Some(store) => store.write(path, data, hash).await,
+        None => write_file_hashed(path, data, hash).await,
+    }
+}
+
+/// Date the file or directory at `path` to `at`.
+fn set_modified(path: &std::path::Path, at: SystemTime) -> Result<()> {
+    std::fs::File::open(path)
+        .and_then(|file| file.set_modified(at))
+        .with_context(|| format!("setting the modification time of {}", path.display()))
+}
+
+/// The `Last-Modified` time of `resp`, if it sent a valid one.
+fn last_modified(resp: &reqwest::Response) -> Option<SystemTime> {
+    resp.headers()
+        .get(LAST_MODIFIED)
+        .and_then(|v| v.to_str().ok())
+        .and_then(schedule::parse_http_date)
+}
+
+/// Mirror the stream at `start_url` into `out_dir`.
+async fn mirror_stream(
+    start_url: &str,
+    out_dir: PathBuf,
+    options: &MirrorOptions,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    if !options.status_file {
+        return mirror_into(start_url, out_dir, options, reporter).await;
+    }
+    let snapshots = status::Snapshots::start(&out_dir, start_url, reporter.clone());
+    let result = mirror_into(start_url, out_dir, options, &snapshots.reporter()).await;
+    snapshots.finish(&result, reporter).await;
+    result
+}
+
+/// [`mirror_stream`] without `--status-file`.
+async fn mirror_into(
+    start_url: &str,
+    out_dir: PathBuf,
+    options: &MirrorOptions,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    let start_url =
+        Url::parse(start_url).with_context(|| format!("parsing start URL '{}'", start_url))?;
+
+    let expectations = match &options.expect {
+        Some(path) => Some(expect::load(path).await?),
+        None => None,
+    };
+
+    #[cfg(not(feature = "archive"))]
+    if options.output_format == OutputFormat::TarZst {
+        return Err(anyhow!(
+            "--output-format tar.zst requires the `archive` feature. Build with --features archive."
+        ));
+    }
+
+    // An archived mirror's directory is removed unless it holds an earlier
+    // mirror being updated.
+    let existed = tokio::fs::metadata(&out_dir).await.is_ok();
+    if !options.plans_only() {
+        tokio::fs::create_dir_all(&out_dir)
+            .await
+            .with_context(|| format!("creating output dir {}", out_dir.display()))?;
+    }
+
+    // How far the origin's clock is ahead of ours, measured if it matters.
+    let clock_skew = match options.start_at {
+        Some(at) => {
+            let client = user_agent::http_client(
+                options
+                    .user_agent
+                    .as_deref()
+                    .unwrap_or(&user_agent::default_user_agent()),
+            );
+            let skew = schedule::measure_clock_skew(&client, &start_url).await;
+            if let Some(skew) = skew {
+                schedule::report_clock_skew(skew, reporter);
+            }
+            schedule::wait_until(at, skew.unwrap_or(0.0), reporter).await;
+            skew
+        }
+        None => None,
+    };
+    #[cfg(not(feature = "hls"))]
+    let _ = clock_skew;
+
+    // The recording window starts once we actually begin, not at scheduling time.
+    let deadline = options.record_for.map(|d| tokio::time::Instant::now() + d);
+
+    let paths: Arc<dyn PathMapper> = match &options.companion_url {
+        // Both manifests keep their place below the directory they share, so
+        // references from one to the other's files resolve in the mirror.
+        Some(companion) => Arc::new(OriginPaths::shared(&start_url, companion)),
+        None => Arc::new(OriginPaths::new(&start_url)),
+    };
+    let mut mirror = Mirror::new(out_dir, paths, reporter.clone());
+    if let Some(ua) = &options.user_agent {
+        mirror.client = user_agent::http_client(ua);
+    }
+    mirror.live = options.is_live();
+    mirror.filter = options.filter.clone();
+    mirror.skip_failed = options.skip_failed;
+    mirror.strict = options.strict;
+    mirror.playback_order = options.playback_order;
+    mirror.abr_switches = options.abr_switch.clone();
+    mirror.abr_trace = options.abr_trace.clone();
+    mirror.preserve_mtime = !options.no_preserve_mtime;
+    mirror.timeouts = timeouts::Timeouts {
+        min_throughput: options.min_throughput,
+        response: options
+            .response_timeout
+            .unwrap_or(timeouts::DEFAULT_RESPONSE),
+    };
+    if options.race_origins {
+        mirror.race = race::Race::enabled();
+    }
+    if !options.plans_only() {
+        mirror.limits = quota::Limits::new(
+            options.max_rate,
+            options.disk_quota,
+            options.slot.as_ref(),
+            &mirror.out_dir,
+        )
+        .await?;
+    }
+    mirror.write_queue = options.write_queue;
+    if let Some(dir) = &options.segment_store
+        && !options.plans_only()
+    {
+        mirror.segment_store = Some(store::SegmentStore::open(dir).await?);
+    }
+    if options.checksums || options.sign.is_some() {
+        mirror.digests = Some(digests::Digests::default());
+    }
+    mirror.ignore_query_params = options.ignore_query_params.clone();
+    mirror.size_check = options.size_check.clone();
+    mirror.manifests_only = options.manifests_only;
+    mirror.segments_only = options.segments_only;
+    mirror.probe_segments = options.probe_segments;
+    mirror.normalize_manifests = options.normalize_manifests;
+    mirror.preserve_relative = options.preserve_relative;
+    mirror.rewrite_base = options.rewrite_base.clone();
+    mirror.fetched_before = state::load(&mirror.out_dir).await?;
+    // Compared without the query parameters that don't affect the content,
+    // such as tokens that change between runs.
+    let source = mirror.identity(&start_url);
+    let sources = state::sources(&mirror.out_dir).await?;
+    if !sources.is_empty() && !sources.contains(&source) {
+        let message = format!(
+            "{} holds a mirror of {}, not of {}",
+            mirror.out_dir.display(),
+            sources.join(" and "),
+            source
+        );
+        if options.plans_only() {
+            reporter.report(Event::Warning { message });
+        } else if !options.force {
+            bail!(
+                "{}; refusing to mix the two (use --force to do so anyway)",
+                message
+            );
+        }
+    }
+    if !options.plans_only()
+        && mirror.fetched_before.is_empty()
+        && existed
+        && std::fs::read_dir(&mirror.out_dir)?.next().is_some()
+    {
+        reporter.report(Event::Detail {
+            message: format!(
+                "{} already holds files; --plan lists what a run would add, replace or leave alone there",
+                mirror.out_dir.display()
+            ),
+        });
+    }
+    // Files of a reproducible mirror are dated to a fixed time, which
+    // provenance stamps use as well.
+    let reproducible_at = if options.reproducible {
+        Some(reproducible::timestamp()?)
+    } else {
+        None
+    };
+    if options.provenance {
+        mirror.catalog.provenance = Some(provenance::Provenance::new(
+            &start_url,
+            &format!("{:?}", options),
+            reproducible_at.unwrap_or_else(SystemTime::now),
+        ));
+    }
+    if !options.plans_only() {
+        let mut state = state::StateFile::open(&mirror.out_dir).await?;
+        if !sources.contains(&source) {
+            state.record_source(&source).await?;
+        }
+        mirror.state = Some(state);
+    }
+    #[cfg(feature = "hls")]
+    {
+        mirror.treat_as = options.treat_as;
+        mirror.drop_lagging = options.drop_lagging;
+        mirror.resume_recording = options.resume_recording;
+        mirror.retention = options.retention;
+        mirror.chunk_every = options.chunk_every;
+        mirror.upload_to = options.upload_to.clone();
+        // Already reported while waiting.
+        mirror.clock_skew = clock_skew;
+        let mut keys = options.keys.clone();
+        if let Some(path) = &options.key_file {
+            keys.extend(keyring::load(path).await?);
+        }
+        mirror.keyring = keyring::Keyring::new(keys);
+    }
+
+    // The start URL and, for a combined capture, its companion in the other
+    // format.
+    let roots: Vec<(Url, StreamFormat)> = std::iter::once((start_url.clone(), options.format))
+        .chain(
+            options
+                .companion_url
+                .iter()
+                .map(|url| (url.clone(), StreamFormat::Auto)),
+        )
+        .collect();
+
+    if options.plans_only() {
+        mirror.plan = Some(Vec::new());
+        for (url, format) in &roots {
+            mirror.mirror_root(url.clone(), *format).await?;
+        }
+        #[cfg(feature = "hls")]
+        {
+            mirror.report_mixed_playlist_states();
+            mirror.report_unused_keys()?;
+        }
+        let checked = match &expectations {
+            Some(expectations) => mirror.check_expectations(expectations),
+            None => Ok(()),
+        };
+        if options.plan {
+            plan::report(&mirror.out_dir, &mirror.planned_writes, reporter)?;
+            return checked;
+        }
+        let planned = mirror.plan.take().unwrap_or_default();
+        let manifests = mirror.visited.len() - planned.len() - mirror.tally.up_to_date;
+        estimate::estimate(
+            &mirror.client,
+            planned,
+            manifests,
+            options.concurrency,
+            reporter,
+        )
+        .await;
+        return checked;
+    }
+
+    if let Some(path) = &options.header_variants {
+        let variants = variants::load(path).await?;
+        let user_agent = options
+            .user_agent
+            .clone()
+            .unwrap_or_else(user_agent::default_user_agent);
+        mirror
+            .mirror_header_variants(start_url.clone(), options.format, &variants, &user_agent)
+            .await?;
+    } else {
+        for (url, format) in &roots {
+            #[cfg(any(feature = "hls", feature = "dash"))]
+            {
+                let (files, priority) = mirror.discover(url.clone(), *format).await?;
+                mirror.fetch_priority(priority).await?;
+                // Live windows move on while downloading; their segments are
+                // fetched as the playlists are processed.
+                if !options.is_live() {
+                    mirror.download_planned(files, options.concurrency).await?;
+                }
+            }
+            mirror.mirror_root(url.clone(), *format).await?;
+        }
+        if options.companion_url.is_some() {
+            dedupe::link_duplicates(&mirror.out_dir, reporter).await?;
+        }
+    }
+    #[cfg(feature = "hls")]
+    {
+        mirror.report_mixed_playlist_states();
+        // Keys may still come up while recording.
+        if !options.is_live() {
+            mirror.report_unused_keys()?;
+        }
+        if options.master.synthesize_master {
+            mirror
+                .write_synthetic_master(&start_url, &options.master)
+                .await?;
+        }
+    }
+    // Live recordings are checked once recording has ended.
+    let checked = if options.is_live() {
+        Ok(())
+    } else {
+        if let Some(share) = options.verify_sample {
+            mirror.verify_sample(share).await?;
+        }
+        mirror.check_segment_sizes().await
+    };
+    if options.reproducible {
+        mirror.catalog.sort();
+    }
+    mirror.catalog.write(&mirror.out_dir).await?;
+    checked?;
+    if let Some(expectations) = &expectations {
+        mirror.check_expectations(expectations)?;
+    }
+
+    if options.is_live() {
+        #[cfg(feature = "hls")]
+        {
+            mirror.record_live(deadline, options.concurrency).await?;
+            mirror.report_unused_keys()?;
+            if let Some(share) = options.verify_sample {
+                mirror.verify_sample(share).await?;
+            }
+            let checked = mirror.check_segment_sizes().await;
+            if options.reproducible {
+                mirror.catalog.sort();
+            }
+            mirror.catalog.write(&mirror.out_dir).await?;
+            checked?;
+        }
+        #[cfg(not(feature = "hls"))]
+        {
+            let _ = deadline;
+            return Err(anyhow!(
+                "Live recording requires the `hls` feature. Build with --features hls."
+            ));
+        }
+    }
+
+    if options.checksums || options.sign.is_some() {
+        let catalog = checksums::write(
+            &mirror.out_dir,
+            mirror.digests.clone().unwrap_or_default(),
+            reporter,
+        )
+        .await?;
+        if let Some(key) = &options.sign {
+            signing::sign(
+                &mirror.out_dir,
+                key,
+                &catalog,
+                reproducible_at.unwrap_or_else(SystemTime::now),
+                reporter,
+            )
+            .await?;
+        }
+    }
+
+    if let Some(at) = reproducible_at {
+        // The state file is rewritten sorted.
+        if let Some(state) = mirror.state.take() {
+            state.close().await?;
+        }
+        reproducible::finish(&mirror.out_dir, at).await?;
+    }
+
+    mirror.unsupported.summarize(reporter);
+    mirror.race.summarize(reporter);
+    if let Some(path) = &options.race_trace {
+        mirror.race.write_trace(path).await?;
+    }
+    reporter.report(Event::Status {
+        tag: "SUM",
+        message: mirror.tally.summary(),
+    });
+
+    if options.self_test {
+        #[cfg(not(feature = "serve"))]
+        return Err(anyhow!(
+            "--self-test requires the `serve` feature. Build with --features serve."
+        ));
+        #[cfg(feature = "serve")]
+        for (url, format) in &roots {
+            let root_manifest = mirror.path_for_url(url, true);
+            selftest::self_test(
+                &mirror.out_dir,
+                &root_manifest,
+                *format,
+                options.concurrency,
+                reporter,
+            )
+            .await?;
+        }
+    }
+
+    #[cfg(feature = "archive")]
+    if options.output_format == OutputFormat::TarZst {
+        if let Some(state) = mirror.state.take() {
+            state.close().await?;
+        }
+        let path = archive::archive_path(&mirror.out_dir);
+        archive::pack(&mirror.out_dir, &path, reporter).await?;
+        if !existed {
+            tokio::fs::remove_dir_all(&mirror.out_dir)
+                .await
+                .with_context(|| format!("removing {}", mirror.out_dir.display()))?;
+        }
+    }
+    #[cfg(not(feature = "archive"))]
+    let _ = existed;
+
+    Ok(())
+}
+
+/// Run the command line with the arguments of the process; what the
+/// `streamrip` binary does.
+pub async fn run() -> Result<()> {
+    let args = Args::parse_from(profile::expand(std::env::args_os().collect())?);
+    if args.porcelain {
+        units::set_porcelain();
+    }
+    let reporter = report::reporter(args.report);
+    if args.debug_http {
+        debug_http::enable(reporter.clone());
+    }
+
+    match args.command {
+        #[cfg(feature = "serve")]
+        Some(Command::Serve {
+            dir,
+            root,
+            bind,
+            auth,
+            token,
+            decrypt_with,
+        }) => {
+            if let Some(path) = decrypt_with {
+                encrypt::decrypt_with(&path)?;
+            }
+            let layout = match (dir, root) {
+                (Some(dir), _) => serve::Layout::Single(dir),
+                (None, Some(root)) => serve::Layout::Multi(root),
+                (None, None) => unreachable!("clap requires --dir or --root"),
+            };
+            let access = serve::Access { basic: auth, token };
+            return serve::serve(layout, bind, access, reporter).await;
+        }
+        #[cfg(feature = "archive")]
+        Some(Command::Extract { archive, dir }) => {
+            let dir = dir.unwrap_or_else(|| archive::default_extract_dir(&archive));
+            return archive::extract(&archive, &dir, &reporter).await;
+        }
+        #[cfg(all(feature = "hls", feature = "dash"))]
+        Some(Command::Convert { manifest, output }) => {
+            return convert::convert(&manifest, output.as_deref(), &reporter).await;
+        }
+        Some(Command::Verify {
+            dir,
+            fail_fast,
+            signature,
+        }) => {
+            if let Some(key) = signature {
+                let failed = signing::verify(&dir, &key, fail_fast, &reporter).await?;
+                if failed > 0 {
+                    return Err(anyhow!(
+                        "{} file(s) missing or changed since the mirror was signed",
+                        failed
+                    ));
+                }
+            }
+            let mismatched = verify::verify_rewrites(&dir, fail_fast, &reporter).await?;
+            if mismatched > 0 {
+                return Err(anyhow!(
+                    "{} manifest(s) changed beyond their URIs",
+                    mismatched
+                ));
+            }
+            return Ok(());
+        }
+        Some(Command::Completions { shell }) => {
+            let mut command = Args::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+        Some(Command::Watch {
+            jobs,
+            list_url,
+            output_root,
+            interval,
+            max_jobs,
+            max_rate,
+            #[cfg(feature = "serve")]
+            status_addr,
+        }) => {
+            let source = match (jobs, list_url) {
+                (Some(dir), _) => watch::Source::Directory(dir),
+                (None, Some(url)) => watch::Source::UrlList(url),
+                (None, None) => unreachable!("clap requires --jobs or --list-url"),
+            };
+            #[cfg(not(feature = "serve"))]
+            let status_addr = None;
+            return watch::watch(
+                source,
+                output_root,
+                Duration::from_secs(interval),
+                max_jobs as usize,
+                max_rate,
+                status_addr,
+                reporter,
+            )
+            .await;
+        }
+        None => {}
+    }
+
+    let (Some(start_url), Some(out_dir)) = (args.start_url, args.output_dir) else {
+        return Err(anyhow!("--start-url and --output-dir are required"));
+    };
+
+    if let Some(spec) = &args.options.encrypt_output {
+        encrypt::enable(spec)?;
+    }
+    let result = mirror_stream(&start_url, out_dir, &args.options, &reporter).await;
+    hosts::summarize(&reporter);
+    result?;
+
+    reporter.report(Event::Done);
+    Ok(())
+}
@@ -0,0 +1,1759 @@
+//! Live HLS recording: keep refreshing media playlists, download new segments
+//! and finalize the accumulated playlists as VOD when recording stops.
+//!
+//! While recording, each media playlist is kept in two local forms: the
+//! regular path holds the origin's current sliding window (for watching
+//! along, "relive"), and a sibling `*.archive.m3u8` accumulates every segment
+//! captured so far ("rewatch"). Master playlists get an `*.archive.m3u8`
+//! sibling referencing the archive playlists.
+
+use crate::container::Container;
+use crate::debug_http::SendLogged;
+use crate::discover::{self, DownloadOptions};
+use crate::disk::{DiskWriter, Writes};
+use crate::playlist::{self, HlsMediaPlaylist, HlsPlaylist, Keys, Layout, Line, Tag};
+use crate::quota::Limits;
+use crate::race::Race;
+use crate::report::{Event, Phase, Progress, Resource, SharedReporter};
+use crate::schedule;
+use crate::store::SegmentStore;
+use crate::timeouts::Timeouts;
+use crate::units;
+use crate::upload::{Uploader, Uploads};
+use crate::{Mirror, PlannedFile, PlaylistTreatment};
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::Client;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{Semaphore, mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use url::Url;
+
+/// Refresh interval used until a playlist's `#EXT-X-TARGETDURATION` is known.
+const DEFAULT_TARGET_DURATION: f64 = 6.0;
+
+/// Tags that describe the playlist as a whole rather than the segment that follows.
+const PLAYLIST_TAGS: &[&str] = &[
+    "#EXTM3U",
+    "#EXT-X-VERSION",
+    "#EXT-X-TARGETDURATION",
+    "#EXT-X-MEDIA-SEQUENCE",
+    "#EXT-X-DISCONTINUITY-SEQUENCE",
+    "#EXT-X-PLAYLIST-TYPE",
+    "#EXT-X-INDEPENDENT-SEGMENTS",
+    "#EXT-X-START",
+    "#EXT-X-ENDLIST",
+    "#EXT-X-ALLOW-CACHE",
+    "#EXT-X-SERVER-CONTROL",
+    "#EXT-X-PART-INF",
+];
+
+/// A media playlist being recorded, accumulating every segment seen so far.
+#[derive(Clone)]
+struct LivePlaylist {
+    url: Url,
+    local_path: PathBuf,
+    /// Playlist-level tags from the first refresh, minus the ones we regenerate.
+    header: Vec<String>,
+    /// Rewritten segment lines (tags followed by the URI), keyed by media sequence number.
+    segments: BTreeMap<u64, Vec<String>>,
+    /// Local file of each segment that has one, for `--retention`.
+    files: BTreeMap<u64, PathBuf>,
+    /// Whether `--retention` dropped segments from the start of the archive.
+    trimmed: bool,
+    /// Discontinuities dropped along with them.
+    trimmed_discontinuities: u64,
+    /// Media sequence number the accumulated segments start from after a
+    /// chunk was cut (`--chunk-every`); earlier ones went into the chunk.
+    cut_at: Option<u64>,
+    /// `#EXT-X-MAP` and `#EXT-X-KEY` lines in effect at the cut, for the
+    /// next segment.
+    carried: Vec<String>,
+    /// The origin's current sliding window, as rewritten lines.
+    window: Vec<String>,
+    /// Media sequence number of the first segment in that window.
+    window_start: u64,
+    /// Line endings and byte order mark of the origin's playlist.
+    layout: Layout,
+    target_duration: f64,
+    ended: bool,
+    /// The origin's media sequence numbers reached `u64::MAX`, past which
+    /// segments can't be numbered, so the recording ended there.
+    exhausted: bool,
+    /// Keep refreshing even after `#EXT-X-ENDLIST` (`--treat-as live`).
+    ignore_endlist: bool,
+    /// Server advertised `CAN-BLOCK-RELOAD=YES` in `#EXT-X-SERVER-CONTROL`.
+    can_block_reload: bool,
+    /// Consecutive reloads that brought no new segments.
+    unchanged_reloads: u32,
+    next_reload: Instant,
+    /// Seconds the origin's clock was ahead of ours at the last refresh,
+    /// from its `Date` header.
+    clock_skew: Option<f64>,
+    /// Provenance comment for the archive playlist (`--provenance`).
+    stamp: Option<String>,
+    /// URI lines of the segments taken up from an interrupted recording
+    /// (`--resume-recording`).
+    resumed_uris: HashSet<String>,
+}
+
+impl LivePlaylist {
+    fn new(url: Url, local_path: PathBuf) -> Self {
+        Self {
+            url,
+            local_path,
+            header: Vec::new(),
+            segments: BTreeMap::new(),
+            files: BTreeMap::new(),
+            trimmed: false,
+            trimmed_discontinuities: 0,
+            cut_at: None,
+            carried: Vec::new(),
+            window: Vec::new(),
+            window_start: 0,
+            layout: Layout::default(),
+            target_duration: DEFAULT_TARGET_DURATION,
+            ended: false,
+            exhausted: false,
+            ignore_endlist: false,
+            can_block_reload: false,
+            unchanged_reloads: 0,
+            next_reload: Instant::now(),
+            clock_skew: None,
+            stamp: None,
+            resumed_uris: HashSet::new(),
+        }
+    }
+
+    /// Take up the segments of the archive playlist an interrupted
+    /// recording left behind (`--resume-recording`), numbered on from its
+    /// `#EXT-X-MEDIA-SEQUENCE`, and return how many there are. Where that
+    /// recording lost segments, the numbers fall short of the origin's, so
+    /// segments the origin still lists are recognized by their URI instead.
+    async fn resume(&mut self) -> Result<usize> {
+        let path = archive_path(&self.local_path);
+        if !tokio::fs::try_exists(&path).await? {
+            return Ok(0);
+        }
+        let data = crate::encrypt::read(&path)
+            .await
+            .with_context(|| format!("reading {}", path.display()))?;
+        let text = String::from_utf8_lossy(&data);
+        // Comments such as the provenance stamp are the playlist's, not the
+        // first segment's.
+        let lines: Vec<String> = text
+            .lines()
+            .filter(|line| !line.starts_with('#') || line.starts_with("#EXT"))
+            .map(str::to_string)
+            .collect();
+        let dir = self.local_path.parent().unwrap_or(Path::new(""));
+        let files: Vec<Option<PathBuf>> = lines
+            .iter()
+            .filter_map(|line| match Line::parse(line) {
+                Line::Uri { uri, .. } => Some(Url::parse(uri).is_err().then(|| dir.join(uri))),
+                _ => None,
+            })
+            .collect();
+        let resumed = self.merge(&lines, &files);
+        self.resumed_uris = self
+            .segments
+            .values()
+            .filter_map(|lines| lines.last().cloned())
+            .collect();
+        // The origin's playlist decides these.
+        self.header.clear();
+        self.window.clear();
+        self.ended = false;
+        Ok(resumed)
+    }
+
+    /// Next media sequence number we have not seen yet.
+    fn next_sequence(&self) -> u64 {
+        self.segments
+            .keys()
+            .next_back()
+            .map_or(self.cut_at.unwrap_or(0), |s| s + 1)
+    }
+
+    /// Whether any segment was recorded yet, including ones cut off into
+    /// chunks.
+    fn has_recorded(&self) -> bool {
+        !self.segments.is_empty() || self.cut_at.is_some()
+    }
+
+    /// URL to reload. With blocking reload, ask the server to hold the
+    /// response until the next segment is available (`_HLS_msn`).
+    fn reload_url(&self) -> Url {
+        let mut url = self.url.clone();
+        if self.can_block_reload && self.has_recorded() {
+            url.query_pairs_mut()
+                .append_pair("_HLS_msn", &self.next_sequence().to_string());
+        }
+        url
+    }
+
+    /// Schedule the next reload per RFC 8216 section 6.3.4: one target duration
+    /// after a reload that changed the playlist, half of it after an unchanged
+    /// one, backing off further while it keeps not changing. Timing is measured
+    /// from when the reload started.
+    fn schedule_reload(&mut self, started: Instant, changed: bool) {
+        let target = self.target_duration.max(0.5);
+
+        if changed {
+            self.unchanged_reloads = 0;
+        } else {
+            self.unchanged_reloads = self.unchanged_reloads.saturating_add(1);
+        }
+
+        let wait = if changed && self.can_block_reload {
+            // The server holds the next request until new media exists.
+            0.0
+        } else if changed {
+            target
+        } else {
+            let backoff = 1.5f64.powi(self.unchanged_reloads.saturating_sub(1).min(8) as i32);
+            (target / 2.0 * backoff).min(target * 1.5)
+        };
+
+        self.next_reload = started + Duration::from_secs_f64(wait);
+    }
+
+    /// Merge a freshly rewritten playlist into the accumulated segment list,
+    /// returning the number of new segments. `files` holds the local file of
+    /// each URI line, in order.
+    fn merge(&mut self, lines: &[String], files: &[Option<PathBuf>]) -> usize {
+        let mut media_sequence = 0u64;
+        let mut header = Vec::new();
+        let mut pending = Vec::new();
+        let mut index = 0u64;
+        let mut in_header = true;
+        let mut added = 0usize;
+
+        for line in lines {
+            match Line::parse(line) {
+                Line::Blank(_) => continue,
+                Line::Tag(tag) => {
+                    let is_playlist_tag = PLAYLIST_TAGS.contains(&tag.name);
+                    match tag.name {
+                        "#EXT-X-MEDIA-SEQUENCE" => {
+                            media_sequence = tag.value().parse().unwrap_or(0);
+                        }
+                        "#EXT-X-TARGETDURATION" => {
+                            self.target_duration =
+                                tag.duration().unwrap_or(DEFAULT_TARGET_DURATION);
+                            header.push(line.clone());
+                        }
+                        "#EXT-X-ENDLIST" => self.ended = !self.ignore_endlist,
+                        "#EXT-X-SERVER-CONTROL" => {
+                            self.can_block_reload =
+                                tag.attribute("CAN-BLOCK-RELOAD").as_deref() == Some("YES");
+                            if in_header {
+                                header.push(line.clone());
+                            }
+                        }
+                        "#EXT-X-PLAYLIST-TYPE" => {}
+                        _ if in_header && is_playlist_tag => header.push(line.clone()),
+                        _ if is_playlist_tag => {}
+                        _ => {
+                            in_header = false;
+                            pending.push(line.clone());
+                        }
+                    }
+                    continue;
+                }
+                Line::Uri { .. } => {}
+            }
+
+            in_header = false;
+            // The number after the last segment's must fit as well, for the
+            // next reload and cut.
+            let Some(seq) = media_sequence
+                .checked_add(index)
+                .filter(|&seq| seq < u64::MAX)
+            else {
+                self.exhausted = true;
+                self.ended = true;
+                break;
+            };
+            let file = files.get(index as usize).cloned().flatten();
+            index += 1;
+            pending.push(line.clone());
+            let segment = std::mem::take(&mut pending);
+
+            if self.cut_at.is_some_and(|cut_at| seq < cut_at) || self.resumed_uris.contains(line) {
+                continue;
+            }
+            if let std::collections::btree_map::Entry::Vacant(e) = self.segments.entry(seq) {
+                e.insert(carry_tags(&mut self.carried, segment));
+                if let Some(file) = file {
+                    self.files.insert(seq, file);
+                }
+                added += 1;
+            }
+        }
+
+        if self.header.is_empty() {
+            self.header = header;
+        }
+        self.window = lines.to_vec();
+        self.window_start = media_sequence;
+
+        added
+    }
+
+    /// Drop the oldest segments beyond `retention` of media time from the
+    /// archive, keeping those still in the origin's window, and return the
+    /// local files no remaining segment refers to.
+    fn trim(&mut self, retention: Duration) -> Vec<PathBuf> {
+        let durations: Vec<(u64, f64)> = self
+            .segments
+            .iter()
+            .map(|(seq, lines)| (*seq, self.segment_duration(lines)))
+            .collect();
+        let mut total: f64 = durations.iter().map(|(_, d)| d).sum();
+
+        let mut removed = Vec::new();
+        for (seq, duration) in durations {
+            if total <= retention.as_secs_f64() || seq >= self.window_start {
+                break;
+            }
+            total -= duration;
+            let Some(lines) = self.segments.remove(&seq) else {
+                continue;
+            };
+            self.trimmed = true;
+            // Its own discontinuity goes away, as does the one marking a gap
+            // before the next segment, which is now the first.
+            if has_discontinuity(&lines) {
+                self.trimmed_discontinuities += 1;
+            }
+            if self
+                .segments
+                .first_key_value()
+                .is_some_and(|(next, lines)| *next != seq + 1 && !has_discontinuity(lines))
+            {
+                self.trimmed_discontinuities += 1;
+            }
+            if let Some(file) = self.files.remove(&seq) {
+                removed.push(file);
+            }
+
+            // The init section and keys in effect carry over to the new
+            // first segment.
+            if let Some(mut next) = self.segments.first_entry() {
+                let mut carried = carried_tags(std::slice::from_ref(&lines));
+                let segment = std::mem::take(next.get_mut());
+                *next.get_mut() = carry_tags(&mut carried, segment);
+            }
+        }
+
+        // Byte ranges of one file may be several segments.
+        removed.retain(|file| !self.files.values().any(|f| f == file));
+        removed.dedup();
+        removed
+    }
+
+    /// Take the segments accumulated so far as a playlist of their own,
+    /// continuing with the ones that follow (`--chunk-every`).
+    fn cut(&mut self) -> LivePlaylist {
+        let segments = std::mem::take(&mut self.segments);
+        let files = std::mem::take(&mut self.files);
+        if let Some((last, _)) = segments.last_key_value() {
+            self.cut_at = Some(last + 1);
+        }
+        let lines: Vec<Vec<String>> = segments.values().cloned().collect();
+        if !lines.is_empty() {
+            self.carried = carried_tags(&lines);
+        }
+        // Discontinuities of the cut segments no longer count in this playlist.
+        let mut prev: Option<u64> = None;
+        for (seq, lines) in &segments {
+            if has_discontinuity(lines) || prev.is_some_and(|p| p + 1 != *seq) {
+                self.trimmed_discontinuities += 1;
+            }
+            prev = Some(*seq);
+        }
+
+        let mut chunk = self.clone();
+        chunk.segments = segments;
+        chunk.files = files;
+        chunk
+    }
+
+    /// Duration of a segment from its `#EXTINF`, or the target duration.
+    fn segment_duration(&self, lines: &[String]) -> f64 {
+        lines
+            .iter()
+            .find_map(|line| match Line::parse(line) {
+                Line::Tag(tag) if tag.is("#EXTINF") => tag.duration(),
+                _ => None,
+            })
+            .unwrap_or(self.target_duration)
+    }
+
+    /// Lines of the accumulated playlist, borrowing the segments' rather than
+    /// copying them. A final playlist is marked as VOD and terminated with
+    /// `#EXT-X-ENDLIST`.
+    fn lines(&self, is_final: bool) -> Vec<Cow<'_, str>> {
+        let mut out: Vec<Cow<'_, str>> = Vec::new();
+        let first = self.segments.keys().next().copied().unwrap_or(0);
+
+        let mut has_discontinuity_sequence = false;
+        for line in &self.header {
+            match Line::parse(line) {
+                Line::Tag(tag) if tag.is("#EXT-X-DISCONTINUITY-SEQUENCE") => {
+                    has_discontinuity_sequence = true;
+                    let sequence = tag.value().parse().unwrap_or(0u64);
+                    out.push(
+                        format!(
+                            "#EXT-X-DISCONTINUITY-SEQUENCE:{}",
+                            sequence.saturating_add(self.trimmed_discontinuities)
+                        )
+                        .into(),
+                    );
+                }
+                _ => out.push(line.into()),
+            }
+        }
+        if out.first().map(|l| l.trim()) != Some("#EXTM3U") {
+            out.insert(0, "#EXTM3U".into());
+        }
+        if let Some(stamp) = &self.stamp {
+            out.insert(1, stamp.into());
+        }
+        // An EVENT playlist may only grow; one trimmed by `--retention` is a
+        // plain live playlist until it is final.
+        if is_final || !self.trimmed {
+            out.push(
+                format!(
+                    "#EXT-X-PLAYLIST-TYPE:{}",
+                    if is_final { "VOD" } else { "EVENT" }
+                )
+                .into(),
+            );
+        }
+        out.push(format!("#EXT-X-MEDIA-SEQUENCE:{first}").into());
+        if !has_discontinuity_sequence && self.trimmed_discontinuities > 0 {
+            out.push(
+                format!(
+                    "#EXT-X-DISCONTINUITY-SEQUENCE:{}",
+                    self.trimmed_discontinuities
+                )
+                .into(),
+            );
+        }
+
+        let mut prev: Option<u64> = None;
+        let mut keys = Keys::default();
+        for (offset, (seq, lines)) in self.segments.iter().enumerate() {
+            // Keys are distinct and start at `first`, so no larger than `seq`.
+            let position = first + offset as u64;
+            // Segments that slid out of the window between refreshes are lost;
+            // mark the gap so players don't assume continuous timestamps.
+            if prev.is_some_and(|p| p + 1 != *seq) && !has_discontinuity(lines) {
+                out.push("#EXT-X-DISCONTINUITY".into());
+            }
+            for line in lines {
+                if let Line::Tag(tag) = Line::parse(line) {
+                    keys.apply(&tag);
+                }
+            }
+            // After a gap, segments are numbered lower than by the origin;
+            // keys deriving the IV from the number get the origin's.
+            let (tags, uri) = lines.split_at(lines.len().saturating_sub(1));
+            out.extend(tags.iter().map(|line| Cow::from(line.as_str())));
+            if *seq != position {
+                out.extend(keys.pinned_ivs(*seq).into_iter().map(Cow::from));
+            }
+            out.extend(uri.iter().map(|line| Cow::from(line.as_str())));
+            prev = Some(*seq);
+        }
+
+        if is_final {
+            out.push("#EXT-X-ENDLIST".into());
+        }
+        out
+    }
+
+    /// Write the accumulated playlist to `path` (see [`lines`](Self::lines)).
+    async fn write_archive(&self, path: &Path, is_final: bool) -> Result<()> {
+        // Archives are playlists of our own, so they always end in a newline.
+        let layout = Layout {
+            final_newline: true,
+            ..self.layout
+        };
+        let partial = partial_path(path);
+        crate::write_playlist(&partial, layout, self.lines(is_final)).await?;
+        replace(&partial, path).await
+    }
+
+    /// Write the sliding-window and archive playlists. Once recording is
+    /// final, the regular path receives the complete VOD as well so that the
+    /// mirror's master playlist plays the whole recording.
+    async fn write(&self, is_final: bool) -> Result<()> {
+        self.write_archive(&archive_path(&self.local_path), is_final)
+            .await?;
+
+        if is_final {
+            self.write_archive(&self.local_path, true).await
+        } else if !self.window.is_empty() {
+            write_text(&self.local_path, &self.layout.render(&self.window)).await
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The last `#EXT-X-MAP` line among `segments` and the `#EXT-X-KEY` lines
+/// in effect after them, which apply to the segments that follow, moved to
+/// the new first segment when `--retention` drops the one that had them.
+fn carried_tags(segments: &[Vec<String>]) -> Vec<String> {
+    let mut map = None;
+    let mut keys = Keys::default();
+    for line in segments.iter().flatten() {
+        if let Line::Tag(tag) = Line::parse(line) {
+            if tag.is("#EXT-X-MAP") {
+                map = Some(line.clone());
+            }
+            keys.apply(&tag);
+        }
+    }
+    map.into_iter()
+        .chain(keys.lines().map(str::to_string))
+        .collect()
+}
+
+/// Put the `carried` tags the segment doesn't override in front of it,
+/// using them up: its own `#EXT-X-MAP` replaces the carried one, and a key
+/// of its own those of the same `KEYFORMAT`, or all of them if it is
+/// `METHOD=NONE`.
+fn carry_tags(carried: &mut Vec<String>, mut segment: Vec<String>) -> Vec<String> {
+    if carried.is_empty() {
+        return segment;
+    }
+    let own: Vec<Tag> = segment
+        .iter()
+        .filter_map(|l| match Line::parse(l) {
+            Line::Tag(tag) => Some(tag),
+            _ => None,
+        })
+        .collect();
+    let overridden = |tag: &Tag| {
+        own.iter().any(|own| {
+            own.is(tag.name)
+                && (!tag.is("#EXT-X-KEY")
+                    || own.attribute("METHOD").as_deref() == Some("NONE")
+                    || playlist::key_format(own) == playlist::key_format(tag))
+        })
+    };
+    let missing: Vec<String> = std::mem::take(carried)
+        .into_iter()
+        .filter(|line| match Line::parse(line) {
+            Line::Tag(tag) => !overridden(&tag),
+            _ => false,
+        })
+        .collect();
+    segment.splice(0..0, missing);
+    segment
+}
+
+fn has_discontinuity(lines: &[String]) -> bool {
+    lines.iter().any(|l| l.trim() == "#EXT-X-DISCONTINUITY")
+}
+
+impl LivePlaylist {
+    /// Queue the sliding-window and archive playlists for upload.
+    fn upload(&self, uploads: &Uploads) {
+        uploads.put(&archive_path(&self.local_path));
+        uploads.put(&self.local_path);
+    }
+}
+
+/// Write `text` to `path` the way playlists of a recording are written
+/// (see [`replace`]).
+async fn write_text(path: &Path, text: &str) -> Result<()> {
+    let partial = partial_path(path);
+    crate::write_file(&partial, text.as_bytes()).await?;
+    replace(&partial, path).await
+}
+
+/// `dir/index.m3u8` -> `dir/index.m3u8.partial.<pid>`, where a playlist of
+/// the recording is written before it takes the place of the one at `path`.
+fn partial_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(
+        "{}.partial.{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ))
+}
+
+/// Move the playlist written to `partial` to `path`. Players and `serve`
+/// reload playlists all through a recording, and so see either the old one
+/// or the new one in full, never one half written.
+async fn replace(partial: &Path, path: &Path) -> Result<()> {
+    tokio::fs::rename(partial, path)
+        .await
+        .with_context(|| format!("replacing {}", path.display()))
+}
+
+/// `dir/index.m3u8` -> `dir/index.archive.m3u8`
+fn archive_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_else(|| "m3u8".to_string());
+    path.with_file_name(format!("{stem}.archive.{ext}"))
+}
+
+/// Write an archive sibling for each master playlist, pointing every live
+/// media playlist reference at its archive counterpart.
+async fn write_archive_masters(
+    masters: &[(PathBuf, Vec<String>, Layout)],
+    playlists: &[LivePlaylist],
+    reference: impl Fn(&Path, &Path) -> String,
+) -> Result<()> {
+    for (master_path, lines, layout) in masters {
+        let Some(master_dir) = master_path.parent() else {
+            continue;
+        };
+
+        // Nested masters have archive siblings of their own.
+        let replacements: HashMap<String, String> = playlists
+            .iter()
+            .map(|p| &p.local_path)
+            .chain(masters.iter().map(|(path, _, _)| path))
+            .map(|path| {
+                (
+                    reference(path, master_dir),
+                    reference(&archive_path(path), master_dir),
+                )
+            })
+            .collect();
+
+        let rewritten: Vec<String> = lines
+            .iter()
+            .map(|line| match Line::parse(line) {
+                Line::Uri { uri, .. } => replacements
+                    .get(uri)
+                    .cloned()
+                    .unwrap_or_else(|| line.clone()),
+                // URI attributes wherever they are in the attribute list.
+                Line::Tag(tag) if !tag.is_comment() => {
+                    let mut new_line = String::with_capacity(line.len());
+                    let mut copied = 0;
+                    for (attr, (start, end)) in playlist::attribute_spans(line) {
+                        if !playlist::is_uri_attribute(attr) {
+                            continue;
+                        }
+                        if let Some(archive) = replacements.get(&line[start..end]) {
+                            new_line.push_str(&line[copied..start]);
+                            new_line.push_str(archive);
+                            copied = end;
+                        }
+                    }
+                    new_line.push_str(&line[copied..]);
+                    new_line
+                }
+                _ => line.clone(),
+            })
+            .collect();
+
+        let layout = Layout {
+            final_newline: true,
+            ..*layout
+        };
+        write_text(&archive_path(master_path), &layout.render(&rewritten)).await?;
+    }
+    Ok(())
+}
+
+/// How long a segment freshly listed in `playlist` may be unavailable
+/// before a 404 counts as a failure: one target duration, by which time the
+/// next playlist refresh is due anyway.
+pub(crate) fn availability_window(playlist: &HlsMediaPlaylist<'_>) -> Duration {
+    Duration::from_secs_f64(playlist.target_duration.unwrap_or(DEFAULT_TARGET_DURATION))
+}
+
+impl Mirror {
+    /// Keep recording every live media playlist discovered during the initial
+    /// mirror until all of them end, `deadline` passes, or Ctrl-C is pressed.
+    ///
+    /// Each media playlist is polled by a task of its own, with its own
+    /// reload schedule, downloading its new segments (`concurrency` at a time
+    /// across all of them) and writing its local playlists. This supervisor
+    /// rewrites the fetched playlists, since that needs the mirror's state,
+    /// keeps the books on what was downloaded and reports renditions that
+    /// fall behind.
+    pub(crate) async fn record_live(
+        &mut self,
+        deadline: Option<Instant>,
+        concurrency: usize,
+    ) -> Result<()> {
+        self.reporter.progress(Progress::Phase(Phase::Recording));
+        let mut playlists = Vec::new();
+        for (url, path) in std::mem::take(&mut self.live_playlists) {
+            let mut playlist = LivePlaylist::new(url, path);
+            playlist.ignore_endlist = self.treat_as == PlaylistTreatment::Live;
+            playlist.stamp = self
+                .catalog
+                .provenance
+                .as_ref()
+                .map(|provenance| provenance.hls_comment(&playlist.url));
+            if self.resume_recording {
+                let resumed = playlist.resume().await?;
+                if resumed > 0 {
+                    self.live_status(format!(
+                        "{}: resuming after {} recorded segment(s)",
+                        playlist.url, resumed
+                    ));
+                }
+            }
+            playlists.push(playlist);
+        }
+
+        if playlists.is_empty() {
+            self.reporter.report(Event::Detail {
+                message: "no live media playlists found; nothing to record".to_string(),
+            });
+            return Ok(());
+        }
+
+        self.live_status(format!("recording {} media playlist(s)", playlists.len()));
+        write_archive_masters(&self.master_playlists, &playlists, |target, dir| {
+            self.reference(target, dir)
+        })
+        .await?;
+
+        let uploader = self.upload_to.clone().map(|base| {
+            Uploader::start(
+                self.client.clone(),
+                base,
+                self.out_dir.clone(),
+                self.reporter.clone(),
+            )
+        });
+        let uploads = uploader.as_ref().map(Uploader::uploads);
+        if let Some(uploads) = &uploads {
+            // Everything mirrored before recording started.
+            uploads.put_tree(&self.out_dir).await?;
+        }
+
+        let (messages, mut inbox) = mpsc::unbounded_channel();
+        let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+        let writer = DiskWriter::start(
+            self.write_queue,
+            self.reporter.clone(),
+            self.digests.clone(),
+        );
+        let count = playlists.len();
+        let mut renditions = Vec::new();
+        let mut tasks = JoinSet::new();
+        for (index, playlist) in playlists.into_iter().enumerate() {
+            let (stop, stopped) = watch::channel(false);
+            let (cut, cuts) = mpsc::unbounded_channel();
+            renditions.push(Rendition::new(&playlist, stop, cut));
+            let recorder = Recorder {
+                index,
+                client: self.client.clone(),
+                reporter: self.reporter.clone(),
+                semaphore: semaphore.clone(),
+                messages: messages.clone(),
+                normalize_manifests: self.normalize_manifests,
+                preserve_mtime: self.preserve_mtime,
+                timeouts: self.timeouts,
+                race: self.race.clone(),
+                limits: self.limits.clone(),
+                segment_store: self.segment_store.clone(),
+                writes: writer.writes(),
+                retention: self.retention,
+                uploads: uploads.clone(),
+            };
+            tasks.spawn(recorder.run(playlist, stopped, cuts));
+        }
+        drop(messages);
+
+        let deadline = async move {
+            match deadline {
+                Some(deadline) => tokio::time::sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(deadline);
+        let mut lag_report = tokio::time::interval(LAG_REPORT_INTERVAL);
+        lag_report.tick().await;
+        let mut chunk_timer = self
+            .chunk_every
+            .map(|every| tokio::time::interval_at(Instant::now() + every, every));
+        let mut chunks = Chunks::default();
+
+        let mut finished: Vec<Option<LivePlaylist>> = vec![None; count];
+        let mut error = None;
+        let mut stopping = false;
+        let stop_all = |renditions: &[Rendition]| {
+            for rendition in renditions {
+                rendition.stop.send_replace(true);
+            }
+        };
+        while !tasks.is_empty() {
+            // Messages first: a recorder's last ones arrive before it is
+            // joined.
+            tokio::select! {
+                biased;
+                Some(message) = inbox.recv() => match message {
+                    Message::Cut { index, playlist } => {
+                        chunks.arrived(index, *playlist);
+                        self.write_ready_chunk(&mut chunks, uploads.as_ref()).await?;
+                    }
+                    message => self.handle_live_message(message, &mut renditions).await?,
+                },
+                Some(joined) = tasks.join_next() => match joined? {
+                    Ok(playlist) => {
+                        let index = renditions
+                            .iter()
+                            .position(|r| r.url == playlist.url)
+                            .unwrap_or_default();
+                        let playlist = finished[index].insert(playlist);
+                        // It can't answer a cut anymore.
+                        if chunks.is_awaiting(index) {
+                            chunks.arrived(index, playlist.cut());
+                            self.write_ready_chunk(&mut chunks, uploads.as_ref()).await?;
+                        }
+                    }
+                    Err(e) => {
+                        // Writing a local playlist failed; stop recording.
+                        stop_all(&renditions);
+                        stopping = true;
+                        error.get_or_insert(e);
+                    }
+                },
+                _ = &mut deadline, if !stopping => {
+                    self.live_status("recording window ended".to_string());
+                    stop_all(&renditions);
+                    stopping = true;
+                }
+                _ = tokio::signal::ctrl_c(), if !stopping => {
+                    self.live_status("interrupted, finalizing".to_string());
+                    stop_all(&renditions);
+                    stopping = true;
+                }
+                _ = lag_report.tick() => self.report_lag(&renditions),
+                _ = tick(&mut chunk_timer), if !stopping && !chunks.is_pending() => {
+                    chunks.start(count);
+                    for (index, rendition) in renditions.iter().enumerate() {
+                        match &mut finished[index] {
+                            Some(playlist) => chunks.arrived(index, playlist.cut()),
+                            None => chunks.request(index, &rendition.cut),
+                        }
+                    }
+                    self.write_ready_chunk(&mut chunks, uploads.as_ref()).await?;
+                }
+            }
+        }
+        // Every refresh waited for its segments to be written already.
+        let _ = writer.finish().await;
+        if !stopping {
+            if renditions.iter().any(|r| r.dropped) {
+                self.live_status("no renditions left to record".to_string());
+            } else {
+                self.live_status("all playlists ended".to_string());
+            }
+        }
+
+        for playlist in finished.iter().flatten() {
+            playlist.write(true).await?;
+            if let Some(uploads) = &uploads {
+                playlist.upload(uploads);
+            }
+        }
+        if self.chunk_every.is_some() {
+            // The output directory keeps the last chunk as well.
+            chunks.start(count);
+            for (index, playlist) in finished.iter_mut().enumerate() {
+                if let Some(playlist) = playlist {
+                    chunks.arrived(index, playlist.cut());
+                }
+            }
+            chunks.is_final = true;
+            self.write_ready_chunk(&mut chunks, uploads.as_ref())
+                .await?;
+        }
+        drop(uploads);
+        if let Some(uploader) = uploader {
+            uploader.finish().await;
+        }
+
+        error.map_or(Ok(()), Err)
+    }
+
+    /// Write the chunk being cut once every rendition's part has arrived:
+    /// its media playlists as VOD, the master playlists and every file they
+    /// refer to, hard-linked from the output directory where possible. Cut
+    /// segments are then deleted from the output directory, unless this is
+    /// the final chunk.
+    async fn write_ready_chunk(
+        &mut self,
+        chunks: &mut Chunks,
+        uploads: Option<&Uploads>,
+    ) -> Result<()> {
+        if !chunks.is_pending() || !chunks.awaiting.is_empty() {
+            return Ok(());
+        }
+        let playlists: Vec<LivePlaylist> = chunks.playlists.drain(..).flatten().collect();
+        let dir = chunk_dir(&self.out_dir, chunks.number);
+        chunks.number += 1;
+
+        let mut manifests = Vec::new();
+        for playlist in &playlists {
+            let relative = playlist.local_path.strip_prefix(&self.out_dir)?;
+            let path = dir.join(relative);
+            playlist.write_archive(&path, true).await?;
+            manifests.push(relative.to_path_buf());
+        }
+        for (path, lines, layout) in &self.master_playlists {
+            let relative = path.strip_prefix(&self.out_dir)?;
+            crate::write_file(&dir.join(relative), layout.render(lines).as_bytes()).await?;
+            manifests.push(relative.to_path_buf());
+        }
+        fill_chunk(&self.out_dir, &dir, manifests, self.rewrite_base.as_deref()).await?;
+        self.live_status(format!("chunk {} written", dir.display()));
+
+        if chunks.is_final {
+            return Ok(());
+        }
+        // Files of segments still in the origin's window stay for watching
+        // along, as does the last one of each playlist, whose byte ranges
+        // may continue in the next chunk.
+        let mut keep = HashSet::new();
+        for playlist in &playlists {
+            keep.extend(
+                playlist
+                    .files
+                    .range(playlist.window_start..)
+                    .map(|(_, file)| file.clone()),
+            );
+            keep.extend(playlist.files.values().next_back().cloned());
+        }
+        let candidates: Vec<PathBuf> = std::mem::take(&mut chunks.leftover)
+            .into_iter()
+            .chain(playlists.iter().flat_map(|p| p.files.values().cloned()))
+            .collect();
+        for file in candidates {
+            if keep.contains(&file) {
+                chunks.leftover.push(file);
+                continue;
+            }
+            let size = tokio::fs::metadata(&file).await.map_or(0, |m| m.len());
+            match tokio::fs::remove_file(&file).await {
+                Ok(()) => self.limits.release(size),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => self.reporter.report(Event::Warning {
+                    message: format!("removing {}: {}", file.display(), e),
+                }),
+            }
+            if let Some(uploads) = uploads {
+                uploads.delete(&file);
+            }
+        }
+        chunks.leftover.sort();
+        chunks.leftover.dedup();
+        Ok(())
+    }
+
+    async fn handle_live_message(
+        &mut self,
+        message: Message,
+        renditions: &mut [Rendition],
+    ) -> Result<()> {
+        match message {
+            // Chunks are assembled by `record_live`.
+            Message::Cut { .. } => {}
+            Message::Rewrite { index, text, reply } => {
+                let planned = self.plan_refresh(&renditions[index], &text).await;
+                // The recorder is gone if recording stopped meanwhile.
+                let _ = reply.send(planned);
+            }
+            Message::Downloaded(results) => {
+                for (download, result) in results {
+                    let url = &download.file.url;
+                    match result {
+                        Ok(container) => {
+                            self.tally.fetched += 1;
+                            let identity = self.identity(url);
+                            if let Some(state) = &mut self.state {
+                                state.record(&identity).await?;
+                            }
+                            if download.segment {
+                                self.segments
+                                    .push((download.file.rendition.clone(), url.clone()));
+                            }
+                            if let Some(container) = container
+                                && !self.catalog.has_container(&download.file.rendition)
+                            {
+                                self.catalog
+                                    .add_container(&download.file.rendition, container);
+                            }
+                        }
+                        // Allow the next refresh to try again.
+                        Err(_) => {
+                            self.visited.remove(&self.dedup_key(url));
+                        }
+                    }
+                }
+            }
+            Message::Refreshed {
+                index,
+                took,
+                target_duration,
+                pending,
+                expired,
+                clock_skew,
+                error,
+            } => {
+                if let Some(skew) = clock_skew {
+                    self.note_clock_skew(skew);
+                }
+                let rendition = &mut renditions[index];
+                rendition.target_duration = target_duration;
+                rendition.took = took;
+                rendition.pending = pending;
+                rendition.expired += expired;
+                match error {
+                    None => rendition.refreshed = Some(Instant::now()),
+                    Some(e) => self.reporter.report(Event::Warning {
+                        message: format!("refreshing {}: {:#}", rendition.url, e),
+                    }),
+                }
+                if expired > 0 {
+                    self.reporter.report(Event::Warning {
+                        message: format!(
+                            "{}: {} segment(s) expired before they could be recorded",
+                            rendition.url, expired
+                        ),
+                    });
+                }
+                self.check_lag(rendition, expired > 0);
+                self.reporter.progress(Progress::Lag(worst_lag(renditions)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep count of the refreshes in a row that left `rendition` behind the
+    /// live edge: ones that took longer than a target duration, found more
+    /// than [`MAX_PENDING_SEGMENTS`] segments published since the previous
+    /// one, or found segments expired. Warns when a rendition starts falling
+    /// behind and, with `--drop-lagging`, stops recording it once it has
+    /// been behind for that many refreshes.
+    fn check_lag(&self, rendition: &mut Rendition, expired: bool) {
+        let mut reasons = Vec::new();
+        if rendition.took.as_secs_f64() > rendition.target_duration {
+            reasons.push(format!(
+                "refreshing took {}, target duration is {}",
+                units::duration(rendition.took.as_secs_f64()),
+                units::duration(rendition.target_duration)
+            ));
+        }
+        if rendition.pending > MAX_PENDING_SEGMENTS {
+            reasons.push(format!(
+                "{} segments published since the previous refresh",
+                rendition.pending
+            ));
+        }
+        if reasons.is_empty() && !expired {
+            rendition.behind = 0;
+            return;
+        }
+
+        rendition.behind += 1;
+        if rendition.behind == 1 && !reasons.is_empty() {
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "{} is falling behind: {}",
+                    rendition.url,
+                    reasons.join(", ")
+                ),
+            });
+        }
+        if let Some(limit) = self.drop_lagging
+            && rendition.behind >= limit
+            && !rendition.dropped
+        {
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "dropping {}: behind the live edge for {} refreshes in a row",
+                    rendition.url, rendition.behind
+                ),
+            });
+            rendition.stop.send_replace(true);
+            rendition.dropped = true;
+        }
+    }
+
+    /// Rewrite a freshly fetched media playlist of `rendition`, collecting
+    /// the files it references that still need to be downloaded instead of
+    /// downloading them.
+    async fn plan_refresh(&mut self, rendition: &Rendition, text: &str) -> Result<Refresh> {
+        self.tally.fetched += 1;
+        let Some(HlsPlaylist::Media(media)) = HlsPlaylist::parse(text) else {
+            bail!("not an HLS media playlist");
+        };
+        let local_dir = rendition
+            .local_path
+            .parent()
+            .ok_or_else(|| {
+                anyhow!(
+                    "manifest path has no parent: {}",
+                    rendition.local_path.display()
+                )
+            })?
+            .to_path_buf();
+
+        let previous_rendition = std::mem::replace(&mut self.rendition, rendition.url.to_string());
+        let previous_plan = self.plan.replace(Vec::new());
+        let known: HashSet<Url> = self.discovered.keys().cloned().collect();
+        let lines = self
+            .rewrite_manifest(&rendition.url, &media.lines, &local_dir)
+            .await;
+        let planned = std::mem::replace(&mut self.plan, previous_plan).unwrap_or_default();
+
+        // Manifests referenced by the playlist (interstitial assets) were
+        // only fetched while planning; rewrite and write them now. Their
+        // files are among the planned ones, so they aren't duplicates.
+        let nested: Vec<Url> = self
+            .discovered
+            .keys()
+            .filter(|url| !known.contains(*url))
+            .cloned()
+            .collect();
+        if !nested.is_empty() {
+            let planned_keys: Vec<Url> = planned.iter().map(|f| self.dedup_key(&f.url)).collect();
+            self.prefetched.extend(planned_keys.iter().cloned());
+            for url in nested {
+                self.visited.remove(&url);
+                if let Err(e) = self.mirror_manifest(url.clone()).await {
+                    self.reporter.report(Event::Warning {
+                        message: format!("mirroring {}: {:#}", url, e),
+                    });
+                }
+            }
+            for key in &planned_keys {
+                self.prefetched.remove(key);
+            }
+        }
+        self.rendition = previous_rendition;
+
+        let uris: Vec<Option<Url>> = media
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::Uri { uri, .. } => Some(rendition.url.join(uri).ok()),
+                _ => None,
+            })
+            .collect();
+        let files = uris
+            .iter()
+            .map(|url| {
+                url.as_ref()
+                    .filter(|url| crate::is_fetchable(url))
+                    .map(|url| self.path_for_url(url, false))
+            })
+            .collect();
+        let segments: HashSet<Url> = uris.into_iter().flatten().collect();
+        let downloads = planned
+            .into_iter()
+            .map(|file| Download {
+                path: self.path_for_url(&file.url, false),
+                segment: segments.contains(&file.url),
+                file,
+            })
+            .collect();
+
+        Ok(Refresh {
+            lines: lines?,
+            downloads,
+            files,
+        })
+    }
+
+    /// Log the origin's clock skew when first measured and whenever it
+    /// changes by a second or more.
+    fn note_clock_skew(&mut self, skew: f64) {
+        if self
+            .clock_skew
+            .is_none_or(|last| (skew - last).abs() >= 1.0)
+        {
+            schedule::report_clock_skew(skew, &self.reporter);
+            self.clock_skew = Some(skew);
+        }
+    }
+
+    /// Report how far behind each rendition is: the time since its last
+    /// successful refresh beyond one target duration, the segments that
+    /// refresh found pending and those that expired unrecorded so far.
+    fn report_lag(&self, renditions: &[Rendition]) {
+        let now = Instant::now();
+        let lags: Vec<String> = renditions
+            .iter()
+            .map(|r| {
+                if r.dropped {
+                    return format!("{} dropped", r.url);
+                }
+                format!(
+                    "{} lag {}, {} pending, {} expired (refresh {}, target {})",
+                    r.url,
+                    units::duration(r.lag(now)),
+                    r.pending,
+                    r.expired,
+                    units::duration(r.took.as_secs_f64()),
+                    units::duration(r.target_duration)
+                )
+            })
+            .collect();
+        self.live_status(lags.join("; "));
+        self.reporter.progress(Progress::Lag(worst_lag(renditions)));
+    }
+
+    fn live_status(&self, message: String) {
+        self.reporter.report(Event::Status {
+            tag: "LIVE",
+            message,
+        });
+    }
+}
+
+/// How often the supervisor reports the lag of every rendition.
+const LAG_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// More segments than this published between two refreshes means the
+/// recorder is not keeping up with the live edge: refreshing once per target
+/// duration finds one, or two with some jitter.
+const MAX_PENDING_SEGMENTS: usize = 2;
+
+/// What a rendition's recorder asks of, or tells, the supervisor.
+enum Message {
+    /// The segments a recorder had accumulated when asked for a chunk.
+    Cut {
+        index: usize,
+        playlist: Box<LivePlaylist>,
+    },
+    /// Rewrite a freshly fetched playlist; the reply carries the rewritten
+    /// lines and the files still to be downloaded.
+    Rewrite {
+        index: usize,
+        text: String,
+        reply: oneshot::Sender<Result<Refresh>>,
+    },
+    /// Files of a refresh were downloaded, or failed to.
+    Downloaded(Vec<(Download, Result<Option<Container>>)>),
+    /// A refresh is done, after `took`, having found `pending` segments
+    /// published since the previous one and `expired` ones gone before they
+    /// could be recorded; `error` if it failed. `clock_skew` is how far the
+    /// origin's clock is ahead of ours, if it sent a `Date`.
+    Refreshed {
+        index: usize,
+        took: Duration,
+        target_duration: f64,
+        pending: usize,
+        expired: u64,
+        clock_skew: Option<f64>,
+        error: Option<anyhow::Error>,
+    },
+}
+
+/// A rewritten playlist and the files it references that are not mirrored yet.
+struct Refresh {
+    lines: Vec<String>,
+    downloads: Vec<Download>,
+    /// Local file of each URI line, if it has one.
+    files: Vec<Option<PathBuf>>,
+}
+
+struct Download {
+    file: PlannedFile,
+    path: PathBuf,
+    /// A media segment, as opposed to a key or init segment.
+    segment: bool,
+}
+
+/// What the supervisor knows about a rendition being recorded.
+struct Rendition {
+    url: Url,
+    local_path: PathBuf,
+    target_duration: f64,
+    /// Duration of the last refresh.
+    took: Duration,
+    /// When the last successful refresh finished.
+    refreshed: Option<Instant>,
+    /// Segments the last refresh found published since the one before.
+    pending: usize,
+    /// Segments that expired from the origin's window before they could be
+    /// recorded.
+    expired: u64,
+    /// Refreshes in a row that left the rendition behind the live edge.
+    behind: u32,
+    /// Stops the rendition's recorder.
+    stop: watch::Sender<bool>,
+    /// Asks the rendition's recorder for a chunk.
+    cut: mpsc::UnboundedSender<()>,
+    /// Recording stopped because it couldn't keep up (`--drop-lagging`).
+    dropped: bool,
+}
+
+impl Rendition {
+    fn new(
+        playlist: &LivePlaylist,
+        stop: watch::Sender<bool>,
+        cut: mpsc::UnboundedSender<()>,
+    ) -> Self {
+        Self {
+            url: playlist.url.clone(),
+            local_path: playlist.local_path.clone(),
+            target_duration: playlist.target_duration,
+            took: Duration::ZERO,
+            refreshed: None,
+            pending: 0,
+            expired: 0,
+            behind: 0,
+            stop,
+            cut,
+            dropped: false,
+        }
+    }
+
+    /// Seconds since the last successful refresh beyond one target duration.
+    fn lag(&self, now: Instant) -> f64 {
+        let since = self.refreshed.map_or(Duration::ZERO, |t| now - t);
+        (since.as_secs_f64() - self.target_duration).max(0.0)
+    }
+}
+
+/// The lag of the rendition furthest behind, among those still recorded.
+fn worst_lag(renditions: &[Rendition]) -> f64 {
+    let now = Instant::now();
+    renditions
+        .iter()
+        .filter(|r| !r.dropped)
+        .map(|r| r.lag(now))
+        .fold(0.0, f64::max)
+}
+
+/// The chunk being cut (`--chunk-every`), assembled from the renditions'
+/// parts as their recorders hand them over.
+#[derive(Default)]
+struct Chunks {
+    /// Number of the chunk being cut, or of the next one.
+    number: usize,
+    /// Each rendition's part, by rendition index; empty while no chunk is
+    /// being cut.
+    playlists: Vec<Option<LivePlaylist>>,
+    /// Renditions whose part hasn't arrived yet.
+    awaiting: HashSet<usize>,
+    /// The last chunk, cut when recording stops.
+    is_final: bool,
+    /// Files of cut segments that were still needed when their chunk was
+    /// written.
+    leftover: Vec<PathBuf>,
+}
+
+impl Chunks {
+    fn start(&mut self, renditions: usize) {
+        self.playlists = vec![None; renditions];
+    }
+
+    fn is_pending(&self) -> bool {
+        !self.playlists.is_empty()
+    }
+
+    fn request(&mut self, index: usize, cut: &mpsc::UnboundedSender<()>) {
+        if cut.send(()).is_ok() {
+            self.awaiting.insert(index);
+        }
+    }
+
+    fn is_awaiting(&self, index: usize) -> bool {
+        self.awaiting.contains(&index)
+    }
+
+    fn arrived(&mut self, index: usize, playlist: LivePlaylist) {
+        self.awaiting.remove(&index);
+        self.playlists[index] = Some(playlist);
+    }
+}
+
+/// `event` -> `event_000`, the directory of chunk `number`.
+fn chunk_dir(out_dir: &Path, number: usize) -> PathBuf {
+    let name = out_dir
+        .file_name()
+        .map_or_else(|| "chunk".into(), |name| name.to_string_lossy());
+    out_dir.with_file_name(format!("{name}_{number:03}"))
+}
+
+/// Give `chunk_dir` every file the `manifests` written to it refer to,
+/// following referenced playlists in turn, from the same place in `out_dir`:
+/// hard links where possible, copies otherwise.
+async fn fill_chunk(
+    out_dir: &Path,
+    chunk_dir: &Path,
+    mut manifests: Vec<PathBuf>,
+    rewrite_base: Option<&str>,
+) -> Result<()> {
+    let mut seen: HashSet<PathBuf> = manifests.iter().cloned().collect();
+    while let Some(manifest) = manifests.pop() {
+        let text = crate::encrypt::read(&chunk_dir.join(&manifest)).await?;
+        let text = String::from_utf8_lossy(&text);
+        let Some(playlist) = HlsPlaylist::parse(&text) else {
+            continue;
+        };
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+
+        for line in playlist.lines() {
+            let references: Vec<&str> = match line {
+                Line::Uri { uri, .. } => vec![uri],
+                Line::Tag(tag) if !tag.is_comment() => crate::playlist::attribute_spans(tag.line)
+                    .into_iter()
+                    .filter(|(name, _)| crate::playlist::is_uri_attribute(name))
+                    .map(|(_, (start, end))| &tag.line[start..end])
+                    .collect(),
+                _ => continue,
+            };
+            for reference in references {
+                let Some(relative) = local_reference(reference, dir, rewrite_base) else {
+                    continue;
+                };
+                if !seen.insert(relative.clone()) {
+                    continue;
+                }
+                let source = out_dir.join(&relative);
+                let target = chunk_dir.join(&relative);
+                if !tokio::fs::try_exists(&source).await.unwrap_or(false) {
+                    continue;
+                }
+                if let Some(parent) = target.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                if tokio::fs::hard_link(&source, &target).await.is_err() {
+                    tokio::fs::copy(&source, &target)
+                        .await
+                        .with_context(|| format!("copying {}", source.display()))?;
+                }
+                if relative.extension().is_some_and(|e| e == "m3u8") {
+                    manifests.push(relative);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Path below the output directory that a rewritten reference in a
+/// manifest in `dir` (relative to the output directory) names, if it names
+/// a mirrored file at all.
+fn local_reference(reference: &str, dir: &Path, rewrite_base: Option<&str>) -> Option<PathBuf> {
+    if reference.contains(['?', '#']) {
+        return None;
+    }
+    let (mut path, rest) = match rewrite_base {
+        Some(base) if reference.starts_with(base) => (PathBuf::new(), &reference[base.len()..]),
+        _ if reference.starts_with('/') || Url::parse(reference).is_ok() => return None,
+        _ => (dir.to_path_buf(), reference),
+    };
+    for segment in rest.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if !path.pop() {
+                    return None;
+                }
+            }
+            segment => path.push(segment),
+        }
+    }
+    Some(path)
+}
+
+/// Wait for the next tick of `timer`, or forever without one.
+async fn tick(timer: &mut Option<tokio::time::Interval>) {
+    match timer {
+        Some(timer) => {
+            timer.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Polls one media playlist and downloads its new segments.
+struct Recorder {
+    index: usize,
+    client: Client,
+    reporter: SharedReporter,
+    /// Shared by all recorders, bounding concurrent downloads.
+    semaphore: Arc<Semaphore>,
+    messages: mpsc::UnboundedSender<Message>,
+    normalize_manifests: bool,
+    /// Date segments to their `Last-Modified` header.
+    preserve_mtime: bool,
+    /// How long requests may take (`--min-throughput`).
+    timeouts: Timeouts,
+    /// Other origins to race segment downloads against.
+    race: Race,
+    /// Rate and disk limits of the run.
+    limits: Limits,
+    /// Where segments are kept with `--segment-store`.
+    segment_store: Option<SegmentStore>,
+    /// Where downloaded segments are written, shared by all recorders.
+    writes: Writes,
+    /// How much of the recording to keep (`--retention`).
+    retention: Option<Duration>,
+    /// Where finished files go for `--upload-to`.
+    uploads: Option<Uploads>,
+}
+
+impl Recorder {
+    /// Refresh `playlist` on its schedule until it ends or `stop` is
+    /// signalled, returning it for finalizing.
+    async fn run(
+        self,
+        mut playlist: LivePlaylist,
+        mut stop: watch::Receiver<bool>,
+        mut cuts: mpsc::UnboundedReceiver<()>,
+    ) -> Result<LivePlaylist> {
+        while !playlist.ended && !*stop.borrow() {
+            tokio::select! {
+                _ = tokio::time::sleep_until(playlist.next_reload) => {}
+                Some(()) = cuts.recv() => {
+                    let chunk = playlist.cut();
+                    playlist.write(false).await?;
+                    self.upload(&playlist);
+                    self.send(Message::Cut {
+                        index: self.index,
+                        playlist: Box::new(chunk),
+                    })?;
+                    continue;
+                }
+                _ = stop.changed() => break,
+            }
+
+            let started = Instant::now();
+            let recorded = playlist.has_recorded().then(|| playlist.next_sequence());
+            let (added, expired, error) = match self.refresh(&mut playlist).await {
+                Ok(added) => {
+                    let removed = self
+                        .retention
+                        .map(|retention| playlist.trim(retention))
+                        .unwrap_or_default();
+                    playlist.write(false).await?;
+                    self.upload(&playlist);
+                    self.remove(&removed).await;
+                    // Segments that left the window since the last refresh
+                    // without being recorded.
+                    let expired =
+                        recorded.map_or(0, |next| playlist.window_start.saturating_sub(next));
+                    (added, expired, None)
+                }
+                Err(e) => (0, 0, Some(e)),
+            };
+            playlist.schedule_reload(started, added > 0 || playlist.ended);
+            self.send(Message::Refreshed {
+                index: self.index,
+                took: started.elapsed(),
+                target_duration: playlist.target_duration,
+                // The first refresh catches up on the whole window.
+                pending: if recorded.is_some() { added } else { 0 },
+                expired,
+                clock_skew: playlist.clock_skew,
+                error,
+            })?;
+        }
+        Ok(playlist)
+    }
+
+    async fn refresh(&self, playlist: &mut LivePlaylist) -> Result<usize> {
+        let request = self.client.get(playlist.reload_url());
+        let sent = SystemTime::now();
+        let resp = if playlist.can_block_reload {
+            // RFC 8216bis: a blocked reload should be abandoned after three target durations.
+            request
+                .timeout(Duration::from_secs_f64(playlist.target_duration * 3.0))
+                .send_logged()
+                .await
+                .with_context(|| format!("GET {}", playlist.url))?
+        } else {
+            self.timeouts.send(request, &playlist.url).await?
+        }
+        .error_for_status()
+        .with_context(|| format!("status error for {}", playlist.url))?;
+        let received = SystemTime::now();
+        playlist.clock_skew = resp
+            .headers()
+            .get(reqwest::header::DATE)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| schedule::clock_skew(date, sent, received));
+        let body = self.timeouts.body(resp, &playlist.url).await?;
+        let text = String::from_utf8_lossy(&body).into_owned();
+
+        let Some(HlsPlaylist::Media(media)) = HlsPlaylist::parse(&text) else {
+            bail!("not an HLS media playlist");
+        };
+        let window = availability_window(&media);
+        if !self.normalize_manifests {
+            playlist.layout = Layout::of(&text);
+        }
+
+        let (reply, planned) = oneshot::channel();
+        self.send(Message::Rewrite {
+            index: self.index,
+            text,
+            reply,
+        })?;
+        let Refresh {
+            lines,
+            downloads,
+            files,
+        } = planned.await.map_err(|_| anyhow!("recording stopped"))??;
+
+        let results = self.download(downloads, window).await;
+        if let Some(uploads) = &self.uploads {
+            for (download, result) in &results {
+                if result.is_ok() {
+                    uploads.put(&download.path);
+                }
+            }
+        }
+        let failure = results
+            .iter()
+            .find_map(|(_, result)| result.as_ref().err().map(|e| format!("{:#}", e)));
+        self.send(Message::Downloaded(results))?;
+        if let Some(failure) = failure {
+            bail!(failure);
+        }
+
+        let added = playlist.merge(&lines, &files);
+        if playlist.exhausted {
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "{}: media sequence numbers reach {}, recording stops",
+                    playlist.url,
+                    u64::MAX
+                ),
+            });
+        }
+        if added > 0 {
+            self.reporter.report(Event::Status {
+                tag: "LIVE",
+                message: format!("{} +{} segment(s)", playlist.url, added),
+            });
+        }
+        Ok(added)
+    }
+
+    /// Download the files of one refresh, retrying 404s within `window`,
+    /// and return each with its outcome in playlist order.
+    async fn download(
+        &self,
+        downloads: Vec<Download>,
+        window: Duration,
+    ) -> Vec<(Download, Result<Option<Container>>)> {
+        let mut tasks = JoinSet::new();
+        for (index, download) in downloads.iter().enumerate() {
+            let client = self.client.clone();
+            let reporter = self.reporter.clone();
+            let semaphore = self.semaphore.clone();
+            let url = download.file.url.clone();
+            let path = download.path.clone();
+            let options = DownloadOptions {
+                encrypted: download.file.encrypted,
+                availability_window: Some(window),
+                timeouts: self.timeouts,
+                race: self.race.clone(),
+                limits: self.limits.clone(),
+                preserve_mtime: self.preserve_mtime,
+                segment_store: self.segment_store.clone(),
+                writes: Some(self.writes.clone()),
+            };
+            tasks.spawn(async move {
+                let result = match semaphore.acquire_owned().await {
+                    Ok(_permit) => {
+                        reporter.report(Event::Fetch {
+                            kind: Resource::Binary,
+                            url: url.as_str(),
+                            path: &path,
+                        });
+                        discover::download(&client, &url, &path, options, &reporter).await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                (index, result)
+            });
+        }
+
+        let mut results: Vec<Option<Result<Option<Container>>>> =
+            downloads.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            match joined {
+                Ok((index, result)) => results[index] = Some(result),
+                Err(e) => {
+                    // A panicked download; count it against every file left.
+                    for result in results.iter_mut().filter(|r| r.is_none()) {
+                        *result = Some(Err(anyhow!("download task failed: {}", e)));
+                    }
+                }
+            }
+        }
+        // Segments go on disk before the playlists listing them.
+        if let Err(e) = self.writes.flush().await {
+            for result in results.iter_mut().flatten().filter(|r| r.is_ok()) {
+                *result = Err(anyhow!("writing segments: {:#}", e));
+            }
+        }
+
+        downloads
+            .into_iter()
+            .zip(results)
+            .map(|(download, result)| {
+                (
+                    download,
+                    result.unwrap_or_else(|| Err(anyhow!("download task failed"))),
+                )
+            })
+            .collect()
+    }
+
+    fn upload(&self, playlist: &LivePlaylist) {
+        if let Some(uploads) = &self.uploads {
+            playlist.upload(uploads);
+        }
+    }
+
+    /// Delete segment files dropped by `--retention`.
+    async fn remove(&self, files: &[PathBuf]) {
+        for file in files {
+            let size = tokio::fs::metadata(file).await.map_or(0, |m| m.len());
+            match tokio::fs::remove_file(file).await {
+                Ok(()) => self.limits.release(size),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => self.reporter.report(Event::Warning {
+                    message: format!("removing {}: {}", file.display(), e),
+                }),
+            }
+            if let Some(uploads) = &self.uploads {
+                uploads.delete(file);
+            }
+        }
+        if !files.is_empty() {
+            self.reporter.report(Event::Detail {
+                message: format!("retention: removed {} segment file(s)", files.len()),
+            });
+        }
+    }
+
+    fn send(&self, message: Message) -> Result<()> {
+        self.messages
+            .send(message)
+            .map_err(|_| anyhow!("recording stopped"))
+    }
+}
@@ -0,0 +1,72 @@
+//! `--plan`: what mirroring would do to an output directory that already
+//! holds files, such as an earlier capture.
+//!
+//! A planning pass fetches the manifests and nothing else, noting every file
+//! it would write. Those are then held against the files on disk: the ones
+//! missing would be added, the ones present replaced, and whatever else is
+//! there is left alone, as are segments an earlier run of the same mirror
+//! has downloaded already.
+
+use crate::catalog::CATALOG_FILE;
+use crate::report::{Event, SharedReporter};
+use crate::state::STATE_FILE;
+use crate::status::STATUS_FILE;
+use crate::units;
+use anyhow::Result;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// Report, file by file, what a run writing `writes` would add to,
+/// replace in and leave alone in `out_dir`.
+pub(crate) fn report(out_dir: &Path, writes: &[PathBuf], reporter: &SharedReporter) -> Result<()> {
+    let mut existing = Vec::new();
+    if out_dir.is_dir() {
+        crate::collect_files(out_dir, out_dir, &mut existing)?;
+    }
+    // The state and status files are streamrip's own bookkeeping, not part
+    // of the mirror.
+    let existing: BTreeSet<String> = existing
+        .into_iter()
+        .filter(|file| file != STATE_FILE && file != STATUS_FILE)
+        .collect();
+    // The catalog is written at the end of every run.
+    let written: BTreeSet<String> = writes
+        .iter()
+        .map(|path| relative(out_dir, path))
+        .chain(std::iter::once(CATALOG_FILE.to_string()))
+        .collect();
+
+    let (replaced, added): (Vec<&String>, Vec<&String>) =
+        written.iter().partition(|file| existing.contains(*file));
+    let kept: Vec<&String> = existing.difference(&written).collect();
+    for (tag, files) in [("ADD", &added), ("REPL", &replaced), ("KEEP", &kept)] {
+        for file in files {
+            reporter.report(Event::Status {
+                tag,
+                message: file.to_string(),
+            });
+        }
+    }
+    reporter.report(Event::Status {
+        tag: "PLAN",
+        message: format!(
+            "{} file(s) to add, {} to replace and {} left alone in {}",
+            units::count(added.len()),
+            units::count(replaced.len()),
+            units::count(kept.len()),
+            out_dir.display()
+        ),
+    });
+    Ok(())
+}
+
+/// `path` relative to `out_dir`, with forward slashes as [`crate::collect_files`]
+/// gives them.
+fn relative(out_dir: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(out_dir).unwrap_or(path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
@@ -0,0 +1,135 @@
+//! Keys and init segments are mirrored before any media segment, so that
+//! authentication and DRM problems show up within seconds instead of after
+//! gigabytes of media.
+//!
+//! The discovery pass (see [`crate::discover`]) collects the keys and init
+//! segments the manifests reference; these are downloaded and checked before
+//! anything else.
+
+use crate::Mirror;
+use crate::container::Container;
+use crate::report::Event;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use url::Url;
+
+/// Kind of resource fetched ahead of media.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// An HLS `#EXT-X-KEY` or `#EXT-X-SESSION-KEY`.
+    #[cfg(feature = "hls")]
+    Key,
+    /// An HLS `#EXT-X-MAP` or a DASH initialization segment.
+    Init,
+}
+
+/// A key or init segment found by the discovery pass.
+#[derive(Debug, Clone)]
+pub struct PriorityFile {
+    pub kind: Priority,
+    pub rendition: String,
+    pub url: Url,
+}
+
+/// The keys and init segments found by a walk, in the order found, once
+/// each.
+#[derive(Debug, Default)]
+pub struct PriorityFiles {
+    files: Vec<PriorityFile>,
+    /// Kind of each of `files`, by its [`Mirror::dedup_key`].
+    kinds: HashMap<Url, Priority>,
+}
+
+impl PriorityFiles {
+    /// Kind of the file with the dedup key `key`, if it was found.
+    pub fn kind(&self, key: &Url) -> Option<Priority> {
+        self.kinds.get(key).copied()
+    }
+
+    pub fn into_files(self) -> Vec<PriorityFile> {
+        self.files
+    }
+}
+
+impl Mirror {
+    /// Remember a key or init segment while collecting them.
+    pub(crate) fn note_priority(&mut self, kind: Priority, url: &Url) {
+        let key = self.dedup_key(url);
+        if let Some(found) = &mut self.priority
+            && let Entry::Vacant(entry) = found.kinds.entry(key)
+        {
+            entry.insert(kind);
+            found.files.push(PriorityFile {
+                kind,
+                rendition: self.rendition.clone(),
+                url: url.clone(),
+            });
+        }
+    }
+
+    /// Mirror the keys and init segments found by the discovery pass,
+    /// warning about any that don't look right.
+    pub(crate) async fn fetch_priority(&mut self, files: Vec<PriorityFile>) -> Result<()> {
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        self.reporter.report(Event::Status {
+            tag: "PRIO",
+            message: format!(
+                "fetching {} key(s) and init segment(s) before media",
+                files.len()
+            ),
+        });
+
+        for file in files {
+            let previous = std::mem::replace(&mut self.rendition, file.rendition);
+            let result = self.mirror_binary(file.url.clone()).await;
+            self.rendition = previous;
+            #[cfg(feature = "hls")]
+            if let Err(e) = &result
+                && file.kind == Priority::Key
+                && self.keyring.has_keys_by_order()
+            {
+                // Not downloaded again; the rewrite pass gives it a key
+                // supplied by order.
+                let key = self.dedup_key(&file.url);
+                self.visited.insert(key.clone());
+                self.failed.insert(key, format!("{:#}", e));
+                continue;
+            }
+            result?;
+            self.prefetched.insert(self.dedup_key(&file.url));
+            self.check_priority(file.kind, &file.url).await;
+        }
+
+        Ok(())
+    }
+
+    /// Warn about keys that aren't 16 bytes and init segments that are
+    /// neither fMP4 nor WebM, which usually are error pages.
+    async fn check_priority(&mut self, kind: Priority, url: &Url) {
+        let path = self.path_for_url(url, false);
+        let Ok(data) = crate::encrypt::read(&path).await else {
+            return;
+        };
+
+        let problem = match kind {
+            #[cfg(feature = "hls")]
+            Priority::Key if data.len() != 16 => {
+                format!("key {} is {} bytes, expected 16", url, data.len())
+            }
+            Priority::Init
+                if !matches!(
+                    Container::sniff(&data),
+                    Some(Container::Fmp4 | Container::WebM)
+                ) =>
+            {
+                format!("init segment {} is neither fMP4 nor WebM", url)
+            }
+            _ => return,
+        };
+        self.reporter.report(Event::Warning { message: problem });
+    }
+}
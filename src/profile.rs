@@ -0,0 +1,118 @@
+//! Named profiles (`--profile nightly-sports`): saved bundles of options for
+//! recurring capture jobs.
+//!
+//! A profiles file holds `[name]` sections of `key = value` lines, where a
+//! key is any long option of a mirroring run (`start_url`, `output_dir`,
+//! `max_height`, `header_variants`, ...; `-` and `_` are interchangeable).
+//! Boolean options take `true` or `false`. A selected profile's options are
+//! placed before those on the command line, so options given there override
+//! the profile's, and repeatable ones (`rep_id`, `ignore_query_params`, ...)
+//! add to them.
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// Where profiles are read from unless `--profiles` says otherwise:
+/// `$XDG_CONFIG_HOME/streamrip/profiles`, or `~/.config/streamrip/profiles`.
+pub fn default_path() -> Option<PathBuf> {
+    let config = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config.join("streamrip").join("profiles"))
+}
+
+/// `args` (the program's command line) with the options of the profile
+/// selected by `--profile`, if any, inserted after the program name.
+pub fn expand(args: Vec<OsString>) -> Result<Vec<OsString>> {
+    let name = option(&args, "--profile");
+    let Some(name) = name else {
+        return Ok(args);
+    };
+    let path = match option(&args, "--profiles") {
+        Some(path) => PathBuf::from(path),
+        None => default_path().ok_or_else(|| {
+            anyhow!("no profiles file: set --profiles, $XDG_CONFIG_HOME or $HOME")
+        })?,
+    };
+    let options = load(&path, &name)?;
+
+    let mut expanded = Vec::with_capacity(args.len() + options.len());
+    let mut args = args.into_iter();
+    expanded.extend(args.next());
+    expanded.extend(options.into_iter().map(OsString::from));
+    expanded.extend(args);
+    Ok(expanded)
+}
+
+/// The value of the long option `flag` in `args`, as `--flag value` or
+/// `--flag=value`.
+fn option(args: &[OsString], flag: &str) -> Option<String> {
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == flag {
+            return args.next().map(|value| value.into_owned());
+        }
+        if let Some(value) = arg.strip_prefix(flag).and_then(|a| a.strip_prefix('=')) {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// The options of profile `name` in the profiles file at `path`, as
+/// command-line arguments.
+fn load(path: &Path, name: &str) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading profiles {}", path.display()))?;
+    parse(&text, name)
+        .with_context(|| format!("reading profile '{}' from {}", name, path.display()))
+}
+
+fn parse(text: &str, name: &str) -> Result<Vec<String>> {
+    let mut found = false;
+    let mut in_profile = false;
+    let mut options = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_profile = section.trim() == name;
+            found |= in_profile;
+            continue;
+        }
+        if !in_profile {
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("line {}: expected `key = value`", number + 1))?;
+        let key = key.trim().replace('_', "-");
+        let value = value.trim();
+        if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            bail!("line {}: invalid option '{}'", number + 1, key);
+        }
+        if key == "profile" || key == "profiles" {
+            bail!("line {}: profiles can't select other profiles", number + 1);
+        }
+        match value {
+            "true" => options.push(format!("--{}", key)),
+            "false" => {}
+            value => options.push(format!("--{}={}", key, value)),
+        }
+    }
+
+    if !found {
+        bail!("no such profile");
+    }
+    Ok(options)
+}
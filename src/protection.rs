@@ -0,0 +1,180 @@
+//! DRM signalling: DASH `ContentProtection` descriptors, HLS key tags and
+//! `pssh` boxes in ISO BMFF init segments, collected into the catalog.
+
+use crate::Mirror;
+use crate::bmff;
+use crate::catalog::{Protection, system_name};
+use crate::container::Container;
+use crate::report::Event;
+use anyhow::{Result, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use url::Url;
+
+/// System id of Apple FairPlay, which HLS signals by `KEYFORMAT` only.
+#[cfg(feature = "hls")]
+const FAIRPLAY_SYSTEM_ID: &str = "94ce86fb-07ff-4f43-adb8-93d2fa968ca2";
+
+impl Mirror {
+    /// Add `protection` to the catalog, reporting it the first time it is
+    /// seen. Fails under `--strict` if it names a DRM system.
+    pub(crate) fn record_protection(&mut self, protection: Protection) -> Result<()> {
+        let mut message = protection.rendition.clone();
+        match (&protection.system_id, &protection.scheme) {
+            (Some(id), _) => {
+                message.push_str(&format!(": {}", system_name(id).unwrap_or(id)));
+            }
+            (None, Some(scheme)) => message.push_str(&format!(": {scheme}")),
+            (None, None) => {}
+        }
+        if let Some(kid) = &protection.default_kid {
+            message.push_str(&format!(", KID {kid}"));
+        }
+        if protection.pssh.is_some() {
+            message.push_str(&format!(" (PSSH from {})", protection.origin));
+        }
+
+        if self.strict && protection.system_id.is_some() {
+            bail!("{} is DRM-protected (--strict)", message);
+        }
+        if self.catalog.add_protection(protection) {
+            self.reporter.report(Event::Status {
+                tag: "DRM",
+                message,
+            });
+        }
+        Ok(())
+    }
+
+    /// Record the `pssh` boxes of a downloaded init segment.
+    pub(crate) async fn scan_init_segment(&mut self, url: &Url) -> Result<()> {
+        if self.plan.is_some() {
+            return Ok(());
+        }
+        let Some(path) = self.url_to_path.get(&self.dedup_key(url)) else {
+            return Ok(());
+        };
+        let Ok(data) = crate::encrypt::read(path).await else {
+            return Ok(());
+        };
+        if Container::sniff(&data) != Some(Container::Fmp4) {
+            return Ok(());
+        }
+
+        for (system_id, pssh) in pssh_boxes(&data) {
+            self.record_protection(Protection {
+                rendition: self.rendition.clone(),
+                origin: "init",
+                scheme: None,
+                system_id: Some(system_id),
+                default_kid: None,
+                pssh: Some(BASE64.encode(pssh)),
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The `pssh` boxes in the `moov` and `moof` boxes of an ISO BMFF file, as
+/// (system id, complete box).
+pub fn pssh_boxes(data: &[u8]) -> Vec<(String, &[u8])> {
+    let mut found = Vec::new();
+    collect_pssh(data, &mut found);
+    found
+}
+
+fn collect_pssh<'a>(data: &'a [u8], found: &mut Vec<(String, &'a [u8])>) {
+    for found_box in bmff::boxes(data) {
+        match &found_box.kind {
+            b"moov" | b"moof" => collect_pssh(found_box.body, found),
+            // version and flags, then the 16-byte system id
+            b"pssh" if found_box.body.len() >= 20 => {
+                found.push((format_uuid(&found_box.body[4..20]), found_box.whole));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn format_uuid(bytes: &[u8]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// Normalize a UUID as written in manifests (`urn:uuid:` prefix, braces,
+/// upper case) to the lowercase hyphenated form.
+fn normalize_uuid(value: &str) -> String {
+    let value = value.trim();
+    let value = value
+        .strip_prefix("urn:uuid:")
+        .or_else(|| value.strip_prefix("URN:UUID:"))
+        .unwrap_or(value);
+    value.trim_matches(['{', '}']).to_ascii_lowercase()
+}
+
+/// `ContentProtection` descriptors directly below a DASH element.
+#[cfg(feature = "dash")]
+pub fn content_protection(node: &roxmltree::Node, rendition: &str) -> Vec<Protection> {
+    node.children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "ContentProtection")
+        .map(|cp| {
+            let scheme = cp.attribute("schemeIdUri");
+            Protection {
+                rendition: rendition.to_string(),
+                origin: "mpd",
+                scheme: scheme.map(str::to_string),
+                system_id: scheme
+                    .filter(|s| s.to_ascii_lowercase().starts_with("urn:uuid:"))
+                    .map(normalize_uuid),
+                default_kid: cp
+                    .attributes()
+                    .find(|a| a.name() == "default_KID")
+                    .map(|a| normalize_uuid(a.value())),
+                pssh: cp
+                    .children()
+                    .find(|n| n.is_element() && n.tag_name().name() == "pssh")
+                    .and_then(|n| n.text())
+                    .map(|t| t.trim().to_string()),
+            }
+        })
+        .collect()
+}
+
+/// DRM signalling of an `#EXT-X-KEY` or `#EXT-X-SESSION-KEY` tag, unless it
+/// has `METHOD=NONE`.
+#[cfg(feature = "hls")]
+pub fn hls_key_protection(line: &str, rendition: &str) -> Option<Protection> {
+    let method = crate::playlist::attribute(line, "METHOD")?;
+    if method == "NONE" {
+        return None;
+    }
+    let keyformat = crate::playlist::attribute(line, "KEYFORMAT").unwrap_or("identity".to_string());
+
+    let system_id = if keyformat.to_ascii_lowercase().starts_with("urn:uuid:") {
+        Some(normalize_uuid(&keyformat))
+    } else if keyformat == "com.apple.streamingkeydelivery" {
+        Some(FAIRPLAY_SYSTEM_ID.to_string())
+    } else {
+        None
+    };
+
+    // Widevine and PlayReady carry their PSSH box inline as a data: URI.
+    let pssh = crate::playlist::attribute(line, "URI")
+        .and_then(|uri| uri.split_once(";base64,").map(|(_, data)| data.to_string()));
+
+    Some(Protection {
+        rendition: rendition.to_string(),
+        origin: "playlist",
+        scheme: Some(format!("{method}/{keyformat}")),
+        system_id,
+        default_kid: None,
+        pssh,
+    })
+}
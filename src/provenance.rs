@@ -0,0 +1,108 @@
+//! Provenance stamps (`--provenance`): which version of streamrip mirrored
+//! what, when and with which options, written into every rewritten manifest
+//! as a comment and into `catalog.json`.
+//!
+//! Stamps are opt-in: without them, mirroring the same input twice yields
+//! byte-identical manifests.
+
+#![cfg_attr(not(all(feature = "hls", feature = "dash")), allow(dead_code))]
+
+use crate::debug_http::redact_url;
+use crate::report::json_string;
+use crate::schedule;
+use std::time::SystemTime;
+use url::Url;
+
+/// Start of a provenance comment in an HLS playlist.
+const HLS_PREFIX: &str = "# streamrip ";
+
+/// Start of a provenance comment in an MPD.
+const XML_PREFIX: &str = "<!-- streamrip ";
+
+/// How and when a mirror was made.
+#[derive(Debug, Clone)]
+pub struct Provenance {
+    /// Start URL, with credentials redacted.
+    pub source: String,
+    /// When the run started, as RFC 3339.
+    pub mirrored_at: String,
+    /// Hash of the options the run was given, to tell runs with the same
+    /// options apart from others.
+    pub options: String,
+}
+
+impl Provenance {
+    /// Provenance of a run starting `at` from `start_url`; `options` is a
+    /// description of every option given (their `Debug` output).
+    pub fn new(start_url: &Url, options: &str, at: SystemTime) -> Self {
+        Self {
+            source: redact_url(start_url),
+            mirrored_at: schedule::format_timestamp(at),
+            options: format!("{:016x}", fnv1a(options.as_bytes())),
+        }
+    }
+
+    fn describe(&self, source: &Url) -> String {
+        format!(
+            "{} mirror of {}, made {}, options {}",
+            env!("CARGO_PKG_VERSION"),
+            redact_url(source),
+            self.mirrored_at,
+            self.options
+        )
+    }
+
+    /// Comment line stamping an HLS playlist mirrored from `source`, to
+    /// follow `#EXTM3U`.
+    pub fn hls_comment(&self, source: &Url) -> String {
+        format!("{}{}", HLS_PREFIX, self.describe(source))
+    }
+
+    /// `text` of an MPD mirrored from `source` with a stamp after its XML
+    /// declaration, or at the start.
+    pub fn stamp_xml(&self, text: &str, source: &Url) -> String {
+        // `--` may not appear inside an XML comment.
+        let comment = format!(
+            "{}{} -->",
+            XML_PREFIX,
+            self.describe(source).replace("--", "%2D%2D")
+        );
+        let newline = if text.contains("\r\n") { "\r\n" } else { "\n" };
+        let body = text.trim_start_matches('\u{feff}');
+        let start = text.len() - body.len();
+        match body.starts_with("<?xml").then(|| body.find("?>")).flatten() {
+            Some(end) => {
+                let (head, tail) = text.split_at(start + end + 2);
+                format!("{}{}{}{}", head, newline, comment, tail)
+            }
+            None => {
+                let (bom, tail) = text.split_at(start);
+                format!("{}{}{}{}", bom, comment, newline, tail)
+            }
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"tool\": \"streamrip\", \"version\": {}, \"source\": {}, \"mirrored_at\": {}, \"options\": {}}}",
+            json_string(env!("CARGO_PKG_VERSION")),
+            json_string(&self.source),
+            json_string(&self.mirrored_at),
+            json_string(&self.options)
+        )
+    }
+}
+
+/// Whether `line` of a rewritten manifest is a provenance stamp.
+pub fn is_stamp(line: &str) -> bool {
+    let line = line.trim_start_matches('\u{feff}').trim_start();
+    line.starts_with(HLS_PREFIX) || line.starts_with(XML_PREFIX)
+}
+
+/// 64-bit FNV-1a, which unlike the standard library's hasher is the same
+/// across builds.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
@@ -0,0 +1,243 @@
+//! Download rate and disk limits of a run (`--max-rate`, `--disk-quota`).
+//!
+//! A run may cap its download rate and the size of its output directory.
+//! The jobs of `streamrip watch` may in addition share a rate cap of the
+//! whole watcher, each getting the part its `bandwidth_share` is of the
+//! shares of all jobs running. Rates hold on average: once a file is
+//! downloaded, the download waits until the bytes so far fit the rate,
+//! holding up the next request of the run. A live recording with a larger
+//! share thus keeps up while a huge mirror next to it takes what is left.
+
+#![cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+
+use crate::units;
+use anyhow::{Context, Result, anyhow, bail};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Rate cap shared by the jobs of a watcher (`watch --max-rate`).
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Scheduler {
+    state: Arc<Mutex<Shares>>,
+}
+
+#[derive(Debug, Default)]
+struct Shares {
+    /// Bytes per second for all jobs together.
+    max_rate: Option<u64>,
+    /// Share of each job running, by its number.
+    running: HashMap<u64, u32>,
+    next: u64,
+}
+
+impl Scheduler {
+    pub fn new(max_rate: Option<u64>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(Shares {
+                max_rate,
+                ..Shares::default()
+            })),
+        }
+    }
+
+    /// The part of the watcher's rate a job of `share` gets when it runs.
+    pub fn slot(&self, share: u32) -> Slot {
+        Slot {
+            scheduler: self.clone(),
+            share: share.max(1),
+        }
+    }
+
+    /// Bytes per second job `id` may download at now.
+    fn rate(&self, id: u64) -> Option<u64> {
+        let shares = self.state.lock().expect("scheduler poisoned");
+        let max_rate = shares.max_rate?;
+        let total: u64 = shares.running.values().map(|&s| u64::from(s)).sum();
+        let share = u64::from(*shares.running.get(&id)?);
+        Some((max_rate * share / total.max(1)).max(1))
+    }
+}
+
+/// A job's share of the watcher's rate.
+#[derive(Clone)]
+pub(crate) struct Slot {
+    scheduler: Scheduler,
+    share: u32,
+}
+
+impl std::fmt::Debug for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Slot").field("share", &self.share).finish()
+    }
+}
+
+/// Limits of one run, shared by all of its downloads; unlimited by default.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Limits {
+    state: Option<Arc<RunLimits>>,
+}
+
+#[derive(Debug)]
+struct RunLimits {
+    /// Bytes per second (`--max-rate`).
+    max_rate: Option<u64>,
+    /// The watcher's rate and this run's number with it.
+    slot: Option<(Scheduler, u64)>,
+    /// Bytes the output directory may hold (`--disk-quota`).
+    disk_quota: Option<u64>,
+    usage: Mutex<Usage>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    /// Bytes in the output directory, as far as known.
+    used: u64,
+    /// When the bytes downloaded so far fit the rate.
+    paid_until: Instant,
+}
+
+impl Limits {
+    /// Limits for a run into `out_dir`, whose present content counts against
+    /// `disk_quota`.
+    pub async fn new(
+        max_rate: Option<u64>,
+        disk_quota: Option<u64>,
+        slot: Option<&Slot>,
+        out_dir: &Path,
+    ) -> Result<Self> {
+        if max_rate.is_none() && disk_quota.is_none() && slot.is_none() {
+            return Ok(Self::default());
+        }
+        let used = match disk_quota {
+            Some(_) => directory_size(out_dir)
+                .await
+                .with_context(|| format!("measuring {}", out_dir.display()))?,
+            None => 0,
+        };
+        let slot = slot.map(|slot| {
+            let mut shares = slot.scheduler.state.lock().expect("scheduler poisoned");
+            let id = shares.next;
+            shares.next += 1;
+            shares.running.insert(id, slot.share);
+            (slot.scheduler.clone(), id)
+        });
+        Ok(Self {
+            state: Some(Arc::new(RunLimits {
+                max_rate,
+                slot,
+                disk_quota,
+                usage: Mutex::new(Usage {
+                    used,
+                    paid_until: Instant::now(),
+                }),
+            })),
+        })
+    }
+
+    /// Account for a downloaded file of `size` bytes before it is written:
+    /// fail if it would exceed the disk quota, and wait until the run's
+    /// downloads fit its rate.
+    pub async fn take(&self, size: u64) -> Result<()> {
+        let Some(limits) = &self.state else {
+            return Ok(());
+        };
+        let rate = [
+            limits.max_rate,
+            limits
+                .slot
+                .as_ref()
+                .and_then(|(scheduler, id)| scheduler.rate(*id)),
+        ]
+        .into_iter()
+        .flatten()
+        .min();
+        let until = {
+            let mut usage = limits.usage.lock().expect("usage poisoned");
+            if let Some(quota) = limits.disk_quota
+                && usage.used + size > quota
+            {
+                bail!(
+                    "disk quota of {} reached ({} used)",
+                    units::size(quota),
+                    units::size(usage.used)
+                );
+            }
+            usage.used += size;
+            rate.map(|rate| {
+                let start = usage.paid_until.max(Instant::now());
+                usage.paid_until = start + Duration::from_secs_f64(size as f64 / rate as f64);
+                usage.paid_until
+            })
+        };
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+        Ok(())
+    }
+
+    /// Account for files of `size` bytes deleted from the output directory.
+    #[cfg_attr(not(feature = "hls"), allow(dead_code))]
+    pub fn release(&self, size: u64) {
+        if let Some(limits) = &self.state {
+            let mut usage = limits.usage.lock().expect("usage poisoned");
+            usage.used = usage.used.saturating_sub(size);
+        }
+    }
+}
+
+impl Drop for RunLimits {
+    fn drop(&mut self) {
+        if let Some((scheduler, id)) = &self.slot {
+            scheduler
+                .state
+                .lock()
+                .expect("scheduler poisoned")
+                .running
+                .remove(id);
+        }
+    }
+}
+
+/// Total size of the files below `dir`; zero if it doesn't exist yet.
+async fn directory_size(dir: &Path) -> Result<u64> {
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e.into()),
+    };
+    let mut size = 0;
+    while let Some(entry) = entries.next_entry().await? {
+        let file_type = entry.file_type().await?;
+        if file_type.is_dir() {
+            size += Box::pin(directory_size(&entry.path())).await?;
+        } else if file_type.is_file() {
+            size += entry.metadata().await?.len();
+        }
+    }
+    Ok(size)
+}
+
+/// Parse `--disk-quota`: bytes, with an optional `k`, `M`, `G` or `T`
+/// suffix for binary multiples (`20G`).
+pub fn parse_size(s: &str) -> Result<u64> {
+    let trimmed = s.trim().trim_end_matches(['B', 'b']);
+    let (number, multiple) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1u64 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        Some((i, 't' | 'T')) => (&trimmed[..i], 1 << 40),
+        _ => (trimmed, 1),
+    };
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("expected a size such as 500M or 20G, got '{}'", s))?;
+    let size = (value * multiple as f64) as u64;
+    if size == 0 {
+        bail!("the disk quota must be above zero");
+    }
+    Ok(size)
+}
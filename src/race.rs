@@ -0,0 +1,297 @@
+//! Racing equivalent origins (`--race-origins`).
+//!
+//! Manifests served by several CDNs name the same content more than once: a
+//! DASH element may have several `BaseURL`s, and HLS content steering lists
+//! each variant once per pathway (`PATHWAY-ID`). Each such pair of locations
+//! is remembered as a pair of URL prefixes. With `--race-origins`, a file
+//! below one of them is requested from both at once, happy-eyeballs style,
+//! and the first successful response is downloaded; the other request is
+//! dropped. A slow origin thus costs no time, and a failing one is stepped
+//! around. Which origin served each file is summarized at the end of the
+//! run and, with `--race-trace`, written down as JSON lines.
+
+#![cfg_attr(not(all(feature = "hls", feature = "dash")), allow(dead_code))]
+
+use crate::Mirror;
+use crate::playlist::{HlsMasterPlaylist, Line};
+use crate::report::{Event, SharedReporter, json_string};
+use crate::timeouts::Timeouts;
+use crate::units;
+use anyhow::{Context, Result};
+use reqwest::{Client, Response};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+use url::Url;
+
+/// Equivalent origins and what each served; shared by all downloads of a
+/// run. Disabled unless created with [`Race::enabled`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Race {
+    state: Option<Arc<Mutex<State>>>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// Groups of URL prefixes naming the same content, the first as the
+    /// manifest refers to it.
+    groups: Vec<Vec<String>>,
+    served: Vec<Served>,
+}
+
+/// A file downloaded from the faster origin.
+#[derive(Debug)]
+struct Served {
+    url: String,
+    origin: String,
+    /// Until the response started.
+    millis: u128,
+}
+
+impl Race {
+    pub fn enabled() -> Self {
+        Self {
+            state: Some(Arc::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.state.is_some()
+    }
+
+    /// Remember that `primary` and `alternates` name the same content.
+    pub fn add(&self, primary: &Url, alternates: &[Url], reporter: &SharedReporter) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let mut group = vec![primary.to_string()];
+        for alternate in alternates {
+            let alternate = alternate.to_string();
+            if !group.contains(&alternate) {
+                group.push(alternate);
+            }
+        }
+        let mut state = state.lock().expect("race state poisoned");
+        if group.len() < 2 || state.groups.contains(&group) {
+            return;
+        }
+        reporter.report(Event::Detail {
+            message: format!("racing {} against {}", group[0], group[1..].join(", ")),
+        });
+        state.groups.push(group);
+    }
+
+    /// Remember the pathways of `master`, at `url`, as equivalent: variants
+    /// that differ only in their `PATHWAY-ID` and URI name the same content
+    /// below different prefixes.
+    pub fn add_pathways(&self, url: &Url, master: &HlsMasterPlaylist, reporter: &SharedReporter) {
+        if !self.is_enabled() {
+            return;
+        }
+        // Variants by their attributes without PATHWAY-ID.
+        let mut by_key: BTreeMap<String, Vec<(String, Url)>> = BTreeMap::new();
+        for variant in master.variants.iter().filter(|v| !v.i_frame) {
+            let (Line::Tag(tag), Some(uri)) = (master.lines[variant.tag], variant.uri) else {
+                continue;
+            };
+            let (Some(pathway), Line::Uri { uri, .. }) =
+                (tag.attribute("PATHWAY-ID"), master.lines[uri])
+            else {
+                continue;
+            };
+            let Ok(target) = url.join(uri) else {
+                continue;
+            };
+            let key: Vec<&str> = tag
+                .value()
+                .split(',')
+                .filter(|a| !a.starts_with("PATHWAY-ID="))
+                .collect();
+            by_key
+                .entry(key.join(","))
+                .or_default()
+                .push((pathway, target));
+        }
+        for variants in by_key.values() {
+            let Some((pathway, first)) = variants.first() else {
+                continue;
+            };
+            for (other_pathway, other) in &variants[1..] {
+                if other_pathway == pathway {
+                    continue;
+                }
+                if let Some((a, b)) = distinct_prefixes(first, other) {
+                    self.add(&a, &[b], reporter);
+                }
+            }
+        }
+    }
+
+    /// The other location of `url`, if it is below a known origin, and the
+    /// prefixes of both.
+    fn alternate(&self, url: &Url) -> Option<(Url, String, String)> {
+        let state = self.state.as_ref()?.lock().expect("race state poisoned");
+        let text = url.as_str();
+        // The most specific prefix decides.
+        let (group, index) = state
+            .groups
+            .iter()
+            .flat_map(|group| {
+                group
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, prefix)| text.starts_with(prefix.as_str()))
+                    .map(move |(index, _)| (group, index))
+            })
+            .max_by_key(|(group, index)| group[*index].len())?;
+        let prefix = &group[index];
+        let other = &group[if index == 0 { 1 } else { 0 }];
+        let alternate = Url::parse(&format!("{}{}", other, &text[prefix.len()..])).ok()?;
+        Some((alternate, prefix.clone(), other.clone()))
+    }
+
+    /// Send a GET for `url`, raced against its other location if it has
+    /// one. A response that isn't successful loses to the other origin's;
+    /// if both fail, that of `url` is returned.
+    pub async fn send(&self, client: &Client, url: &Url, timeouts: Timeouts) -> Result<Response> {
+        let Some((alternate, origin, other_origin)) = self.alternate(url) else {
+            return timeouts.send(client.get(url.clone()), url).await;
+        };
+        let started = Instant::now();
+        let primary = timeouts.send(client.get(url.clone()), url);
+        let other = timeouts.send(client.get(alternate.clone()), &alternate);
+        tokio::pin!(primary, other);
+        let won = |result: &Result<Response>| {
+            result.as_ref().is_ok_and(|resp| resp.status().is_success())
+        };
+        let (first_is_primary, first) = tokio::select! {
+            result = &mut primary => (true, result),
+            result = &mut other => (false, result),
+        };
+        let (primary_result, other_result) = if won(&first) {
+            self.record(
+                url,
+                if first_is_primary {
+                    &origin
+                } else {
+                    &other_origin
+                },
+                started,
+            );
+            return first;
+        } else if first_is_primary {
+            (first, other.await)
+        } else {
+            (primary.await, first)
+        };
+        if won(&primary_result) {
+            self.record(url, &origin, started);
+            primary_result
+        } else if won(&other_result) {
+            self.record(url, &other_origin, started);
+            other_result
+        } else {
+            primary_result
+        }
+    }
+
+    fn record(&self, url: &Url, origin: &str, started: Instant) {
+        if let Some(state) = &self.state {
+            state
+                .lock()
+                .expect("race state poisoned")
+                .served
+                .push(Served {
+                    url: url.to_string(),
+                    origin: origin.to_string(),
+                    millis: started.elapsed().as_millis(),
+                });
+        }
+    }
+
+    /// Report how many files each origin served.
+    pub fn summarize(&self, reporter: &SharedReporter) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let state = state.lock().expect("race state poisoned");
+        if state.served.is_empty() {
+            if state.groups.is_empty() {
+                reporter.report(Event::Warning {
+                    message: "--race-origins: the manifests name no equivalent origins".to_string(),
+                });
+            }
+            return;
+        }
+        let mut wins: BTreeMap<&str, usize> = BTreeMap::new();
+        for served in &state.served {
+            *wins.entry(&served.origin).or_default() += 1;
+        }
+        let wins: Vec<String> = wins
+            .into_iter()
+            .map(|(origin, count)| format!("{} served {}", origin, units::count(count)))
+            .collect();
+        reporter.report(Event::Status {
+            tag: "RACE",
+            message: wins.join(", "),
+        });
+    }
+
+    /// Write which origin served each raced file to `path`, one JSON object
+    /// per line.
+    pub async fn write_trace(&self, path: &Path) -> Result<()> {
+        let mut out = String::new();
+        if let Some(state) = &self.state {
+            for served in &state.lock().expect("race state poisoned").served {
+                out.push_str(&format!(
+                    "{{\"url\":{},\"origin\":{},\"ms\":{}}}\n",
+                    json_string(&served.url),
+                    json_string(&served.origin),
+                    served.millis
+                ));
+            }
+        }
+        tokio::fs::write(path, out)
+            .await
+            .with_context(|| format!("writing race trace {}", path.display()))
+    }
+}
+
+impl Mirror {
+    /// Remember `others`, BaseURLs relative to `parent`, as locations of
+    /// the same content as `base`.
+    #[cfg(feature = "dash")]
+    pub(crate) fn race_base_urls(&self, parent: &Url, base: &Url, others: &[&str]) {
+        let others: Vec<Url> = others
+            .iter()
+            .filter_map(|other| parent.join(other).ok())
+            .collect();
+        if !others.is_empty() {
+            self.race.add(base, &others, &self.reporter);
+        }
+    }
+}
+
+/// The prefixes of `a` and `b` before the path they have in common, e.g.
+/// `https://cdn-a/` and `https://cdn-b/` for `https://cdn-a/live/v1.m3u8`
+/// and `https://cdn-b/live/v1.m3u8`; `None` if there is no difference.
+fn distinct_prefixes(a: &Url, b: &Url) -> Option<(Url, Url)> {
+    let a_parts: Vec<&str> = a.path().split('/').collect();
+    let b_parts: Vec<&str> = b.path().split('/').collect();
+    let common = a_parts
+        .iter()
+        .rev()
+        .zip(b_parts.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    // The file names may differ too; then the directories are the prefixes.
+    let keep = |parts: &[&str]| parts[..parts.len() - common.max(1)].join("/") + "/";
+    let mut a_prefix = a.clone();
+    a_prefix.set_path(&keep(&a_parts));
+    a_prefix.set_query(None);
+    let mut b_prefix = b.clone();
+    b_prefix.set_path(&keep(&b_parts));
+    b_prefix.set_query(None);
+    (a_prefix != b_prefix).then_some((a_prefix, b_prefix))
+}
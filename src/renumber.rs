@@ -0,0 +1,144 @@
+//! Keeping a rewritten HLS media playlist consistent when segments are left
+//! out of it: the tags describing a dropped segment go with it, a gap in the
+//! middle is marked as a discontinuity, and dropping leading segments is
+//! reflected in `#EXT-X-MEDIA-SEQUENCE` and `#EXT-X-DISCONTINUITY-SEQUENCE`.
+//! Segments after a gap are numbered one less for every segment dropped, so
+//! those encrypted with a key taking its IV from the media sequence number
+//! are given the origin's number as an explicit IV.
+
+use crate::playlist::Keys;
+
+/// Tags that describe only the segment that follows them. Everything else
+/// (keys, maps, date ranges, discontinuities) carries over to the next
+/// segment when one is dropped.
+const SEGMENT_TAGS: &[&str] = &[
+    "#EXTINF",
+    "#EXT-X-BYTERANGE",
+    "#EXT-X-PROGRAM-DATE-TIME",
+    "#EXT-X-GAP",
+    "#EXT-X-BITRATE",
+];
+
+const DISCONTINUITY: &str = "#EXT-X-DISCONTINUITY";
+
+/// Bookkeeping for segments dropped while rewriting one media playlist.
+#[derive(Debug, Default)]
+pub struct Renumbering {
+    /// The origin's number of its first segment.
+    media_sequence: u64,
+    /// Index of the first output line belonging to the current segment.
+    segment_start: usize,
+    kept: usize,
+    dropped: usize,
+    leading_dropped: u64,
+    gap_pending: bool,
+}
+
+impl Renumbering {
+    /// Note the playlist's `#EXT-X-MEDIA-SEQUENCE`.
+    pub fn media_sequence(&mut self, first: u64) {
+        self.media_sequence = first;
+    }
+
+    /// Note that a URI line was just appended to `lines`, its segment under
+    /// `keys`.
+    pub fn keep(&mut self, lines: &mut Vec<String>, keys: &Keys) {
+        if std::mem::take(&mut self.gap_pending)
+            && !lines[self.segment_start..]
+                .iter()
+                .any(|l| l.trim() == DISCONTINUITY)
+        {
+            lines.insert(self.segment_start, DISCONTINUITY.to_string());
+        }
+        if self.dropped as u64 > self.leading_dropped {
+            let sequence = self
+                .media_sequence
+                .saturating_add((self.kept + self.dropped) as u64);
+            let uri = lines.len() - 1;
+            lines.splice(uri..uri, keys.pinned_ivs(sequence));
+        }
+        self.kept += 1;
+        self.segment_start = lines.len();
+    }
+
+    /// Drop the segment whose tags end `lines` (its URI line not appended).
+    pub fn drop_segment(&mut self, lines: &mut Vec<String>) {
+        let mut i = self.segment_start;
+        while i < lines.len() {
+            if SEGMENT_TAGS.contains(&tag_name(lines[i].trim())) {
+                lines.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        self.dropped += 1;
+        if self.kept == 0 {
+            self.leading_dropped += 1;
+        } else {
+            self.gap_pending = true;
+        }
+    }
+
+    /// Number of segments dropped so far.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Fix up the sequence numbers once the playlist is complete.
+    pub fn finish(&self, lines: &mut Vec<String>) {
+        if self.dropped == 0 {
+            return;
+        }
+
+        if self.leading_dropped > 0 {
+            add_to_counter(lines, "#EXT-X-MEDIA-SEQUENCE", self.leading_dropped);
+        }
+
+        // Discontinuities carried over to the new first segment are counted
+        // by #EXT-X-DISCONTINUITY-SEQUENCE instead, as a server sliding its
+        // window would do.
+        let first_segment = lines
+            .iter()
+            .position(|l| {
+                let l = l.trim();
+                !l.is_empty() && !l.starts_with('#')
+            })
+            .unwrap_or(lines.len());
+        let before = lines.len();
+        let mut index = 0;
+        lines.retain(|l| {
+            index += 1;
+            index > first_segment || l.trim() != DISCONTINUITY
+        });
+        let folded = (before - lines.len()) as u64;
+        if folded > 0 {
+            add_to_counter(lines, "#EXT-X-DISCONTINUITY-SEQUENCE", folded);
+        }
+    }
+}
+
+/// Add `by` to the value of a playlist counter tag, inserting it after
+/// `#EXT-X-TARGETDURATION` (or `#EXTM3U`) if absent.
+fn add_to_counter(lines: &mut Vec<String>, tag: &str, by: u64) {
+    if let Some(line) = lines.iter_mut().find(|l| tag_name(l.trim()) == tag) {
+        let value: u64 = line
+            .split_once(':')
+            .and_then(|(_, v)| v.trim().parse().ok())
+            .unwrap_or(0);
+        // The value is the origin's; it may be anything up to u64::MAX.
+        *line = format!("{tag}:{}", value.saturating_add(by));
+        return;
+    }
+
+    let anchor = lines
+        .iter()
+        .position(|l| tag_name(l.trim()) == "#EXT-X-TARGETDURATION")
+        .or_else(|| lines.iter().position(|l| l.trim() == "#EXTM3U"))
+        .map_or(0, |i| i + 1);
+    lines.insert(anchor, format!("{tag}:{by}"));
+}
+
+fn tag_name(line: &str) -> &str {
+    line.split_once(':').map_or(line, |(name, _)| name)
+}
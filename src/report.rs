@@ -0,0 +1,368 @@
+//! Progress and diagnostics reporting, decoupled from the mirroring logic.
+//!
+//! Everything user-facing is emitted as an [`Event`] through a [`Reporter`];
+//! the command line picks one of the implementations below.
+
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Kind of resource being downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    /// An HLS playlist.
+    #[cfg(feature = "hls")]
+    Playlist,
+    /// A DASH MPD.
+    #[cfg(feature = "dash")]
+    Mpd,
+    /// Any other file (segments, keys, subtitles, ...).
+    Binary,
+}
+
+impl Resource {
+    fn tag(self) -> &'static str {
+        match self {
+            #[cfg(feature = "hls")]
+            Resource::Playlist => "M3U8",
+            #[cfg(feature = "dash")]
+            Resource::Mpd => "MPD",
+            Resource::Binary => "BIN",
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            #[cfg(feature = "hls")]
+            Resource::Playlist => "playlist",
+            #[cfg(feature = "dash")]
+            Resource::Mpd => "mpd",
+            Resource::Binary => "binary",
+        }
+    }
+}
+
+/// Something worth telling the user about.
+#[derive(Debug)]
+pub enum Event<'a> {
+    /// A resource is being downloaded to a local path.
+    Fetch {
+        kind: Resource,
+        url: &'a str,
+        path: &'a Path,
+    },
+    /// A status update, tagged with a short category such as `LIVE` or `SERV`.
+    Status { tag: &'static str, message: String },
+    /// A detail about the preceding event, such as a retry or a skip.
+    Detail { message: String },
+    /// Something went wrong, but processing continues.
+    Warning { message: String },
+    /// A job or resource failed.
+    Failure { subject: String, message: String },
+    /// The run finished successfully.
+    Done,
+}
+
+/// Machine-readable progress of a run, for reporters that track jobs
+/// rather than print (see [`crate::jobs`]).
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "hls"), allow(dead_code))]
+pub enum Progress {
+    /// The run moved on to another phase.
+    Phase(Phase),
+    /// This many more files are about to be downloaded.
+    Planned(usize),
+    /// One of the planned files was downloaded.
+    Completed,
+    /// One of the planned files failed and was skipped.
+    Failed,
+    /// A file of this many bytes was downloaded.
+    Bytes(u64),
+    /// The live rendition furthest behind is this many seconds late.
+    Lag(f64),
+    /// This many downloaded files wait in the disk write queue, which holds
+    /// `capacity`; downloads wait while it is full.
+    WriteQueue { pending: usize, capacity: usize },
+}
+
+/// Phases of a run, in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(not(feature = "hls"), allow(dead_code))]
+pub enum Phase {
+    /// Fetching manifests and planning the downloads.
+    Planning,
+    /// Downloading the planned files.
+    Downloading,
+    /// Recording live renditions.
+    Recording,
+}
+
+/// Receives every event emitted while mirroring, recording or serving.
+pub trait Reporter: Send + Sync {
+    fn report(&self, event: Event<'_>);
+
+    /// Receive a progress update; ignored unless the reporter tracks jobs.
+    #[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+    fn progress(&self, _update: Progress) {}
+}
+
+/// Shared handle to a reporter.
+pub type SharedReporter = Arc<dyn Reporter>;
+
+/// Output styles selectable on the command line.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// One human-readable line per event
+    #[default]
+    Human,
+    /// One JSON object per line
+    Json,
+    /// Only warnings and failures, on stderr
+    Quiet,
+    /// A single, continuously updated status line
+    Tui,
+}
+
+/// Create the reporter for the given format.
+pub fn reporter(format: ReportFormat) -> SharedReporter {
+    match format {
+        ReportFormat::Human => Arc::new(HumanReporter::default()),
+        ReportFormat::Json => Arc::new(JsonReporter),
+        ReportFormat::Quiet => Arc::new(QuietReporter),
+        ReportFormat::Tui => Arc::new(TuiReporter::default()),
+    }
+}
+
+/// Plain line-oriented output on stdout.
+#[derive(Default)]
+pub struct HumanReporter {
+    /// When the write queue was last reported.
+    queue_reported: Mutex<Option<Instant>>,
+}
+
+/// How often the write queue is reported at most by [`HumanReporter`].
+const QUEUE_REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+impl Reporter for HumanReporter {
+    fn report(&self, event: Event<'_>) {
+        match event {
+            Event::Fetch { kind, url, path } => {
+                println!("[{:<4}] {} -> {}", kind.tag(), url, path.display())
+            }
+            Event::Status { tag, message } => println!("[{:<4}] {}", tag, message),
+            Event::Detail { message } => println!("  -> {}", message),
+            Event::Warning { message } => println!("[WARN] {}", message),
+            Event::Failure { subject, message } => println!("[FAIL] {}: {}", subject, message),
+            Event::Done => println!("Done."),
+        }
+    }
+
+    fn progress(&self, update: Progress) {
+        // Files queued for the disk are only news once they pile up.
+        let Progress::WriteQueue { pending, capacity } = update else {
+            return;
+        };
+        let mut reported = self
+            .queue_reported
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if pending * 2 < capacity || reported.is_some_and(|at| at.elapsed() < QUEUE_REPORT_INTERVAL)
+        {
+            return;
+        }
+        *reported = Some(Instant::now());
+        let waiting = if pending >= capacity {
+            ", downloads wait for the disk"
+        } else {
+            ""
+        };
+        println!(
+            "[DISK] write queue {}/{} file(s){}",
+            pending, capacity, waiting
+        );
+    }
+}
+
+/// Machine-readable output: one JSON object per line on stdout.
+pub struct JsonReporter;
+
+impl Reporter for JsonReporter {
+    fn report(&self, event: Event<'_>) {
+        let line = match event {
+            Event::Fetch { kind, url, path } => format!(
+                "{{\"event\":\"fetch\",\"kind\":\"{}\",\"url\":{},\"path\":{}}}",
+                kind.name(),
+                json_string(url),
+                json_string(&path.display().to_string())
+            ),
+            Event::Status { tag, message } => format!(
+                "{{\"event\":\"status\",\"tag\":{},\"message\":{}}}",
+                json_string(tag),
+                json_string(&message)
+            ),
+            Event::Detail { message } => {
+                format!(
+                    "{{\"event\":\"detail\",\"message\":{}}}",
+                    json_string(&message)
+                )
+            }
+            Event::Warning { message } => {
+                format!(
+                    "{{\"event\":\"warning\",\"message\":{}}}",
+                    json_string(&message)
+                )
+            }
+            Event::Failure { subject, message } => format!(
+                "{{\"event\":\"failure\",\"subject\":{},\"message\":{}}}",
+                json_string(&subject),
+                json_string(&message)
+            ),
+            Event::Done => "{\"event\":\"done\"}".to_string(),
+        };
+        println!("{line}");
+    }
+
+    fn progress(&self, update: Progress) {
+        if let Progress::WriteQueue { pending, capacity } = update {
+            println!(
+                "{{\"event\":\"write_queue\",\"pending\":{},\"capacity\":{}}}",
+                pending, capacity
+            );
+        }
+    }
+}
+
+/// Only warnings and failures, on stderr.
+pub struct QuietReporter;
+
+impl Reporter for QuietReporter {
+    fn report(&self, event: Event<'_>) {
+        match event {
+            Event::Warning { message } => eprintln!("[WARN] {}", message),
+            Event::Failure { subject, message } => eprintln!("[FAIL] {}: {}", subject, message),
+            _ => {}
+        }
+    }
+}
+
+/// Forwards only warnings and failures to another reporter, keeping internal
+/// passes (such as walking a mirror) quiet.
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub struct ProblemsOnly(pub SharedReporter);
+
+impl Reporter for ProblemsOnly {
+    fn report(&self, event: Event<'_>) {
+        if matches!(event, Event::Warning { .. } | Event::Failure { .. }) {
+            self.0.report(event);
+        }
+    }
+}
+
+/// Drops every event, for internal passes whose findings the regular pass
+/// reports again.
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+pub struct Silent;
+
+impl Reporter for Silent {
+    fn report(&self, _event: Event<'_>) {}
+}
+
+/// A single status line on stderr, rewritten in place; warnings and failures
+/// are printed above it.
+#[derive(Default)]
+pub struct TuiReporter {
+    state: Mutex<TuiState>,
+}
+
+#[derive(Default)]
+struct TuiState {
+    manifests: usize,
+    binaries: usize,
+    warnings: usize,
+    /// Files waiting for the disk, and how many the queue holds.
+    write_queue: Option<(usize, usize)>,
+    last: String,
+}
+
+impl TuiState {
+    fn draw(&self, out: &mut impl Write) {
+        // Keep the tail of long URLs, which is the informative part.
+        let count = self.last.chars().count();
+        let last = if count > 60 {
+            let tail: String = self.last.chars().skip(count - 57).collect();
+            format!("...{tail}")
+        } else {
+            self.last.clone()
+        };
+        let queue = self
+            .write_queue
+            .map(|(pending, capacity)| format!(", disk queue {}/{}", pending, capacity))
+            .unwrap_or_default();
+        let _ = write!(
+            out,
+            "\r\x1b[2K{} manifest(s), {} file(s), {} warning(s){} | {}",
+            self.manifests, self.binaries, self.warnings, queue, last
+        );
+        let _ = out.flush();
+    }
+}
+
+impl Reporter for TuiReporter {
+    fn report(&self, event: Event<'_>) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let mut err = std::io::stderr().lock();
+
+        match event {
+            Event::Fetch { kind, url, .. } => {
+                if kind == Resource::Binary {
+                    state.binaries += 1;
+                } else {
+                    state.manifests += 1;
+                }
+                state.last = url.to_string();
+            }
+            Event::Status { message, .. } => state.last = message,
+            Event::Detail { .. } => {}
+            Event::Warning { message } => {
+                state.warnings += 1;
+                let _ = writeln!(err, "\r\x1b[2K[WARN] {}", message);
+            }
+            Event::Failure { subject, message } => {
+                let _ = writeln!(err, "\r\x1b[2K[FAIL] {}: {}", subject, message);
+            }
+            Event::Done => {
+                state.last = "done".to_string();
+                state.draw(&mut err);
+                let _ = writeln!(err);
+                return;
+            }
+        }
+
+        state.draw(&mut err);
+    }
+
+    fn progress(&self, update: Progress) {
+        if let Progress::WriteQueue { pending, capacity } = update {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            state.write_queue = Some((pending, capacity));
+            state.draw(&mut std::io::stderr().lock());
+        }
+    }
+}
+
+/// Encode `s` as a JSON string literal.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,70 @@
+//! Reproducible mirrors (`--reproducible`): two runs against an unchanged
+//! origin produce bit-identical output trees, down to file times.
+//!
+//! Manifests and segments are the same anyway; what differs between runs is
+//! the order things finish in (the catalog, the state file), wall-clock time
+//! embedded with `--provenance`, and the modification times of the files.
+//! All of them are pinned to `SOURCE_DATE_EPOCH` if set, or to the Unix
+//! epoch.
+
+use crate::state::STATE_FILE;
+use anyhow::{Context, Result, anyhow};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Time every file of a reproducible mirror is dated to.
+pub fn timestamp() -> Result<SystemTime> {
+    match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => {
+            let secs: u64 = epoch
+                .trim()
+                .parse()
+                .map_err(|_| anyhow!("invalid SOURCE_DATE_EPOCH '{}'", epoch))?;
+            Ok(UNIX_EPOCH + Duration::from_secs(secs))
+        }
+        Err(_) => Ok(UNIX_EPOCH),
+    }
+}
+
+/// Make the tree below `out_dir` independent of the run that wrote it: sort
+/// the state file and date every file and directory to `at`.
+pub async fn finish(out_dir: &Path, at: SystemTime) -> Result<()> {
+    let state = out_dir.join(STATE_FILE);
+    match tokio::fs::read_to_string(&state).await {
+        Ok(text) => {
+            let mut lines: Vec<&str> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+            lines.sort_unstable();
+            lines.dedup();
+            let sorted: String = lines.iter().map(|l| format!("{l}\n")).collect();
+            if sorted != text {
+                tokio::fs::write(&state, sorted)
+                    .await
+                    .with_context(|| format!("writing {}", state.display()))?;
+            }
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("reading {}", state.display())),
+    }
+
+    let out_dir = out_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || set_times(&out_dir, at))
+        .await
+        .context("setting file times")?
+}
+
+/// Date everything below `dir`, and `dir` itself last, to `at`.
+fn set_times(dir: &Path, at: SystemTime) -> Result<()> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading directory {}", dir.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<PathBuf>>>()
+        .with_context(|| format!("reading directory {}", dir.display()))?;
+    for path in entries {
+        if path.is_dir() {
+            set_times(&path, at)?;
+        } else {
+            crate::set_modified(&path, at)?;
+        }
+    }
+    crate::set_modified(dir, at)
+}
@@ -0,0 +1,151 @@
+//! The resources of a stream as an async [`Stream`], for embedding the
+//! traversal without the downloading (see [`discover_resources`]).
+//!
+//! The walk is the discovery pass of a mirror: manifests are fetched and
+//! parsed, everything they reference is yielded as it is found, and nothing
+//! else is downloaded or written. What to do with each resource — download
+//! it, store it elsewhere, or just count it — is up to the caller. The walk
+//! waits while the caller is busy and stops when the stream is dropped.
+
+use crate::priority::Priority;
+use crate::report::{SharedReporter, Silent};
+use crate::{Mirror, OriginPaths, PathMapper, StreamFormat};
+use anyhow::{Result, anyhow};
+use futures_core::Stream;
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc;
+use tokio::task::AbortHandle;
+use url::Url;
+
+/// Resources the walk may be ahead of the caller by.
+const BUFFER: usize = 64;
+
+/// What kind of resource was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    /// An HLS playlist or a DASH MPD.
+    Manifest,
+    /// An HLS `#EXT-X-KEY` or `#EXT-X-SESSION-KEY`.
+    Key,
+    /// An HLS `#EXT-X-MAP` or a DASH initialization segment.
+    Init,
+    /// A media segment or any other file (subtitles, thumbnails, ...).
+    Segment,
+}
+
+/// A resource referenced by the stream's manifests.
+#[derive(Debug, Clone)]
+pub struct DiscoveredResource {
+    pub kind: ResourceKind,
+    pub url: Url,
+    /// Where a mirror keeps it, relative to the output directory.
+    pub path: PathBuf,
+    /// Media playlist URL or DASH Representation id the resource belongs
+    /// to; `None` for manifests.
+    pub rendition: Option<String>,
+    /// Playback duration in seconds, if it is a media segment with a known
+    /// one.
+    pub duration: Option<f64>,
+    /// Whether it is a segment encrypted as a whole (`METHOD=AES-128`), so
+    /// it carries no container signature.
+    pub encrypted: bool,
+}
+
+/// The resources below a start URL, in the order the walk finds them: each
+/// manifest before what it references. Ends with an error if the walk fails.
+///
+/// Dropping the stream stops the walk.
+#[derive(Debug)]
+pub struct ResourceStream {
+    receiver: mpsc::Receiver<Result<DiscoveredResource>>,
+    walk: AbortHandle,
+}
+
+/// Walk the HLS playlist or DASH MPD at `start_url`, requesting manifests
+/// with `client`, and yield every resource they reference, each once.
+///
+/// Must be called within a Tokio runtime, on which the walk runs.
+pub fn discover_resources(start_url: Url, client: Client) -> ResourceStream {
+    let paths = Arc::new(OriginPaths::new(&start_url));
+    discover_resources_with(start_url, client, paths)
+}
+
+/// Like [`discover_resources`], with the paths of the resources decided by
+/// `paths`.
+pub fn discover_resources_with(
+    start_url: Url,
+    client: Client,
+    paths: Arc<dyn PathMapper>,
+) -> ResourceStream {
+    let (sender, receiver) = mpsc::channel(BUFFER);
+    // Paths relative to the output directory, wherever that is.
+    let silent: SharedReporter = Arc::new(Silent);
+    let mut walker = Mirror::new(PathBuf::new(), paths, silent);
+    walker.client = client;
+    walker.plan = Some(Vec::new());
+    walker.priority = Some(Default::default());
+    walker.resources = Some(sender.clone());
+    let walk = tokio::spawn(async move {
+        if let Err(e) = walker.mirror_root(start_url, StreamFormat::Auto).await {
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+    ResourceStream {
+        receiver,
+        walk: walk.abort_handle(),
+    }
+}
+
+impl Stream for ResourceStream {
+    type Item = Result<DiscoveredResource>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+impl Drop for ResourceStream {
+    fn drop(&mut self) {
+        self.walk.abort();
+    }
+}
+
+impl Mirror {
+    /// Yield a resource found at `path` to the [`ResourceStream`] walking,
+    /// if any, once the caller is ready for it.
+    pub(crate) async fn announce(&self, url: &Url, path: &Path, is_manifest: bool) -> Result<()> {
+        let Some(sender) = &self.resources else {
+            return Ok(());
+        };
+        let priority = self
+            .priority
+            .as_ref()
+            .and_then(|found| found.kind(&self.dedup_key(url)));
+        let kind = match priority {
+            _ if is_manifest => ResourceKind::Manifest,
+            #[cfg(feature = "hls")]
+            Some(Priority::Key) => ResourceKind::Key,
+            Some(Priority::Init) => ResourceKind::Init,
+            None => ResourceKind::Segment,
+        };
+        let resource = DiscoveredResource {
+            kind,
+            url: url.clone(),
+            path: path.to_path_buf(),
+            rendition: (!is_manifest).then(|| self.rendition.clone()),
+            duration: self
+                .segment_duration
+                .filter(|_| kind == ResourceKind::Segment),
+            encrypted: self.keys.whole_segments()
+                && matches!(kind, ResourceKind::Init | ResourceKind::Segment),
+        };
+        sender
+            .send(Ok(resource))
+            .await
+            .map_err(|_| anyhow!("the resource stream was dropped"))
+    }
+}
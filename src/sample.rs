@@ -0,0 +1,117 @@
+//! `--verify-sample`: spot checks of mirrored segments against the origin.
+//!
+//! Once the segments are down, a random share of them is downloaded again
+//! and compared by SHA-256 with the local copy. A CDN serving corrupted or
+//! truncated payloads now and then is unlikely to do so to the same segment
+//! twice, so a clean sample gives statistical confidence in the mirror
+//! without downloading it all again, and a mismatch points at the segment
+//! to distrust.
+
+use crate::Mirror;
+use crate::report::Event;
+use crate::units;
+use anyhow::{Result, anyhow, bail};
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use url::Url;
+
+/// Parse a `--verify-sample` share such as `1%` or `0.5%` into a fraction.
+pub fn parse_share(s: &str) -> Result<f64> {
+    let percent: f64 = s
+        .trim()
+        .strip_suffix('%')
+        .and_then(|n| n.trim().parse().ok())
+        .ok_or_else(|| anyhow!("expected a percentage such as 1%, got '{}'", s))?;
+    if !(percent > 0.0 && percent <= 100.0) {
+        bail!("the share must be above 0% and at most 100%, got '{}'", s);
+    }
+    Ok(percent / 100.0)
+}
+
+/// `count` of the indices `0..len`, picked at random.
+fn pick(len: usize, count: usize) -> Result<Vec<usize>> {
+    let rng = SystemRandom::new();
+    let mut indices: Vec<usize> = (0..len).collect();
+    // The first `count` steps of a Fisher-Yates shuffle.
+    for i in 0..count.min(len) {
+        let mut bytes = [0; 8];
+        rng.fill(&mut bytes)
+            .map_err(|_| anyhow!("no random numbers available"))?;
+        let j = i + (u64::from_le_bytes(bytes) % (len - i) as u64) as usize;
+        indices.swap(i, j);
+    }
+    indices.truncate(count);
+    Ok(indices)
+}
+
+impl Mirror {
+    /// Download `share` of the media segments mirrored so far once more and
+    /// report each whose local copy differs; under `--strict`, fail on the
+    /// first. Segments the origin doesn't serve anymore are left out.
+    pub(crate) async fn verify_sample(&mut self, share: f64) -> Result<()> {
+        let mut seen = HashSet::new();
+        let mut segments: Vec<Url> = Vec::new();
+        let noted: Vec<Url> = self.segments.iter().map(|(_, url)| url.clone()).collect();
+        for url in noted {
+            if !seen.insert(self.dedup_key(&url)) {
+                continue;
+            }
+            // Dropped or failed segments have no local file.
+            let path = self.path_for_url(&url, false);
+            if tokio::fs::metadata(&path).await.is_ok() {
+                segments.push(url);
+            }
+        }
+        if segments.is_empty() {
+            return Ok(());
+        }
+
+        let count = ((segments.len() as f64 * share).ceil() as usize).clamp(1, segments.len());
+        let (mut identical, mut differing, mut unavailable) = (0, 0, 0);
+        for index in pick(segments.len(), count)? {
+            let url = &segments[index];
+            let path = self.path_for_url(url, false);
+            let local = Sha256::digest(crate::encrypt::read(&path).await?);
+            let remote = match self.fetch_binary(url).await {
+                Ok(resp) => self.timeouts.body(resp, url).await,
+                Err(e) => Err(e),
+            };
+            let remote = match remote {
+                Ok(body) => Sha256::digest(&body),
+                Err(e) => {
+                    self.reporter.report(Event::Detail {
+                        message: format!("could not download {} again: {:#}", url, e),
+                    });
+                    unavailable += 1;
+                    continue;
+                }
+            };
+            if local == remote {
+                identical += 1;
+            } else {
+                differing += 1;
+                self.warn_or_fail(format!(
+                    "{} differs from the origin's copy downloaded again",
+                    url
+                ))?;
+            }
+        }
+
+        let mut message = format!(
+            "{} of {} segment(s) downloaded again: {} identical, {} different",
+            units::count(identical + differing + unavailable),
+            units::count(segments.len()),
+            units::count(identical),
+            units::count(differing)
+        );
+        if unavailable > 0 {
+            message.push_str(&format!(", {} unavailable", units::count(unavailable)));
+        }
+        self.reporter.report(Event::Status {
+            tag: "SMPL",
+            message,
+        });
+        Ok(())
+    }
+}
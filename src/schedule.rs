@@ -0,0 +1,293 @@
+//! Parsing of command-line timestamps and durations for scheduled recording,
+//! and the origin's notion of the current time.
+//!
+//! A machine with a badly set clock would start scheduled recordings at the
+//! wrong moment, so the offset of the origin's clock is measured from the
+//! `Date` header of its responses and scheduling follows the origin.
+
+use crate::debug_http::SendLogged;
+use crate::report::{Event, SharedReporter};
+use crate::units;
+use anyhow::{Result, anyhow};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest duration accepted, a century; deadlines further out than that
+/// are mistakes, and far enough out they can't be represented at all.
+const MAX_DURATION: Duration = Duration::from_secs(100 * 365 * 86400);
+
+/// Parse a human-friendly duration such as `90`, `45s`, `30m`, `1h30m` or `01:30:00`.
+///
+/// A bare number is interpreted as seconds.
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    let s = s.trim();
+    let invalid = || {
+        anyhow!(
+            "invalid duration '{}' (expected e.g. 90, 45s, 1h30m or 01:30:00)",
+            s
+        )
+    };
+
+    if s.contains(':') {
+        let mut secs = 0.0;
+        for part in s.split(':') {
+            secs = secs * 60.0 + non_negative(part).ok_or_else(invalid)?;
+        }
+        return duration(secs).ok_or_else(invalid);
+    }
+
+    if let Ok(secs) = s.parse::<f64>() {
+        return duration(secs).ok_or_else(invalid);
+    }
+
+    let mut total = 0.0;
+    let mut num = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            num.push(c);
+            continue;
+        }
+        let v = non_negative(&num).ok_or_else(invalid)?;
+        num.clear();
+        total += v * match c {
+            'd' => 86400.0,
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            _ => return Err(invalid()),
+        };
+    }
+    if !num.is_empty() {
+        return Err(invalid());
+    }
+
+    duration(total).ok_or_else(invalid)
+}
+
+/// `secs` as a duration, unless negative, not a number or longer than
+/// [`MAX_DURATION`].
+fn duration(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs)
+        .ok()
+        .filter(|d| *d <= MAX_DURATION)
+}
+
+/// `s` as a finite, non-negative number.
+fn non_negative(s: &str) -> Option<f64> {
+    s.parse::<f64>().ok().filter(|v| v.is_finite() && *v >= 0.0)
+}
+
+/// Parse an RFC 3339 timestamp (`2026-10-16T20:00:00Z`, `2026-10-16T22:00:00+02:00`)
+/// or a Unix timestamp in seconds.
+pub fn parse_timestamp(s: &str) -> Result<SystemTime> {
+    let s = s.trim();
+    let invalid = || {
+        anyhow!(
+            "invalid timestamp '{}' (expected RFC 3339 such as 2026-10-16T20:00:00Z, or Unix seconds)",
+            s
+        )
+    };
+
+    if let Ok(secs) = s.parse::<u64>() {
+        return UNIX_EPOCH
+            .checked_add(Duration::from_secs(secs))
+            .ok_or_else(invalid);
+    }
+
+    let (date, rest) = s.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+    let mut date_parts = date.splitn(3, '-');
+    let mut next = || -> Result<i64> {
+        date_parts
+            .next()
+            .and_then(|p| p.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let (year, month, day) = (next()?, next()?, next()?);
+
+    // Split off the UTC offset.
+    let (time, offset_secs) = if let Some(t) = rest.strip_suffix(['Z', 'z']) {
+        (t, 0)
+    } else if let Some(idx) = rest.rfind(['+', '-']) {
+        let (t, off) = rest.split_at(idx);
+        let sign = if off.starts_with('-') { -1 } else { 1 };
+        let (oh, om) = off[1..].split_once(':').ok_or_else(invalid)?;
+        let oh: i64 = oh.parse::<u8>().map_err(|_| invalid())?.into();
+        let om: i64 = om.parse::<u8>().map_err(|_| invalid())?.into();
+        (t, sign * (oh * 3600 + om * 60))
+    } else {
+        return Err(invalid());
+    };
+
+    let mut time_parts = time.splitn(3, ':');
+    let hour: i64 = time_parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(invalid)?;
+    let minute: i64 = time_parts
+        .next()
+        .and_then(|p| p.parse().ok())
+        .ok_or_else(invalid)?;
+    let second = time_parts
+        .next()
+        .map(|p| non_negative(p).ok_or_else(invalid))
+        .transpose()?
+        .unwrap_or(0.0);
+
+    // RFC 3339 has four-digit years; seconds go up to 60 for leap seconds.
+    if !(0..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || second >= 61.0
+        || !(-86400..=86400).contains(&offset_secs)
+    {
+        return Err(invalid());
+    }
+
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86400 + hour * 3600 + minute * 60 - offset_secs;
+    if secs < 0 {
+        return Err(invalid());
+    }
+
+    Ok(UNIX_EPOCH + Duration::from_secs(secs as u64) + Duration::from_secs_f64(second))
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Format `at` as an RFC 3339 timestamp in UTC, to the second.
+pub fn format_timestamp(at: SystemTime) -> String {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The proleptic Gregorian date `days` after 1970-01-01; the inverse of
+/// [`days_from_civil`].
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Offsets between the origin's clock and ours below this are not worth
+/// a warning: `Date` headers have a resolution of one second.
+const CLOCK_SKEW_TOLERANCE: f64 = 2.0;
+
+/// Parse an HTTP date in the preferred IMF-fixdate format
+/// (`Sun, 06 Nov 1994 08:49:37 GMT`).
+pub fn parse_http_date(s: &str) -> Option<SystemTime> {
+    let mut parts = s.split_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.splitn(3, ':').map(|p| p.parse::<i64>().ok());
+    let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" {
+        return None;
+    }
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u64::try_from(secs)
+        .ok()
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Seconds the origin's clock is ahead of ours (negative if behind), from
+/// the `Date` header of a response to a request sent at `sent` and answered
+/// at `received`.
+pub fn clock_skew(date: &str, sent: SystemTime, received: SystemTime) -> Option<f64> {
+    let origin = parse_http_date(date)?;
+    let local = sent + received.duration_since(sent).unwrap_or_default() / 2;
+    // `Date` is truncated to the second.
+    let origin = origin + Duration::from_millis(500);
+    Some(match origin.duration_since(local) {
+        Ok(ahead) => ahead.as_secs_f64(),
+        Err(behind) => -behind.duration().as_secs_f64(),
+    })
+}
+
+/// Measure how far the clock of the origin serving `url` is off ours.
+pub async fn measure_clock_skew(client: &reqwest::Client, url: &url::Url) -> Option<f64> {
+    let sent = SystemTime::now();
+    let resp = client.head(url.clone()).send_logged().await.ok()?;
+    let received = SystemTime::now();
+    let date = resp.headers().get(reqwest::header::DATE)?.to_str().ok()?;
+    clock_skew(date, sent, received)
+}
+
+/// Log a measured clock skew; a warning if it is noticeable.
+pub fn report_clock_skew(skew: f64, reporter: &SharedReporter) {
+    let message = format!(
+        "origin clock is {} {} the local clock",
+        units::duration(skew.abs()),
+        if skew >= 0.0 { "ahead of" } else { "behind" }
+    );
+    if skew.abs() < CLOCK_SKEW_TOLERANCE {
+        reporter.report(Event::Detail { message });
+    } else {
+        reporter.report(Event::Warning {
+            message: format!("{message}; following the origin's time"),
+        });
+    }
+}
+
+/// Sleep until `at` on the origin's clock, which is `skew` seconds ahead of
+/// ours, returning immediately if it has already passed.
+pub async fn wait_until(at: SystemTime, skew: f64, reporter: &SharedReporter) {
+    let local = if skew >= 0.0 {
+        at.checked_sub(Duration::from_secs_f64(skew))
+    } else {
+        at.checked_add(Duration::from_secs_f64(-skew))
+    }
+    .unwrap_or(at);
+    if let Ok(remaining) = local.duration_since(SystemTime::now()) {
+        reporter.report(Event::Status {
+            tag: "WAIT",
+            message: format!(
+                "{} until scheduled start",
+                units::duration(remaining.as_secs_f64())
+            ),
+        });
+        tokio::time::sleep(remaining).await;
+    }
+}
@@ -0,0 +1,305 @@
+//! Selection of the renditions to mirror by id, group, height and bandwidth.
+
+use sha2::{Digest, Sha256};
+#[cfg(any(feature = "hls", feature = "dash"))]
+use std::collections::HashSet;
+#[cfg(feature = "hls")]
+use url::Url;
+
+/// Limits on which renditions (HLS variants, DASH Representations) are mirrored.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct RenditionFilter {
+    /// Only mirror the DASH Representation with this id (repeatable); adaptation
+    /// sets containing none of the given ids are left alone
+    #[arg(long = "rep-id", value_name = "ID")]
+    pub rep_ids: Vec<String>,
+
+    /// Skip renditions taller than this many pixels
+    #[arg(long, value_name = "PIXELS")]
+    pub max_height: Option<u32>,
+
+    /// Skip renditions whose bandwidth exceeds this many bits per second
+    #[arg(long, value_name = "BPS")]
+    pub max_bandwidth: Option<u64>,
+
+    /// Only mirror the HLS `#EXT-X-MEDIA` group with this GROUP-ID (repeatable),
+    /// and the variants using it; groups of other types are left alone
+    #[arg(long = "group-id", value_name = "ID")]
+    pub group_ids: Vec<String>,
+
+    /// Only mirror the DASH AdaptationSet with this id (repeatable); adaptation
+    /// sets of other content types are left alone
+    #[arg(long = "adaptation-set", value_name = "ID")]
+    pub adaptation_sets: Vec<String>,
+
+    /// Skip the HLS `#EXT-X-MEDIA` rendition with this NAME or the DASH
+    /// Representation with this id (repeatable)
+    #[arg(long = "exclude-rendition", value_name = "NAME")]
+    pub exclude_renditions: Vec<String>,
+
+    /// Only mirror the renditions with these ids, as `--estimate` lists them
+    /// (comma-separated or repeated); groups of alternatives containing none
+    /// of them are left alone
+    #[arg(long, value_name = "IDS", value_delimiter = ',')]
+    pub select: Vec<String>,
+}
+
+/// The stable id of a rendition (a media playlist URL or Representation id):
+/// `r` and 8 hex digits, derived from the playlist's path and query or the
+/// id, so it stays the same across runs, hosts and reordered manifests.
+pub fn rendition_id(rendition: &str) -> String {
+    let name = match url::Url::parse(rendition) {
+        Ok(url) => url[url::Position::BeforePath..].to_string(),
+        Err(_) => rendition.to_string(),
+    };
+    let digest = Sha256::digest(name.as_bytes());
+    let hex: String = digest[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("r{hex}")
+}
+
+/// What is known about one rendition when selecting.
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Candidate<'a> {
+    pub id: Option<&'a str>,
+    /// Its [`rendition_id`], for `--select`.
+    pub stable_id: Option<&'a str>,
+    pub height: Option<u32>,
+    pub bandwidth: Option<u64>,
+    /// Ruled out by name or group, whatever else passes; never kept as the
+    /// fallback.
+    pub excluded: bool,
+}
+
+/// What is known about one DASH AdaptationSet when selecting.
+#[cfg(feature = "dash")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AdaptationSetCandidate<'a> {
+    pub id: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+}
+
+#[cfg_attr(not(any(feature = "hls", feature = "dash")), allow(dead_code))]
+impl RenditionFilter {
+    /// Whether any limit was given.
+    pub fn is_active(&self) -> bool {
+        !self.rep_ids.is_empty()
+            || self.max_height.is_some()
+            || self.max_bandwidth.is_some()
+            || !self.group_ids.is_empty()
+            || !self.adaptation_sets.is_empty()
+            || !self.exclude_renditions.is_empty()
+            || !self.select.is_empty()
+    }
+
+    /// Whether `--select` names the rendition with the stable id `id`.
+    pub fn is_selected(&self, id: Option<&str>) -> bool {
+        id.is_some_and(|id| self.select.iter().any(|s| s == id))
+    }
+
+    /// Whether `--exclude-rendition` names `name`.
+    pub fn is_excluded(&self, name: Option<&str>) -> bool {
+        name.is_some_and(|name| self.exclude_renditions.iter().any(|n| n == name))
+    }
+
+    /// Decide which AdaptationSets of a Period to keep: where `--adaptation-set`
+    /// names one of a content type, the others of that type are dropped.
+    #[cfg(feature = "dash")]
+    pub fn select_adaptation_sets(&self, sets: &[AdaptationSetCandidate<'_>]) -> Vec<bool> {
+        let named = |set: &AdaptationSetCandidate<'_>| {
+            set.id
+                .is_some_and(|id| self.adaptation_sets.iter().any(|a| a == id))
+        };
+        let selected_types: HashSet<Option<&str>> = sets
+            .iter()
+            .filter(|set| named(set))
+            .map(|set| set.content_type)
+            .collect();
+        sets.iter()
+            .map(|set| named(set) || !selected_types.contains(&set.content_type))
+            .collect()
+    }
+
+    /// Decide which of a group of alternative renditions to keep.
+    ///
+    /// If nothing in the group passes, the lowest-bandwidth candidate (among
+    /// those matching `--rep-id`, if any do) is kept so the group doesn't
+    /// vanish; the second return value reports that fallback.
+    pub fn select(&self, candidates: &[Candidate<'_>]) -> (Vec<bool>, bool) {
+        let by_id = !self.rep_ids.is_empty()
+            && candidates
+                .iter()
+                .any(|c| c.id.is_some_and(|id| self.rep_ids.iter().any(|r| r == id)));
+        let by_selection = candidates.iter().any(|c| self.is_selected(c.stable_id));
+        let id_matches = |c: &Candidate<'_>| {
+            !c.excluded
+                && (!by_id || c.id.is_some_and(|id| self.rep_ids.iter().any(|r| r == id)))
+                && (!by_selection || self.is_selected(c.stable_id))
+        };
+
+        let mut keep: Vec<bool> = candidates
+            .iter()
+            .map(|c| {
+                id_matches(c)
+                    && self
+                        .max_height
+                        .is_none_or(|max| c.height.is_none_or(|h| h <= max))
+                    && self
+                        .max_bandwidth
+                        .is_none_or(|max| c.bandwidth.is_none_or(|b| b <= max))
+            })
+            .collect();
+
+        let mut fallback = false;
+        if !candidates.is_empty()
+            && !keep.contains(&true)
+            && let Some((lowest, _)) = candidates
+                .iter()
+                .enumerate()
+                .filter(|(_, c)| id_matches(c))
+                .min_by_key(|(_, c)| c.bandwidth.unwrap_or(u64::MAX))
+        {
+            keep[lowest] = true;
+            fallback = true;
+        }
+
+        (keep, fallback)
+    }
+
+    /// Remove the variants and `#EXT-X-MEDIA` renditions of an HLS master
+    /// playlist that don't pass the filter. `#EXT-X-STREAM-INF` and
+    /// `#EXT-X-I-FRAME-STREAM-INF` variants are selected independently;
+    /// I-frame variants are optional, so they are all dropped rather than kept
+    /// as a fallback. Variants using a group left without renditions are
+    /// dropped along with it. Returns the filtered playlist, the number of
+    /// variants and renditions removed, and whether a fallback variant had to
+    /// be kept. `url` is the playlist's, which the stable ids of its
+    /// renditions are derived from.
+    #[cfg(feature = "hls")]
+    pub fn filter_master_playlist<'a>(
+        &self,
+        url: &Url,
+        playlist: crate::playlist::HlsMasterPlaylist<'a>,
+    ) -> (crate::playlist::HlsMasterPlaylist<'a>, usize, bool) {
+        let mut keep_lines = vec![true; playlist.lines.len()];
+        let mut removed = 0;
+        let mut any_fallback = false;
+        let stable_id = |uri: Option<&str>| {
+            uri.and_then(|uri| url.join(uri).ok())
+                .map(|url| rendition_id(url.as_str()))
+        };
+
+        // Groups are selected per TYPE, like AdaptationSets per content type.
+        let selected_types: HashSet<&str> = playlist
+            .media
+            .iter()
+            .filter(|m| {
+                m.group_id
+                    .as_ref()
+                    .is_some_and(|g| self.group_ids.contains(g))
+            })
+            .filter_map(|m| m.kind.as_deref())
+            .collect();
+        // And so are renditions by stable id.
+        let media_ids: Vec<Option<String>> = playlist
+            .media
+            .iter()
+            .map(|m| stable_id(media_uri(&playlist.lines[m.tag]).as_deref()))
+            .collect();
+        let chosen_types: HashSet<&str> = playlist
+            .media
+            .iter()
+            .zip(&media_ids)
+            .filter(|(_, id)| self.is_selected(id.as_deref()))
+            .filter_map(|(m, _)| m.kind.as_deref())
+            .collect();
+        let mut groups = HashSet::new();
+        let mut kept_groups = HashSet::new();
+        for (media, id) in playlist.media.iter().zip(&media_ids) {
+            let (Some(kind), Some(group)) = (media.kind.as_deref(), media.group_id.as_deref())
+            else {
+                continue;
+            };
+            groups.insert((kind, group));
+            let kept = !self.is_excluded(media.name.as_deref())
+                && (!selected_types.contains(kind) || self.group_ids.iter().any(|g| g == group))
+                && (!chosen_types.contains(kind) || self.is_selected(id.as_deref()));
+            if kept {
+                kept_groups.insert((kind, group));
+            } else {
+                removed += 1;
+                keep_lines[media.tag] = false;
+            }
+        }
+        let dropped_groups: HashSet<_> = groups.difference(&kept_groups).collect();
+
+        for i_frame in [false, true] {
+            let variants: Vec<_> = playlist
+                .variants
+                .iter()
+                .filter(|v| v.i_frame == i_frame)
+                .collect();
+            let ids: Vec<Option<String>> = variants
+                .iter()
+                .map(|v| match v.uri {
+                    Some(uri) => stable_id(match playlist.lines[uri] {
+                        crate::playlist::Line::Uri { uri, .. } => Some(uri),
+                        _ => None,
+                    }),
+                    None => stable_id(media_uri(&playlist.lines[v.tag]).as_deref()),
+                })
+                .collect();
+            let candidates: Vec<Candidate<'_>> = variants
+                .iter()
+                .zip(&ids)
+                .map(|(v, stable_id)| {
+                    let crate::playlist::Line::Tag(tag) = playlist.lines[v.tag] else {
+                        unreachable!("variants are tag lines");
+                    };
+                    let excluded = ["AUDIO", "VIDEO", "SUBTITLES", "CLOSED-CAPTIONS"]
+                        .into_iter()
+                        .any(|kind| {
+                            tag.attribute(kind).is_some_and(|group| {
+                                dropped_groups.contains(&(kind, group.as_str()))
+                            })
+                        });
+                    Candidate {
+                        id: None,
+                        stable_id: stable_id.as_deref(),
+                        height: v.height,
+                        bandwidth: v.bandwidth,
+                        excluded,
+                    }
+                })
+                .collect();
+
+            let (mut keep, fallback) = self.select(&candidates);
+            if !i_frame {
+                any_fallback |= fallback;
+            } else if fallback {
+                keep.fill(false);
+            }
+            for (variant, kept) in variants.into_iter().zip(keep) {
+                if kept {
+                    continue;
+                }
+                removed += 1;
+                keep_lines[variant.tag] = false;
+                if let Some(uri) = variant.uri {
+                    keep_lines[uri] = false;
+                }
+            }
+        }
+
+        (playlist.retain(&keep_lines), removed, any_fallback)
+    }
+}
+
+/// The `URI` attribute of an `#EXT-X-MEDIA` or `#EXT-X-I-FRAME-STREAM-INF` line.
+#[cfg(feature = "hls")]
+fn media_uri(line: &crate::playlist::Line<'_>) -> Option<String> {
+    match line {
+        crate::playlist::Line::Tag(tag) => tag.attribute("URI"),
+        _ => None,
+    }
+}
@@ -0,0 +1,141 @@
+//! `--self-test`: serve a finished mirror on an ephemeral local port and walk
+//! it as a player would, checking that every manifest and file it references
+//! is served, with the size of the file on disk.
+
+use crate::debug_http::SendLogged;
+use crate::report::{Event, ProblemsOnly, SharedReporter};
+use crate::serve::{self, Access, Layout};
+use crate::{Mirror, OriginPaths, StreamFormat};
+use anyhow::{Context, Result, anyhow};
+use reqwest::header::CONTENT_LENGTH;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use url::Url;
+
+/// Serve `out_dir`, walk it starting at `root_manifest` and check every
+/// referenced file, at most `concurrency` requests at a time.
+pub(crate) async fn self_test(
+    out_dir: &Path,
+    root_manifest: &Path,
+    format: StreamFormat,
+    concurrency: usize,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    let quiet: SharedReporter = Arc::new(ProblemsOnly(reporter.clone()));
+
+    let bound = serve::bind(
+        Layout::Single(out_dir.to_path_buf()),
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+        Access::default(),
+        quiet.clone(),
+    )
+    .await?;
+    let addr = bound.local_addr()?;
+    let server = tokio::spawn(bound.run());
+
+    let result = walk(out_dir, root_manifest, addr, format, concurrency, quiet).await;
+    server.abort();
+
+    let (files, failures) = result?;
+    reporter.report(Event::Status {
+        tag: "TEST",
+        message: format!(
+            "served {} file(s) from http://{}/, {} failure(s)",
+            files, addr, failures
+        ),
+    });
+
+    if failures > 0 {
+        return Err(anyhow!(
+            "self-test failed: {} referenced file(s) not served correctly",
+            failures
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the number of files checked and the number of failures.
+async fn walk(
+    out_dir: &Path,
+    root_manifest: &Path,
+    addr: SocketAddr,
+    format: StreamFormat,
+    concurrency: usize,
+    reporter: SharedReporter,
+) -> Result<(usize, usize)> {
+    let rel = root_manifest
+        .strip_prefix(out_dir)
+        .unwrap_or(root_manifest)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+    let mut root_url = Url::parse(&format!("http://{addr}/"))?;
+    root_url.set_path(&rel);
+
+    // Planning fetches every manifest through the server and collects the
+    // files they reference, without writing anything.
+    let paths = Arc::new(OriginPaths::new(&root_url));
+    let mut walker = Mirror::new(out_dir.to_path_buf(), paths, reporter.clone());
+    walker.plan = Some(Vec::new());
+    walker
+        .mirror_root(root_url.clone(), format)
+        .await
+        .with_context(|| format!("walking {}", root_url))?;
+    let planned = walker.plan.take().unwrap_or_default();
+
+    let semaphore = Arc::new(Semaphore::new(concurrency.max(1)));
+    let mut tasks = JoinSet::new();
+    for file in &planned {
+        let client = walker.client.clone();
+        let url = file.url.clone();
+        let local =
+            serve::percent_decode(url.path().trim_start_matches('/')).map(|p| out_dir.join(p));
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let resp = client
+                .head(url.clone())
+                .send_logged()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| (url.clone(), format!("{e}")))?;
+            let served = resp
+                .headers()
+                .get(CONTENT_LENGTH)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse::<u64>().ok());
+            let on_disk = match &local {
+                Some(path) => tokio::fs::metadata(path).await.ok().map(|m| m.len()),
+                None => None,
+            };
+            match (served, on_disk) {
+                (Some(s), Some(d)) if s == d => Ok(()),
+                (s, d) => Err((
+                    url,
+                    format!(
+                        "served {} bytes, {} bytes on disk",
+                        s.map_or("?".to_string(), |s| s.to_string()),
+                        d.map_or("?".to_string(), |d| d.to_string())
+                    ),
+                )),
+            }
+        });
+    }
+
+    let mut failures = 0;
+    while let Some(result) = tasks.join_next().await {
+        if let Ok(Err((url, message))) = result {
+            failures += 1;
+            reporter.report(Event::Failure {
+                subject: url.to_string(),
+                message,
+            });
+        }
+    }
+
+    Ok((planned.len(), failures))
+}
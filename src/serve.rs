@@ -0,0 +1,495 @@
+//! Minimal static file server for locally hosting one or more mirrored streams.
+
+use anyhow::{Context, Result, anyhow};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
+use hyper::header::{
+    ACCESS_CONTROL_ALLOW_ORIGIN, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, SET_COOKIE,
+    WWW_AUTHENTICATE,
+};
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+use crate::container::Container;
+use crate::report::{Event, SharedReporter, json_string};
+
+/// Name of the cookie used to remember a token presented via the query string,
+/// so that relative segment requests issued by a player stay authorized.
+const TOKEN_COOKIE: &str = "streamrip_token";
+
+/// Access control applied to every request.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    /// Expected `user:pass` pair for HTTP basic authentication.
+    pub basic: Option<String>,
+    /// Shared secret accepted via `?token=`, `Authorization: Bearer` or cookie.
+    pub token: Option<String>,
+}
+
+impl Access {
+    fn is_open(&self) -> bool {
+        self.basic.is_none() && self.token.is_none()
+    }
+}
+
+enum Authorized {
+    Yes,
+    /// Authorized via query token; the response should set the token cookie.
+    SetCookie,
+    No,
+}
+
+/// How the served directory maps onto request paths.
+#[derive(Debug, Clone)]
+pub enum Layout {
+    /// A single mirror, served at `/`.
+    Single(PathBuf),
+    /// A directory of mirrors, each served at `/assets/<name>/` with an index at `/`.
+    Multi(PathBuf),
+}
+
+/// A mirrored stream discovered below a multi-mirror root.
+struct Asset {
+    name: String,
+    /// Top-level manifests of the asset, relative to its directory.
+    manifests: Vec<String>,
+}
+
+struct Server {
+    root: PathBuf,
+    multi: bool,
+    access: Access,
+    reporter: SharedReporter,
+}
+
+/// A server bound to its listening socket but not yet accepting connections.
+pub struct Bound {
+    listener: TcpListener,
+    server: Arc<Server>,
+}
+
+/// Serve the given layout over HTTP on `addr` until the process is terminated.
+pub async fn serve(
+    layout: Layout,
+    addr: SocketAddr,
+    access: Access,
+    reporter: SharedReporter,
+) -> Result<()> {
+    bind(layout, addr, access, reporter).await?.run().await
+}
+
+/// Bind a server for the given layout to `addr`; port 0 picks a free port.
+pub async fn bind(
+    layout: Layout,
+    addr: SocketAddr,
+    access: Access,
+    reporter: SharedReporter,
+) -> Result<Bound> {
+    let (root, multi) = match layout {
+        Layout::Single(root) => (root, false),
+        Layout::Multi(root) => (root, true),
+    };
+    let root = tokio::fs::canonicalize(&root)
+        .await
+        .with_context(|| format!("resolving serve directory {}", root.display()))?;
+
+    if !addr.ip().is_loopback() && access.is_open() {
+        reporter.report(Event::Warning {
+            message: format!(
+                "serving on {} without --auth or --token; the mirror is readable by anyone who can reach this address",
+                addr
+            ),
+        });
+    }
+
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("binding {}", addr))?;
+    let addr = listener.local_addr()?;
+
+    reporter.report(Event::Status {
+        tag: "SERV",
+        message: format!("{} on http://{}/", root.display(), addr),
+    });
+
+    if multi {
+        for asset in discover_assets(&root).await? {
+            reporter.report(Event::Status {
+                tag: "SERV",
+                message: format!("  /assets/{}/ ({})", asset.name, asset.manifests.join(", ")),
+            });
+        }
+    }
+
+    let server = Arc::new(Server {
+        root,
+        multi,
+        access,
+        reporter,
+    });
+
+    Ok(Bound { listener, server })
+}
+
+impl Bound {
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept and serve connections until the process is terminated.
+    pub async fn run(self) -> Result<()> {
+        let Bound { listener, server } = self;
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let server = server.clone();
+
+            let reporter = server.reporter.clone();
+
+            tokio::spawn(async move {
+                let service = service_fn(move |req| {
+                    let server = server.clone();
+                    async move { Ok::<_, std::convert::Infallible>(server.handle(req).await) }
+                });
+
+                if let Err(e) = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    reporter.report(Event::Warning {
+                        message: format!("connection from {} failed: {}", peer, e),
+                    });
+                }
+            });
+        }
+    }
+}
+
+impl Server {
+    async fn handle(&self, req: Request<Incoming>) -> Response<Full<Bytes>> {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+
+        let authorized = self.authorize(&req);
+        if let Authorized::No = authorized {
+            let mut resp = status(StatusCode::UNAUTHORIZED);
+            if self.access.basic.is_some() {
+                resp.headers_mut().insert(
+                    WWW_AUTHENTICATE,
+                    "Basic realm=\"streamrip\"".parse().expect("static header"),
+                );
+            }
+            return resp;
+        }
+
+        let head_only = req.method() == Method::HEAD;
+        let mut resp = match self.route(req.uri().path()) {
+            Route::File(path) => match crate::encrypt::read(&path).await {
+                Ok(data) => file_response(&path, data, head_only),
+                Err(_) => status(StatusCode::NOT_FOUND),
+            },
+            Route::Index(format) => match discover_assets(&self.root).await {
+                Ok(assets) => index_response(&assets, format, head_only),
+                Err(e) => {
+                    self.reporter.report(Event::Warning {
+                        message: format!("listing {}: {:#}", self.root.display(), e),
+                    });
+                    status(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            },
+            Route::NotFound => status(StatusCode::NOT_FOUND),
+        };
+
+        if let (Authorized::SetCookie, Some(token)) = (authorized, &self.access.token)
+            && let Ok(v) =
+                format!("{TOKEN_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict").parse()
+        {
+            resp.headers_mut().insert(SET_COOKIE, v);
+        }
+
+        resp
+    }
+
+    fn authorize(&self, req: &Request<Incoming>) -> Authorized {
+        if self.access.is_open() {
+            return Authorized::Yes;
+        }
+
+        let auth_header = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+
+        if let Some(expected) = &self.access.basic
+            && let Some(encoded) = auth_header.strip_prefix("Basic ")
+            && let Ok(decoded) = BASE64.decode(encoded.trim())
+            && constant_time_eq(&decoded, expected.as_bytes())
+        {
+            return Authorized::Yes;
+        }
+
+        if let Some(expected) = &self.access.token {
+            if let Some(bearer) = auth_header.strip_prefix("Bearer ")
+                && constant_time_eq(bearer.trim().as_bytes(), expected.as_bytes())
+            {
+                return Authorized::Yes;
+            }
+
+            let cookie_ok = req
+                .headers()
+                .get_all(COOKIE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(';'))
+                .filter_map(|c| c.trim().split_once('='))
+                .any(|(k, v)| {
+                    k == TOKEN_COOKIE && constant_time_eq(v.as_bytes(), expected.as_bytes())
+                });
+            if cookie_ok {
+                return Authorized::Yes;
+            }
+
+            let query_ok = req
+                .uri()
+                .query()
+                .into_iter()
+                .flat_map(|q| url::form_urlencoded::parse(q.as_bytes()))
+                .any(|(k, v)| k == "token" && constant_time_eq(v.as_bytes(), expected.as_bytes()));
+            if query_ok {
+                return Authorized::SetCookie;
+            }
+        }
+
+        Authorized::No
+    }
+
+    fn route(&self, request_path: &str) -> Route {
+        if !self.multi {
+            return self
+                .resolve(request_path)
+                .map_or(Route::NotFound, Route::File);
+        }
+
+        match request_path {
+            "/" | "/index.html" | "/assets" | "/assets/" => Route::Index(IndexFormat::Html),
+            "/index.json" => Route::Index(IndexFormat::Json),
+            _ => request_path
+                .strip_prefix("/assets/")
+                .and_then(|p| self.resolve(p))
+                .map_or(Route::NotFound, Route::File),
+        }
+    }
+
+    /// Map a request path onto a file below the served root, rejecting
+    /// anything that would escape it.
+    fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(request_path)?;
+        let mut path = self.root.clone();
+
+        for component in Path::new(decoded.trim_start_matches('/')).components() {
+            match component {
+                Component::Normal(part) => path.push(part),
+                Component::CurDir => {}
+                _ => return None,
+            }
+        }
+
+        if path.is_dir() {
+            return None;
+        }
+        Some(path)
+    }
+}
+
+enum Route {
+    File(PathBuf),
+    Index(IndexFormat),
+    NotFound,
+}
+
+#[derive(Clone, Copy)]
+enum IndexFormat {
+    Html,
+    Json,
+}
+
+/// List the subdirectories of `root` that contain at least one top-level
+/// HLS or DASH manifest, sorted by name.
+async fn discover_assets(root: &Path) -> Result<Vec<Asset>> {
+    let mut assets = Vec::new();
+    let mut entries = tokio::fs::read_dir(root).await?;
+
+    while let Some(entry) = entries.next_entry().await? {
+        if !entry.file_type().await?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') {
+            continue;
+        }
+
+        let mut manifests = Vec::new();
+        let mut files = tokio::fs::read_dir(entry.path()).await?;
+        while let Some(file) = files.next_entry().await? {
+            let file_name = file.file_name().to_string_lossy().to_string();
+            let lower = file_name.to_ascii_lowercase();
+            if lower.ends_with(".m3u8") || lower.ends_with(".mpd") {
+                manifests.push(file_name);
+            }
+        }
+
+        if !manifests.is_empty() {
+            manifests.sort();
+            assets.push(Asset { name, manifests });
+        }
+    }
+
+    assets.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(assets)
+}
+
+fn index_response(assets: &[Asset], format: IndexFormat, head_only: bool) -> Response<Full<Bytes>> {
+    let (ctype, body) = match format {
+        IndexFormat::Html => ("index.html", render_index_html(assets)),
+        IndexFormat::Json => ("index.json", render_index_json(assets)),
+    };
+    file_response(Path::new(ctype), body.into_bytes(), head_only)
+}
+
+fn render_index_json(assets: &[Asset]) -> String {
+    let items: Vec<String> = assets
+        .iter()
+        .map(|a| {
+            let manifests: Vec<String> = a
+                .manifests
+                .iter()
+                .map(|m| json_string(&format!("/assets/{}/{}", a.name, m)))
+                .collect();
+            format!(
+                "{{\"name\":{},\"path\":{},\"manifests\":[{}]}}",
+                json_string(&a.name),
+                json_string(&format!("/assets/{}/", a.name)),
+                manifests.join(",")
+            )
+        })
+        .collect();
+    format!("{{\"assets\":[{}]}}\n", items.join(","))
+}
+
+fn render_index_html(assets: &[Asset]) -> String {
+    let mut html = String::from(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>streamrip</title></head><body>\n<h1>Mirrored streams</h1>\n<ul>\n",
+    );
+    for a in assets {
+        html.push_str(&format!("<li>{}<ul>", html_escape(&a.name)));
+        for m in &a.manifests {
+            let href = format!("/assets/{}/{}", a.name, m);
+            html.push_str(&format!(
+                "<li><a href=\"{}\">{}</a></li>",
+                html_escape(&href),
+                html_escape(m)
+            ));
+        }
+        html.push_str("</ul></li>\n");
+    }
+    html.push_str("</ul>\n</body></html>\n");
+    html
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn file_response(path: &Path, data: Vec<u8>, head_only: bool) -> Response<Full<Bytes>> {
+    let len = data.len();
+    let content_type = content_type_for(path, &data);
+    let body = if head_only {
+        Bytes::new()
+    } else {
+        Bytes::from(data)
+    };
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, len)
+        .header(ACCESS_CONTROL_ALLOW_ORIGIN, "*")
+        .body(Full::new(body))
+        .expect("valid response")
+}
+
+fn status(code: StatusCode) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(code)
+        .body(Full::new(Bytes::from(
+            code.canonical_reason().unwrap_or("").to_string(),
+        )))
+        .expect("valid response")
+}
+
+/// Content type by extension, falling back to sniffing the segment
+/// container for extension-less or unusual segment names.
+fn content_type_for(path: &Path, data: &[u8]) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+
+    match ext.as_str() {
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "mpd" => "application/dash+xml",
+        "ts" => "video/mp2t",
+        "mp4" | "m4s" | "m4v" | "cmfv" => "video/mp4",
+        "m4a" | "cmfa" => "audio/mp4",
+        "aac" => "audio/aac",
+        "vtt" | "webvtt" => "text/vtt",
+        "json" => "application/json",
+        "html" => "text/html; charset=utf-8",
+        "webm" => "video/webm",
+        _ => Container::sniff(data).map_or("application/octet-stream", Container::mime_type),
+    }
+}
+
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parse a `user:pass` pair given on the command line.
+pub fn parse_basic_auth(s: &str) -> Result<String> {
+    match s.split_once(':') {
+        Some((user, _)) if !user.is_empty() => Ok(s.to_string()),
+        _ => Err(anyhow!("expected --auth in the form user:pass")),
+    }
+}
@@ -0,0 +1,282 @@
+//! Signed mirrors (`--sign KEY`, `streamrip verify --signature PUBKEY`).
+//!
+//! At the end of a run, the checksum catalog `SHA256SUMS` (see
+//! [`crate::checksums`]) is signed with a
+//! [minisign](https://jedisct1.github.io/minisign/) key into
+//! `SHA256SUMS.minisig`. Whoever receives a copy of the mirror can then check
+//! that the list is the one the key's owner signed, and that every file on it
+//! is present and unchanged.
+//!
+//! Secret keys must be unencrypted (`minisign -G -W`); signatures are
+//! minisign's original Ed25519 ones, which `minisign -V` accepts as well.
+
+use crate::checksums::{self, SUMS_FILE};
+use crate::digests::Digests;
+use crate::report::{Event, SharedReporter};
+use anyhow::{Context, Result, anyhow, bail};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use ring::signature::{ED25519, Ed25519KeyPair, UnparsedPublicKey};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The minisign signature of [`SUMS_FILE`].
+pub const SIGNATURE_FILE: &str = "SHA256SUMS.minisig";
+
+/// Signature algorithm of minisign keys and (non-prehashed) signatures.
+const ALGORITHM: &[u8; 2] = b"Ed";
+
+const UNTRUSTED_COMMENT: &str = "untrusted comment: ";
+const TRUSTED_COMMENT: &str = "trusted comment: ";
+
+/// A minisign secret key.
+struct SecretKey {
+    id: [u8; 8],
+    pair: Ed25519KeyPair,
+}
+
+/// A minisign public key.
+struct PublicKey {
+    id: [u8; 8],
+    key: [u8; 32],
+}
+
+/// Sign `catalog`, the checksum catalog just written below `out_dir`, with
+/// the secret key at `key`, dating the signature to `at`.
+pub async fn sign(
+    out_dir: &Path,
+    key: &Path,
+    catalog: &str,
+    at: SystemTime,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    let key = SecretKey::read(key)?;
+    let count = catalog.lines().count();
+    let signature = key.pair.sign(catalog.as_bytes());
+    let trusted = format!(
+        "timestamp:{}\tfile:{}",
+        at.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()),
+        SUMS_FILE
+    );
+    let global = key
+        .pair
+        .sign(&[signature.as_ref(), trusted.as_bytes()].concat());
+    let minisig = format!(
+        "{}signature from streamrip secret key {}\n{}\n{}{}\n{}\n",
+        UNTRUSTED_COMMENT,
+        key_id(&key.id),
+        BASE64.encode([ALGORITHM.as_slice(), &key.id, signature.as_ref()].concat()),
+        TRUSTED_COMMENT,
+        trusted,
+        BASE64.encode(global.as_ref())
+    );
+
+    // In the clear even with --encrypt-output, like the catalog it signs.
+    let path = out_dir.join(SIGNATURE_FILE);
+    tokio::fs::write(&path, minisig)
+        .await
+        .with_context(|| format!("writing {}", path.display()))?;
+    reporter.report(Event::Status {
+        tag: "SIGN",
+        message: format!(
+            "{} file(s) listed in {} signed with key {}",
+            count,
+            SUMS_FILE,
+            key_id(&key.id)
+        ),
+    });
+    Ok(())
+}
+
+/// Check the signature of the checksum list below `dir` with the public key
+/// at `key`, then every file on the list. Files missing or changed are
+/// failures; files not on the list are warnings. With `fail_fast`, stop at
+/// the first failure.
+///
+/// Returns the number of failures; a signature that doesn't verify is an
+/// error.
+pub async fn verify(
+    dir: &Path,
+    key: &Path,
+    fail_fast: bool,
+    reporter: &SharedReporter,
+) -> Result<usize> {
+    let key = PublicKey::read(key)?;
+    let sums_path = dir.join(SUMS_FILE);
+    let list = tokio::fs::read(&sums_path)
+        .await
+        .with_context(|| format!("reading {}", sums_path.display()))?;
+    let signature_path = dir.join(SIGNATURE_FILE);
+    let minisig = tokio::fs::read_to_string(&signature_path)
+        .await
+        .with_context(|| format!("reading {}", signature_path.display()))?;
+    let trusted = key
+        .verify(&list, &minisig)
+        .with_context(|| format!("checking {}", signature_path.display()))?;
+    reporter.report(Event::Status {
+        tag: "VRFY",
+        message: format!(
+            "{} signed by key {} ({})",
+            SUMS_FILE,
+            key_id(&key.id),
+            trusted
+        ),
+    });
+
+    let list =
+        String::from_utf8(list).with_context(|| format!("reading {}", sums_path.display()))?;
+    let listed: Vec<(&str, &str)> = list
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            line.split_once("  ")
+                .ok_or_else(|| anyhow!("malformed line in {}: '{}'", sums_path.display(), line))
+        })
+        .collect::<Result<_>>()?;
+
+    let owned = dir.to_path_buf();
+    // Nothing was written here by this process; every file is read.
+    let (present, _) =
+        tokio::task::spawn_blocking(move || checksums::list(&owned, &Digests::default()))
+            .await
+            .context("hashing the mirror")??;
+    let present: HashMap<&str, &str> = present
+        .iter()
+        .map(|(hash, path)| (path.as_str(), hash.as_str()))
+        .collect();
+    let signed: HashSet<&str> = listed.iter().map(|(_, path)| *path).collect();
+    let mut unlisted: Vec<&str> = present
+        .keys()
+        .copied()
+        .filter(|path| !signed.contains(path))
+        .collect();
+    unlisted.sort_unstable();
+
+    let mut failed = 0;
+    for (hash, path) in &listed {
+        let message = match present.get(path) {
+            None => "missing",
+            Some(actual) if actual != hash => "content differs from the signed checksum",
+            Some(_) => continue,
+        };
+        reporter.report(Event::Failure {
+            subject: dir.join(path).display().to_string(),
+            message: message.to_string(),
+        });
+        failed += 1;
+        if fail_fast {
+            break;
+        }
+    }
+    for path in &unlisted {
+        reporter.report(Event::Warning {
+            message: format!("{}: not in {}", dir.join(path).display(), SUMS_FILE),
+        });
+    }
+
+    reporter.report(Event::Status {
+        tag: "VRFY",
+        message: format!(
+            "{} signed file(s), {} missing or changed, {} unlisted",
+            listed.len(),
+            failed,
+            unlisted.len()
+        ),
+    });
+    Ok(failed)
+}
+
+impl SecretKey {
+    fn read(path: &Path) -> Result<Self> {
+        let data = read_key(path)?;
+        // Algorithm, KDF and checksum algorithms, KDF salt, ops and memory
+        // limits, then key ID, secret key (seed and public key) and checksum.
+        if data.len() != 158 || &data[..2] != ALGORITHM {
+            bail!("{} is not a minisign secret key", path.display());
+        }
+        if data[2..4] != [0, 0] {
+            bail!(
+                "{} is encrypted with a password; create an unencrypted key with `minisign -G -W`",
+                path.display()
+            );
+        }
+        let id = data[54..62].try_into().expect("8 bytes");
+        let pair = Ed25519KeyPair::from_seed_and_public_key(&data[62..94], &data[94..126])
+            .map_err(|e| anyhow!("invalid minisign secret key in {}: {}", path.display(), e))?;
+        Ok(Self { id, pair })
+    }
+}
+
+impl PublicKey {
+    fn read(path: &Path) -> Result<Self> {
+        let data = read_key(path)?;
+        if data.len() != 42 || &data[..2] != ALGORITHM {
+            bail!("{} is not a minisign public key", path.display());
+        }
+        Ok(Self {
+            id: data[2..10].try_into().expect("8 bytes"),
+            key: data[10..].try_into().expect("32 bytes"),
+        })
+    }
+
+    /// Check `minisig` as a signature of `data`; returns its trusted comment.
+    fn verify<'a>(&self, data: &[u8], minisig: &'a str) -> Result<&'a str> {
+        let mut lines = minisig
+            .lines()
+            .filter(|line| !line.starts_with(UNTRUSTED_COMMENT));
+        let (Some(signature), Some(trusted), Some(global)) =
+            (lines.next(), lines.next(), lines.next())
+        else {
+            bail!("not a minisign signature");
+        };
+        let signature = BASE64
+            .decode(signature.trim())
+            .context("decoding the signature")?;
+        if signature.len() != 74 {
+            bail!("not a minisign signature");
+        }
+        if &signature[..2] != ALGORITHM {
+            bail!("prehashed minisign signatures aren't supported");
+        }
+        if signature[2..10] != self.id {
+            bail!(
+                "signed with key {}, not {}",
+                key_id(&signature[2..10]),
+                key_id(&self.id)
+            );
+        }
+        let trusted = trusted
+            .strip_prefix(TRUSTED_COMMENT)
+            .ok_or_else(|| anyhow!("the trusted comment is missing"))?;
+        let global = BASE64
+            .decode(global.trim())
+            .context("decoding the signature")?;
+
+        let key = UnparsedPublicKey::new(&ED25519, self.key);
+        key.verify(data, &signature[10..])
+            .map_err(|_| anyhow!("the signature doesn't match {}", SUMS_FILE))?;
+        key.verify(&[&signature[10..], trusted.as_bytes()].concat(), &global)
+            .map_err(|_| anyhow!("the trusted comment has been tampered with"))?;
+        Ok(trusted)
+    }
+}
+
+/// The decoded key in the minisign key file at `path`.
+fn read_key(path: &Path) -> Result<Vec<u8>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading minisign key {}", path.display()))?;
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with(UNTRUSTED_COMMENT))
+        .ok_or_else(|| anyhow!("no key in {}", path.display()))?;
+    BASE64
+        .decode(line)
+        .with_context(|| format!("decoding minisign key {}", path.display()))
+}
+
+/// Key IDs as minisign prints them.
+fn key_id(id: &[u8]) -> String {
+    id.iter().rev().map(|b| format!("{:02X}", b)).collect()
+}
@@ -0,0 +1,93 @@
+//! Per-mirror state persisted between runs in the output directory.
+//!
+//! The state file lists the identity of every binary resource already
+//! downloaded, one per line, so later runs into the same directory (repeated
+//! live recordings, updates of a mirror) don't fetch shared init segments,
+//! keys or subtitles again.
+//!
+//! It also names the start URLs mirrored into the directory, on lines of
+//! their own starting with `#`, so that a run with another start URL doesn't
+//! interleave a second mirror with the first.
+
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::Path;
+use tokio::io::AsyncWriteExt;
+
+/// File name of the state file inside the output directory.
+pub const STATE_FILE: &str = ".streamrip-state";
+
+/// Prefix of the lines naming a start URL.
+const SOURCE_PREFIX: &str = "# source ";
+
+/// Open state file, appended to as resources are downloaded.
+#[derive(Debug)]
+pub struct StateFile {
+    file: tokio::fs::File,
+}
+
+/// Identities of the resources recorded by previous runs into `out_dir`.
+pub async fn load(out_dir: &Path) -> Result<HashSet<String>> {
+    Ok(read(out_dir)
+        .await?
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Start URLs recorded by previous runs into `out_dir`.
+pub async fn sources(out_dir: &Path) -> Result<Vec<String>> {
+    Ok(read(out_dir)
+        .await?
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix(SOURCE_PREFIX))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Text of the state file in `out_dir`; empty if there is none.
+async fn read(out_dir: &Path) -> Result<String> {
+    let path = out_dir.join(STATE_FILE);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("reading {}", path.display())),
+    }
+}
+
+impl StateFile {
+    pub async fn open(out_dir: &Path) -> Result<Self> {
+        let path = out_dir.join(STATE_FILE);
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+            .with_context(|| format!("opening {}", path.display()))?;
+        Ok(Self { file })
+    }
+
+    /// Record a downloaded resource.
+    pub async fn record(&mut self, identity: &str) -> Result<()> {
+        self.file
+            .write_all(format!("{identity}\n").as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Record the start URL being mirrored.
+    pub async fn record_source(&mut self, source: &str) -> Result<()> {
+        self.file
+            .write_all(format!("{SOURCE_PREFIX}{source}\n").as_bytes())
+            .await?;
+        Ok(())
+    }
+
+    /// Flush everything recorded and close the file.
+    pub async fn close(mut self) -> Result<()> {
+        self.file.flush().await?;
+        Ok(())
+    }
+}
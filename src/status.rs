@@ -0,0 +1,95 @@
+//! `--status-file`: snapshots of a run's progress in the output directory.
+//!
+//! While mirroring, `status.json` is rewritten every few seconds with what
+//! [`crate::jobs`] keeps about a job of the watcher: the phase, how many of
+//! the planned files are done, bytes, rate, the estimated time left,
+//! warnings and failures. External tools and people can check on a long
+//! headless mirror by reading it instead of attaching to its output. The
+//! file is replaced by a rename, so it is never seen half written.
+
+use crate::jobs::JobBoard;
+use crate::report::{Event, SharedReporter};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// File name of the status file inside the output directory.
+pub const STATUS_FILE: &str = "status.json";
+
+/// How often the status file is rewritten.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Status of one run, written to its output directory until [`finish`]ed.
+///
+/// [`finish`]: Snapshots::finish
+pub(crate) struct Snapshots {
+    board: JobBoard,
+    id: String,
+    path: PathBuf,
+    writer: tokio::task::JoinHandle<()>,
+    reporter: SharedReporter,
+}
+
+impl Snapshots {
+    /// Start writing the status of mirroring `start_url` into `out_dir`.
+    /// Events reported through [`Snapshots::reporter`] go on to `inner`.
+    pub fn start(out_dir: &Path, start_url: &str, inner: SharedReporter) -> Self {
+        let board = JobBoard::default();
+        let id = out_dir
+            .file_name()
+            .unwrap_or(out_dir.as_os_str())
+            .to_string_lossy()
+            .into_owned();
+        let reporter = board.start(&id, start_url, inner);
+        let path = out_dir.join(STATUS_FILE);
+        let writer = {
+            let (board, id, path) = (board.clone(), id.clone(), path.clone());
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(INTERVAL);
+                loop {
+                    ticks.tick().await;
+                    // The output directory may not exist yet; the next
+                    // snapshot will do.
+                    let _ = write(&board, &id, &path).await;
+                }
+            })
+        };
+        Self {
+            board,
+            id,
+            path,
+            writer,
+            reporter,
+        }
+    }
+
+    /// Reporter of the run, keeping the status up to date.
+    pub fn reporter(&self) -> SharedReporter {
+        self.reporter.clone()
+    }
+
+    /// Write the final status, the run having ended with `result`.
+    pub async fn finish(self, result: &Result<()>, reporter: &SharedReporter) {
+        self.writer.abort();
+        self.board.finish(&self.id, result);
+        // An archived mirror's directory is gone.
+        if self.path.parent().is_some_and(Path::is_dir)
+            && let Err(e) = write(&self.board, &self.id, &self.path).await
+        {
+            reporter.report(Event::Warning {
+                message: format!("writing {}: {:#}", self.path.display(), e),
+            });
+        }
+    }
+}
+
+/// Replace the status file at `path` with the status of job `id`.
+async fn write(board: &JobBoard, id: &str, path: &Path) -> Result<()> {
+    let Some(json) = board.job_json(id) else {
+        return Ok(());
+    };
+    let partial = path.with_extension("json.partial");
+    tokio::fs::write(&partial, json).await?;
+    tokio::fs::rename(&partial, path).await?;
+    Ok(())
+}
@@ -0,0 +1,106 @@
+//! Shared segment store (`--segment-store`): downloaded files kept once,
+//! named by their content, with the mirrors linking to them.
+//!
+//! Mirrors of related streams (events of the same channel, repeated
+//! recordings) share most of their init segments, slates and keys, and
+//! sometimes their segments. With a store, each file lives once as
+//! `<store>/<first two hex digits>/<SHA-256><extension>`, and each mirror's
+//! tree holds a symlink to it in the file's place.
+
+use crate::digests::{self, Sha256Digest};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Directory of content-addressed files.
+#[derive(Debug, Clone)]
+pub(crate) struct SegmentStore {
+    /// Absolute, so links resolve from anywhere in a mirror.
+    dir: Arc<Path>,
+}
+
+impl SegmentStore {
+    /// Open the store at `dir`, creating it if needed.
+    pub async fn open(dir: &Path) -> Result<Self> {
+        tokio::fs::create_dir_all(dir)
+            .await
+            .with_context(|| format!("creating segment store {}", dir.display()))?;
+        let dir = tokio::fs::canonicalize(dir)
+            .await
+            .with_context(|| format!("resolving segment store {}", dir.display()))?;
+        Ok(Self { dir: dir.into() })
+    }
+
+    /// Store `data` unless the store has it already, and link `path` to it.
+    /// Returns the SHA-256 of the file `path` leads to if asked to `hash`
+    /// it and it is known: a file stored sealed by an earlier run isn't.
+    pub async fn write(
+        &self,
+        path: &Path,
+        data: &[u8],
+        hash: bool,
+    ) -> Result<Option<Sha256Digest>> {
+        let content = digests::sha256(data);
+        let stored = self.path_for(path, &content);
+        let sealed = crate::encrypt::enabled();
+        let mut written = None;
+        if tokio::fs::metadata(&stored).await.is_err() {
+            // Written under a temporary name first, so other runs sharing the
+            // store never link to a partial file.
+            let partial = stored.with_file_name(format!(
+                "{}.partial.{}",
+                stored.file_name().unwrap_or_default().to_string_lossy(),
+                std::process::id()
+            ));
+            written = crate::write_file_hashed(&partial, data, hash && sealed).await?;
+            tokio::fs::rename(&partial, &stored)
+                .await
+                .with_context(|| format!("storing {}", stored.display()))?;
+        }
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("creating directory {}", parent.display()))?;
+        }
+        // A file or link from an earlier run.
+        match tokio::fs::remove_file(path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("replacing {}", path.display()));
+            }
+            _ => {}
+        }
+        link(&stored, path)
+            .await
+            .with_context(|| format!("linking {} to {}", path.display(), stored.display()))?;
+        // Unsealed, the stored file is `data`, as its name says.
+        Ok(match (hash, sealed) {
+            (false, _) => None,
+            (true, true) => written,
+            (true, false) => Some(content),
+        })
+    }
+
+    /// Where content with the SHA-256 `content`, mirrored to `path`, is kept.
+    fn path_for(&self, path: &Path, content: &Sha256Digest) -> PathBuf {
+        let hash: String = content.iter().map(|b| format!("{:02x}", b)).collect();
+        let extension = path
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+        self.dir
+            .join(&hash[..2])
+            .join(format!("{}{}", hash, extension))
+    }
+}
+
+#[cfg(unix)]
+async fn link(target: &Path, path: &Path) -> std::io::Result<()> {
+    tokio::fs::symlink(target, path).await
+}
+
+/// Symlinks need privileges on Windows; hard links work within a volume.
+#[cfg(not(unix))]
+async fn link(target: &Path, path: &Path) -> std::io::Result<()> {
+    tokio::fs::hard_link(target, path).await
+}
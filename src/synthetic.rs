@@ -0,0 +1,315 @@
+//! Synthetic master playlists (`--synthesize-master`): when the start URL is
+//! an HLS media playlist, a master playlist listing it is written next to it,
+//! for players that only open master playlists.
+//!
+//! The variant's `BANDWIDTH` (peak) and `AVERAGE-BANDWIDTH` are measured from
+//! the mirrored segments and their durations; `CODECS` and `RESOLUTION` are
+//! read from the sample entries of the fMP4 init segment, where there is one.
+//! `--master-bandwidth`, `--master-codecs` and `--master-resolution` set them
+//! where that fails or guesses wrong.
+
+#![cfg_attr(not(feature = "hls"), allow(dead_code))]
+
+use crate::bmff;
+#[cfg(feature = "hls")]
+use crate::playlist::{HlsPlaylist, Layout, Line};
+#[cfg(feature = "hls")]
+use crate::report::Event;
+#[cfg(feature = "hls")]
+use crate::{Mirror, encrypt};
+use anyhow::{Result, anyhow};
+#[cfg(feature = "hls")]
+use std::path::{Path, PathBuf};
+#[cfg(feature = "hls")]
+use url::Url;
+
+/// Name of the synthesized master playlist, next to the media playlist.
+pub const FILE_NAME: &str = "master.m3u8";
+
+/// Name used instead when the media playlist is called [`FILE_NAME`] itself.
+const FALLBACK_NAME: &str = "master-synthetic.m3u8";
+
+/// What to put in a synthesized master playlist.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct MasterOptions {
+    /// When the start URL is an HLS media playlist rather than a master
+    /// playlist, also write a master playlist listing it, for players that
+    /// require one
+    #[arg(long, conflicts_with_all = ["estimate", "header_variants"])]
+    pub synthesize_master: bool,
+
+    /// BANDWIDTH of the synthesized master playlist's variant, instead of the
+    /// peak bitrate measured from the segments
+    #[arg(long, value_name = "BPS", requires = "synthesize_master")]
+    pub master_bandwidth: Option<u64>,
+
+    /// CODECS of the synthesized master playlist's variant (e.g.
+    /// avc1.64001f,mp4a.40.2), instead of those read from the init segment
+    #[arg(long, value_name = "CODECS", requires = "synthesize_master")]
+    pub master_codecs: Option<String>,
+
+    /// RESOLUTION of the synthesized master playlist's variant (e.g.
+    /// 1280x720), instead of the one read from the init segment
+    #[arg(long, value_name = "WxH", value_parser = parse_resolution, requires = "synthesize_master")]
+    pub master_resolution: Option<(u32, u32)>,
+}
+
+/// Parse `--master-resolution`.
+pub fn parse_resolution(s: &str) -> Result<(u32, u32)> {
+    s.trim()
+        .split_once(['x', 'X'])
+        .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+        .ok_or_else(|| anyhow!("expected WIDTHxHEIGHT, e.g. 1280x720, got '{}'", s))
+}
+
+/// What the sample entries of an init segment say about its tracks.
+#[derive(Debug, Default)]
+struct Tracks {
+    /// RFC 6381 codec strings, or `None` if any track's couldn't be read.
+    codecs: Option<Vec<String>>,
+    resolution: Option<(u32, u32)>,
+}
+
+/// Read the tracks of the ISO BMFF init segment `data`.
+fn tracks(data: &[u8]) -> Tracks {
+    let mut entries = Vec::new();
+    collect_sample_entries(data, &mut entries);
+    let mut tracks = Tracks {
+        codecs: Some(Vec::new()),
+        resolution: None,
+    };
+    if entries.is_empty() {
+        tracks.codecs = None;
+    }
+    for (kind, body) in entries {
+        // Encrypted entries name the original format in sinf/frma.
+        let kind = match &kind {
+            b"encv" | b"enca" => {
+                let offset = if &kind == b"encv" { 78 } else { 28 };
+                body.get(offset..)
+                    .and_then(|children| child(children, b"sinf"))
+                    .and_then(|sinf| child(sinf, b"frma"))
+                    .and_then(|frma| frma.get(..4))
+                    .map_or(kind, |f| [f[0], f[1], f[2], f[3]])
+            }
+            _ => kind,
+        };
+        let codec = match &kind {
+            b"avc1" | b"avc3" => {
+                if body.len() >= 28 && tracks.resolution.is_none() {
+                    let width = u16::from_be_bytes([body[24], body[25]]);
+                    let height = u16::from_be_bytes([body[26], body[27]]);
+                    tracks.resolution = Some((width.into(), height.into()));
+                }
+                body.get(78..)
+                    .and_then(|children| child(children, b"avcC"))
+                    .filter(|avcc| avcc.len() >= 4)
+                    .map(|avcc| {
+                        format!(
+                            "{}.{:02x}{:02x}{:02x}",
+                            String::from_utf8_lossy(&kind),
+                            avcc[1],
+                            avcc[2],
+                            avcc[3]
+                        )
+                    })
+            }
+            // AAC-LC, by far the most common; anything else needs
+            // --master-codecs.
+            b"mp4a" => Some("mp4a.40.2".to_string()),
+            b"ac-3" | b"ec-3" | b"Opus" | b"fLaC" => {
+                Some(String::from_utf8_lossy(&kind).into_owned())
+            }
+            _ => None,
+        };
+        match (codec, &mut tracks.codecs) {
+            (Some(codec), Some(codecs)) => codecs.push(codec),
+            _ => tracks.codecs = None,
+        }
+    }
+    tracks
+}
+
+/// The sample entries of every track, as (format, entry body).
+fn collect_sample_entries<'a>(data: &'a [u8], found: &mut Vec<([u8; 4], &'a [u8])>) {
+    for found_box in bmff::boxes(data) {
+        let body = found_box.body;
+        match &found_box.kind {
+            b"moov" | b"trak" | b"mdia" | b"minf" | b"stbl" => collect_sample_entries(body, found),
+            // version and flags, then the entry count
+            b"stsd" if body.len() >= 8 => {
+                found.extend(bmff::boxes(&body[8..]).map(|entry| (entry.kind, entry.body)))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The body of the first `kind` box among `data`'s.
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    bmff::boxes(data)
+        .find(|found| &found.kind == kind)
+        .map(|found| found.body)
+}
+
+#[cfg(feature = "hls")]
+impl Mirror {
+    /// Write a master playlist for the media playlist mirrored from `url`
+    /// (the start URL), unless it is a master playlist itself.
+    pub(crate) async fn write_synthetic_master(
+        &mut self,
+        url: &Url,
+        options: &MasterOptions,
+    ) -> Result<()> {
+        if !self.master_playlists.is_empty() {
+            self.reporter.report(Event::Detail {
+                message: format!("{} is a master playlist, not synthesizing one", url),
+            });
+            return Ok(());
+        }
+        let media_path = self.path_for_url(url, true);
+        let Ok(data) = encrypt::read(&media_path).await else {
+            self.reporter.report(Event::Detail {
+                message: format!("{} is not an HLS playlist, not synthesizing a master", url),
+            });
+            return Ok(());
+        };
+        let text = String::from_utf8_lossy(&data);
+        let Some(HlsPlaylist::Media(media)) = HlsPlaylist::parse(&text) else {
+            return Ok(());
+        };
+        let dir = media_path
+            .parent()
+            .ok_or_else(|| anyhow!("manifest path has no parent: {}", media_path.display()))?
+            .to_path_buf();
+
+        let (peak, average, init) = self.measure(&media.lines, &dir).await;
+        let Some(bandwidth) = options.master_bandwidth.or(peak) else {
+            self.reporter.report(Event::Warning {
+                message: format!(
+                    "can't measure the bandwidth of {} (no segments mirrored); set --master-bandwidth to synthesize a master playlist",
+                    url
+                ),
+            });
+            return Ok(());
+        };
+        let tracks = match init {
+            Some(init) => encrypt::read(&init)
+                .await
+                .map(|data| tracks(&data))
+                .unwrap_or_default(),
+            None => Default::default(),
+        };
+
+        let mut attributes = format!("BANDWIDTH={}", bandwidth);
+        if let Some(average) = average.filter(|_| options.master_bandwidth.is_none()) {
+            attributes.push_str(&format!(",AVERAGE-BANDWIDTH={}", average));
+        }
+        match options
+            .master_codecs
+            .clone()
+            .or(tracks.codecs.map(|c| c.join(",")))
+        {
+            Some(codecs) => attributes.push_str(&format!(",CODECS=\"{}\"", codecs)),
+            None => self.reporter.report(Event::Detail {
+                message: "codecs unknown, leaving CODECS out of the synthesized master playlist (see --master-codecs)".to_string(),
+            }),
+        }
+        if let Some((width, height)) = options.master_resolution.or(tracks.resolution) {
+            attributes.push_str(&format!(",RESOLUTION={}x{}", width, height));
+        }
+
+        let name = if media_path.file_name().is_some_and(|n| n == FILE_NAME) {
+            FALLBACK_NAME
+        } else {
+            FILE_NAME
+        };
+        let path = dir.join(name);
+        let mut lines = vec!["#EXTM3U".to_string()];
+        if let Some(provenance) = &self.catalog.provenance {
+            lines.push(provenance.hls_comment(url));
+        }
+        lines.push(format!("#EXT-X-STREAM-INF:{}", attributes));
+        lines.push(self.reference(&media_path, &dir));
+
+        let layout = Layout::default();
+        crate::write_playlist(&path, layout, &lines).await?;
+        self.reporter.report(Event::Status {
+            tag: "MSTR",
+            message: format!("{} synthesized ({})", path.display(), attributes),
+        });
+        // Copied into live chunks and archives like any other master.
+        self.master_playlists.push((path, lines, layout));
+        Ok(())
+    }
+
+    /// Peak and average bitrate of the mirrored segments of a rewritten
+    /// media playlist in `dir`, and its first init segment.
+    async fn measure(
+        &self,
+        lines: &[Line<'_>],
+        dir: &Path,
+    ) -> (Option<u64>, Option<u64>, Option<PathBuf>) {
+        let mut duration = None;
+        let mut range = None;
+        let mut init = None;
+        let (mut peak, mut bits, mut seconds) = (0.0_f64, 0.0, 0.0);
+        for line in lines {
+            match line {
+                Line::Tag(tag) if tag.is("#EXTINF") => {
+                    duration = tag.duration();
+                }
+                Line::Tag(tag) if tag.is("#EXT-X-BYTERANGE") => {
+                    range = tag
+                        .value()
+                        .split('@')
+                        .next()
+                        .and_then(|l| l.trim().parse::<u64>().ok());
+                }
+                Line::Tag(tag) if tag.is("#EXT-X-MAP") && init.is_none() => {
+                    init = tag.attribute("URI").map(|uri| self.local_file(&uri, dir));
+                }
+                Line::Uri { uri, .. } => {
+                    let (Some(secs), range) = (duration.take(), range.take()) else {
+                        continue;
+                    };
+                    let size = match range {
+                        Some(length) => Some(length),
+                        None => tokio::fs::metadata(self.local_file(uri, dir))
+                            .await
+                            .ok()
+                            .map(|m| m.len()),
+                    };
+                    let Some(size) = size.filter(|_| secs > 0.0) else {
+                        continue;
+                    };
+                    let segment_bits = size as f64 * 8.0;
+                    peak = peak.max(segment_bits / secs);
+                    bits += segment_bits;
+                    seconds += secs;
+                }
+                _ => {}
+            }
+        }
+        if seconds == 0.0 {
+            return (None, None, init);
+        }
+        (
+            Some(peak.ceil() as u64),
+            Some((bits / seconds).ceil() as u64),
+            init,
+        )
+    }
+
+    /// The file a rewritten playlist in `dir` refers to as `reference`.
+    fn local_file(&self, reference: &str, dir: &Path) -> PathBuf {
+        match self
+            .rewrite_base
+            .as_deref()
+            .and_then(|base| reference.strip_prefix(base))
+        {
+            Some(relative) => self.out_dir.join(relative),
+            None => dir.join(reference),
+        }
+    }
+}
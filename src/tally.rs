@@ -0,0 +1,48 @@
+//! Counts of what happened to the resources of a run, reported at the end
+//! so that re-runs show how much was already up to date.
+
+use crate::units;
+
+/// What happened to every resource a run came across.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Tally {
+    /// Manifests and files downloaded.
+    pub fetched: usize,
+    /// Files skipped because an earlier run downloaded them.
+    pub up_to_date: usize,
+    /// References to a resource already handled in this run.
+    pub duplicates: usize,
+    /// Renditions left out by the rendition filter.
+    pub filtered: usize,
+    /// Segments that failed to download and were dropped.
+    pub failed: usize,
+    /// Media segments left out by `--manifests-only`.
+    pub skipped: usize,
+}
+
+impl Tally {
+    /// One-line summary such as `12,431 up-to-date, 12 fetched, 0 failed`.
+    pub fn summary(&self) -> String {
+        let mut parts = vec![
+            format!("{} up-to-date", units::count(self.up_to_date)),
+            format!("{} fetched", units::count(self.fetched)),
+            format!("{} failed", units::count(self.failed)),
+        ];
+        if self.duplicates > 0 {
+            parts.push(format!(
+                "{} duplicate reference(s)",
+                units::count(self.duplicates)
+            ));
+        }
+        if self.filtered > 0 {
+            parts.push(format!(
+                "{} rendition(s) filtered",
+                units::count(self.filtered)
+            ));
+        }
+        if self.skipped > 0 {
+            parts.push(format!("{} segment(s) skipped", units::count(self.skipped)));
+        }
+        parts.join(", ")
+    }
+}
@@ -0,0 +1,173 @@
+//! DASH thumbnail tracks: image AdaptationSets whose segments are tiles of
+//! trick-play previews. Next to the MPD, each thumbnail Representation gets
+//! a WebVTT index (`<id>.thumbnails.vtt`, with `#xywh=` media fragments as
+//! understood by most web players) and the same information as JSON.
+
+use crate::Mirror;
+use crate::report::{Event, json_string};
+use anyhow::Result;
+use roxmltree::Node;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Scheme of the property describing the tile grid, e.g. `value="10x1"`.
+const TILE_SCHEMES: &[&str] = &[
+    "http://dashif.org/thumbnail_tile",
+    "http://dashif.org/guidelines/thumbnail_tile",
+];
+
+/// Most tiles one image is taken to hold; larger grids are malformed and
+/// would make an index of millions of entries per image.
+const MAX_TILES: u32 = 1024;
+
+/// A thumbnail Representation and its mirrored tile images.
+pub struct ThumbnailTrack {
+    pub representation: String,
+    pub columns: u32,
+    pub rows: u32,
+    /// Size of a whole tile image.
+    pub width: u32,
+    pub height: u32,
+    /// Duration covered by one tile image, in seconds.
+    pub segment_secs: f64,
+    pub urls: Vec<Url>,
+}
+
+/// Whether an AdaptationSet or Representation carries images.
+pub fn is_image(node: &Node) -> bool {
+    node.attribute("contentType") == Some("image")
+        || node
+            .attribute("mimeType")
+            .is_some_and(|m| m.starts_with("image/"))
+}
+
+/// Tile grid (columns, rows) from an `EssentialProperty` or
+/// `SupplementalProperty`; the Representation's takes precedence. Grids
+/// without tiles or of more than [`MAX_TILES`] count as a single tile.
+pub fn tiling(aset: &Node, rep: &Node) -> (u32, u32) {
+    [rep, aset]
+        .into_iter()
+        .flat_map(|n| n.children())
+        .filter(|n| {
+            n.is_element()
+                && matches!(
+                    n.tag_name().name(),
+                    "EssentialProperty" | "SupplementalProperty"
+                )
+                && n.attribute("schemeIdUri")
+                    .is_some_and(|s| TILE_SCHEMES.contains(&s))
+        })
+        .find_map(|n| {
+            let (c, r) = n.attribute("value")?.split_once(['x', 'X'])?;
+            Some((c.trim().parse::<u32>().ok()?, r.trim().parse().ok()?))
+        })
+        .filter(|&(c, r)| {
+            c.checked_mul(r)
+                .is_some_and(|tiles| (1..=MAX_TILES).contains(&tiles))
+        })
+        .unwrap_or((1, 1))
+}
+
+impl Mirror {
+    /// Write the WebVTT and JSON thumbnail index for `track` into `mpd_dir`.
+    pub(crate) async fn write_thumbnail_index(
+        &mut self,
+        track: &ThumbnailTrack,
+        mpd_dir: &Path,
+    ) -> Result<()> {
+        let tile_width = track.width / track.columns;
+        let tile_height = track.height / track.rows;
+        let per_image = track
+            .columns
+            .checked_mul(track.rows)
+            .filter(|&tiles| tiles <= MAX_TILES)
+            .unwrap_or(1);
+        let thumb_secs = track.segment_secs / per_image as f64;
+
+        let mut vtt = String::from("WEBVTT\n");
+        let mut entries = Vec::new();
+        for (i, url) in track.urls.iter().enumerate() {
+            let image_path = self.path_for_url(url, false);
+            let image = self.reference(&image_path, mpd_dir);
+            for t in 0..per_image {
+                let start = i as f64 * track.segment_secs + t as f64 * thumb_secs;
+                let end = start + thumb_secs;
+                let x = (t % track.columns) * tile_width;
+                let y = (t / track.columns) * tile_height;
+                vtt.push_str(&format!(
+                    "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+                    vtt_time(start),
+                    vtt_time(end),
+                    image,
+                    x,
+                    y,
+                    tile_width,
+                    tile_height
+                ));
+                entries.push(format!(
+                    "    {{\"start\": {:.3}, \"end\": {:.3}, \"image\": {}, \"x\": {}, \"y\": {}, \"w\": {}, \"h\": {}}}",
+                    start,
+                    end,
+                    json_string(&image),
+                    x,
+                    y,
+                    tile_width,
+                    tile_height
+                ));
+            }
+        }
+
+        let json = format!(
+            "{{\n  \"representation\": {},\n  \"columns\": {},\n  \"rows\": {},\n  \"thumbnails\": [\n{}\n  ]\n}}\n",
+            json_string(&track.representation),
+            track.columns,
+            track.rows,
+            entries.join(",\n")
+        );
+
+        let base = sanitize(&track.representation);
+        let vtt_path: PathBuf = mpd_dir.join(format!("{base}.thumbnails.vtt"));
+        self.write_output(&vtt_path, vtt.as_bytes()).await?;
+        self.write_output(
+            &mpd_dir.join(format!("{base}.thumbnails.json")),
+            json.as_bytes(),
+        )
+        .await?;
+
+        self.reporter.report(Event::Status {
+            tag: "THMB",
+            message: format!(
+                "{}: {} thumbnails in {} images -> {}",
+                track.representation,
+                entries.len(),
+                track.urls.len(),
+                vtt_path.display()
+            ),
+        });
+        Ok(())
+    }
+}
+
+fn vtt_time(secs: f64) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// Representation ids may contain characters unsuitable for file names.
+fn sanitize(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,104 @@
+//! Request timeouts scaled by what is being downloaded (`--min-throughput`).
+//!
+//! A fixed timeout either kills a 50 MB segment on a slow link or waits far
+//! too long on a stalled 2 KB playlist. Instead, a response has to start
+//! within `--response-timeout`, and its body may then take that long again
+//! plus its size at the minimum throughput: the `Content-Length` when it is
+//! sent, or else what has arrived so far, so a body of unknown size fails as
+//! soon as it falls behind.
+
+use crate::debug_http::SendLogged;
+use crate::hosts::{self, Outcome};
+use crate::units;
+use anyhow::{Context, Result, anyhow, bail};
+use bytes::{Bytes, BytesMut};
+use reqwest::{RequestBuilder, Response};
+use std::time::Duration;
+use tokio::time::Instant;
+use url::Url;
+
+/// Default for `--response-timeout`.
+pub const DEFAULT_RESPONSE: Duration = Duration::from_secs(10);
+
+/// How long requests may take; without a minimum throughput, unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    /// Bytes per second.
+    pub min_throughput: Option<u64>,
+    /// Until the response starts, and the body's allowance on top of its size.
+    pub response: Duration,
+}
+
+impl Timeouts {
+    /// Send `request` for `url`, failing if no response starts in time.
+    pub async fn send(self, request: RequestBuilder, url: &Url) -> Result<Response> {
+        if self.min_throughput.is_none() {
+            return request
+                .send_logged()
+                .await
+                .with_context(|| format!("GET {}", url));
+        }
+        tokio::time::timeout(self.response, request.send_logged())
+            .await
+            .map_err(|_| {
+                hosts::record(url, Outcome::Timeout, self.response);
+                anyhow!(
+                    "GET {}: no response within {}",
+                    url,
+                    units::duration(self.response.as_secs_f64())
+                )
+            })?
+            .with_context(|| format!("GET {}", url))
+    }
+
+    /// The body of `resp`, for `url`, failing once it arrives slower than
+    /// the minimum throughput allows.
+    pub async fn body(self, mut resp: Response, url: &Url) -> Result<Bytes> {
+        let Some(rate) = self.min_throughput else {
+            return Ok(resp.bytes().await?);
+        };
+        let started = Instant::now();
+        let expected = resp.content_length();
+        let mut body = BytesMut::with_capacity(expected.unwrap_or(0) as usize);
+        loop {
+            let size = expected.unwrap_or(0).max(body.len() as u64);
+            let deadline =
+                started + self.response + Duration::from_secs_f64(size as f64 / rate as f64);
+            match tokio::time::timeout_at(deadline, resp.chunk()).await {
+                Ok(chunk) => match chunk.with_context(|| format!("reading {}", url))? {
+                    Some(chunk) => body.extend_from_slice(&chunk),
+                    None => return Ok(body.freeze()),
+                },
+                Err(_) => bail!(
+                    "{} arrived slower than {}/s: {} of {} in {}",
+                    url,
+                    units::size(rate),
+                    units::size(body.len() as u64),
+                    expected.map_or_else(|| "an unknown size".to_string(), units::size),
+                    units::duration(started.elapsed().as_secs_f64())
+                ),
+            }
+        }
+    }
+}
+
+/// Parse `--min-throughput`: bytes per second, with an optional `k`, `M` or
+/// `G` suffix for binary multiples (`64k` is 65536).
+pub fn parse_throughput(s: &str) -> Result<u64> {
+    let trimmed = s.trim().trim_end_matches("/s");
+    let (number, multiple) = match trimmed.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&trimmed[..i], 1 << 10),
+        Some((i, 'm' | 'M')) => (&trimmed[..i], 1 << 20),
+        Some((i, 'g' | 'G')) => (&trimmed[..i], 1 << 30),
+        _ => (trimmed, 1),
+    };
+    let value: f64 = number
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("expected bytes per second such as 64k or 1.5M, got '{}'", s))?;
+    let rate = (value * multiple as f64) as u64;
+    if rate == 0 {
+        bail!("the rate must be above zero");
+    }
+    Ok(rate)
+}
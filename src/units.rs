@@ -0,0 +1,106 @@
+//! Sizes, durations and counts in log lines.
+//!
+//! By default they are written for people: binary units (`1.5 GiB`),
+//! `hh:mm:ss` durations and grouped digits, with the decimal and grouping
+//! separators of the locale (`LC_ALL`, `LC_NUMERIC` or `LANG`). With
+//! `--porcelain` they are written the same way everywhere instead (`1610612736
+//! bytes`, `5025.0s`, `12431`), for scripts that parse the output.
+
+use std::sync::OnceLock;
+
+/// Set with `--porcelain`.
+static PORCELAIN: OnceLock<()> = OnceLock::new();
+
+/// Write plain numbers from now on.
+pub fn set_porcelain() {
+    let _ = PORCELAIN.set(());
+}
+
+fn porcelain() -> bool {
+    PORCELAIN.get().is_some()
+}
+
+/// A byte count, e.g. `1.5 GiB`.
+pub fn size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    if porcelain() {
+        return format!("{bytes} bytes");
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{} {}", decimal(value), UNITS[unit])
+    }
+}
+
+/// A duration in seconds, e.g. `01:23:45`, or `00:00:01.5` under a minute
+/// where tenths matter.
+pub fn duration(secs: f64) -> String {
+    if porcelain() {
+        return format!("{secs:.1}s");
+    }
+    let secs = secs.max(0.0);
+    // Tenths that would round up to a full minute are shown as one.
+    if secs < 59.95 {
+        return format!("00:00:{:0>4}", decimal(secs));
+    }
+    let whole = secs.round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        whole / 3600,
+        whole / 60 % 60,
+        whole % 60
+    )
+}
+
+/// A count, e.g. `12,431`.
+pub fn count(n: usize) -> String {
+    let digits = n.to_string();
+    if porcelain() {
+        return digits;
+    }
+    let separator = separators().1;
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(separator);
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `value` with one decimal place and the locale's decimal separator.
+fn decimal(value: f64) -> String {
+    let text = format!("{value:.1}");
+    match separators().0 {
+        '.' => text,
+        separator => text.replace('.', &separator.to_string()),
+    }
+}
+
+/// Decimal and digit grouping separators of the locale.
+fn separators() -> (char, char) {
+    static SEPARATORS: OnceLock<(char, char)> = OnceLock::new();
+    *SEPARATORS.get_or_init(|| {
+        let locale = ["LC_ALL", "LC_NUMERIC", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+        match language {
+            "de" | "es" | "it" | "nl" | "pt" | "da" | "id" | "tr" | "el" => (',', '.'),
+            "fr" | "ru" | "pl" | "cs" | "sk" | "sv" | "fi" | "nb" | "no" | "uk" | "hu" => {
+                (',', '\u{202f}')
+            }
+            _ => ('.', ','),
+        }
+    })
+}
@@ -0,0 +1,94 @@
+//! Manifest features streamrip recognizes but doesn't mirror.
+//!
+//! Each is reported with what it means for the mirror the first time it is
+//! seen, and counted; the counts are repeated at the end of the run, so an
+//! incomplete mirror is noticed before playback.
+
+#![cfg_attr(not(all(feature = "hls", feature = "dash")), allow(dead_code))]
+
+use crate::report::{Event, SharedReporter};
+use std::collections::BTreeMap;
+
+/// A recognized but unsupported tag or element.
+pub(crate) struct Feature {
+    pub name: &'static str,
+    /// What it means for the mirror.
+    pub effect: &'static str,
+}
+
+/// HLS tags.
+pub(crate) const HLS_TAGS: &[Feature] = &[
+    Feature {
+        name: "#EXT-X-DEFINE",
+        effect: "variables aren't substituted, URIs using them are mirrored as written",
+    },
+    Feature {
+        name: "#EXT-X-SKIP",
+        effect: "the segments a playlist delta update skips are missing from the mirror",
+    },
+    Feature {
+        name: "#EXT-X-PRELOAD-HINT",
+        effect: "hinted parts may not be published yet and fail to download",
+    },
+];
+
+/// DASH elements.
+pub(crate) const DASH_ELEMENTS: &[Feature] = &[
+    Feature {
+        name: "SegmentTimeline",
+        effect: "the media segments of timeline templates are not mirrored",
+    },
+    Feature {
+        name: "SegmentList",
+        effect: "listed segments are not mirrored",
+    },
+];
+
+/// Unsupported features seen during a run.
+#[derive(Debug, Default)]
+pub(crate) struct Unsupported {
+    seen: BTreeMap<&'static str, usize>,
+}
+
+impl Unsupported {
+    /// Count `feature`, seen in `url`; returns the warning to report the
+    /// first time.
+    pub fn note(&mut self, feature: &Feature, url: &str) -> Option<String> {
+        let count = self.seen.entry(feature.name).or_default();
+        *count += 1;
+        (*count == 1).then(|| {
+            format!(
+                "{} in {} is not supported: {}",
+                feature.name, url, feature.effect
+            )
+        })
+    }
+
+    /// Warn once more about everything seen, with counts.
+    pub fn summarize(&self, reporter: &SharedReporter) {
+        if self.seen.is_empty() {
+            return;
+        }
+        let counts: Vec<String> = self
+            .seen
+            .iter()
+            .map(|(name, count)| format!("{} ({}x)", name, count))
+            .collect();
+        reporter.report(Event::Warning {
+            message: format!(
+                "the mirror may be incomplete, unsupported manifest features: {}",
+                counts.join(", ")
+            ),
+        });
+    }
+}
+
+/// The unsupported HLS tag `name`, if it is one.
+pub(crate) fn hls_tag(name: &str) -> Option<&'static Feature> {
+    HLS_TAGS.iter().find(|f| f.name == name)
+}
+
+/// The unsupported DASH element `name`, if it is one.
+pub(crate) fn dash_element(name: &str) -> Option<&'static Feature> {
+    DASH_ELEMENTS.iter().find(|f| f.name == name)
+}
@@ -0,0 +1,208 @@
+//! Uploading a live recording to remote storage while it is being made.
+//!
+//! Files are PUT below a base URL (a WebDAV share, or any server or object
+//! store accepting plain PUTs) under their path in the output directory, one
+//! at a time and in the order they were finalized: the segments of a refresh
+//! before the playlists listing them, so the remote copy never refers to a
+//! segment it doesn't have yet. Segments removed locally (`--retention`) are
+//! DELETEd remotely.
+
+#![cfg_attr(not(feature = "hls"), allow(dead_code))]
+
+use crate::debug_http::SendLogged;
+use crate::report::{Event, SharedReporter};
+use crate::state::STATE_FILE;
+use crate::status::STATUS_FILE;
+use anyhow::{Context, Result, anyhow};
+use reqwest::Client;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use url::Url;
+
+/// Attempts per file before its upload counts as failed.
+const ATTEMPTS: u32 = 3;
+
+enum Job {
+    Put(PathBuf),
+    Delete(PathBuf),
+}
+
+/// Queue of files to upload, shared by everything that writes them.
+#[derive(Clone)]
+pub(crate) struct Uploads {
+    queue: mpsc::UnboundedSender<Job>,
+}
+
+impl Uploads {
+    /// Upload the current content of `path`.
+    pub fn put(&self, path: &Path) {
+        let _ = self.queue.send(Job::Put(path.to_path_buf()));
+    }
+
+    /// Remove the remote copy of `path`.
+    pub fn delete(&self, path: &Path) {
+        let _ = self.queue.send(Job::Delete(path.to_path_buf()));
+    }
+
+    /// Upload every file below `dir` (except the state file), media before
+    /// playlists.
+    pub async fn put_tree(&self, dir: &Path) -> Result<()> {
+        let mut files = Vec::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            let mut entries = tokio::fs::read_dir(&dir)
+                .await
+                .with_context(|| format!("reading directory {}", dir.display()))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(path);
+                } else if entry.file_name() != STATE_FILE && entry.file_name() != STATUS_FILE {
+                    files.push(path);
+                }
+            }
+        }
+
+        files.sort_by_key(|path| (path.extension().is_some_and(|e| e == "m3u8"), path.clone()));
+        for file in &files {
+            self.put(file);
+        }
+        Ok(())
+    }
+}
+
+/// Background task working through the upload queue.
+pub(crate) struct Uploader {
+    uploads: Uploads,
+    task: JoinHandle<()>,
+}
+
+impl Uploader {
+    /// Start uploading files below `out_dir` to the same paths below `base`.
+    pub fn start(client: Client, base: Url, out_dir: PathBuf, reporter: SharedReporter) -> Self {
+        let (queue, jobs) = mpsc::unbounded_channel();
+        let task = tokio::spawn(work(client, base, out_dir, reporter, jobs));
+        Self {
+            uploads: Uploads { queue },
+            task,
+        }
+    }
+
+    pub fn uploads(&self) -> Uploads {
+        self.uploads.clone()
+    }
+
+    /// Wait until everything queued is uploaded. Other [`Uploads`] handles
+    /// must be dropped by then.
+    pub async fn finish(self) {
+        drop(self.uploads);
+        let _ = self.task.await;
+    }
+}
+
+async fn work(
+    client: Client,
+    base: Url,
+    out_dir: PathBuf,
+    reporter: SharedReporter,
+    mut jobs: mpsc::UnboundedReceiver<Job>,
+) {
+    let mut uploaded = 0usize;
+    let mut failed = 0usize;
+
+    while let Some(job) = jobs.recv().await {
+        let (path, result) = match job {
+            Job::Put(path) => {
+                let result = put(&client, &base, &out_dir, &path).await;
+                if matches!(result, Ok(true)) {
+                    uploaded += 1;
+                }
+                (path, result.map(|_| ()))
+            }
+            Job::Delete(path) => {
+                let result = delete(&client, &base, &out_dir, &path).await;
+                (path, result)
+            }
+        };
+        if let Err(e) = result {
+            failed += 1;
+            reporter.report(Event::Warning {
+                message: format!("uploading {}: {:#}", path.display(), e),
+            });
+        }
+    }
+
+    reporter.report(Event::Status {
+        tag: "UPLD",
+        message: format!("{} file(s) uploaded, {} failed", uploaded, failed),
+    });
+}
+
+/// PUT `path`, retrying on errors; `false` if the file is gone already.
+async fn put(client: &Client, base: &Url, out_dir: &Path, path: &Path) -> Result<bool> {
+    let body = match tokio::fs::read(path).await {
+        Ok(body) => body,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+        Err(e) => return Err(e).with_context(|| format!("reading {}", path.display())),
+    };
+    let url = remote_url(base, out_dir, path)?;
+
+    let mut attempt = 1;
+    loop {
+        let result = client
+            .put(url.clone())
+            .body(body.clone())
+            .send_logged()
+            .await
+            .and_then(|resp| resp.error_for_status());
+        match result {
+            Ok(_) => return Ok(true),
+            Err(_) if attempt < ATTEMPTS => {
+                tokio::time::sleep(Duration::from_secs(attempt.into())).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e).with_context(|| format!("PUT {}", url)),
+        }
+    }
+}
+
+async fn delete(client: &Client, base: &Url, out_dir: &Path, path: &Path) -> Result<()> {
+    let url = remote_url(base, out_dir, path)?;
+    let resp = client
+        .delete(url.clone())
+        .send_logged()
+        .await
+        .with_context(|| format!("DELETE {}", url))?;
+    if resp.status() != reqwest::StatusCode::NOT_FOUND {
+        resp.error_for_status()
+            .with_context(|| format!("status error for DELETE {}", url))?;
+    }
+    Ok(())
+}
+
+/// `base` followed by the path of `path` below `out_dir`.
+fn remote_url(base: &Url, out_dir: &Path, path: &Path) -> Result<Url> {
+    let relative = path
+        .strip_prefix(out_dir)
+        .with_context(|| format!("{} is outside the output directory", path.display()))?;
+    let mut url = base.clone();
+    url.path_segments_mut()
+        .map_err(|_| anyhow!("{} can't be a base URL", base))?
+        .pop_if_empty()
+        .extend(relative.iter().map(|c| c.to_string_lossy()));
+    Ok(url)
+}
+
+/// Parse `--upload-to`: an http(s) URL, given a trailing slash if it lacks one.
+pub fn parse_base(s: &str) -> Result<Url> {
+    let mut url = Url::parse(s.trim()).map_err(|e| anyhow!("invalid URL '{}': {}", s, e))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        anyhow::bail!("expected an http or https URL, got '{}'", s);
+    }
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    Ok(url)
+}
@@ -0,0 +1,52 @@
+//! `User-Agent` selection: a literal string or a named player preset, since
+//! many origins serve different manifests (or block) depending on the player.
+
+use anyhow::{Result, bail};
+
+/// Named presets accepted by `--user-agent`.
+const PRESETS: &[(&str, &str)] = &[
+    (
+        "safari-ios",
+        "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1",
+    ),
+    (
+        "chrome",
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36",
+    ),
+    (
+        "exoplayer",
+        "ExoPlayerLib/2.19.1 (Linux; Android 14) ExoPlayerLib/2.19.1",
+    ),
+];
+
+/// The user agent sent when none is configured.
+pub fn default_user_agent() -> String {
+    format!("{}/{}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"))
+}
+
+/// Parse a `--user-agent` value: a preset name, or any other string used as is.
+pub fn parse(value: &str) -> Result<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        bail!(
+            "empty user agent (presets: {})",
+            PRESETS
+                .iter()
+                .map(|(name, _)| *name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(PRESETS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(value))
+        .map_or_else(|| value.to_string(), |(_, ua)| ua.to_string()))
+}
+
+/// Build the HTTP client used for all requests of a run.
+pub fn http_client(user_agent: &str) -> reqwest::Client {
+    reqwest::Client::builder()
+        .user_agent(user_agent)
+        .build()
+        .expect("failed to build reqwest client")
+}
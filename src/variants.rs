@@ -0,0 +1,198 @@
+//! Header variants: fetching the same stream several times with different
+//! request headers (user agents, `X-Forwarded-For`, ...) to audit how an
+//! origin manipulates manifests per client.
+//!
+//! Every variant gets its own tree of manifests (`manifest.<name>.m3u8`),
+//! while binary resources are shared and downloaded once. The resources each
+//! variant referenced are listed in `catalog.json`.
+
+use crate::report::Event;
+use crate::{Mirror, StreamFormat};
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, USER_AGENT};
+use std::collections::HashSet;
+use std::path::Path;
+use url::Url;
+
+/// A named set of request headers.
+#[derive(Debug, Clone)]
+pub struct HeaderVariant {
+    pub name: String,
+    pub headers: HeaderMap,
+}
+
+/// What one variant referenced, for `catalog.json`.
+#[derive(Debug, Clone)]
+pub struct VariantRecord {
+    pub name: String,
+    /// Local path of the variant's root manifest, relative to the output directory.
+    pub manifest: String,
+    /// URLs of every manifest and file the variant referenced.
+    pub resources: Vec<String>,
+}
+
+/// Read a header variants file.
+///
+/// Each variant starts with a `[name]` line followed by `Header: value`
+/// lines; a `User-Agent` may name a `--user-agent` preset. Names may contain
+/// letters, digits, `-` and `_`. Lines starting with `#` are comments.
+pub async fn load(path: &Path) -> Result<Vec<HeaderVariant>> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("reading header variants {}", path.display()))?;
+    parse(&text).with_context(|| format!("parsing header variants {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Vec<HeaderVariant>> {
+    let mut variants: Vec<HeaderVariant> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let name = name.trim();
+            if name.is_empty()
+                || !name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!("line {}: invalid variant name '{}'", number + 1, name);
+            }
+            if variants.iter().any(|v| v.name == name) {
+                bail!("line {}: duplicate variant '{}'", number + 1, name);
+            }
+            variants.push(HeaderVariant {
+                name: name.to_string(),
+                headers: HeaderMap::new(),
+            });
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| anyhow!("line {}: expected 'Header: value'", number + 1))?;
+        let variant = variants
+            .last_mut()
+            .ok_or_else(|| anyhow!("line {}: header outside of a [variant]", number + 1))?;
+        let key = HeaderName::from_bytes(key.trim().as_bytes())
+            .with_context(|| format!("line {}: invalid header name", number + 1))?;
+        let value = if key == USER_AGENT {
+            crate::user_agent::parse(value)?
+        } else {
+            value.trim().to_string()
+        };
+        let value = HeaderValue::from_str(&value)
+            .with_context(|| format!("line {}: invalid header value", number + 1))?;
+        variant.headers.append(key, value);
+    }
+
+    if variants.is_empty() {
+        bail!("no [variant] sections");
+    }
+    Ok(variants)
+}
+
+/// `manifest.m3u8` → `manifest.<variant>.m3u8`
+pub fn variant_path(path: &Path, variant: &str) -> std::path::PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let name = match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{stem}.{variant}.{ext}"),
+        None => format!("{name}.{variant}"),
+    };
+    path.with_file_name(name)
+}
+
+impl Mirror {
+    /// Mirror the stream at `url` once per header variant.
+    ///
+    /// Manifests are fetched again for every variant and written to
+    /// variant-specific paths; files already downloaded for an earlier
+    /// variant are only recorded, not fetched again.
+    pub(crate) async fn mirror_header_variants(
+        &mut self,
+        url: Url,
+        format: StreamFormat,
+        variants: &[HeaderVariant],
+        user_agent: &str,
+    ) -> Result<()> {
+        for variant in variants {
+            self.client = reqwest::Client::builder()
+                .user_agent(user_agent)
+                .default_headers(variant.headers.clone())
+                .build()
+                .context("building HTTP client")?;
+
+            // Manifests are per variant; forget the previous variant's.
+            for manifest in std::mem::take(&mut self.variant_manifests) {
+                self.visited.remove(&manifest);
+                self.url_to_path.remove(&manifest);
+            }
+
+            self.reporter.report(Event::Status {
+                tag: "VARI",
+                message: format!("fetching {} as {}", url, variant.name),
+            });
+
+            self.variant = Some(variant.name.clone());
+            self.variant_resources = Some(Vec::new());
+            let result = self.mirror_root(url.clone(), format).await;
+            let mut resources = self.variant_resources.take().unwrap_or_default();
+            let root = self.path_for_url(&url, true);
+            self.variant = None;
+            result?;
+
+            let mut seen = HashSet::new();
+            resources.retain(|r| seen.insert(r.clone()));
+            self.catalog.variants.push(VariantRecord {
+                name: variant.name.clone(),
+                manifest: root
+                    .strip_prefix(&self.out_dir)
+                    .unwrap_or(&root)
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                resources,
+            });
+        }
+
+        let sets: Vec<HashSet<&String>> = self
+            .catalog
+            .variants
+            .iter()
+            .map(|v| v.resources.iter().collect())
+            .collect();
+        for (index, record) in self.catalog.variants.iter().enumerate() {
+            let exclusive = record
+                .resources
+                .iter()
+                .filter(|r| {
+                    sets.iter()
+                        .enumerate()
+                        .all(|(other, set)| other == index || !set.contains(r))
+                })
+                .count();
+            self.reporter.report(Event::Status {
+                tag: "VARI",
+                message: format!(
+                    "{}: {} resources, {} not referenced by any other variant",
+                    record.name,
+                    record.resources.len(),
+                    exclusive
+                ),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Note a resource referenced while a header variant is being mirrored.
+    pub(crate) fn note_variant_resource(&mut self, url: &Url) {
+        if let Some(resources) = &mut self.variant_resources {
+            resources.push(url.to_string());
+        }
+    }
+}
@@ -0,0 +1,211 @@
+//! Round-trip verification of rewritten manifests against their `.orig` copies.
+
+use crate::playlist::{self, Line};
+use crate::provenance;
+use crate::report::{Event, SharedReporter};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Placeholder substituted for every URI before comparing.
+const URI_MASK: &str = "<uri>";
+
+/// How much a difference matters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// Harmless to players: whitespace, comments, an appended
+    /// `#EXT-X-ENDLIST`.
+    Warning,
+    Error,
+}
+
+/// One line that differs.
+struct Finding {
+    severity: Severity,
+    message: String,
+}
+
+/// Compare every `*.orig` manifest below `dir` with its rewritten counterpart,
+/// ignoring URIs, and report each line that differs otherwise: as a failure,
+/// or as a warning if players won't notice. With `fail_fast`, stop at the
+/// first failure.
+///
+/// Returns the number of manifests with failures. Live recordings,
+/// `--treat-as vod` runs and rendition filters intentionally change
+/// manifests and will show up here.
+pub async fn verify_rewrites(
+    dir: &Path,
+    fail_fast: bool,
+    reporter: &SharedReporter,
+) -> Result<usize> {
+    let mut checked = 0;
+    let mut mismatched = 0;
+    let mut warnings = 0;
+
+    for orig_path in find_orig_files(dir).await? {
+        let rewritten_path = orig_path.with_extension("");
+        let orig = tokio::fs::read_to_string(&orig_path)
+            .await
+            .with_context(|| format!("reading {}", orig_path.display()))?;
+        let rewritten = match tokio::fs::read_to_string(&rewritten_path).await {
+            // Provenance stamps are ours, not the origin's.
+            Ok(text) => text
+                .split_inclusive('\n')
+                .filter(|line| !provenance::is_stamp(line))
+                .collect::<String>(),
+            Err(e) => {
+                reporter.report(Event::Failure {
+                    subject: rewritten_path.display().to_string(),
+                    message: format!("missing rewritten manifest: {}", e),
+                });
+                mismatched += 1;
+                if fail_fast {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        checked += 1;
+        let is_hls = rewritten_path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("m3u8"));
+
+        let findings = if is_hls {
+            diff_lines(&mask_hls(&orig), &mask_hls(&rewritten), true)
+        } else {
+            diff_lines(
+                &orig.lines().map(str::to_string).collect::<Vec<_>>(),
+                &rewritten.lines().map(str::to_string).collect::<Vec<_>>(),
+                false,
+            )
+        };
+
+        let mut failed = false;
+        for finding in findings {
+            match finding.severity {
+                Severity::Warning => {
+                    warnings += 1;
+                    reporter.report(Event::Warning {
+                        message: format!("{}: {}", rewritten_path.display(), finding.message),
+                    });
+                }
+                Severity::Error => {
+                    failed = true;
+                    reporter.report(Event::Failure {
+                        subject: rewritten_path.display().to_string(),
+                        message: finding.message,
+                    });
+                    if fail_fast {
+                        break;
+                    }
+                }
+            }
+        }
+        if failed {
+            mismatched += 1;
+            if fail_fast {
+                break;
+            }
+        }
+    }
+
+    reporter.report(Event::Status {
+        tag: "VRFY",
+        message: format!(
+            "{} manifest(s) checked, {} differ, {} warning(s){}",
+            checked,
+            mismatched,
+            warnings,
+            if fail_fast && mismatched > 0 {
+                "; stopped at the first failure"
+            } else {
+                ""
+            }
+        ),
+    });
+
+    Ok(mismatched)
+}
+
+async fn find_orig_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+
+    while let Some(dir) = pending.pop() {
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("reading directory {}", dir.display()))?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if entry.file_type().await?.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|e| e == "orig") {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Replace every URI in an HLS manifest with a placeholder: URI lines as a
+/// whole, and the values of URI attributes in tags (including interstitial
+/// `X-ASSET-URI` and `X-ASSET-LIST`).
+fn mask_hls(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| match Line::parse(line) {
+            Line::Blank(_) => String::new(),
+            Line::Tag(tag) if tag.is_comment() => line.to_string(),
+            Line::Tag(_) => mask_uri_attributes(line),
+            Line::Uri { .. } => URI_MASK.to_string(),
+        })
+        .collect()
+}
+
+fn mask_uri_attributes(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut copied = 0;
+    for (name, (start, end)) in playlist::attribute_spans(line) {
+        if playlist::is_uri_attribute(name) {
+            out.push_str(&line[copied..start]);
+            out.push_str(URI_MASK);
+            copied = end;
+        }
+    }
+    out.push_str(&line[copied..]);
+    out
+}
+
+/// Line-by-line comparison; manifests are rewritten in place, so line
+/// numbers correspond one to one.
+fn diff_lines(orig: &[String], rewritten: &[String], is_hls: bool) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    for i in 0..orig.len().max(rewritten.len()) {
+        let a = orig.get(i).map(String::as_str);
+        let b = rewritten.get(i).map(String::as_str);
+        if a == b {
+            continue;
+        }
+        let severity = match (a, b) {
+            (Some(a), Some(b)) if a.trim() == b.trim() => Severity::Warning,
+            (Some(a), Some(b)) if is_hls && is_comment(a) && is_comment(b) => Severity::Warning,
+            (None, Some(b)) if is_hls && b.trim() == "#EXT-X-ENDLIST" => Severity::Warning,
+            _ => Severity::Error,
+        };
+        findings.push(Finding {
+            severity,
+            message: format!(
+                "line {}: expected {:?}, found {:?}",
+                i + 1,
+                a.unwrap_or("<end of file>"),
+                b.unwrap_or("<end of file>")
+            ),
+        });
+    }
+    findings
+}
+
+fn is_comment(line: &str) -> bool {
+    matches!(Line::parse(line), Line::Tag(tag) if tag.is_comment())
+}
@@ -0,0 +1,607 @@
+//! Watch-folder mode: poll a job directory or a URL list and mirror each new entry.
+
+use crate::debug_http::SendLogged;
+use crate::jobs::JobBoard;
+use crate::quota::{self, Scheduler};
+use crate::report::{Event, SharedReporter};
+use crate::{MirrorOptions, mirror_stream, schedule, timeouts, user_agent};
+use anyhow::{Context, Result, anyhow};
+use std::collections::{HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+
+/// File in the output root recording start URLs taken from a URL list that
+/// were already mirrored, so restarts don't redo them.
+const DONE_LIST: &str = ".streamrip-watch-done";
+
+/// Directory in the output root holding a job file for every job queued or
+/// running, with its progress, so a restarted watcher picks them up again.
+const QUEUE_DIR: &str = ".streamrip-watch-queue";
+
+/// Where new jobs come from.
+#[derive(Debug, Clone)]
+pub enum Source {
+    /// A directory polled for `*.job` files.
+    Directory(PathBuf),
+    /// A URL serving a plain-text list of start URLs.
+    UrlList(String),
+}
+
+/// A single mirroring job.
+#[derive(Debug)]
+struct Job {
+    start_url: String,
+    /// Output directory name, relative to the output root.
+    name: String,
+    options: MirrorOptions,
+    /// Its part of the watcher's `--max-rate` against the other jobs
+    /// running (`bandwidth_share`).
+    share: u32,
+    /// The `key = value` lines the job was given as.
+    definition: String,
+    /// The `*.job` file it came from, renamed once it is finished; `None`
+    /// for an entry of a URL list.
+    job_file: Option<PathBuf>,
+    /// When it started running, if it did.
+    started: Option<SystemTime>,
+}
+
+impl Job {
+    /// Continue the job where an earlier watcher left off: the recording
+    /// window counts from when it started, and a live recording continues
+    /// its archive playlists. Files already downloaded are skipped anyway.
+    fn resume(&mut self, started: SystemTime) {
+        let began = self.options.start_at.map_or(started, |at| at.max(started));
+        if let Some(record_for) = self.options.record_for {
+            let elapsed = SystemTime::now().duration_since(began).unwrap_or_default();
+            self.options.record_for = Some(record_for.saturating_sub(elapsed));
+        }
+        self.options.resume_recording = true;
+        self.started = Some(started);
+    }
+}
+
+/// Poll `source` every `interval` and mirror each new job below `output_root`,
+/// running up to `max_jobs` of them at a time, which share `max_rate`.
+///
+/// Runs until the process is terminated. A failing job is reported and does
+/// not stop the watcher. With `status_addr`, the status of every job is
+/// served as JSON there (see [`crate::jobs`]).
+pub async fn watch(
+    source: Source,
+    output_root: PathBuf,
+    interval: Duration,
+    max_jobs: usize,
+    max_rate: Option<u64>,
+    status_addr: Option<std::net::SocketAddr>,
+    reporter: SharedReporter,
+) -> Result<()> {
+    tokio::fs::create_dir_all(&output_root)
+        .await
+        .with_context(|| format!("creating output root {}", output_root.display()))?;
+
+    let board = status_addr.map(|_| JobBoard::default());
+    #[cfg(feature = "serve")]
+    if let (Some(addr), Some(board)) = (status_addr, &board) {
+        crate::jobs::serve(addr, board.clone(), reporter.clone()).await?;
+    }
+    let scheduler = max_rate.map(|rate| Scheduler::new(Some(rate)));
+
+    let client = user_agent::http_client(&user_agent::default_user_agent());
+    let mut done = load_done_list(&output_root).await?;
+    let mut pending: VecDeque<Job> = recover(&output_root, board.as_ref(), &reporter)
+        .await?
+        .into();
+    // Names of the jobs pending or running.
+    let mut active: HashSet<String> = pending.iter().map(|job| job.name.clone()).collect();
+    let mut running = JoinSet::new();
+
+    let message = match &source {
+        Source::Directory(dir) => format!("polling {} for *.job files", dir.display()),
+        Source::UrlList(url) => format!("polling {}", url),
+    };
+    reporter.report(Event::Status {
+        tag: "WTCH",
+        message,
+    });
+
+    let mut next_poll = Instant::now();
+    loop {
+        while running.len() < max_jobs.max(1)
+            && let Some(mut job) = pending.pop_front()
+        {
+            if let Some(scheduler) = &scheduler {
+                job.options.slot = Some(scheduler.slot(job.share));
+            }
+            let output_root = output_root.clone();
+            let board = board.clone();
+            let reporter = reporter.clone();
+            running.spawn(async move {
+                let outcome = run_job(&mut job, &output_root, board.as_ref(), &reporter).await;
+                (job, outcome)
+            });
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_poll) => {
+                let polled = match &source {
+                    Source::Directory(dir) => {
+                        poll_directory(dir, &active, &reporter)
+                            .await
+                            .with_context(|| format!("polling {}", dir.display()))
+                    }
+                    Source::UrlList(url) => {
+                        poll_url_list(&client, url, &done, &active)
+                            .await
+                            .with_context(|| format!("polling {}", url))
+                    }
+                };
+                match polled {
+                    Ok(jobs) => {
+                        for job in jobs {
+                            persist(&output_root, &job).await?;
+                            if let Some(board) = &board {
+                                board.queue(&job.name, &job.start_url);
+                            }
+                            active.insert(job.name.clone());
+                            pending.push_back(job);
+                        }
+                    }
+                    Err(e) => reporter.report(Event::Warning {
+                        message: format!("{:#}", e),
+                    }),
+                }
+                next_poll = Instant::now() + interval;
+            }
+            Some(joined) = running.join_next() => {
+                let (job, outcome) = joined?;
+                complete(&job, outcome, &output_root, &mut done, &reporter).await?;
+                active.remove(&job.name);
+            }
+        }
+    }
+}
+
+/// The jobs an earlier watcher left queued or running, the latter to be
+/// resumed, except those whose job file is gone.
+async fn recover(
+    output_root: &Path,
+    board: Option<&JobBoard>,
+    reporter: &SharedReporter,
+) -> Result<Vec<Job>> {
+    let queue = output_root.join(QUEUE_DIR);
+    let mut entries = match tokio::fs::read_dir(&queue).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("listing {}", queue.display())),
+    };
+    let mut paths = Vec::new();
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "job") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut jobs = Vec::new();
+    for path in paths {
+        let mut job = match read_job_file(&path, true).await {
+            Ok(job) => job,
+            Err(e) => {
+                reporter.report(Event::Warning {
+                    message: format!("dropping queued job {}: {:#}", path.display(), e),
+                });
+                tokio::fs::remove_file(&path).await?;
+                continue;
+            }
+        };
+        if let Some(job_file) = &job.job_file
+            && !tokio::fs::try_exists(job_file).await?
+        {
+            reporter.report(Event::Detail {
+                message: format!("{} was removed; not resuming it", job_file.display()),
+            });
+            forget(output_root, &job).await?;
+            continue;
+        }
+        if let Some(started) = job.started {
+            job.resume(started);
+        }
+        if let Some(board) = board {
+            board.queue(&job.name, &job.start_url);
+        }
+        jobs.push(job);
+    }
+
+    if !jobs.is_empty() {
+        let running = jobs.iter().filter(|job| job.started.is_some()).count();
+        reporter.report(Event::Status {
+            tag: "WTCH",
+            message: format!(
+                "resuming {} job(s) of an earlier run, {} of them started",
+                jobs.len(),
+                running
+            ),
+        });
+    }
+    Ok(jobs)
+}
+
+/// The new `*.job` files in `dir`. Invalid ones are renamed to
+/// `*.job.failed` right away; those of a name already queued or running
+/// (`active`) wait for a later poll.
+async fn poll_directory(
+    dir: &Path,
+    active: &HashSet<String>,
+    reporter: &SharedReporter,
+) -> Result<Vec<Job>> {
+    let mut pending = Vec::new();
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "job") && entry.file_type().await?.is_file() {
+            pending.push(path);
+        }
+    }
+    pending.sort();
+
+    let mut jobs: Vec<Job> = Vec::new();
+    for path in pending {
+        let job = match read_job_file(&path, false).await {
+            Ok(job) => job,
+            Err(e) => {
+                reporter.report(Event::Failure {
+                    subject: path.display().to_string(),
+                    message: format!("{:#}", e),
+                });
+                finish_job_file(&path, "failed").await?;
+                continue;
+            }
+        };
+        if active.contains(&job.name) || jobs.iter().any(|other| other.name == job.name) {
+            continue;
+        }
+        jobs.push(job);
+    }
+
+    Ok(jobs)
+}
+
+/// Fetch the URL list and return every entry not mirrored before, nor
+/// queued or running (`active`).
+async fn poll_url_list(
+    client: &reqwest::Client,
+    list_url: &str,
+    done: &HashSet<String>,
+    active: &HashSet<String>,
+) -> Result<Vec<Job>> {
+    let text = client
+        .get(list_url)
+        .send_logged()
+        .await
+        .with_context(|| format!("GET {}", list_url))?
+        .error_for_status()
+        .with_context(|| format!("status error for {}", list_url))?
+        .text()
+        .await?;
+
+    let mut jobs: Vec<Job> = Vec::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let start_url = parts.next().unwrap_or_default().to_string();
+        if done.contains(&start_url) {
+            continue;
+        }
+
+        let name = parts
+            .next()
+            .map(sanitize_name)
+            .unwrap_or_else(|| name_from_url(&start_url));
+        if active.contains(&name) || jobs.iter().any(|other| other.name == name) {
+            continue;
+        }
+        jobs.push(Job {
+            definition: format!("start_url = {}\noutput_dir = {}\n", start_url, name),
+            start_url,
+            name,
+            options: MirrorOptions::default(),
+            share: 1,
+            job_file: None,
+            started: None,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Run `job`, noting in its queue entry when it started.
+async fn run_job(
+    job: &mut Job,
+    output_root: &Path,
+    board: Option<&JobBoard>,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    if job.started.is_none() {
+        job.started = Some(SystemTime::now());
+        persist(output_root, job).await?;
+    }
+    let out_dir = output_root.join(&job.name);
+    let reporter = match board {
+        Some(board) => board.start(&job.name, &job.start_url, reporter.clone()),
+        None => reporter.clone(),
+    };
+    reporter.report(Event::Status {
+        tag: "JOB",
+        message: format!("{} -> {}", job.start_url, out_dir.display()),
+    });
+    let result = mirror_stream(&job.start_url, out_dir, &job.options, &reporter).await;
+    if let Some(board) = board {
+        board.finish(&job.name, &result);
+    }
+    result
+}
+
+/// Book a job as finished with `outcome` and take it off the queue: its
+/// job file is renamed to `*.job.done` or `*.job.failed`, and an entry of a
+/// URL list is added to the done list, or retried on the next poll.
+async fn complete(
+    job: &Job,
+    outcome: Result<()>,
+    output_root: &Path,
+    done: &mut HashSet<String>,
+    reporter: &SharedReporter,
+) -> Result<()> {
+    let subject = match &job.job_file {
+        Some(path) => path.display().to_string(),
+        None => job.start_url.clone(),
+    };
+    if let Err(e) = &outcome {
+        reporter.report(Event::Failure {
+            subject,
+            message: format!("{:#}", e),
+        });
+    }
+    match (&job.job_file, &outcome) {
+        (Some(path), Ok(())) => finish_job_file(path, "done").await?,
+        (Some(path), Err(_)) => finish_job_file(path, "failed").await?,
+        (None, Ok(())) => {
+            record_done(output_root, &job.start_url).await?;
+            done.insert(job.start_url.clone());
+        }
+        (None, Err(_)) => {}
+    }
+    forget(output_root, job).await
+}
+
+async fn finish_job_file(path: &Path, suffix: &str) -> Result<()> {
+    let mut finished = path.to_path_buf().into_os_string();
+    finished.push(format!(".{suffix}"));
+    tokio::fs::rename(path, &finished)
+        .await
+        .with_context(|| format!("renaming finished job {}", path.display()))
+}
+
+/// Write the queue entry of `job`: its definition, where it came from and
+/// when it started.
+async fn persist(output_root: &Path, job: &Job) -> Result<()> {
+    let mut text = job.definition.clone();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    if let Some(path) = &job.job_file {
+        text.push_str(&format!("job_file = {}\n", path.display()));
+    }
+    if let Some(started) = job.started {
+        text.push_str(&format!(
+            "started = {}\n",
+            schedule::format_timestamp(started)
+        ));
+    }
+    let queue = output_root.join(QUEUE_DIR);
+    tokio::fs::create_dir_all(&queue)
+        .await
+        .with_context(|| format!("creating {}", queue.display()))?;
+    // Replaced in one step, so a crash leaves the old entry or the new one.
+    let path = queue.join(format!("{}.job", job.name));
+    let partial = queue.join(format!("{}.job.part", job.name));
+    tokio::fs::write(&partial, text)
+        .await
+        .with_context(|| format!("writing {}", partial.display()))?;
+    tokio::fs::rename(&partial, &path)
+        .await
+        .with_context(|| format!("writing {}", path.display()))
+}
+
+async fn forget(output_root: &Path, job: &Job) -> Result<()> {
+    let path = output_root
+        .join(QUEUE_DIR)
+        .join(format!("{}.job", job.name));
+    match tokio::fs::remove_file(&path).await {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(e).with_context(|| format!("removing {}", path.display()))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Parse a job file made of `key = value` lines.
+///
+/// Recognized keys are `start_url` (required), `output_dir` (relative to
+/// the output root; defaults to the job file's stem), `format`, `skip_failed`,
+/// `segment_store`, `ignore_query_params` (comma-separated), `user_agent`, the
+/// recording options `live`, `record_for` and `start_at`, the limits
+/// `concurrency`, `max_rate`, `disk_quota` and `bandwidth_share`, and the rendition
+/// filters `max_height`, `max_bandwidth`, and `rep_id`, `group_id`,
+/// `adaptation_set` and `exclude_rendition` (comma-separated).
+/// Lines starting with `#` are comments. Entries of the queue (`queued`)
+/// have `job_file` and `started` as well.
+async fn read_job_file(path: &Path, queued: bool) -> Result<Job> {
+    let text = tokio::fs::read_to_string(path)
+        .await
+        .with_context(|| format!("reading job file {}", path.display()))?;
+
+    let mut start_url = None;
+    let mut name = None;
+    let mut options = MirrorOptions::default();
+    let mut definition = String::new();
+    // Absolute, for a watcher restarted elsewhere.
+    let mut job_file = if queued {
+        None
+    } else {
+        Some(std::path::absolute(path)?)
+    };
+    let mut started = None;
+    let mut share = 1;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            definition.push_str(line);
+            definition.push('\n');
+            continue;
+        }
+        let (key, value) = trimmed
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected `key = value`, got '{}'", trimmed))?;
+        match key.trim() {
+            "job_file" if queued => {
+                job_file = Some(PathBuf::from(value.trim()));
+                continue;
+            }
+            "started" if queued => {
+                started = Some(schedule::parse_timestamp(value)?);
+                continue;
+            }
+            "start_url" => start_url = Some(value.trim().to_string()),
+            "output_dir" => name = Some(sanitize_name(value.trim())),
+            "format" => {
+                options.format = clap::ValueEnum::from_str(value.trim(), true)
+                    .map_err(|e| anyhow!("invalid format: {}", e))?
+            }
+            "live" => options.live = parse_bool(value.trim())?,
+            "skip_failed" => options.skip_failed = parse_bool(value.trim())?,
+            "segment_store" => options.segment_store = Some(PathBuf::from(value.trim())),
+            "ignore_query_params" => options
+                .ignore_query_params
+                .extend(value.split(',').map(|name| name.trim().to_string())),
+            "user_agent" => options.user_agent = Some(user_agent::parse(value)?),
+            "record_for" => options.record_for = Some(schedule::parse_duration(value)?),
+            "start_at" => options.start_at = Some(schedule::parse_timestamp(value)?),
+            "concurrency" => options.concurrency = parse_number(value.trim())?,
+            "max_rate" => options.max_rate = Some(timeouts::parse_throughput(value)?),
+            "disk_quota" => options.disk_quota = Some(quota::parse_size(value)?),
+            "bandwidth_share" => share = parse_number(value.trim())?,
+            "max_height" => options.filter.max_height = Some(parse_number(value.trim())?),
+            "max_bandwidth" => options.filter.max_bandwidth = Some(parse_number(value.trim())?),
+            "rep_id" => options
+                .filter
+                .rep_ids
+                .extend(value.split(',').map(|id| id.trim().to_string())),
+            "group_id" => options
+                .filter
+                .group_ids
+                .extend(value.split(',').map(|id| id.trim().to_string())),
+            "adaptation_set" => options
+                .filter
+                .adaptation_sets
+                .extend(value.split(',').map(|id| id.trim().to_string())),
+            "exclude_rendition" => options
+                .filter
+                .exclude_renditions
+                .extend(value.split(',').map(|name| name.trim().to_string())),
+            other => return Err(anyhow!("unknown job option '{}'", other)),
+        }
+        definition.push_str(line);
+        definition.push('\n');
+    }
+
+    let start_url = start_url.ok_or_else(|| anyhow!("job file is missing start_url"))?;
+    let name = match name {
+        Some(name) => name,
+        None => sanitize_name(&path.file_stem().unwrap_or_default().to_string_lossy()),
+    };
+
+    Ok(Job {
+        start_url,
+        name,
+        options,
+        share,
+        definition,
+        job_file,
+        started,
+    })
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" | "yes" | "1" => Ok(true),
+        "false" | "no" | "0" => Ok(false),
+        _ => Err(anyhow!("expected a boolean, got '{}'", value)),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("expected a number, got '{}'", value))
+}
+
+/// Derive an output directory name from a start URL's host and path.
+fn name_from_url(start_url: &str) -> String {
+    let name = match url::Url::parse(start_url) {
+        Ok(url) => format!(
+            "{}{}",
+            url.host_str().unwrap_or_default(),
+            url.path().rsplit_once('.').map_or(url.path(), |(p, _)| p)
+        ),
+        Err(_) => start_url.to_string(),
+    };
+    sanitize_name(&name)
+}
+
+/// Reduce a name to a single safe path component.
+fn sanitize_name(name: &str) -> String {
+    let mut safe: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    safe = safe.trim_matches(|c| c == '.' || c == '_').to_string();
+    safe.truncate(100);
+    if safe.is_empty() {
+        safe.push_str("job");
+    }
+    safe
+}
+
+async fn load_done_list(output_root: &Path) -> Result<HashSet<String>> {
+    match tokio::fs::read_to_string(output_root.join(DONE_LIST)).await {
+        Ok(text) => Ok(text.lines().map(|l| l.trim().to_string()).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashSet::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn record_done(output_root: &Path, start_url: &str) -> Result<()> {
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(output_root.join(DONE_LIST))
+        .await?;
+    file.write_all(format!("{start_url}\n").as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamrip_core::playlist::{attribute, attribute_spans};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+    for (name, (start, end)) in attribute_spans(line) {
+        // Spans are what rewriting splices URIs into.
+        assert!(start <= end);
+        assert!(line.get(start..end).is_some());
+        let _ = attribute(line, name);
+    }
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamrip_core::mpd::parse_duration;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Some(secs) = parse_duration(text) {
+        assert!(secs.is_finite() && secs >= 0.0);
+    }
+});
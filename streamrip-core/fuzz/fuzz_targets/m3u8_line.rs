@@ -0,0 +1,35 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamrip_core::playlist::{HlsPlaylist, Layout, Line, MAX_DURATION};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    for line in text.lines() {
+        match Line::parse(line) {
+            Line::Tag(tag) => {
+                let _ = tag.value();
+                let _ = tag.is_comment();
+                if let Some(secs) = tag.duration() {
+                    assert!((0.0..=MAX_DURATION).contains(&secs));
+                }
+            }
+            Line::Uri { line, uri } => {
+                assert_eq!(Line::with_uri(line, uri), line);
+            }
+            Line::Blank(_) => {}
+        }
+    }
+    if let Some(playlist) = HlsPlaylist::parse(text) {
+        let lines: Vec<String> = text
+            .strip_prefix('\u{feff}')
+            .unwrap_or(text)
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(lines.len(), playlist.lines().len());
+        let _ = Layout::of(text).render(&lines);
+    }
+});
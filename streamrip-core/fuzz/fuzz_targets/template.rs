@@ -0,0 +1,18 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use streamrip_core::template::{self, MAX_WIDTH, Piece};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(tmpl) = std::str::from_utf8(data) else {
+        return;
+    };
+    for piece in template::parse(tmpl) {
+        if let Piece::Identifier { width, written, .. } = piece {
+            assert!(width.is_none_or(|w| w <= MAX_WIDTH));
+            assert!(written.starts_with('$') && written.ends_with('$'));
+        }
+    }
+    let _ = template::expand(tmpl, "video", u64::MAX, Some(u64::MAX));
+    let _ = template::has_sub_number(tmpl);
+});
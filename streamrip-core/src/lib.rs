@@ -0,0 +1,27 @@
+//! The manifest parsing and rewriting of streamrip, free of I/O.
+//!
+//! Nothing here touches files or the network, or needs an async runtime, so
+//! the crate builds for `wasm32-unknown-unknown` as well: a manifest
+//! inspector in the browser reads and rewrites manifests with the very code
+//! that mirrors them. `streamrip` adds the fetching, downloading and writing
+//! on top.
+//!
+//! The parsers take whatever an origin serves. Malformed lines, attribute
+//! lists, durations and templates come out as `None` or as text kept as
+//! written, never as a panic; the cargo-fuzz targets in `fuzz/` check that,
+//! and the unit tests keep the inputs they tripped over.
+
+#![forbid(unsafe_code)]
+
+#[cfg(feature = "dash")]
+pub mod mpd;
+pub mod paths;
+pub mod playlist;
+#[cfg(feature = "hls")]
+pub mod rewrite;
+#[cfg(feature = "dash")]
+pub mod template;
+
+pub use paths::{OriginPaths, PathMapper};
+#[cfg(feature = "hls")]
+pub use rewrite::rewrite_hls;
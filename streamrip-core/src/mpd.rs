@@ -0,0 +1,378 @@
+//! Typed model of DASH MPDs.
+//!
+//! The Period / AdaptationSet / Representation hierarchy of a parsed MPD,
+//! with the attributes mirroring and rendition filtering rely on resolved
+//! once: BaseURLs, Period durations, inherited heights and segment
+//! templates. Each level keeps its XML node for everything else.
+
+use crate::template::SegmentTemplate;
+use anyhow::{Context, Result};
+use roxmltree::{Document, Node};
+use url::Url;
+
+/// The Periods of an MPD.
+#[derive(Debug, Clone)]
+pub struct MpdModel<'a, 'input> {
+    pub periods: Vec<Period<'a, 'input>>,
+    /// Duration attributes (`mediaPresentationDuration`, Period `start` and
+    /// `duration`) that are not ISO 8601 durations, with their values. They
+    /// count as missing.
+    pub unparsed_durations: Vec<(&'static str, &'a str)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Period<'a, 'input> {
+    pub base_url: Option<&'a str>,
+    /// Further `BaseURL`s: the same content at other locations.
+    pub other_base_urls: Vec<&'a str>,
+    /// Duration in seconds, from `@duration` or the surrounding Periods.
+    pub duration_secs: Option<f64>,
+    pub adaptation_sets: Vec<AdaptationSet<'a, 'input>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdaptationSet<'a, 'input> {
+    pub node: Node<'a, 'input>,
+    pub id: Option<&'a str>,
+    /// `@contentType`, or the type part of the `@mimeType` of the set or
+    /// its first Representation (`video`, `audio`, `text`, ...).
+    pub content_type: Option<&'a str>,
+    pub base_url: Option<&'a str>,
+    pub other_base_urls: Vec<&'a str>,
+    pub representations: Vec<Representation<'a, 'input>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Representation<'a, 'input> {
+    pub node: Node<'a, 'input>,
+    pub id: Option<&'a str>,
+    pub base_url: Option<&'a str>,
+    pub other_base_urls: Vec<&'a str>,
+    pub bandwidth: Option<u64>,
+    /// Height of the Representation, or of its AdaptationSet.
+    pub height: Option<u32>,
+    /// The SegmentTemplate merged from the Representation, AdaptationSet
+    /// and Period levels.
+    pub template: Option<SegmentTemplate<'a, 'input>>,
+}
+
+impl<'a, 'input> MpdModel<'a, 'input> {
+    /// Model the MPD of `doc`; `None` if its root element is not `MPD`.
+    pub fn parse(doc: &'a Document<'input>) -> Option<Self> {
+        let root = doc.root_element();
+        if root.tag_name().name() != "MPD" {
+            return None;
+        }
+
+        let mut unparsed_durations = Vec::new();
+        let duration_secs =
+            duration_attribute(root, "mediaPresentationDuration", &mut unparsed_durations);
+        let period_nodes = children(root, "Period");
+        let durations = period_durations(&period_nodes, duration_secs, &mut unparsed_durations);
+
+        let periods = period_nodes
+            .into_iter()
+            .zip(durations)
+            .map(|(period, duration_secs)| Period {
+                base_url: base_url(period),
+                other_base_urls: other_base_urls(period),
+                duration_secs,
+                adaptation_sets: children(period, "AdaptationSet")
+                    .into_iter()
+                    .map(|aset| AdaptationSet {
+                        node: aset,
+                        id: aset.attribute("id"),
+                        content_type: content_type(aset),
+                        base_url: base_url(aset),
+                        other_base_urls: other_base_urls(aset),
+                        representations: children(aset, "Representation")
+                            .into_iter()
+                            .map(|rep| Representation {
+                                node: rep,
+                                id: rep.attribute("id"),
+                                base_url: base_url(rep),
+                                other_base_urls: other_base_urls(rep),
+                                bandwidth: rep.attribute("bandwidth").and_then(|b| b.parse().ok()),
+                                height: rep
+                                    .attribute("height")
+                                    .or(aset.attribute("height"))
+                                    .and_then(|h| h.parse().ok()),
+                                template: SegmentTemplate::inherited(&[rep, aset, period]),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        Some(Self {
+            periods,
+            unparsed_durations,
+        })
+    }
+}
+
+fn children<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Vec<Node<'a, 'input>> {
+    node.children()
+        .filter(|n| n.is_element() && n.tag_name().name() == name)
+        .collect()
+}
+
+fn content_type<'a>(aset: Node<'a, '_>) -> Option<&'a str> {
+    aset.attribute("contentType").or_else(|| {
+        aset.attribute("mimeType")
+            .or_else(|| {
+                children(aset, "Representation")
+                    .first()
+                    .and_then(|rep| rep.attribute("mimeType"))
+            })
+            .and_then(|mime| mime.split('/').next())
+    })
+}
+
+/// What a Representation's BaseURL names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BaseUrlKind {
+    /// A directory, given with a trailing slash.
+    Directory,
+    /// A directory written without its trailing slash (e.g. `video/1.5mbps`
+    /// or `audio_eng=128000`), next to which the template's segments live.
+    UnterminatedDirectory,
+    /// The media itself, e.g. a single-file `.mp4` or a `.webvtt` subtitle.
+    File,
+}
+
+/// Classify a Representation BaseURL. Only a trailing slash makes it a
+/// directory. Otherwise it names the media file, unless the Representation
+/// has a SegmentTemplate: then its segments are the media, and the BaseURL
+/// is a directory written without the slash, whatever its last component
+/// looks like.
+pub fn base_url_kind(base_url: &str, has_template: bool) -> BaseUrlKind {
+    let path = base_url.split(['?', '#']).next().unwrap_or_default();
+    if path.ends_with('/') {
+        BaseUrlKind::Directory
+    } else if has_template {
+        BaseUrlKind::UnterminatedDirectory
+    } else {
+        BaseUrlKind::File
+    }
+}
+
+/// The base URL of a `level` (`Period`, `AdaptationSet`, ...) with the
+/// `BaseURL` `base_url`, below `parent`; `parent` itself without one.
+pub fn join_base_url(parent: &Url, base_url: Option<&str>, level: &str) -> Result<Url> {
+    match base_url {
+        Some(b) => parent
+            .join(b)
+            .with_context(|| format!("joining {} BaseURL '{}' to {}", level, b, parent)),
+        None => Ok(parent.clone()),
+    }
+}
+
+/// The element's own `BaseURL`, without surrounding whitespace.
+fn base_url<'a>(node: Node<'a, '_>) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.is_element() && n.tag_name().name() == "BaseURL")
+        .and_then(|n| n.text())
+        .map(str::trim)
+}
+
+/// The element's `BaseURL`s after the first.
+fn other_base_urls<'a>(node: Node<'a, '_>) -> Vec<&'a str> {
+    node.children()
+        .filter(|n| n.is_element() && n.tag_name().name() == "BaseURL")
+        .skip(1)
+        .filter_map(|n| n.text())
+        .map(str::trim)
+        .collect()
+}
+
+/// Duration of each Period in seconds: its `@duration`, else the time until
+/// the next Period's `@start`, else (for the last Period) the rest of the
+/// `mediaPresentationDuration`. Malformed attributes are ignored and noted in
+/// `unparsed`.
+fn period_durations<'a>(
+    periods: &[Node<'a, '_>],
+    mpd_duration_secs: Option<f64>,
+    unparsed: &mut Vec<(&'static str, &'a str)>,
+) -> Vec<Option<f64>> {
+    let starts: Vec<Option<f64>> = periods
+        .iter()
+        .map(|p| duration_attribute(*p, "start", unparsed))
+        .collect();
+
+    periods
+        .iter()
+        .enumerate()
+        .map(|(i, period)| {
+            if let Some(duration) = duration_attribute(*period, "duration", unparsed) {
+                return Some(duration);
+            }
+            let start = starts[i].unwrap_or(0.0);
+            match starts.get(i + 1) {
+                Some(next) => next.map(|next| next - start),
+                None => mpd_duration_secs.map(|total| total - start),
+            }
+            .filter(|d| *d > 0.0)
+        })
+        .collect()
+}
+
+/// The duration attribute `name` of `node` in seconds; noted in `unparsed`
+/// if it is there but malformed.
+fn duration_attribute<'a>(
+    node: Node<'a, '_>,
+    name: &'static str,
+    unparsed: &mut Vec<(&'static str, &'a str)>,
+) -> Option<f64> {
+    let value = node.attribute(name)?;
+    let secs = parse_duration(value);
+    if secs.is_none() {
+        unparsed.push((name, value));
+    }
+    secs
+}
+
+/// Parse an ISO 8601 duration, the `xs:duration` of MPD attributes, into
+/// seconds: `PnYnMnWnDTnHnMnS` with any of the components left out, such as
+/// `PT3M30.840S`, `P0DT1H` or `P1DT12H`. Components may be fractional, with
+/// a decimal point or comma.
+///
+/// Years and months have no fixed length; like the DASH reference player,
+/// a year counts as 365 days and a month as 30. `None` for anything else,
+/// including negative durations and components out of order or repeated.
+pub fn parse_duration(s: &str) -> Option<f64> {
+    let rest = s.trim().strip_prefix('P')?;
+    let (date, time) = match rest.split_once('T') {
+        Some((date, time)) => (date, Some(time)),
+        None => (rest, None),
+    };
+    let (date_secs, date_count) = components(date, DATE_UNITS)?;
+    let (time_secs, time_count) = match time {
+        // `T` only precedes a time.
+        Some(time) => components(time, TIME_UNITS).filter(|(_, count)| *count > 0)?,
+        None => (0.0, 0),
+    };
+    let secs = date_secs + time_secs;
+    (date_count + time_count > 0 && secs.is_finite()).then_some(secs)
+}
+
+/// Designators of the date part of a duration, in order, with their length
+/// in seconds.
+const DATE_UNITS: &[(u8, f64)] = &[
+    (b'Y', 365.0 * 86_400.0),
+    (b'M', 30.0 * 86_400.0),
+    (b'W', 7.0 * 86_400.0),
+    (b'D', 86_400.0),
+];
+
+/// Designators of the time part of a duration, as [`DATE_UNITS`].
+const TIME_UNITS: &[(u8, f64)] = &[(b'H', 3_600.0), (b'M', 60.0), (b'S', 1.0)];
+
+/// Seconds and number of the components of one part of a duration, each a
+/// decimal number followed by one of `units`, in their order.
+fn components(part: &str, units: &[(u8, f64)]) -> Option<(f64, usize)> {
+    let bytes = part.as_bytes();
+    let mut secs = 0.0;
+    let mut count = 0;
+    let mut units = units.iter();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        while i < bytes.len() && (bytes[i].is_ascii_digit() || matches!(bytes[i], b'.' | b',')) {
+            i += 1;
+        }
+        let value: f64 = part[start..i].replace(',', ".").parse().ok()?;
+        let designator = *bytes.get(i)?;
+        i += 1;
+        let (_, length) = units.find(|(unit, _)| *unit == designator)?;
+        secs += value * length;
+        count += 1;
+    }
+    Some((secs, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_duration;
+
+    #[test]
+    fn all_components() {
+        assert_eq!(parse_duration("PT3M30S"), Some(210.0));
+        assert_eq!(
+            parse_duration("P1DT2H3M4S"),
+            Some(86_400.0 + 7_200.0 + 180.0 + 4.0)
+        );
+        assert_eq!(parse_duration("P0DT1H"), Some(3_600.0));
+        assert_eq!(parse_duration("P2W"), Some(14.0 * 86_400.0));
+        // A year as 365 days, a month as 30.
+        assert_eq!(parse_duration("P1Y1M"), Some(395.0 * 86_400.0));
+        assert_eq!(parse_duration(" PT10S\n"), Some(10.0));
+    }
+
+    #[test]
+    fn month_and_minute_by_position() {
+        assert_eq!(parse_duration("P1M"), Some(30.0 * 86_400.0));
+        assert_eq!(parse_duration("PT1M"), Some(60.0));
+        assert_eq!(parse_duration("P1MT1M"), Some(30.0 * 86_400.0 + 60.0));
+    }
+
+    #[test]
+    fn fractional_seconds() {
+        assert_eq!(parse_duration("PT3M30.840S"), Some(210.84));
+        assert_eq!(parse_duration("PT0.5S"), Some(0.5));
+        assert_eq!(parse_duration("PT.5S"), Some(0.5));
+        assert_eq!(parse_duration("PT1.5H"), Some(5_400.0));
+    }
+
+    #[test]
+    fn comma_decimals() {
+        assert_eq!(parse_duration("PT0,5S"), Some(0.5));
+        assert_eq!(parse_duration("P0,5D"), Some(43_200.0));
+        assert_eq!(parse_duration("PT1,2,3S"), None);
+    }
+
+    #[test]
+    fn time_components_need_t() {
+        assert_eq!(parse_duration("P1H"), None);
+        assert_eq!(parse_duration("P30S"), None);
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(parse_duration("T1H"), None);
+    }
+
+    #[test]
+    fn empty_durations() {
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("P"), None);
+        assert_eq!(parse_duration("PT"), None);
+        assert_eq!(parse_duration("P1DT"), None);
+        assert_eq!(parse_duration("PS"), None);
+    }
+
+    #[test]
+    fn negative_durations() {
+        assert_eq!(parse_duration("-PT5S"), None);
+        assert_eq!(parse_duration("PT-5S"), None);
+        assert_eq!(parse_duration("P-1D"), None);
+    }
+
+    #[test]
+    fn components_out_of_order_or_repeated() {
+        assert_eq!(parse_duration("PT1S1M"), None);
+        assert_eq!(parse_duration("PT1S1S"), None);
+        assert_eq!(parse_duration("P1D1Y"), None);
+        assert_eq!(parse_duration("P1DT1HT1M"), None);
+    }
+
+    #[test]
+    fn overflowing_input() {
+        let huge = format!("P{}Y", "9".repeat(400));
+        assert_eq!(parse_duration(&huge), None);
+        let huge = format!("PT{}S", "9".repeat(400));
+        assert_eq!(parse_duration(&huge), None);
+        // Finite on their own, infinite added up.
+        let big = format!("P{}DT{}S", "9".repeat(303), "9".repeat(308));
+        assert_eq!(parse_duration(&big), None);
+        assert_eq!(parse_duration("PT1e3S"), None);
+    }
+}
@@ -0,0 +1,177 @@
+//! Where in a mirror each resource is kept.
+//!
+//! A [`PathMapper`] turns the URL of a resource into a path below the output
+//! directory. The mirror asks once per resource and remembers the answer, so
+//! every manifest referring to a file points at the same place. Paths of
+//! manifests mirrored for a header variant get the variant's suffix on top.
+//! [`OriginPaths`], the layout of the origin below the start URL, is the
+//! default.
+//!
+//! Origins may nest deeply and name files at length. Paths longer than
+//! Windows' `MAX_PATH` are left to the standard library, which opens them as
+//! `\\?\` paths, but no file system takes a single name longer than 255
+//! bytes, so [`OriginPaths`] shortens those with [`shorten_names`].
+
+use sha2::{Digest, Sha256};
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+use url::Url;
+
+/// Longest file or directory name [`OriginPaths`] gives, in bytes. File
+/// systems allow 255; the rest is room for what a mirror appends, such as the
+/// `.orig` of original manifests or the suffix of a header variant.
+pub const MAX_NAME_LEN: usize = 200;
+
+/// Decides the paths of the resources in a mirror.
+pub trait PathMapper: Send + Sync {
+    /// Where to keep the resource at `url`, relative to the output
+    /// directory; `is_manifest` for HLS playlists and DASH MPDs. Different
+    /// resources must get different paths, with names the file system takes
+    /// (see [`shorten_names`]).
+    fn path(&self, url: &Url, is_manifest: bool) -> PathBuf;
+}
+
+/// Paths as on the origin, relative to the directory of the start URL; a
+/// query string becomes part of the file name.
+#[derive(Debug, Clone)]
+pub struct OriginPaths {
+    /// Path segments of the start URL, whose directory is the root.
+    master_url_path_components: Vec<String>,
+}
+
+impl OriginPaths {
+    pub fn new(start_url: &Url) -> Self {
+        Self {
+            master_url_path_components: components(start_url),
+        }
+    }
+
+    /// Paths relative to the directory `start_url` and `companion` share, so
+    /// that references from one manifest to the other's files resolve in the
+    /// mirror.
+    pub fn shared(start_url: &Url, companion: &Url) -> Self {
+        let mut master = components(start_url);
+        let other = components(companion);
+        let shared = master
+            .iter()
+            .zip(&other)
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(master.len() - 1)
+            .min(other.len() - 1);
+        master.truncate(shared);
+        master.push(String::new());
+        Self {
+            master_url_path_components: master,
+        }
+    }
+}
+
+impl PathMapper for OriginPaths {
+    #[allow(unused_variables)]
+    fn path(&self, url: &Url, is_manifest: bool) -> PathBuf {
+        let rel = url
+            .path()
+            .trim_start_matches('/')
+            .split('/')
+            .collect::<Vec<_>>();
+
+        let base = self.master_url_path_components.as_slice();
+
+        // Find the relative difference:
+        // master = ["x","y","z","manifest.ext"]
+        // child  = ["x","y","z","sub","foo.ext"]
+        // -> rel_parts = ["sub","foo.ext"]
+        let mut idx = 0;
+        while idx < base.len().saturating_sub(1)
+            && idx < rel.len().saturating_sub(1)
+            && base[idx] == rel[idx]
+        {
+            idx += 1;
+        }
+
+        let mut local_path = PathBuf::from(rel[idx..].join("/"));
+
+        // Ensure HLS manifest has a .m3u8 extension if none is present
+        #[cfg(feature = "hls")]
+        {
+            if is_manifest && local_path.extension().is_none() {
+                local_path.set_extension("m3u8");
+            }
+        }
+
+        // Handle query string → safe filenames
+        if let Some(q) = url.query() {
+            let fname = local_path.file_name().unwrap_or_default().to_string_lossy();
+            let (stem, ext) = fname
+                .rsplit_once('.')
+                .map(|(s, e)| (s.to_string(), Some(e.to_string())))
+                .unwrap_or((fname.to_string(), None));
+
+            let mut safe: String = q
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            if safe.len() > 32 {
+                safe.truncate(32);
+            }
+
+            let new_name = match ext {
+                Some(ext) => format!("{stem}__q_{safe}.{ext}"),
+                None => format!("{stem}__q_{safe}"),
+            };
+
+            local_path.set_file_name(new_name);
+        }
+
+        shorten_names(&local_path)
+    }
+}
+
+/// `path` with every name longer than [`MAX_NAME_LEN`] cut short and told
+/// apart again by a hash of the whole name. The extension is kept, so that
+/// servers and players still recognize the file, and the same name is
+/// always shortened the same way.
+pub fn shorten_names(path: &Path) -> PathBuf {
+    path.components()
+        .map(|component| match component {
+            Component::Normal(name) if name.len() > MAX_NAME_LEN => {
+                OsString::from(shorten(&name.to_string_lossy()))
+            }
+            other => other.as_os_str().to_os_string(),
+        })
+        .collect()
+}
+
+fn shorten(name: &str) -> String {
+    let digest = Sha256::digest(name.as_bytes());
+    let hash: String = digest[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    let suffix = match name.rsplit_once('.') {
+        Some((_, ext)) if !ext.is_empty() && ext.len() <= 16 => format!("~{hash}.{ext}"),
+        _ => format!("~{hash}"),
+    };
+    let mut keep = MAX_NAME_LEN - suffix.len();
+    while !name.is_char_boundary(keep) {
+        keep -= 1;
+    }
+    format!("{}{}", &name[..keep], suffix)
+}
+
+/// How a file in `base` refers to `target`: its relative path with forward
+/// slashes.
+pub fn relative_reference(target: &Path, base: &Path) -> String {
+    let rel = pathdiff::diff_paths(target, base).unwrap_or_else(|| target.to_path_buf());
+    let parts: Vec<String> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect();
+    parts.join("/")
+}
+
+fn components(url: &Url) -> Vec<String> {
+    url.path()
+        .trim_start_matches('/')
+        .split('/')
+        .map(|s| s.to_string())
+        .collect()
+}
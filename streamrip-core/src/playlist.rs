@@ -0,0 +1,601 @@
+//! Typed model of HLS playlists.
+//!
+//! A playlist is kept as its sequence of lines, each classified as a tag, a
+//! URI or a blank line, so that rewriting can change URIs and leave
+//! everything else as it was: tags the model doesn't know, vendor tags such
+//! as `#EXT-X-CUE-OUT` and comments are written back byte for byte. Master and media playlists additionally
+//! expose what mirroring, filtering and live recording need to know about
+//! them: the variants of a master, the target duration and end marker of a
+//! media playlist.
+
+/// Longest duration a tag may give, in seconds: a year. Longer ones are
+/// bogus, and would overflow timers.
+pub const MAX_DURATION: f64 = 365.0 * 86_400.0;
+
+/// One line of a playlist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    /// A line starting with `#`: a tag, or a comment.
+    Tag(Tag<'a>),
+    /// A URI line: the line as written and the URI without surrounding
+    /// whitespace.
+    Uri { line: &'a str, uri: &'a str },
+    /// An empty (or whitespace-only) line, as written.
+    Blank(&'a str),
+}
+
+impl<'a> Line<'a> {
+    pub fn parse(line: &'a str) -> Self {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            Line::Blank(line)
+        } else if trimmed.starts_with('#') {
+            Line::Tag(Tag::parse(line))
+        } else {
+            Line::Uri { line, uri: trimmed }
+        }
+    }
+
+    /// A URI line with its URI replaced by `uri`, keeping whatever
+    /// surrounds it.
+    pub fn with_uri(line: &str, uri: &str) -> String {
+        let start = line.len() - line.trim_start().len();
+        let end = line.trim_end().len();
+        format!("{}{}{}", &line[..start], uri, &line[end..])
+    }
+}
+
+/// A tag line such as `#EXT-X-KEY:METHOD=AES-128,URI="key.bin"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tag<'a> {
+    /// The line as written.
+    pub line: &'a str,
+    /// The tag name including `#`, e.g. `#EXT-X-KEY`.
+    pub name: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    fn parse(line: &'a str) -> Self {
+        let trimmed = line.trim();
+        let name = trimmed.split_once(':').map_or(trimmed, |(name, _)| name);
+        Self { line, name }
+    }
+
+    /// A line starting with `#` but not `#EXT` is a comment (RFC 8216,
+    /// section 4.1), whatever it contains.
+    pub fn is_comment(&self) -> bool {
+        !self.name.starts_with("#EXT")
+    }
+
+    /// Whether this is the tag `name` (given with its `#`).
+    pub fn is(&self, name: &str) -> bool {
+        self.name == name
+    }
+
+    /// Everything after the colon, e.g. the number of `#EXT-X-TARGETDURATION:6`.
+    pub fn value(&self) -> &'a str {
+        self.line
+            .trim()
+            .split_once(':')
+            .map_or("", |(_, value)| value.trim())
+    }
+
+    /// Seconds given by the tag, such as the duration of an `#EXTINF` (before
+    /// its title) or the value of `#EXT-X-TARGETDURATION`. `None` unless they
+    /// are written as a decimal number of at most [`MAX_DURATION`], so that
+    /// `inf`, `NaN` or hundreds of digits, which Rust would parse, never reach
+    /// a timer.
+    pub fn duration(&self) -> Option<f64> {
+        let value = self.value().split(',').next().unwrap_or_default().trim();
+        if !value.bytes().all(|b| b.is_ascii_digit() || b == b'.') {
+            return None;
+        }
+        value.parse().ok().filter(|secs| *secs <= MAX_DURATION)
+    }
+
+    /// Value of the named attribute, with surrounding quotes removed.
+    pub fn attribute(&self, name: &str) -> Option<String> {
+        attribute(self.line, name)
+    }
+}
+
+/// `KEYFORMAT` of keys that don't give one.
+pub const IDENTITY_KEY_FORMAT: &str = "identity";
+
+/// `KEYFORMAT` of an `#EXT-X-KEY` or `#EXT-X-SESSION-KEY` tag.
+pub fn key_format(tag: &Tag<'_>) -> String {
+    tag.attribute("KEYFORMAT")
+        .unwrap_or_else(|| IDENTITY_KEY_FORMAT.to_string())
+}
+
+/// The `#EXT-X-KEY` tags in effect at some point of a media playlist.
+///
+/// A key applies to the segments after it up to the next `#EXT-X-KEY` of the
+/// same `KEYFORMAT`, so a playlist offering its keys in several formats (for
+/// several DRM systems, say) has a segment under several of them at once,
+/// each rotated on its own; `METHOD=NONE` ends all of them (RFC 8216,
+/// section 4.3.2.4).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Keys {
+    /// In the order they appeared.
+    active: Vec<Key>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Key {
+    format: String,
+    method: String,
+    line: String,
+}
+
+impl Keys {
+    /// Take `tag` into account, if it is an `#EXT-X-KEY`.
+    pub fn apply(&mut self, tag: &Tag<'_>) {
+        if !tag.is("#EXT-X-KEY") {
+            return;
+        }
+        let method = tag.attribute("METHOD").unwrap_or_default();
+        if method == "NONE" {
+            self.active.clear();
+            return;
+        }
+        let format = key_format(tag);
+        self.active.retain(|key| key.format != format);
+        self.active.push(Key {
+            format,
+            method,
+            line: tag.line.to_string(),
+        });
+    }
+
+    /// Whether the segments are encrypted as a whole (`METHOD=AES-128`), so
+    /// they don't look like any container.
+    pub fn whole_segments(&self) -> bool {
+        self.active.iter().any(|key| key.method == "AES-128")
+    }
+
+    /// The lines of the keys in effect, in playlist order.
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.active.iter().map(|key| key.line.as_str())
+    }
+
+    /// Lines to put in front of a segment numbered `sequence` by the origin
+    /// but not in the playlist written, so that it is still decrypted with
+    /// the IV the origin meant: the identity keys in effect without an `IV`,
+    /// which take it from the media sequence number (RFC 8216, section
+    /// 5.2), with that number as their `IV`.
+    pub fn pinned_ivs(&self, sequence: u64) -> Vec<String> {
+        self.active
+            .iter()
+            .filter(|key| key.format == IDENTITY_KEY_FORMAT && attribute(&key.line, "IV").is_none())
+            .map(|key| format!("{},IV=0x{:032X}", key.line.trim_end(), sequence))
+            .collect()
+    }
+}
+
+/// A variant of a master playlist.
+#[derive(Debug, Clone, Copy)]
+pub struct Variant {
+    /// Index of the `#EXT-X-STREAM-INF` or `#EXT-X-I-FRAME-STREAM-INF` line.
+    pub tag: usize,
+    /// Index of the URI line of a `#EXT-X-STREAM-INF` variant.
+    pub uri: Option<usize>,
+    /// An I-frame variant, whose URI is an attribute of the tag itself.
+    pub i_frame: bool,
+    pub bandwidth: Option<u64>,
+    pub height: Option<u32>,
+}
+
+/// An alternative rendition (`#EXT-X-MEDIA`) of a master playlist.
+#[derive(Debug, Clone)]
+pub struct Media {
+    /// Index of the `#EXT-X-MEDIA` line.
+    pub tag: usize,
+    /// `TYPE`, which is also the name of the variant attribute referencing
+    /// the group (`AUDIO`, `VIDEO`, `SUBTITLES`, `CLOSED-CAPTIONS`).
+    pub kind: Option<String>,
+    pub group_id: Option<String>,
+    pub name: Option<String>,
+    pub language: Option<String>,
+}
+
+/// A master playlist, listing variants (and renditions) of one presentation.
+#[derive(Debug, Clone)]
+pub struct HlsMasterPlaylist<'a> {
+    pub lines: Vec<Line<'a>>,
+    pub variants: Vec<Variant>,
+    pub media: Vec<Media>,
+}
+
+impl<'a> HlsMasterPlaylist<'a> {
+    fn from_lines(lines: Vec<Line<'a>>) -> Self {
+        let mut variants = Vec::new();
+        let mut media = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let Line::Tag(tag) = line else {
+                continue;
+            };
+            if tag.is("#EXT-X-MEDIA") {
+                media.push(Media {
+                    tag: i,
+                    kind: tag.attribute("TYPE"),
+                    group_id: tag.attribute("GROUP-ID"),
+                    name: tag.attribute("NAME"),
+                    language: tag.attribute("LANGUAGE"),
+                });
+                continue;
+            }
+            let i_frame = tag.is("#EXT-X-I-FRAME-STREAM-INF");
+            if !i_frame && !tag.is("#EXT-X-STREAM-INF") {
+                continue;
+            }
+            // A STREAM-INF variant's URI is on the next URI line.
+            let uri = if i_frame {
+                None
+            } else {
+                (i + 1..lines.len()).find(|&j| matches!(lines[j], Line::Uri { .. }))
+            };
+            variants.push(Variant {
+                tag: i,
+                uri,
+                i_frame,
+                bandwidth: tag.attribute("BANDWIDTH").and_then(|b| b.parse().ok()),
+                height: tag
+                    .attribute("RESOLUTION")
+                    .and_then(|r| r.split_once('x').and_then(|(_, h)| h.parse().ok())),
+            });
+        }
+        Self {
+            lines,
+            variants,
+            media,
+        }
+    }
+
+    /// The playlist without the lines for which `keep` is false.
+    pub fn retain(self, keep: &[bool]) -> Self {
+        let lines = self
+            .lines
+            .into_iter()
+            .zip(keep)
+            .filter(|(_, kept)| **kept)
+            .map(|(line, _)| line)
+            .collect();
+        Self::from_lines(lines)
+    }
+}
+
+/// A media playlist, listing the segments of one rendition.
+#[derive(Debug, Clone)]
+pub struct HlsMediaPlaylist<'a> {
+    pub lines: Vec<Line<'a>>,
+    /// `#EXT-X-TARGETDURATION`, in seconds. Playlists without one are
+    /// treated as media playlists too but are neither live nor VOD.
+    pub target_duration: Option<f64>,
+    /// Whether `#EXT-X-ENDLIST` is present.
+    pub ended: bool,
+    /// Sum of the `#EXTINF` durations, in seconds.
+    pub duration: f64,
+}
+
+impl<'a> HlsMediaPlaylist<'a> {
+    fn from_lines(lines: Vec<Line<'a>>) -> Self {
+        let mut playlist = Self {
+            lines: Vec::new(),
+            target_duration: None,
+            ended: false,
+            duration: 0.0,
+        };
+        for line in &lines {
+            let Line::Tag(tag) = line else {
+                continue;
+            };
+            match tag.name {
+                "#EXT-X-TARGETDURATION" => playlist.target_duration = tag.duration(),
+                "#EXT-X-ENDLIST" => playlist.ended = true,
+                "#EXTINF" => playlist.duration += tag.duration().unwrap_or(0.0),
+                _ => {}
+            }
+        }
+        playlist.lines = lines;
+        playlist
+    }
+
+    /// Whether this playlist lists segments, as opposed to e.g. a playlist
+    /// made of nothing but tags.
+    pub fn is_media(&self) -> bool {
+        self.target_duration.is_some()
+    }
+}
+
+/// A parsed HLS playlist.
+#[derive(Debug, Clone)]
+pub enum HlsPlaylist<'a> {
+    Master(HlsMasterPlaylist<'a>),
+    Media(HlsMediaPlaylist<'a>),
+}
+
+impl<'a> HlsPlaylist<'a> {
+    /// Parse a playlist; `None` if `text` doesn't start with `#EXTM3U`
+    /// (after a byte order mark, if any).
+    pub fn parse(text: &'a str) -> Option<Self> {
+        let text = text.strip_prefix(BOM).unwrap_or(text);
+        if !text.trim_start().starts_with("#EXTM3U") {
+            return None;
+        }
+        let lines: Vec<Line<'a>> = text.lines().map(Line::parse).collect();
+        let is_master = lines
+            .iter()
+            .any(|l| matches!(l, Line::Tag(tag) if tag.is("#EXT-X-STREAM-INF")));
+        Some(if is_master {
+            HlsPlaylist::Master(HlsMasterPlaylist::from_lines(lines))
+        } else {
+            HlsPlaylist::Media(HlsMediaPlaylist::from_lines(lines))
+        })
+    }
+
+    pub fn lines(&self) -> &[Line<'a>] {
+        match self {
+            HlsPlaylist::Master(master) => &master.lines,
+            HlsPlaylist::Media(media) => &media.lines,
+        }
+    }
+}
+
+/// How a playlist's lines are put together: its newline style, whether it
+/// starts with a UTF-8 byte order mark and whether it ends with a newline.
+///
+/// Rewritten playlists are written with the layout of the original, as some
+/// origins and players compare them strictly. The default is what
+/// `--normalize-manifests` writes: LF, no BOM, a final newline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub crlf: bool,
+    pub bom: bool,
+    pub final_newline: bool,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            crlf: false,
+            bom: false,
+            final_newline: true,
+        }
+    }
+}
+
+impl Layout {
+    /// The layout of `text`, judged by its first line ending.
+    pub fn of(text: &str) -> Self {
+        Self {
+            crlf: text.find('\n').is_some_and(|i| text[..i].ends_with('\r')),
+            bom: text.starts_with(BOM),
+            final_newline: text.ends_with('\n') || text.is_empty(),
+        }
+    }
+
+    /// Join rewritten playlist lines.
+    pub fn render(&self, lines: &[String]) -> String {
+        let newline = if self.crlf { "\r\n" } else { "\n" };
+        let mut text = String::new();
+        if self.bom {
+            text.push(BOM);
+        }
+        text.push_str(&lines.join(newline));
+        if self.final_newline {
+            text.push_str(newline);
+        }
+        text
+    }
+}
+
+/// The UTF-8 byte order mark some playlists start with.
+pub const BOM: char = '\u{feff}';
+
+/// Value of the named attribute in an HLS tag's attribute list, with
+/// surrounding quotes removed.
+pub fn attribute(line: &str, name: &str) -> Option<String> {
+    let (start, end) = attribute_span(line, name)?;
+    Some(line[start..end].to_string())
+}
+
+/// Byte range of the named attribute's value within `line`, excluding
+/// surrounding whitespace and quotes.
+pub fn attribute_span(line: &str, name: &str) -> Option<(usize, usize)> {
+    attribute_spans(line)
+        .into_iter()
+        .find(|(key, _)| *key == name)
+        .map(|(_, span)| span)
+}
+
+/// Whether the attribute `name` holds a URI: `URI` itself, any `*-URI`
+/// (`X-ASSET-URI`, vendor extensions) and interstitial `X-ASSET-LIST`s.
+pub fn is_uri_attribute(name: &str) -> bool {
+    name == "URI" || name.ends_with("-URI") || name == "X-ASSET-LIST"
+}
+
+/// Names and value ranges (as for [`attribute_span`]) of every attribute in
+/// a tag's attribute list, in order.
+///
+/// Follows the attribute-list grammar of RFC 8216, section 4.2, leniently:
+/// whitespace around names, values and commas is allowed, quoted strings may
+/// contain commas, `=` and backslash-escaped quotes, and unquoted values
+/// (decimal and hexadecimal numbers, resolutions, enumerated strings) run up
+/// to the next comma. Anything that isn't a `NAME=value` pair, such as the
+/// duration and title of `#EXTINF`, is skipped.
+pub fn attribute_spans(line: &str) -> Vec<(&str, (usize, usize))> {
+    let Some(colon) = line.find(':') else {
+        return Vec::new();
+    };
+    let bytes = line.as_bytes();
+    let skip_whitespace = |mut i: usize| {
+        while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+
+    let mut spans = Vec::new();
+    let mut i = colon + 1;
+    while i < bytes.len() {
+        i = skip_whitespace(i);
+        let name_start = i;
+        while i < bytes.len() && !matches!(bytes[i], b'=' | b',' | b'"') {
+            i += 1;
+        }
+        let name = line[name_start..i].trim_end();
+
+        let value = if i < bytes.len() && bytes[i] == b'=' && !name.is_empty() {
+            i = skip_whitespace(i + 1);
+            if i < bytes.len() && bytes[i] == b'"' {
+                let begin = i + 1;
+                i = begin;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                let end = i.min(bytes.len());
+                i = end + 1;
+                Some((begin, end))
+            } else {
+                let begin = i;
+                while i < bytes.len() && bytes[i] != b',' {
+                    i += 1;
+                }
+                Some((begin, begin + line[begin..i].trim_end().len()))
+            }
+        } else {
+            None
+        };
+        if let Some(span) = value {
+            spans.push((name, span));
+        }
+
+        // Skip to the next attribute, stepping over quoted strings in
+        // whatever couldn't be parsed.
+        let mut in_quotes = false;
+        while i < bytes.len() && (in_quotes || bytes[i] != b',') {
+            if bytes[i] == b'"' {
+                in_quotes = !in_quotes;
+            }
+            i += 1;
+        }
+        i += 1;
+    }
+
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names and values of the attributes of `line`, checking that every span
+    /// can be sliced out of it.
+    fn attributes(line: &str) -> Vec<(&str, &str)> {
+        attribute_spans(line)
+            .into_iter()
+            .map(|(name, (start, end))| (name, line.get(start..end).expect("span off the line")))
+            .collect()
+    }
+
+    fn duration(line: &str) -> Option<f64> {
+        match Line::parse(line) {
+            Line::Tag(tag) => tag.duration(),
+            other => panic!("not a tag: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn attribute_lists() {
+        assert_eq!(
+            attributes(r#"#EXT-X-KEY:METHOD=AES-128,URI="key.bin",IV=0x1F"#),
+            [("METHOD", "AES-128"), ("URI", "key.bin"), ("IV", "0x1F")]
+        );
+        assert_eq!(
+            attributes(r#"#EXT-X-MEDIA: NAME = "a, b=\"c\"" , DEFAULT=YES "#),
+            [("NAME", r#"a, b=\"c\""#), ("DEFAULT", "YES")]
+        );
+        // The duration and title of `#EXTINF` aren't attributes.
+        assert_eq!(attributes("#EXTINF:10.0,A=1"), [("A", "1")]);
+    }
+
+    #[test]
+    fn malformed_attribute_lists() {
+        assert_eq!(attributes("#EXT-X-KEY"), []);
+        assert_eq!(attributes("#EXT-X-KEY:"), []);
+        assert_eq!(attributes("#EXT-X-KEY:,,,"), []);
+        assert_eq!(attributes("#EXT-X-KEY:=1,URI=x"), [("URI", "x")]);
+        assert_eq!(attributes("#EXT-X-KEY:URI="), [("URI", "")]);
+        assert_eq!(attributes(r#"#EXT-X-KEY:URI=""#), [("URI", "")]);
+        // An unterminated string runs to the end of the line.
+        assert_eq!(
+            attributes(r#"#EXT-X-KEY:URI="key.bin,IV=0x1F"#),
+            [("URI", "key.bin,IV=0x1F")]
+        );
+        // A backslash escaping past the end of the line.
+        assert_eq!(attributes(r#"#EXT-X-KEY:URI="key\"#), [("URI", r"key\")]);
+        // A backslash escaping into a multi-byte character.
+        assert_eq!(
+            attributes("#EXT-X-KEY:URI=\"\\€\",IV=1"),
+            [("URI", "\\€"), ("IV", "1")]
+        );
+        assert_eq!(attributes("#EXT-X-KEY:URI=\"\\"), [("URI", "\\")]);
+        // Quotes in text that isn't an attribute.
+        assert_eq!(attributes(r#"#EXT-X-KEY:"x,y",URI=a,"z"#), [("URI", "a")]);
+        assert_eq!(attributes("#EXT-X-KEY:é=ü,ö"), [("é", "ü")]);
+    }
+
+    #[test]
+    fn attribute_lookup() {
+        let line = r#"#EXT-X-KEY:METHOD=AES-128,URI="key.bin""#;
+        assert_eq!(attribute(line, "URI").as_deref(), Some("key.bin"));
+        assert_eq!(attribute(line, "IV"), None);
+        assert_eq!(
+            attribute(r#"#EXT-X-KEY:URI="key"#, "URI").as_deref(),
+            Some("key")
+        );
+    }
+
+    #[test]
+    fn tag_durations() {
+        assert_eq!(duration("#EXTINF:10,"), Some(10.0));
+        assert_eq!(duration("#EXTINF:9.009,title, with commas"), Some(9.009));
+        assert_eq!(duration("#EXTINF: 4.5 ,"), Some(4.5));
+        assert_eq!(duration("#EXT-X-TARGETDURATION:6"), Some(6.0));
+        assert_eq!(
+            duration("#EXT-X-TARGETDURATION:31536000"),
+            Some(MAX_DURATION)
+        );
+    }
+
+    #[test]
+    fn malformed_tag_durations() {
+        assert_eq!(duration("#EXTINF"), None);
+        assert_eq!(duration("#EXTINF:"), None);
+        assert_eq!(duration("#EXTINF:,"), None);
+        assert_eq!(duration("#EXTINF:."), None);
+        assert_eq!(duration("#EXTINF:1.2.3,"), None);
+        assert_eq!(duration("#EXTINF:-1,"), None);
+        assert_eq!(duration("#EXTINF:+1,"), None);
+        // Parsed by Rust, but no number of seconds.
+        assert_eq!(duration("#EXTINF:inf,"), None);
+        assert_eq!(duration("#EXTINF:infinity,"), None);
+        assert_eq!(duration("#EXTINF:NaN,"), None);
+        assert_eq!(duration("#EXTINF:1e9,"), None);
+        // Would overflow a timer.
+        assert_eq!(duration("#EXT-X-TARGETDURATION:31536001"), None);
+        let huge = format!("#EXT-X-TARGETDURATION:{}", "9".repeat(400));
+        assert_eq!(duration(&huge), None);
+    }
+
+    #[test]
+    fn media_playlist_skips_bogus_durations() {
+        let text = "#EXTM3U\n#EXT-X-TARGETDURATION:inf\n#EXTINF:1e400,\na.ts\n#EXTINF:4,\nb.ts\n";
+        let Some(HlsPlaylist::Media(playlist)) = HlsPlaylist::parse(text) else {
+            panic!("not a media playlist");
+        };
+        assert_eq!(playlist.target_duration, None);
+        assert_eq!(playlist.duration, 4.0);
+    }
+}
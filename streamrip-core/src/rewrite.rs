@@ -0,0 +1,94 @@
+//! Rewriting HLS playlists to the paths of a mirror.
+//!
+//! The decisions a mirror makes about each reference of a playlist live
+//! here: whether it can be fetched at all, whether it names another playlist,
+//! and how the rewritten playlist refers to the local copy. The mirror
+//! fetches and follows what is referenced in between; [`rewrite_hls`] does
+//! nothing but rewrite.
+
+use crate::PathMapper;
+use crate::paths::relative_reference;
+use crate::playlist::{self, HlsPlaylist, Layout, Line, Tag};
+use anyhow::{Context, Result, anyhow};
+use std::path::Path;
+use url::Url;
+
+/// Rewrite the HLS playlist `text`, served at `url`, the way a mirror does:
+/// every URI it references becomes the path `paths` maps it to, relative to
+/// the path of the playlist itself. Line endings and a byte order mark are
+/// kept.
+pub fn rewrite_hls(url: &Url, text: &str, paths: &dyn PathMapper) -> Result<String> {
+    let playlist =
+        HlsPlaylist::parse(text).ok_or_else(|| anyhow!("{} is not an HLS playlist", url))?;
+    let own_path = paths.path(url, true);
+    let dir = own_path.parent().unwrap_or(Path::new(""));
+
+    let mut lines = Vec::with_capacity(playlist.lines().len());
+    // The URI line following #EXT-X-STREAM-INF names a playlist, whatever
+    // its extension.
+    let mut variant_pending = false;
+    for line in playlist.lines() {
+        match *line {
+            Line::Tag(tag) if tag.is_comment() => lines.push(tag.line.to_string()),
+            Line::Tag(tag) => {
+                if tag.is("#EXT-X-STREAM-INF") {
+                    variant_pending = true;
+                }
+                let mut new_line = String::with_capacity(tag.line.len());
+                let mut copied = 0;
+                for (attr, (start, end)) in playlist::attribute_spans(tag.line) {
+                    if !playlist::is_uri_attribute(attr) {
+                        continue;
+                    }
+                    let child_url = resolve(url, &tag.line[start..end])?;
+                    if !is_fetchable(&child_url) {
+                        continue;
+                    }
+                    let target = paths.path(&child_url, names_playlist(tag, attr, &child_url));
+                    new_line.push_str(&tag.line[copied..start]);
+                    new_line.push_str(&relative_reference(&target, dir));
+                    copied = end;
+                }
+                new_line.push_str(&tag.line[copied..]);
+                lines.push(new_line);
+            }
+            Line::Blank(blank) => lines.push(blank.to_string()),
+            Line::Uri { line, uri } => {
+                let child_url = resolve(url, uri)?;
+                let is_variant = std::mem::take(&mut variant_pending);
+                if !is_fetchable(&child_url) {
+                    lines.push(line.to_string());
+                    continue;
+                }
+                let target = paths.path(&child_url, is_variant || is_playlist_url(&child_url));
+                lines.push(Line::with_uri(line, &relative_reference(&target, dir)));
+            }
+        }
+    }
+    Ok(Layout::of(text).render(&lines))
+}
+
+/// The URL a playlist at `url` refers to as `uri`.
+pub fn resolve(url: &Url, uri: &str) -> Result<Url> {
+    url.join(uri)
+        .with_context(|| format!("resolving URI '{}' relative to {}", uri, url))
+}
+
+/// Whether a mirror can fetch `url`; key server schemes such as `skd://`
+/// (FairPlay) or inline `data:` URIs are kept as they are.
+pub fn is_fetchable(url: &Url) -> bool {
+    matches!(url.scheme(), "http" | "https")
+}
+
+/// Whether the URI attribute `attr` of `tag`, resolved to `url`, names a
+/// playlist rather than a file.
+pub fn names_playlist(tag: Tag<'_>, attr: &str, url: &Url) -> bool {
+    attr == "X-ASSET-URI"
+        || (attr == "URI" && (tag.is("#EXT-X-MEDIA") || tag.is("#EXT-X-I-FRAME-STREAM-INF")))
+        || is_playlist_url(url)
+}
+
+/// Whether `url` looks like a playlist by its extension.
+pub fn is_playlist_url(url: &Url) -> bool {
+    url.path().to_ascii_lowercase().ends_with(".m3u8")
+}
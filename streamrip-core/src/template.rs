@@ -0,0 +1,348 @@
+//! DASH `SegmentTemplate` inheritance.
+//!
+//! A template may be declared on the Period, the AdaptationSet and the
+//! Representation at once. Each level only overrides the attributes it sets
+//! (ISO/IEC 23009-1, 5.3.9.1), so `media` may come from the AdaptationSet
+//! while `startNumber` comes from the Representation.
+
+use anyhow::{Context, Result, anyhow, bail};
+use roxmltree::Node;
+use std::ops::RangeInclusive;
+use url::Url;
+
+/// The `SegmentTemplate`s that apply to one Representation, most specific
+/// first.
+#[derive(Debug, Clone)]
+pub struct SegmentTemplate<'a, 'input> {
+    levels: Vec<Node<'a, 'input>>,
+}
+
+impl<'a, 'input> SegmentTemplate<'a, 'input> {
+    /// Collect the templates declared on `elements`, given from the
+    /// Representation up to the Period; `None` if there are none.
+    pub fn inherited(elements: &[Node<'a, 'input>]) -> Option<Self> {
+        let levels: Vec<Node> = elements
+            .iter()
+            .filter_map(|e| {
+                e.children()
+                    .find(|n| n.is_element() && n.tag_name().name() == "SegmentTemplate")
+            })
+            .collect();
+        (!levels.is_empty()).then_some(Self { levels })
+    }
+
+    /// Value of `name` from the most specific template that sets it.
+    pub fn attribute(&self, name: &str) -> Option<&'a str> {
+        self.levels.iter().find_map(|st| st.attribute(name))
+    }
+}
+
+/// Upper bound on `$SubNumber$` parts probed per segment.
+pub const MAX_SUB_SEGMENTS: u64 = 1000;
+
+/// Upper bound on the number of segments of a template, against bogus
+/// `timescale`, `duration` or `endNumber` values.
+pub const MAX_SEGMENTS: u64 = 200_000;
+
+/// HEAD requests spent confirming an inferred last segment number.
+pub const END_PROBES: u64 = 3;
+
+/// Number of segments of `segment_units` (in `timescale` units per second)
+/// covering `total_secs`. A trailing remainder below a millisecond is
+/// rounding noise in the durations, not another segment.
+///
+/// An error for a `timescale` or segment duration of 0, which no number of
+/// segments covers. The count itself is not bounded; see [`MAX_SEGMENTS`].
+pub fn segment_count(total_secs: f64, segment_units: u64, timescale: u64) -> Result<u64> {
+    if timescale == 0 {
+        bail!("timescale 0");
+    }
+    if segment_units == 0 {
+        bail!("segment duration 0");
+    }
+    if total_secs <= 0.0 {
+        return Ok(0);
+    }
+    let segment_secs = segment_units as f64 / timescale as f64;
+    let exact = total_secs / segment_secs;
+    let whole = exact.floor();
+    // Casts saturate, so even absurd counts stay in range.
+    Ok(if (exact - whole) * segment_secs < 0.001 {
+        whole as u64
+    } else {
+        exact.ceil() as u64
+    })
+}
+
+/// Number of the last of `count` (at least one) segments numbered from
+/// `start_number`; an error if it is past the largest number.
+pub fn last_number(start_number: u64, count: u64) -> Result<u64> {
+    count
+        .checked_sub(1)
+        .and_then(|offset| start_number.checked_add(offset))
+        .ok_or_else(|| {
+            anyhow!(
+                "startNumber {} leaves no room for {} segments",
+                start_number,
+                count
+            )
+        })
+}
+
+/// The segment numbers `start_number..=end_number`, at most
+/// [`MAX_SEGMENTS`] of them; an error if the range runs backwards or is
+/// longer.
+pub fn segment_numbers(start_number: u64, end_number: u64) -> Result<RangeInclusive<u64>> {
+    if end_number < start_number {
+        bail!(
+            "endNumber {} is below startNumber {}",
+            end_number,
+            start_number
+        );
+    }
+    if end_number - start_number >= MAX_SEGMENTS {
+        bail!(
+            "startNumber {} to endNumber {} are more than {} segments",
+            start_number,
+            end_number,
+            MAX_SEGMENTS
+        );
+    }
+    Ok(start_number..=end_number)
+}
+
+/// Widest `%0<width>d` format tag honoured, so that a template can't ask
+/// for gigabytes of padding.
+pub const MAX_WIDTH: usize = 32;
+
+/// A piece of a `media` or `initialization` template (ISO/IEC 23009-1,
+/// 5.3.9.4.4).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Piece<'a> {
+    /// Text taken as it is; `$$` becomes a `$`.
+    Literal(&'a str),
+    /// An identifier such as `$Number$`, or `$Number%05d$` with a `width`.
+    Identifier {
+        name: &'a str,
+        width: Option<usize>,
+        /// The identifier as written, `$` signs included.
+        written: &'a str,
+    },
+}
+
+/// Split a template into its pieces.
+///
+/// Takes any input: a `$` without a closing one and anything between two
+/// `$` that isn't an identifier with an optional `%0<width>d` format tag are
+/// literal text, as some origins put `$` in their templates unescaped.
+pub fn parse(tmpl: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut rest = tmpl;
+    while let Some(open) = rest.find('$') {
+        let Some(len) = rest[open + 1..].find('$') else {
+            break;
+        };
+        let close = open + 1 + len;
+        if open > 0 {
+            pieces.push(Piece::Literal(&rest[..open]));
+        }
+        let written = &rest[open..=close];
+        pieces.push(if len == 0 {
+            Piece::Literal("$")
+        } else {
+            identifier(&rest[open + 1..close], written).unwrap_or(Piece::Literal(written))
+        });
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        pieces.push(Piece::Literal(rest));
+    }
+    pieces
+}
+
+/// The identifier `inner`, written as `written`; `None` if it isn't one.
+fn identifier<'a>(inner: &'a str, written: &'a str) -> Option<Piece<'a>> {
+    let (name, format) = match inner.split_once('%') {
+        Some((name, format)) => (name, Some(format)),
+        None => (inner, None),
+    };
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+        return None;
+    }
+    let width = match format {
+        Some(format) => {
+            let digits = format.strip_prefix('0')?.strip_suffix('d')?;
+            if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            Some(digits.parse().ok().filter(|&w| w <= MAX_WIDTH)?)
+        }
+        None => None,
+    };
+    Some(Piece::Identifier {
+        name,
+        width,
+        written,
+    })
+}
+
+/// Fill in `$RepresentationID$`, `$Number$` and, for low-latency chunked
+/// segments, `$SubNumber$`. Other identifiers are kept as written.
+pub fn expand(tmpl: &str, representation_id: &str, number: u64, sub_number: Option<u64>) -> String {
+    let mut path = String::with_capacity(tmpl.len());
+    for piece in parse(tmpl) {
+        match piece {
+            Piece::Literal(text) => path.push_str(text),
+            Piece::Identifier {
+                name: "RepresentationID",
+                ..
+            } => path.push_str(representation_id),
+            Piece::Identifier {
+                name: "Number",
+                width,
+                ..
+            } => path.push_str(&padded(number, width)),
+            Piece::Identifier {
+                name: "SubNumber",
+                width,
+                written,
+            } => match sub_number {
+                Some(sub) => path.push_str(&padded(sub, width)),
+                None => path.push_str(written),
+            },
+            Piece::Identifier { written, .. } => path.push_str(written),
+        }
+    }
+    path
+}
+
+/// URL of the initialization segment of Representation `representation_id`
+/// with the `initialization` template `tmpl`, below `base`.
+pub fn initialization_url(base: &Url, tmpl: &str, representation_id: &str) -> Result<Url> {
+    let path = tmpl.replace("$RepresentationID$", representation_id);
+    base.join(path.trim())
+        .with_context(|| format!("joining init path '{}' to {}", path, base))
+}
+
+/// URL of media segment `number` (and part `sub_number`) of Representation
+/// `representation_id` with the `media` template `tmpl`, below `base`.
+pub fn media_url(
+    base: &Url,
+    tmpl: &str,
+    representation_id: &str,
+    number: u64,
+    sub_number: Option<u64>,
+) -> Result<Url> {
+    let path = expand(tmpl, representation_id, number, sub_number);
+    base.join(path.trim())
+        .with_context(|| format!("joining media path '{}' to {}", path, base))
+}
+
+/// `number` with leading zeros up to `width` digits.
+fn padded(number: u64, width: Option<usize>) -> String {
+    format!("{:0width$}", number, width = width.unwrap_or(0))
+}
+
+/// Whether media segments are split into `$SubNumber$` parts (LL-DASH).
+pub fn has_sub_number(tmpl: &str) -> bool {
+    parse(tmpl).iter().any(|piece| {
+        matches!(
+            piece,
+            Piece::Identifier {
+                name: "SubNumber",
+                ..
+            }
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers() {
+        assert_eq!(
+            expand("$RepresentationID$/seg-$Number$.m4s", "v1", 7, None),
+            "v1/seg-7.m4s"
+        );
+        assert_eq!(
+            expand("seg_$Number%05d$.m4s", "v1", 42, None),
+            "seg_00042.m4s"
+        );
+        assert_eq!(
+            expand("$Number$-$SubNumber%03d$.m4s", "v1", 3, Some(2)),
+            "3-002.m4s"
+        );
+        // Narrower than the number: no truncation.
+        assert_eq!(expand("$Number%02d$", "v1", 12345, None), "12345");
+        assert_eq!(expand("a$$b", "v1", 1, None), "a$b");
+    }
+
+    #[test]
+    fn unknown_identifiers_kept_as_written() {
+        assert_eq!(expand("$Time$.m4s", "v1", 1, None), "$Time$.m4s");
+        assert_eq!(expand("$SubNumber$", "v1", 1, None), "$SubNumber$");
+        assert_eq!(expand("$Bandwidth%04d$", "v1", 1, None), "$Bandwidth%04d$");
+    }
+
+    #[test]
+    fn stray_dollars_are_literal() {
+        assert_eq!(expand("$", "v1", 1, None), "$");
+        assert_eq!(expand("$Number", "v1", 1, None), "$Number");
+        // Dollar signs pair up in order.
+        assert_eq!(
+            expand("price$5/$Number$", "v1", 1, None),
+            "price$5/$Number$"
+        );
+        assert_eq!(expand("$$$", "v1", 1, None), "$$");
+        assert_eq!(expand("$a b$", "v1", 1, None), "$a b$");
+        assert_eq!(expand("$€$", "v1", 1, None), "$€$");
+        assert_eq!(parse("$"), [Piece::Literal("$")]);
+    }
+
+    #[test]
+    fn malformed_format_tags() {
+        for tmpl in [
+            "$Number%$",
+            "$Number%d$",
+            "$Number%0d$",
+            "$Number%5d$",
+            "$Number%05$",
+            "$Number%05x$",
+            "$Number%0-5d$",
+            "$Number%0+5d$",
+            "$Number%05d%05d$",
+            "$%05d$",
+        ] {
+            assert_eq!(parse(tmpl), [Piece::Literal(tmpl)], "{}", tmpl);
+            assert_eq!(expand(tmpl, "v1", 1, None), tmpl);
+        }
+    }
+
+    #[test]
+    fn widths_are_bounded() {
+        assert_eq!(expand("$Number%032d$", "v1", 1, None), format!("{:032}", 1));
+        // Gigabytes of padding, or more than fits a usize.
+        for tmpl in [
+            "$Number%033d$",
+            "$Number%04294967296d$",
+            "$Number%099999999999999999999999d$",
+        ] {
+            assert_eq!(expand(tmpl, "v1", 1, None), tmpl);
+        }
+        assert_eq!(
+            expand("$Number%020d$", "v1", u64::MAX, Some(u64::MAX)),
+            u64::MAX.to_string()
+        );
+    }
+
+    #[test]
+    fn sub_numbers() {
+        assert!(has_sub_number("$Number$_$SubNumber$.m4s"));
+        assert!(has_sub_number("$Number$_$SubNumber%02d$.m4s"));
+        assert!(!has_sub_number("$Number$.m4s"));
+        assert!(!has_sub_number("$$SubNumber$$"));
+        assert!(!has_sub_number("$SubNumber"));
+    }
+}
@@ -0,0 +1,246 @@
+//! BaseURL and SegmentTemplate resolution against MPDs as the common
+//! packagers (Shaka Packager, Bento4, GPAC, Unified Streaming) write them.
+
+#![cfg(feature = "dash")]
+
+use roxmltree::Document;
+use streamrip_core::mpd::{self, BaseUrlKind, MpdModel};
+use streamrip_core::template;
+use url::Url;
+
+/// A Representation of a fixture: its id, what its BaseURL names, and the
+/// URLs of its initialization and first two media segments.
+#[derive(Debug, PartialEq)]
+struct Resolved {
+    id: String,
+    kind: Option<BaseUrlKind>,
+    init: Option<String>,
+    media: Vec<String>,
+}
+
+/// Resolve every Representation of the fixture `name`, served from `mpd_url`.
+fn resolve(name: &str, mpd_url: &str) -> Vec<Resolved> {
+    let path = format!(
+        "{}/tests/fixtures/packagers/{}",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let text = std::fs::read_to_string(path).unwrap();
+    let doc = Document::parse(&text).unwrap();
+    let model = MpdModel::parse(&doc).unwrap();
+    let mpd_url = Url::parse(mpd_url).unwrap();
+
+    let mut resolved = Vec::new();
+    for period in &model.periods {
+        let period_base = mpd::join_base_url(&mpd_url, period.base_url, "Period").unwrap();
+        for aset in &period.adaptation_sets {
+            let aset_base =
+                mpd::join_base_url(&period_base, aset.base_url, "AdaptationSet").unwrap();
+            for rep in &aset.representations {
+                let id = rep.id.unwrap();
+                let base = mpd::join_base_url(&aset_base, rep.base_url, "Representation").unwrap();
+                let kind = rep
+                    .base_url
+                    .map(|b| mpd::base_url_kind(b, rep.template.is_some()));
+                let init = rep
+                    .template
+                    .as_ref()
+                    .and_then(|st| st.attribute("initialization"))
+                    .map(|tmpl| {
+                        template::initialization_url(&base, tmpl, id)
+                            .unwrap()
+                            .to_string()
+                    });
+                let media = match rep.template.as_ref().and_then(|st| st.attribute("media")) {
+                    Some(tmpl) => (1..=2)
+                        .map(|n| {
+                            template::media_url(&base, tmpl, id, n, None)
+                                .unwrap()
+                                .to_string()
+                        })
+                        .collect(),
+                    None if kind == Some(BaseUrlKind::File) => vec![base.to_string()],
+                    None => Vec::new(),
+                };
+                resolved.push(Resolved {
+                    id: id.to_string(),
+                    kind,
+                    init,
+                    media,
+                });
+            }
+        }
+    }
+    resolved
+}
+
+fn rep(id: &str, kind: Option<BaseUrlKind>, init: Option<&str>, media: &[&str]) -> Resolved {
+    Resolved {
+        id: id.to_string(),
+        kind,
+        init: init.map(str::to_string),
+        media: media.iter().map(|m| m.to_string()).collect(),
+    }
+}
+
+#[test]
+fn shaka_packager() {
+    assert_eq!(
+        resolve(
+            "shaka.mpd",
+            "https://cdn.example.com/vod/movie/manifest.mpd"
+        ),
+        [
+            rep(
+                "0",
+                None,
+                Some("https://cdn.example.com/vod/movie/h264_720p/init.mp4"),
+                &[
+                    "https://cdn.example.com/vod/movie/h264_720p/1.m4s",
+                    "https://cdn.example.com/vod/movie/h264_720p/2.m4s",
+                ],
+            ),
+            // Single-file media with a SegmentBase.
+            rep(
+                "1",
+                Some(BaseUrlKind::File),
+                None,
+                &["https://cdn.example.com/vod/movie/audio_en.mp4"],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn bento4() {
+    assert_eq!(
+        resolve("bento4.mpd", "https://cdn.example.com/vod/movie/stream.mpd"),
+        [
+            rep(
+                "video/avc1/1",
+                None,
+                Some("https://cdn.example.com/vod/movie/video/avc1/1/init.mp4"),
+                &[
+                    "https://cdn.example.com/vod/movie/video/avc1/1/seg-1.m4s",
+                    "https://cdn.example.com/vod/movie/video/avc1/1/seg-2.m4s",
+                ],
+            ),
+            rep(
+                "audio/en/mp4a.40.2",
+                None,
+                Some("https://cdn.example.com/vod/movie/audio/en/mp4a.40.2/init.mp4"),
+                &[
+                    "https://cdn.example.com/vod/movie/audio/en/mp4a.40.2/seg-1.m4s",
+                    "https://cdn.example.com/vod/movie/audio/en/mp4a.40.2/seg-2.m4s",
+                ],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn gpac() {
+    assert_eq!(
+        resolve(
+            "gpac.mpd",
+            "https://cdn.example.com/vod/movie/movie_dash.mpd"
+        ),
+        [
+            // A dotted directory with its trailing slash.
+            rep(
+                "1",
+                Some(BaseUrlKind::Directory),
+                Some("https://cdn.example.com/vod/movie/video/1.5mbps/init.mp4"),
+                &[
+                    "https://cdn.example.com/vod/movie/video/1.5mbps/seg_00001.m4s",
+                    "https://cdn.example.com/vod/movie/video/1.5mbps/seg_00002.m4s",
+                ],
+            ),
+            // The same without it: segments resolve next to it, and it isn't
+            // taken for a file for the dot.
+            rep(
+                "2",
+                Some(BaseUrlKind::UnterminatedDirectory),
+                Some("https://cdn.example.com/vod/movie/video/3.0mbps/init.mp4"),
+                &[
+                    "https://cdn.example.com/vod/movie/video/3.0mbps/seg_00001.m4s",
+                    "https://cdn.example.com/vod/movie/video/3.0mbps/seg_00002.m4s",
+                ],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn unified_streaming() {
+    assert_eq!(
+        resolve(
+            "usp.mpd",
+            "https://cdn.example.com/vod/tears-of-steel.ism/.mpd"
+        ),
+        [
+            rep(
+                "audio_eng=128000",
+                None,
+                Some(
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-audio_eng=128000.dash"
+                ),
+                &[
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-audio_eng=128000-1.dash",
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-audio_eng=128000-2.dash",
+                ],
+            ),
+            rep(
+                "video_eng=1500000",
+                None,
+                Some(
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-video_eng=1500000.dash"
+                ),
+                &[
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-video_eng=1500000-1.dash",
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/tears-of-steel-video_eng=1500000-2.dash",
+                ],
+            ),
+            rep(
+                "video_eng=2200000",
+                Some(BaseUrlKind::UnterminatedDirectory),
+                Some(
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/hd/tears-of-steel-video_eng=2200000.dash"
+                ),
+                &[
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/hd/tears-of-steel-video_eng=2200000-1.dash",
+                    "https://cdn.example.com/vod/tears-of-steel.ism/dash/hd/tears-of-steel-video_eng=2200000-2.dash",
+                ],
+            ),
+        ]
+    );
+}
+
+#[test]
+fn trailing_slash_marks_directories() {
+    assert_eq!(
+        mpd::base_url_kind("video/1.5mbps/", false),
+        BaseUrlKind::Directory
+    );
+    assert_eq!(
+        mpd::base_url_kind("video/1.5mbps/", true),
+        BaseUrlKind::Directory
+    );
+    assert_eq!(
+        mpd::base_url_kind("video/1.5mbps", true),
+        BaseUrlKind::UnterminatedDirectory
+    );
+    assert_eq!(
+        mpd::base_url_kind("audio_eng=128000", true),
+        BaseUrlKind::UnterminatedDirectory
+    );
+    assert_eq!(mpd::base_url_kind("movie.mp4", false), BaseUrlKind::File);
+    assert_eq!(
+        mpd::base_url_kind("subs/en.webvtt?v=2", false),
+        BaseUrlKind::File
+    );
+    assert_eq!(
+        mpd::base_url_kind("subs/?v=2", false),
+        BaseUrlKind::Directory
+    );
+}